@@ -53,7 +53,10 @@ export function activate(context: ExtensionContext) {
 	const clientOptions: LanguageClientOptions = {
 		// Register the server for plain text documents
 		documentSelector: [{ scheme: 'file', language: 'nickel' }],
+		initializationOptions: workspace.getConfiguration("nls"),
 		synchronize: {
+			// Notify the server about changes of the settings of the extension
+			configurationSection: "nls",
 			// Notify the server about file changes to '.clientrc files contained in the workspace
 			fileEvents: workspace.createFileSystemWatcher('**/.ncl')
 		}
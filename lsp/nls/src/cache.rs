@@ -1,16 +1,48 @@
-use std::{collections::HashMap, ffi::OsString, io};
+use std::{collections::HashMap, ffi::OsString, path::Path};
 
-use codespan::FileId;
+use anyhow::Result;
+use codespan::{FileId, Files};
+use codespan_lsp::position_to_byte_index;
+use lsp_types::{Position, TextDocumentContentChangeEvent, Url};
 use nickel_lang::{
-    cache::{Cache, CacheError, CacheOp, CachedTerm, EntryState},
+    cache::{normalize_path, Cache, CacheError, CacheOp, EntryState},
     error::TypecheckError,
     typecheck,
 };
 
 use crate::linearization::{completed::Completed, AnalysisHost};
 
+/// Return the name under which a document is stored in the cache. Documents backed by a file are
+/// named after the normalized path of the file, such that imports of the file resolve to the
+/// content of the document instead of the content on disk.
+pub fn document_name(uri: &Url) -> OsString {
+    match uri.to_file_path() {
+        Ok(path) => normalize_path(&path).unwrap_or_else(|_| path.into_os_string()),
+        Err(()) => uri.as_str().into(),
+    }
+}
+
+/// Convert a position to a byte index in a source. Contrary to [position_to_byte_index], which
+/// panics if the line of the position is out of bounds, an error is returned instead.
+fn byte_index(files: &Files<String>, file_id: FileId, position: &Position) -> Result<usize> {
+    files.line_span(file_id, position.line)?;
+    Ok(position_to_byte_index(files, file_id, position)?)
+}
+
 pub trait CacheExt {
-    fn update_content(&mut self, path: impl Into<OsString>, s: String) -> io::Result<FileId>;
+    /// Retrieve the id of the document corresponding to an URI.
+    fn id_of_uri(&self, uri: &Url) -> Option<FileId>;
+    /// Retrieve the URI of a document or of a file loaded by an import.
+    fn uri_of(&self, file_id: FileId) -> Option<Url>;
+    /// Apply the changes of a `didChange` notification to the source of a file, which may be
+    /// either full replacements or range edits, and invalidate the file. Return the importers of
+    /// the file which have been invalidated as well. If a change is invalid, none of them is
+    /// applied.
+    fn apply_changes(
+        &mut self,
+        file_id: FileId,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<Vec<FileId>>;
     fn typecheck_with_analysis(
         &mut self,
         file_id: FileId,
@@ -20,16 +52,49 @@ pub trait CacheExt {
 }
 
 impl CacheExt for Cache {
-    fn update_content(&mut self, path: impl Into<OsString>, source: String) -> io::Result<FileId> {
-        let path: OsString = path.into();
-        if let Some(file_id) = self.id_of(path.clone()) {
-            self.files_mut().update(file_id, source);
-            // invalidate cache so the file gets parsed again
-            self.terms_mut().remove(&file_id);
-            Ok(file_id)
+    fn id_of_uri(&self, uri: &Url) -> Option<FileId> {
+        self.id_of(document_name(uri))
+    }
+
+    fn uri_of(&self, file_id: FileId) -> Option<Url> {
+        let name = self.name(file_id);
+        let path = Path::new(name);
+        if path.is_absolute() {
+            Url::from_file_path(path).ok()
         } else {
-            Ok(self.add_string(path, source))
+            Url::parse(&name.to_string_lossy()).ok()
+        }
+    }
+
+    fn apply_changes(
+        &mut self,
+        file_id: FileId,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<Vec<FileId>> {
+        // The changes are applied to a copy of the source, such that the file is left untouched
+        // if one of them is invalid.
+        let mut files = Files::new();
+        let copy = files.add(self.name(file_id), self.files().source(file_id).clone());
+        for change in changes {
+            let source = match change.range {
+                // Positions of a change are relative to the source resulting from the previous
+                // changes, hence we update the source after each change.
+                Some(range) => {
+                    let start = byte_index(&files, copy, &range.start)?;
+                    let end = byte_index(&files, copy, &range.end)?;
+                    let mut source = files.source(copy).clone();
+                    source.replace_range(start..end, &change.text);
+                    source
+                }
+                None => change.text,
+            };
+
+            files.update(copy, source);
         }
+        self.files_mut().update(file_id, files.source(copy).clone());
+
+        // invalidate cache so the file and its importers get processed again
+        Ok(self.invalidate(file_id))
     }
     fn typecheck_with_analysis<'a>(
         &mut self,
@@ -37,23 +102,188 @@ impl CacheExt for Cache {
         global_env: &typecheck::Environment,
         lin_cache: &mut HashMap<FileId, Completed>,
     ) -> Result<CacheOp<()>, CacheError<TypecheckError>> {
-        if !self.terms_mut().contains_key(&file_id) {
-            return Err(CacheError::NotParsed);
-        }
-
-        // After self.parse(), the cache must be populated
-        let CachedTerm { term, state, .. } = self.terms().get(&file_id).unwrap();
+        let state = match self.entry_state(file_id) {
+            Some(state) => state,
+            None => return Err(CacheError::NotParsed),
+        };
 
-        if *state > EntryState::Typechecked && lin_cache.contains_key(&file_id) {
+        // If the file is being analyzed, we are following an import cycle
+        if (state >= EntryState::Typechecked && lin_cache.contains_key(&file_id))
+            || state == EntryState::Typechecking
+        {
             Ok(CacheOp::Cached(()))
-        } else if *state >= EntryState::Parsed {
-            let host = AnalysisHost::new();
-            let (_, linearized) = typecheck::type_check(term, global_env, self, host)?;
-            self.update_state(file_id, EntryState::Typechecked);
-            lin_cache.insert(file_id, linearized);
-            Ok(CacheOp::Done(()))
         } else {
-            panic!()
+            self.update_state(file_id, EntryState::Typechecking);
+
+            // Imported files are analyzed first, such that their types are known when
+            // typechecking the importer, and their linearizations are available to resolve
+            // references across files. Their errors are reported when they are opened.
+            for import in self.get_all_imports(file_id) {
+                let _ = self.typecheck_with_analysis(import, global_env, lin_cache);
+            }
+
+            let term = self.get_ref(file_id).unwrap().clone();
+            let host = AnalysisHost::new();
+            match typecheck::type_check(&term, global_env, self, host) {
+                Ok((_, linearized)) => {
+                    self.cache_type(file_id);
+                    self.update_state(file_id, EntryState::Typechecked);
+                    lin_cache.insert(file_id, linearized);
+                    Ok(CacheOp::Done(()))
+                }
+                Err(err) => {
+                    self.update_state(file_id, state);
+                    Err(err.into())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::Range;
+
+    use super::*;
+
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: String::from(text),
+        }
+    }
+
+    fn replace(text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: String::from(text),
         }
     }
+
+    #[test]
+    fn sequential_changes() {
+        let mut cache = Cache::new();
+        let file_id = cache.add_string("<test>", String::from("1"));
+
+        cache
+            .apply_changes(
+                file_id,
+                vec![
+                    replace("let x = 1 in\nx + x"),
+                    edit((0, 8), (0, 9), "\"é\""),
+                    edit((0, 11), (0, 11), " ++ \"😀\""),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            cache.files().source(file_id),
+            "let x = \"é\" ++ \"😀\" in\nx + x"
+        );
+
+        // Positions count UTF-16 code units: `é` is one unit and the emoji two, while they are
+        // respectively two and four bytes long.
+        cache
+            .apply_changes(
+                file_id,
+                vec![edit((0, 16), (0, 18), "a"), edit((1, 2), (1, 3), "++")],
+            )
+            .unwrap();
+        assert_eq!(
+            cache.files().source(file_id),
+            "let x = \"é\" ++ \"a\" in\nx ++ x"
+        );
+
+        assert!(cache
+            .apply_changes(file_id, vec![edit((5, 0), (5, 1), "")])
+            .is_err());
+
+        // A batch is applied entirely or not at all
+        assert!(cache
+            .apply_changes(
+                file_id,
+                vec![
+                    replace("1"),
+                    edit((0, 0), (0, 1), "2"),
+                    edit((5, 0), (5, 1), "")
+                ]
+            )
+            .is_err());
+        assert_eq!(
+            cache.files().source(file_id),
+            "let x = \"é\" ++ \"a\" in\nx ++ x"
+        );
+    }
+
+    #[test]
+    fn changes_invalidate_importers() {
+        let dir = std::env::temp_dir().join(format!("nls-invalidate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.ncl"), "import \"b.ncl\"").unwrap();
+        fs::write(dir.join("b.ncl"), "import \"c.ncl\"").unwrap();
+        fs::write(dir.join("c.ncl"), "1").unwrap();
+
+        let mut cache = Cache::new();
+        let a = cache.add_file(dir.join("a.ncl")).unwrap();
+        cache.parse(a).unwrap();
+        cache.resolve_imports(a).unwrap();
+        let b = cache
+            .id_of(normalize_path(&dir.join("b.ncl")).unwrap())
+            .unwrap();
+        let c = cache
+            .id_of(normalize_path(&dir.join("c.ncl")).unwrap())
+            .unwrap();
+
+        let mut importers = cache.apply_changes(c, vec![replace("2")]).unwrap();
+        importers.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(importers, expected);
+        assert_eq!(cache.entry_state(c), None);
+        assert!(cache
+            .apply_changes(a, vec![replace("3")])
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shared_imports_are_analyzed_first() {
+        let dir = std::env::temp_dir().join(format!("nls-shared-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.ncl"), "{name : Str = \"lib\"}").unwrap();
+        fs::write(dir.join("a.ncl"), "import \"lib.ncl\"").unwrap();
+        fs::write(
+            dir.join("b.ncl"),
+            "let lib = import \"lib.ncl\" in (lib.name : Str)",
+        )
+        .unwrap();
+
+        // The library is loaded by the first document, and then imported by the second one
+        let mut cache = Cache::new();
+        let a = cache.add_file(dir.join("a.ncl")).unwrap();
+        cache.parse(a).unwrap();
+        cache.resolve_imports(a).unwrap();
+        let b = cache.add_file(dir.join("b.ncl")).unwrap();
+        cache.parse(b).unwrap();
+        cache.resolve_imports(b).unwrap();
+        let lib = cache
+            .id_of(normalize_path(&dir.join("lib.ncl")).unwrap())
+            .unwrap();
+
+        let mut lin_cache = HashMap::new();
+        assert!(cache
+            .typecheck_with_analysis(b, &typecheck::Environment::new(), &mut lin_cache)
+            .is_ok());
+        assert!(lin_cache.contains_key(&lib));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,69 @@
+use log::warn;
+use serde::Deserialize;
+
+/// The settings of the server. They are given by the client as the `initializationOptions` of the
+/// `initialize` request, and may be updated later by a `workspace/didChangeConfiguration`
+/// notification. Missing settings take their default value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    pub eval: EvalConfig,
+    pub inlay_hints: InlayHintsConfig,
+}
+
+/// The settings of the evaluation of documents on save.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EvalConfig {
+    /// If documents are evaluated when they are saved, to report evaluation errors such as
+    /// contract violations. Disabled by default, as evaluating arbitrary code may be costly.
+    pub enabled: bool,
+    /// The maximum number of steps of the abstract machine.
+    pub max_steps: usize,
+    /// The maximum duration of an evaluation, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        EvalConfig {
+            enabled: false,
+            max_steps: 500_000,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+/// The settings of the inlay hints showing inferred types.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct InlayHintsConfig {
+    /// If the types inferred for `let` bindings, function parameters and record fields are shown
+    /// as inlay hints.
+    pub enabled: bool,
+}
+
+impl Default for InlayHintsConfig {
+    fn default() -> Self {
+        InlayHintsConfig { enabled: true }
+    }
+}
+
+impl Config {
+    /// Read the settings from the JSON value sent by the client. The settings may be wrapped in
+    /// an `nls` section, as VS Code does for the configuration of an extension.
+    pub fn from_json(value: serde_json::Value) -> Config {
+        let value = match value {
+            serde_json::Value::Null => return Config::default(),
+            serde_json::Value::Object(mut map) if map.contains_key("nls") => {
+                map.remove("nls").unwrap()
+            }
+            value => value,
+        };
+
+        serde_json::from_value(value).unwrap_or_else(|err| {
+            warn!("invalid configuration: {}", err);
+            Config::default()
+        })
+    }
+}
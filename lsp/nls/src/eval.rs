@@ -0,0 +1,605 @@
+//! Evaluation of the documents on save, to report the errors which are only detected at runtime,
+//! such as contract violations.
+//!
+//! Evaluation may take a while: it runs on a separate thread, such that the server keeps
+//! answering requests in the meantime. The evaluation thread has its own cache, since the terms
+//! of a cache can't be shared across threads. Its standard library is prepared once, and it is
+//! kept up to date with the content of the documents opened in the editor.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use crossbeam_channel::{Receiver, Sender};
+use log::{debug, warn};
+use lsp_types::{DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Url};
+use nickel_lang::{
+    cache::{Cache, EntryState, GlobalEnv},
+    error::{EvalError, FieldError, FixData, ToDiagnostic},
+    eval::{
+        self,
+        callstack::CallStack,
+        context::EvalContext,
+        debugger::{Debugger, MachineState},
+    },
+    identifier::Ident,
+    position::{RawSpan, TermPos},
+};
+
+use crate::{
+    cache::CacheExt,
+    config::EvalConfig,
+    diagnostic::LocationCompat,
+    linearization::{
+        completed::Completed,
+        imports::{field_of, top_level_record, value_of},
+    },
+    requests::code_actions,
+};
+
+/// The maximum number of errors reported by the evaluation of a document.
+const MAX_ERRORS: usize = 32;
+
+/// The size of the stack of the evaluation thread. Dropping the state of an evaluation interrupted
+/// by its budget, such as a long chain of closures, may recurse deeply.
+const EVAL_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// A document to evaluate, sent to the evaluation thread.
+struct Job {
+    generation: usize,
+    name: OsString,
+    uri: Url,
+    /// The names and the content of the documents opened in the editor, which take precedence
+    /// over the files on disk.
+    documents: Vec<(OsString, String)>,
+    /// The diagnostics of the analysis of the document, published together with the errors of
+    /// the evaluation.
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    config: EvalConfig,
+}
+
+/// The diagnostics of the analysis and of the evaluation of a document.
+pub struct Evaluated {
+    generation: usize,
+    pub uri: Url,
+    pub diagnostics: Vec<lsp_types::Diagnostic>,
+}
+
+/// The handle of the evaluation thread.
+pub struct Evaluator {
+    jobs: Sender<Job>,
+    results: Receiver<Evaluated>,
+    /// The generation of the latest evaluation. The evaluations of the previous generations are
+    /// interrupted by their budget, and their results are discarded.
+    generation: Arc<AtomicUsize>,
+}
+
+impl Evaluator {
+    /// Start the evaluation thread, which first prepares its standard library.
+    pub fn new() -> Evaluator {
+        let (jobs, job_receiver) = crossbeam_channel::unbounded();
+        let (result_sender, results) = crossbeam_channel::unbounded();
+        let generation = Arc::new(AtomicUsize::new(0));
+
+        let worker_generation = Arc::clone(&generation);
+        thread::Builder::new()
+            .name(String::from("evaluation"))
+            .stack_size(EVAL_STACK_SIZE)
+            .spawn(move || run_worker(job_receiver, result_sender, worker_generation))
+            .expect("cannot start the evaluation thread");
+
+        Evaluator {
+            jobs,
+            results,
+            generation,
+        }
+    }
+
+    /// Evaluate a document, cancelling the evaluation in progress if any. The documents opened
+    /// in the editor are given by name and content, and the diagnostics of the analysis are
+    /// published together with the result.
+    pub fn evaluate(
+        &self,
+        name: OsString,
+        uri: Url,
+        documents: Vec<(OsString, String)>,
+        diagnostics: Vec<lsp_types::Diagnostic>,
+        config: EvalConfig,
+    ) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let job = Job {
+            generation,
+            name,
+            uri,
+            documents,
+            diagnostics,
+            config,
+        };
+        if self.jobs.send(job).is_err() {
+            warn!("the evaluation thread has stopped");
+        }
+    }
+
+    /// Cancel the evaluation in progress, if any, whose result would be outdated.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The results of the evaluations, which may be outdated: see [Self::is_current].
+    pub fn results(&self) -> &Receiver<Evaluated> {
+        &self.results
+    }
+
+    /// Return if a result is the one of the latest evaluation, and hasn't been cancelled since.
+    pub fn is_current(&self, evaluated: &Evaluated) -> bool {
+        evaluated.generation == self.generation.load(Ordering::SeqCst)
+    }
+}
+
+/// The state of the evaluation thread.
+struct Worker {
+    cache: Cache,
+    global_env: GlobalEnv,
+    lin_cache: HashMap<FileId, Completed>,
+    generation: Arc<AtomicUsize>,
+}
+
+fn run_worker(jobs: Receiver<Job>, results: Sender<Evaluated>, generation: Arc<AtomicUsize>) {
+    let mut cache = Cache::new();
+    let global_env = match cache.prepare_stdlib() {
+        Ok(global_env) => global_env,
+        Err(err) => {
+            warn!(
+                "cannot prepare the standard library for evaluation: {:?}",
+                err
+            );
+            return;
+        }
+    };
+    let mut worker = Worker {
+        cache,
+        global_env,
+        lin_cache: HashMap::new(),
+        generation,
+    };
+
+    while let Ok(job) = jobs.recv() {
+        // Only the latest job is worth evaluating, the previous ones have been cancelled
+        let job = jobs.try_iter().last().unwrap_or(job);
+        if job.generation != worker.generation.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        let mut diagnostics = job.diagnostics;
+        diagnostics.extend(worker.eval_diagnostics(
+            job.name,
+            job.documents,
+            &job.config,
+            job.generation,
+        ));
+        let evaluated = Evaluated {
+            generation: job.generation,
+            uri: job.uri,
+            diagnostics,
+        };
+        if results.send(evaluated).is_err() {
+            break;
+        }
+    }
+}
+
+/// A debugger interrupting evaluation once it has exceeded its budget of steps or time, or once
+/// it has been cancelled.
+struct Budget {
+    steps: usize,
+    max_steps: usize,
+    deadline: Instant,
+    generation: Arc<AtomicUsize>,
+    current: usize,
+}
+
+impl Budget {
+    fn new(config: &EvalConfig, generation: Arc<AtomicUsize>, current: usize) -> Self {
+        Budget {
+            steps: 0,
+            max_steps: config.max_steps,
+            deadline: Instant::now() + Duration::from_millis(config.timeout_ms),
+            generation,
+            current,
+        }
+    }
+}
+
+impl Debugger for Budget {
+    fn load_sources(&mut self, _files: &Files<String>, _stdlib_ids: &[FileId]) {}
+
+    fn step(&mut self, _state: &MachineState<'_>) -> Result<(), EvalError> {
+        self.steps += 1;
+        // Reading the clock at each step would slow evaluation down noticeably: it is only read
+        // every 1024 steps, as is the generation.
+        if self.steps > self.max_steps
+            || (self.steps & 0x3ff == 0
+                && (Instant::now() > self.deadline
+                    || self.generation.load(Ordering::Relaxed) != self.current))
+        {
+            Err(EvalError::Other(
+                String::from("evaluation budget exceeded"),
+                TermPos::None,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn error(&mut self, _error: &EvalError, _call_stack: &CallStack) {}
+}
+
+impl Worker {
+    /// Update the documents which have changed since the previous evaluation, and invalidate
+    /// them together with their importers.
+    fn update_documents(&mut self, documents: Vec<(OsString, String)>) {
+        for (name, source) in documents {
+            match self.cache.id_of(&name) {
+                Some(file_id) if *self.cache.files().source(file_id) == source => (),
+                _ => {
+                    let file_id = self.cache.replace_string(name, source);
+                    for invalidated in self.cache.invalidate(file_id) {
+                        self.lin_cache.remove(&invalidated);
+                    }
+                    self.lin_cache.remove(&file_id);
+                }
+            }
+        }
+    }
+
+    /// Fully evaluate a document within the budget given by the configuration, and return the
+    /// contract violations, missing field definitions and merge conflicts raised by evaluation.
+    ///
+    /// The document is analyzed first, as the fixes of the errors are computed from its
+    /// linearization. Other evaluation errors, as well as errors preventing evaluation, are not
+    /// reported: the latter are reported by the analysis of the server already.
+    fn eval_diagnostics(
+        &mut self,
+        name: OsString,
+        documents: Vec<(OsString, String)>,
+        config: &EvalConfig,
+        generation: usize,
+    ) -> Vec<lsp_types::Diagnostic> {
+        self.update_documents(documents);
+        let file_id = match self.cache.id_of(&name) {
+            Some(file_id) => file_id,
+            None => return Vec::new(),
+        };
+
+        let Worker {
+            cache,
+            global_env: GlobalEnv { eval_env, type_env },
+            lin_cache,
+            ..
+        } = self;
+        if cache.parse(file_id).is_err()
+            || cache.resolve_imports(file_id).is_err()
+            || cache
+                .typecheck_with_analysis(file_id, type_env, lin_cache)
+                .is_err()
+            || cache.entry_state(file_id) < Some(EntryState::Typechecked)
+            || cache.prepare(file_id, type_env).is_err()
+        {
+            return Vec::new();
+        }
+
+        let mut ctx = EvalContext::new();
+        ctx.set_silence_traces(true);
+        ctx.set_debugger(Budget::new(
+            config,
+            Arc::clone(&self.generation),
+            generation,
+        ));
+
+        let term = cache.get_owned(file_id).unwrap();
+        let errors = match eval::eval_full_collect(term, eval_env, cache, &mut ctx, MAX_ERRORS) {
+            Ok(_) => return Vec::new(),
+            Err(errors) => errors.errors,
+        };
+
+        let contract_id = cache.id_of("<stdlib/contract.ncl>");
+        errors
+            .into_iter()
+            .filter(|field_error| {
+                let reported = matches!(
+                    field_error.error,
+                    EvalError::BlameError(..)
+                        | EvalError::MissingFieldDef(..)
+                        | EvalError::MergeIncompatibleArgs(..)
+                );
+                if !reported {
+                    debug!("evaluation stopped: {:?}", field_error.error);
+                }
+                reported
+            })
+            .map(|field_error| {
+                // Errors such as missing definitions may not have any label in the document, in
+                // which case they are located at the field which raised them.
+                let fallback = field_span(cache, lin_cache, file_id, &field_error.path);
+                let diagnostics = field_error.to_diagnostic(cache.files_mut(), contract_id);
+                let mut diagnostic = to_lsp(diagnostics, file_id, fallback, cache);
+                let data = field_error
+                    .error
+                    .fix_data()
+                    .or_else(|| missing_definition(cache, lin_cache, file_id, &field_error));
+                if let Some(data) = data {
+                    code_actions::attach_fixes(
+                        std::slice::from_mut(&mut diagnostic),
+                        cache,
+                        file_id,
+                        &data,
+                    );
+                }
+                diagnostic
+            })
+            .collect()
+    }
+}
+
+/// Return the span of the declaration of the field at `path` in the result of a document, such as
+/// `foo.bar[2].baz`, or of its innermost parent declared in the document.
+fn field_span(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    file_id: FileId,
+    path: &str,
+) -> Option<RawSpan> {
+    let mut value = top_level_record(cache, lin_cache, file_id)?;
+    let mut span = None;
+
+    for name in path.split('.') {
+        // The elements of arrays are not declared
+        let (name, in_array) = match name.split_once('[') {
+            Some((name, _)) => (name, true),
+            None => (name, false),
+        };
+
+        let field = match field_of(cache, lin_cache, value, &Ident::from(name)) {
+            Some(field) if field.0 == file_id => field,
+            _ => break,
+        };
+        span = Some(field.1.pos);
+
+        value = match value_of(cache, lin_cache, field) {
+            Some(value) if !in_array => value,
+            _ => break,
+        };
+    }
+
+    span
+}
+
+/// Return the fix data of a missing definition of a field of the result of a document. When the
+/// field is forced by the evaluation of the result rather than by an access, the error doesn't
+/// tell which field and which record, which are then given by the path of the field.
+fn missing_definition(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    file_id: FileId,
+    field_error: &FieldError,
+) -> Option<FixData> {
+    let label = match &field_error.error {
+        EvalError::MissingFieldDef(label, _) => label,
+        _ => return None,
+    };
+    let (parent, name) = match field_error.path.rsplit_once('.') {
+        Some((parent, name)) => (Some(parent), name),
+        None => (None, field_error.path.as_str()),
+    };
+    if name.is_empty() || name.contains('[') {
+        return None;
+    }
+
+    let mut record = top_level_record(cache, lin_cache, file_id)?;
+    for parent_name in parent.into_iter().flat_map(|parent| parent.split('.')) {
+        let field = field_of(cache, lin_cache, record, &Ident::from(parent_name))?;
+        record = value_of(cache, lin_cache, field)?;
+    }
+
+    let ty = label.as_ref().map(|label| label.types.as_ref().clone());
+    Some(FixData::MissingFields(
+        vec![(Ident::from(name), ty)],
+        TermPos::Original(record.1.pos),
+    ))
+}
+
+/// Convert the diagnostics of an evaluation error into one LSP diagnostic. Contrary to errors
+/// reported by the analysis, evaluation errors often point to several files, such as the
+/// definition of a contract in an imported file. The diagnostic is located at the first label
+/// in the document, or else at the `fallback` span if it is in the document, or else at its
+/// start. The other labels are reported as related information.
+fn to_lsp(
+    diagnostics: Vec<Diagnostic<FileId>>,
+    file_id: FileId,
+    fallback: Option<RawSpan>,
+    cache: &Cache,
+) -> lsp_types::Diagnostic {
+    let mut diagnostics = diagnostics.into_iter();
+    let main = diagnostics.next().unwrap_or_else(Diagnostic::error);
+    let mut message = main.message.clone();
+    for note in main.notes.iter() {
+        message.push('\n');
+        message.push_str(note);
+    }
+
+    let mut labels: Vec<_> = main.labels.into_iter().collect();
+    // The secondary diagnostics, such as the call stack, only carry labels
+    labels.extend(diagnostics.flat_map(|d| d.labels));
+
+    let located = labels
+        .iter()
+        .position(|label| label.file_id == file_id && label.style == LabelStyle::Primary)
+        .or_else(|| labels.iter().position(|label| label.file_id == file_id));
+    let range = match located {
+        Some(index) => {
+            let label = labels.remove(index);
+            lsp_types::Range::from_codespan(&label.file_id, &label.range, cache.files())
+        }
+        None => match fallback {
+            Some(span) if span.src_id == file_id => lsp_types::Range::from_codespan(
+                &file_id,
+                &(span.start.to_usize()..span.end.to_usize()),
+                cache.files(),
+            ),
+            _ => lsp_types::Range::default(),
+        },
+    };
+
+    // Labels pointing to the standard library or to generated sources, such as the value which
+    // broke a contract, can't be shown by the client as they don't have an URI. The source of the
+    // latter is added to the message instead.
+    let mut related_information = Vec::new();
+    for label in labels {
+        let range = lsp_types::Range::from_codespan(&label.file_id, &label.range, cache.files());
+        match cache.uri_of(label.file_id) {
+            Some(uri) => related_information.push(DiagnosticRelatedInformation {
+                location: Location { uri, range },
+                message: label.message,
+            }),
+            None if !cache.stdlib_ids().contains(&label.file_id) && !label.message.is_empty() => {
+                let source = &cache.files().source(label.file_id)[label.range];
+                message.push_str(&format!("\n{}: {}", label.message, source));
+            }
+            None => (),
+        }
+    }
+
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::Error),
+        code: main.code.map(NumberOrString::String),
+        message,
+        related_information: Some(related_information),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        notification::{DidChangeTextDocument, DidSaveTextDocument},
+        DidChangeTextDocumentParams, DidSaveTextDocumentParams, TextDocumentContentChangeEvent,
+        TextDocumentIdentifier, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    fn save(server: &mut TestServer, text_document: &TextDocumentIdentifier) -> Evaluated {
+        server
+            .notify::<DidSaveTextDocument>(DidSaveTextDocumentParams {
+                text_document: text_document.clone(),
+                text: None,
+            })
+            .unwrap();
+        server
+            .server
+            .evaluator()
+            .results()
+            .recv_timeout(Duration::from_secs(60))
+            .unwrap()
+    }
+
+    #[test]
+    fn errors_on_save() {
+        let mut server = TestServer::new();
+        server.server.config.eval.enabled = true;
+        let doc = server.open("main.ncl", "{a | Num = \"a\", b = 1}");
+
+        let evaluated = save(&mut server, &doc);
+        assert!(server.server.evaluator().is_current(&evaluated));
+        assert_eq!(evaluated.uri, doc.uri);
+        assert_eq!(evaluated.diagnostics.len(), 1);
+        assert_eq!(
+            evaluated.diagnostics[0].code,
+            Some(NumberOrString::String(String::from("E0301")))
+        );
+
+        // The evaluation thread is kept up to date with the documents
+        server.published_diagnostics();
+        server
+            .notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: doc.uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: String::from("{a | Num = 1, b = 1}"),
+                }],
+            })
+            .unwrap();
+        server.server.analyze_pending();
+        let evaluated = save(&mut server, &doc);
+        assert!(evaluated.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn outdated_evaluation() {
+        let mut server = TestServer::new();
+        server.server.config.eval.enabled = true;
+        let doc = server.open("main.ncl", "{a | Num = \"a\"}");
+        server
+            .notify::<DidSaveTextDocument>(DidSaveTextDocumentParams {
+                text_document: doc.clone(),
+                text: None,
+            })
+            .unwrap();
+
+        // A change cancels the evaluation in progress, whose result, if any, is discarded
+        server
+            .notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: doc.uri.clone(),
+                    version: 1,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: String::from("{a | Num = 1}"),
+                }],
+            })
+            .unwrap();
+        server.server.analyze_pending();
+        server
+            .notify::<DidSaveTextDocument>(DidSaveTextDocumentParams {
+                text_document: doc,
+                text: None,
+            })
+            .unwrap();
+        server.published_diagnostics();
+
+        loop {
+            let evaluated = server
+                .server
+                .evaluator()
+                .results()
+                .recv_timeout(Duration::from_secs(60))
+                .unwrap();
+            let current = server.server.evaluator().is_current(&evaluated);
+            crate::files::publish_evaluation(&mut server.server, evaluated);
+            let published = server.published_diagnostics();
+            if current {
+                assert_eq!(published.len(), 1);
+                assert!(published[0].diagnostics.is_empty());
+                break;
+            }
+            assert!(published.is_empty());
+        }
+    }
+}
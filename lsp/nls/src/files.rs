@@ -1,20 +1,26 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use codespan::FileId;
 use codespan_reporting::diagnostic::Diagnostic;
 use log::trace;
 use lsp_server::RequestId;
 use lsp_types::{
-    notification::{DidOpenTextDocument, Notification},
-    DidChangeTextDocumentParams, DidOpenTextDocumentParams, PublishDiagnosticsParams, Url,
+    notification::{DidChangeTextDocument, DidOpenTextDocument, DidSaveTextDocument, Notification},
+    DidChangeTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    PublishDiagnosticsParams, Url,
 };
 use nickel_lang::{
-    cache::{CacheError, CacheOp},
+    cache::{CacheError, EntryState},
     error::ToDiagnostic,
+    lint::{self, LintConfig},
 };
 
-use crate::trace::{param::FileUpdate, Enrich, Trace};
+use crate::{
+    eval::Evaluated,
+    requests::code_actions,
+    trace::{param::FileUpdate, Enrich, Trace},
+};
 
-use super::cache::CacheExt;
+use super::cache::{document_name, CacheExt};
 use super::diagnostic::DiagnosticCompat;
 use super::server::Server;
 
@@ -32,75 +38,195 @@ pub fn handle_open(server: &mut Server, params: DidOpenTextDocumentParams) -> Re
             content: &params.text_document.text,
         },
     );
-    let file_id = server
-        .cache
-        .add_string(params.text_document.uri.as_str(), params.text_document.text);
+    // The document may have been loaded from disk by an import: the content of the editor takes
+    // precedence, and the importers have to be analyzed again.
+    let file_id = server.cache.replace_string(
+        document_name(&params.text_document.uri),
+        params.text_document.text,
+    );
+    let importers = server.cache.invalidate(file_id);
+    server.documents.insert(file_id);
+    server.cancel_evaluation();
 
     parse_and_typecheck(server, params.text_document.uri, file_id)?;
+    for importer in importers {
+        if server.lin_cache.contains_key(&importer) {
+            server.schedule_analysis(importer);
+        }
+    }
     Trace::reply(id);
     Ok(())
 }
 
-pub fn handle_save(server: &mut Server, params: DidChangeTextDocumentParams) -> Result<()> {
+pub fn handle_change(server: &mut Server, params: DidChangeTextDocumentParams) -> Result<()> {
     let id: RequestId = format!(
         "{}#{}",
         params.text_document.uri, params.text_document.version
     )
     .into();
 
-    Trace::receive(id.clone(), DidOpenTextDocument::METHOD);
+    Trace::receive(id.clone(), DidChangeTextDocument::METHOD);
+
+    let uri = params.text_document.uri;
+    // Range edits are relative to the content sent when the document was opened
+    let file_id = server
+        .cache
+        .id_of_uri(&uri)
+        .ok_or_else(|| anyhow!("unknown document {}", uri))?;
+    let importers = server
+        .cache
+        .apply_changes(file_id, params.content_changes)?;
+    server.cancel_evaluation();
+
     Trace::enrich(
         &id,
         FileUpdate {
-            content: &params.content_changes[0].text,
+            content: server.cache.files().source(file_id),
         },
     );
 
-    let file_id = server.cache.update_content(
-        params.text_document.uri.as_str(),
-        params.content_changes[0].text.to_owned(),
-    )?;
+    // The analysis is debounced: it is performed once no change has been received for a while,
+    // or before answering the next request. Importers which are not opened in the editor will be
+    // analyzed when needed.
+    server.schedule_analysis(file_id);
+    for importer in importers {
+        if server.lin_cache.contains_key(&importer) {
+            server.schedule_analysis(importer);
+        }
+    }
 
-    // TODO: make this part more abstracted
-    //       implement typecheck (at least) as part of a persistent AST representation
-    //       for now execute the same as above for handling `open` notifications
-    parse_and_typecheck(server, params.text_document.uri, file_id)?;
     Trace::reply(id);
     Ok(())
 }
 
-fn typecheck(server: &mut Server, file_id: FileId) -> Result<CacheOp<()>, Vec<Diagnostic<FileId>>> {
-    server
+/// Evaluate a saved document if evaluation is enabled. The diagnostics of the analysis are
+/// published right away, and again together with the evaluation errors once the evaluation is
+/// done, unless a document has changed in the meantime. Evaluation errors are cleared by the next
+/// analysis.
+pub fn handle_save(server: &mut Server, params: DidSaveTextDocumentParams) -> Result<()> {
+    if !server.config.eval.enabled {
+        return Ok(());
+    }
+
+    let id: RequestId = format!("{}#save", params.text_document.uri).into();
+    Trace::receive(id.clone(), DidSaveTextDocument::METHOD);
+
+    let uri = params.text_document.uri;
+    let file_id = server
         .cache
-        .typecheck_with_analysis(file_id, &server.global_env, &mut server.lin_cache)
-        .map_err(|error| match error {
-            CacheError::Error(tc_error) => tc_error.to_diagnostic(server.cache.files_mut(), None),
-            CacheError::NotParsed => unreachable!(),
-        })
+        .id_of_uri(&uri)
+        .ok_or_else(|| anyhow!("unknown document {}", uri))?;
+
+    let diagnostics = diagnostics(server, file_id);
+    // Documents which don't typecheck are not evaluated
+    if server.cache.entry_state(file_id) >= Some(EntryState::Typechecked) {
+        let documents = server
+            .documents
+            .iter()
+            .map(|id| {
+                (
+                    server.cache.name(*id).to_owned(),
+                    server.cache.files().source(*id).clone(),
+                )
+            })
+            .collect();
+        let name = server.cache.name(file_id).to_owned();
+        let config = server.config.eval.clone();
+        server
+            .evaluator()
+            .evaluate(name, uri.clone(), documents, diagnostics.clone(), config);
+    }
+    publish_diagnostics(server, uri, diagnostics);
+
+    Trace::reply(id);
+    Ok(())
+}
+
+/// Publish the diagnostics of an evaluation, unless they are outdated.
+pub fn publish_evaluation(server: &mut Server, evaluated: Evaluated) {
+    if server.evaluator().is_current(&evaluated) {
+        publish_diagnostics(server, evaluated.uri, evaluated.diagnostics);
+    }
+}
+
+/// Parse and typecheck a file which has been scheduled for analysis by
+/// [Server::schedule_analysis], and publish the resulting diagnostics.
+pub fn analyze(server: &mut Server, file_id: FileId) -> Result<()> {
+    let uri = server
+        .cache
+        .uri_of(file_id)
+        .ok_or_else(|| anyhow!("no URI for {:?}", server.cache.name(file_id)))?;
+    parse_and_typecheck(server, uri, file_id)
+}
+
+/// Parse a file and resolve its imports, ignoring errors. Files whose imports have been modified
+/// must be parsed again before their importers are typechecked.
+pub fn prepare(server: &mut Server, file_id: FileId) {
+    if server.cache.parse(file_id).is_ok() {
+        let _ = server.cache.resolve_imports(file_id);
+    }
 }
 
 fn parse_and_typecheck(server: &mut Server, uri: Url, file_id: FileId) -> Result<()> {
-    let diagnostics = server
+    let diagnostics = diagnostics(server, file_id);
+    publish_diagnostics(server, uri, diagnostics);
+    Ok(())
+}
+
+/// Parse, lint and typecheck a file, and return the resulting diagnostics. The fixes of a type
+/// error are attached to its diagnostics.
+fn diagnostics(server: &mut Server, file_id: FileId) -> Vec<lsp_types::Diagnostic> {
+    let parse_errs = match server.cache.parse(file_id) {
+        Ok(parse_errs) => parse_errs,
+        Err(parse_err) => {
+            let d = parse_err.to_diagnostic(server.cache.files_mut(), None);
+            return to_lsp(server, d);
+        }
+    };
+
+    // Parse errors are not fatal
+    let mut d = parse_errs
+        .inner()
+        .to_diagnostic(server.cache.files_mut(), None);
+    // Lints run on the parsed term, before typechecking transforms it
+    let warnings = server
         .cache
-        .parse(file_id)
-        .map_err(|parse_err| parse_err.to_diagnostic(server.cache.files_mut(), None))
-        .map(|parse_errs| {
-            // Parse errors are not fatal
-            let mut d = parse_errs
-                .inner()
-                .to_diagnostic(server.cache.files_mut(), None);
-            trace!("Parsed, checking types");
-            let _ = typecheck(server, file_id).map_err(|mut ty_d| d.append(&mut ty_d));
-            d
-        })
-        .unwrap_or_else(|d| d);
-
-    let diagnostics = diagnostics
+        .get_ref(file_id)
+        .map(|t| lint::lint(t, &LintConfig::default()))
+        .unwrap_or_default();
+    d.extend(warnings.to_diagnostic(server.cache.files_mut(), None));
+    // Unresolved imports are typed as `Dyn`: typechecking can proceed
+    if let Err(CacheError::Error(import_err)) = server.cache.resolve_imports(file_id) {
+        d.extend(import_err.to_diagnostic(server.cache.files_mut(), None));
+    }
+    let mut diagnostics = to_lsp(server, d);
+
+    trace!("Parsed, checking types");
+    let result =
+        server
+            .cache
+            .typecheck_with_analysis(file_id, &server.global_env, &mut server.lin_cache);
+    if let Err(CacheError::Error(tc_error)) = result {
+        let d = tc_error.to_diagnostic(server.cache.files_mut(), None);
+        let mut tc_diagnostics = to_lsp(server, d);
+        if let Some(data) = tc_error.fix_data() {
+            code_actions::attach_fixes(&mut tc_diagnostics, &server.cache, file_id, &data);
+        }
+        diagnostics.extend(tc_diagnostics);
+    }
+
+    diagnostics
+}
+
+fn to_lsp(server: &mut Server, diagnostics: Vec<Diagnostic<FileId>>) -> Vec<lsp_types::Diagnostic> {
+    diagnostics
         .into_iter()
         .map(|d| lsp_types::Diagnostic::from_codespan(d, server.cache.files_mut()))
         .flatten()
-        .collect();
+        .collect()
+}
 
+fn publish_diagnostics(server: &mut Server, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) {
     server.notify(lsp_server::Notification::new(
         "textDocument/publishDiagnostics".into(),
         PublishDiagnosticsParams {
@@ -109,6 +235,32 @@ fn parse_and_typecheck(server: &mut Server, uri: Url, file_id: FileId) -> Result
             version: None,
         },
     ));
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use lsp_types::{TextDocumentContentChangeEvent, VersionedTextDocumentIdentifier};
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[test]
+    fn change_unknown_document() {
+        let mut server = TestServer::new();
+        let uri = TestServer::uri("unknown.ncl");
+
+        let result = server.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: 1,
+            },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: String::from("1"),
+            }],
+        });
+        assert!(result.is_err());
+        assert_eq!(server.server.cache.id_of_uri(&uri), None);
+    }
 }
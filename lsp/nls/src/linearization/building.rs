@@ -1,4 +1,7 @@
-use std::{collections::HashMap, mem};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
 
 use log::debug;
 use nickel_lang::{
@@ -55,6 +58,7 @@ impl Building {
             TermKind::Structure => unreachable!(),
             TermKind::Usage(_) => unreachable!(),
             TermKind::Record(_) => unreachable!(),
+            TermKind::Import(_) => unreachable!(),
             TermKind::Declaration(_, ref mut usages, _)
             | TermKind::RecordField { ref mut usages, .. } => usages.push(usage),
         };
@@ -155,14 +159,37 @@ impl Building {
 
     pub(super) fn resolve_record_references(&mut self, mut defers: Vec<(usize, usize, Ident)>) {
         let mut unresolved: Vec<(usize, usize, Ident)> = Vec::new();
+        // Accesses to the fields of an imported file, which are left deferred
+        let mut external: HashSet<usize> = HashSet::new();
+        let mut progress = false;
 
-        while let Some(deferred) = defers.pop() {
+        loop {
+            let deferred = match defers.pop() {
+                Some(deferred) => deferred,
+                // retry the references whose parent was not resolved yet, as long as the previous
+                // pass resolved something
+                None if progress && !unresolved.is_empty() => {
+                    debug!("unresolved references: {:?}", unresolved);
+                    defers = mem::take(&mut unresolved);
+                    progress = false;
+                    continue;
+                }
+                None => break,
+            };
             // child_item: current deferred usage item
             //       i.e.: root.<child>
             // parent_accessor_id: id of the parent usage
             //               i.e.: <parent>.child
             // child_ident: identifier the child item references
             let (child_item, parent_accessor_id, child_ident) = &deferred;
+
+            if external.contains(parent_accessor_id) {
+                debug!("parent references an imported file");
+                external.insert(*child_item);
+                progress = true;
+                continue;
+            }
+
             // resolve the value referenced by the parent accessor element
             // get the parent accessor, and read its resolved reference
             let parent_referenced = self.linearization.get(*parent_accessor_id);
@@ -191,9 +218,22 @@ impl Building {
                 continue;
             }
 
-            let referenced_declaration = parent_declaration
-                // resolve indirection by following the usage
-                .and_then(|parent_declaration| self.resolve_reference(parent_declaration))
+            // resolve indirection by following the usage
+            let parent_value = parent_declaration
+                .and_then(|parent_declaration| self.resolve_reference(parent_declaration));
+
+            if let Some(LinearizationItem {
+                kind: TermKind::Import(_),
+                ..
+            }) = parent_value
+            {
+                debug!("parent references an imported file");
+                external.insert(*child_item);
+                progress = true;
+                continue;
+            }
+
+            let referenced_declaration = parent_value
                 // get record field
                 .and_then(|parent_declaration| match &parent_declaration.kind {
                     TermKind::Record(fields) => {
@@ -251,10 +291,7 @@ impl Building {
                 self.add_usage(referenced_id, *child_item);
             }
 
-            if defers.is_empty() && !unresolved.is_empty() {
-                debug!("unresolved references: {:?}", unresolved);
-                defers = mem::take(&mut unresolved);
-            }
+            progress = true;
         }
     }
 
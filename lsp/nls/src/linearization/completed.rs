@@ -4,6 +4,7 @@ use codespan::ByteIndex;
 use nickel_lang::{
     term::MetaValue,
     typecheck::linearization::{LinearizationState, Scope, ScopeId},
+    types::AbsType,
 };
 
 use super::{
@@ -100,14 +101,13 @@ impl Completed {
         item
     }
 
-    /// Resolve type and meta information for a given item
-    pub fn resolve_item_type_meta(
-        &self,
-        item: &LinearizationItem<Resolved>,
-    ) -> (Resolved, Vec<String>) {
-        let mut extra = Vec::new();
-
-        let item = match item.kind {
+    /// Return the item describing the value of a usage, a declaration or a record field, or the
+    /// item itself if it is unknown.
+    pub fn value_item<'a>(
+        &'a self,
+        item: &'a LinearizationItem<Resolved>,
+    ) -> &'a LinearizationItem<Resolved> {
+        match item.kind {
             TermKind::Usage(UsageState::Resolved(declaration)) => self
                 .get_item(declaration)
                 .and_then(|decl| match decl.kind {
@@ -119,11 +119,72 @@ impl Completed {
                     _ => None,
                 })
                 .unwrap_or(item),
-            TermKind::Declaration(_, _, ValueState::Known(value)) => {
-                self.get_item(value).unwrap_or(item)
-            }
+            TermKind::Declaration(_, _, ValueState::Known(value))
+            | TermKind::RecordField {
+                value: ValueState::Known(value),
+                ..
+            } => self.get_item(value).unwrap_or(item),
             _ => item,
+        }
+    }
+
+    /// Return the type of a declaration or of a record field inferred by the typechecker, if it
+    /// is more precise than `Dyn` and isn't given by a type annotation.
+    pub fn inferred_type(&self, item: &LinearizationItem<Resolved>) -> Option<Resolved> {
+        let is_annotated = |item: &LinearizationItem<Resolved>| {
+            matches!(item.meta, Some(MetaValue { types: Some(_), .. }))
         };
+        // Unresolved unification variables are reported as type variables
+        let is_informative = |ty: &Resolved| !matches!(ty.0, AbsType::Dyn() | AbsType::Var(_));
+
+        let value = self.value_item(item);
+        let annotated = match item.kind {
+            // The annotations of a field are recorded by its item, while the annotation of a
+            // binding is recorded by the item of its value
+            TermKind::RecordField { .. } => is_annotated(item) || is_annotated(value),
+            TermKind::Declaration(..) => is_annotated(value),
+            _ => return None,
+        };
+
+        // Bindings and parameters are retyped by the typechecker, but record fields aren't
+        [&item.ty, &value.ty]
+            .iter()
+            .copied()
+            .find(|ty| is_informative(ty))
+            .filter(|_| !annotated)
+            .cloned()
+    }
+
+    /// Return the metadata of a declaration or of a record field, if any.
+    pub fn item_meta<'a>(&'a self, item: &'a LinearizationItem<Resolved>) -> Option<&'a MetaValue> {
+        match item.kind {
+            // See `inferred_type` for where the metadata is recorded
+            TermKind::RecordField { .. } => item
+                .meta
+                .as_ref()
+                .or_else(|| self.value_item(item).meta.as_ref()),
+            TermKind::Declaration(..) => self.value_item(item).meta.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Return if the value of a binding is a function, in which case it is the placeholder
+    /// recorded before the first parameter.
+    pub fn is_function(&self, value: &LinearizationItem<Resolved>) -> bool {
+        value.kind == TermKind::Structure
+            && matches!(
+                self.get_item(value.id + 1),
+                Some(parameter) if parameter.pos == value.pos && is_parameter(parameter)
+            )
+    }
+
+    /// Resolve type and meta information for a given item
+    pub fn resolve_item_type_meta(
+        &self,
+        item: &LinearizationItem<Resolved>,
+    ) -> (Resolved, Vec<String>) {
+        let mut extra = Vec::new();
+        let item = self.value_item(item);
 
         if let Some(MetaValue {
             ref doc,
@@ -157,3 +218,8 @@ impl Completed {
 }
 
 impl LinearizationState for Completed {}
+
+/// Return if a declaration is a function parameter, whose value is the parameter itself.
+pub fn is_parameter(item: &LinearizationItem<Resolved>) -> bool {
+    matches!(item.kind, TermKind::Declaration(_, _, ValueState::Known(value)) if value == item.id)
+}
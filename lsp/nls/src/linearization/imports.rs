@@ -0,0 +1,201 @@
+//! Resolution of references across files.
+//!
+//! Accesses to the fields of an imported file, such as `lib.field` where `lib` is bound to
+//! `import "lib.ncl"`, can't be resolved while linearizing the importer and are left as
+//! [UsageState::Deferred]. They are resolved on demand using the cached term and the
+//! linearization of the imported file.
+
+use std::collections::HashMap;
+
+use codespan::FileId;
+use nickel_lang::{
+    cache::Cache,
+    identifier::Ident,
+    term::{MetaValue, RichTerm, Term},
+};
+
+use super::{
+    completed::Completed,
+    interface::{Resolved, TermKind, UsageState, ValueState},
+    LinearizationItem,
+};
+
+/// An item together with the file whose linearization it belongs to.
+pub type Located<'a> = (FileId, &'a LinearizationItem<Resolved>);
+
+/// The maximum number of indirections followed, which guards against cyclic definitions.
+const MAX_DEPTH: usize = 32;
+
+/// Return the declaration (a `let` binding, a function parameter or a record field) a usage
+/// refers to, which may be located in another file.
+pub fn resolve_usage<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, item): Located<'a>,
+) -> Option<Located<'a>> {
+    resolve_usage_(cache, lin_cache, (file_id, item), MAX_DEPTH)
+}
+
+fn resolve_usage_<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, item): Located<'a>,
+    depth: usize,
+) -> Option<Located<'a>> {
+    let linearization = lin_cache.get(&file_id)?;
+
+    match &item.kind {
+        TermKind::Usage(UsageState::Resolved(declaration)) => {
+            Some((file_id, linearization.get_item(*declaration)?))
+        }
+        TermKind::Usage(UsageState::Deferred { parent, child }) if depth > 0 => {
+            let parent = linearization.get_item(*parent)?;
+            let value = value_of_(cache, lin_cache, (file_id, parent), depth - 1)?;
+            field_of_(cache, lin_cache, value, child, depth - 1)
+        }
+        _ => None,
+    }
+}
+
+/// Follow usages and declarations up to the value they are bound to.
+pub fn value_of<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    located: Located<'a>,
+) -> Option<Located<'a>> {
+    value_of_(cache, lin_cache, located, MAX_DEPTH)
+}
+
+fn value_of_<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, item): Located<'a>,
+    depth: usize,
+) -> Option<Located<'a>> {
+    if depth == 0 {
+        return None;
+    }
+
+    let linearization = lin_cache.get(&file_id)?;
+
+    match &item.kind {
+        TermKind::Usage(_) => {
+            let declaration = resolve_usage_(cache, lin_cache, (file_id, item), depth - 1)?;
+            value_of_(cache, lin_cache, declaration, depth - 1)
+        }
+        // Declarations introduced by destructuring point to themselves
+        TermKind::Declaration(_, _, ValueState::Known(value))
+        | TermKind::RecordField {
+            value: ValueState::Known(value),
+            ..
+        } if *value != item.id => {
+            let value = linearization.get_item(*value)?;
+            value_of_(cache, lin_cache, (file_id, value), depth - 1)
+        }
+        _ => Some((file_id, item)),
+    }
+}
+
+/// Return the declaration of the field `child` of a value, which is either a record or an import.
+pub fn field_of<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    value: Located<'a>,
+    child: &Ident,
+) -> Option<Located<'a>> {
+    field_of_(cache, lin_cache, value, child, MAX_DEPTH)
+}
+
+fn field_of_<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, value): Located<'a>,
+    child: &Ident,
+    depth: usize,
+) -> Option<Located<'a>> {
+    match &value.kind {
+        TermKind::Record(fields) => {
+            let field = lin_cache.get(&file_id)?.get_item(*fields.get(child)?)?;
+            Some((file_id, field))
+        }
+        TermKind::Import(imported) => top_level_field(cache, lin_cache, *imported, child, depth),
+        _ => None,
+    }
+}
+
+/// Return the declaration of a field of the record an imported file evaluates to.
+fn top_level_field<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    file_id: FileId,
+    child: &Ident,
+    depth: usize,
+) -> Option<Located<'a>> {
+    let record = top_level_record_(cache, lin_cache, file_id, depth)?;
+    field_of_(cache, lin_cache, record, child, depth)
+}
+
+/// Return the record an imported file evaluates to, if it is known statically. The term of the
+/// file is traversed through `let` bindings, metavalues and re-exported imports.
+pub fn top_level_record<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    file_id: FileId,
+) -> Option<Located<'a>> {
+    top_level_record_(cache, lin_cache, file_id, MAX_DEPTH)
+}
+
+fn top_level_record_<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    file_id: FileId,
+    depth: usize,
+) -> Option<Located<'a>> {
+    let linearization = lin_cache.get(&file_id)?;
+    let mut term: &RichTerm = cache.get_ref(file_id)?;
+
+    loop {
+        match term.as_ref() {
+            Term::Let(_, _, body, _) | Term::LetPattern(_, _, _, body) => term = body,
+            Term::MetaValue(MetaValue {
+                value: Some(value), ..
+            }) => term = value,
+            Term::ResolvedImport(imported) if depth > 0 => {
+                return top_level_record_(cache, lin_cache, *imported, depth - 1)
+            }
+            Term::Record(..) | Term::RecRecord(..) => {
+                let pos = term.pos.into_opt()?;
+                return linearization
+                    .linearization
+                    .iter()
+                    .find(|item| matches!(item.kind, TermKind::Record(_)) && item.pos == pos)
+                    .map(|item| (file_id, item));
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Return the usages of a declaration in the linearizations of the other files, that is the
+/// accesses to a field of an imported file.
+pub fn external_usages<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, declaration): Located<'a>,
+) -> Vec<Located<'a>> {
+    lin_cache
+        .iter()
+        .filter(|(other, _)| **other != file_id)
+        .flat_map(|(other, linearization)| {
+            linearization
+                .linearization
+                .iter()
+                .filter(|item| matches!(item.kind, TermKind::Usage(UsageState::Deferred { .. })))
+                .map(move |item| (*other, item))
+        })
+        .filter(|usage| {
+            resolve_usage(cache, lin_cache, *usage).map(|(id, item)| (id, item.id))
+                == Some((file_id, declaration.id))
+        })
+        .collect()
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use codespan::FileId;
 use nickel_lang::{identifier::Ident, typecheck::TypeWrapper, types::Types};
 
 use super::building::ID;
@@ -20,7 +21,8 @@ impl ResolutionState for Resolved {}
 /// 1. Declarations
 /// 2. Usages
 /// 3. Records, listing their fields
-/// 4. wildcard (Structure) for any other kind of term.
+/// 4. Imports, referring to the imported file
+/// 5. wildcard (Structure) for any other kind of term.
 /// Can be extended later to represent Contracts, Records, etc.
 #[derive(Debug, Clone, PartialEq)]
 pub enum TermKind {
@@ -33,6 +35,7 @@ pub enum TermKind {
         usages: Vec<ID>,
         value: ValueState,
     },
+    Import(FileId),
     Structure,
 }
 
@@ -51,7 +54,9 @@ impl ValueState {
     }
 }
 /// Some usages cannot be fully resolved in a first pass (i.e. recursive record fields)
-/// In these cases we defer the resolution to a second pass during linearization
+/// In these cases we defer the resolution to a second pass during linearization.
+/// Accesses to the fields of an imported file stay deferred, and are resolved when queried using
+/// the linearization of the imported file.
 #[derive(Debug, Clone, PartialEq)]
 pub enum UsageState {
     Unbound,
@@ -24,6 +24,7 @@ use self::{
 
 pub mod building;
 pub mod completed;
+pub mod imports;
 pub mod interface;
 
 pub type Environment = nickel_lang::environment::Environment<Ident, usize>;
@@ -152,7 +153,9 @@ impl Linearizer for AnalysisHost {
             }
         }
 
-        if pos == TermPos::None {
+        // Accesses only record the accessed field, which has its own position: contracts such as
+        // `foo.Bar` are parsed without a position for the access itself.
+        if pos == TermPos::None && !matches!(term, Term::Op1(UnaryOp::StaticAccess(_), _)) {
             return;
         }
 
@@ -307,6 +310,14 @@ impl Linearizer for AnalysisHost {
                 self.record_fields =
                     Some((id + 1, field_names.into_iter().enumerate().rev().collect()));
             }
+            Term::ResolvedImport(file_id) => lin.push(LinearizationItem {
+                id,
+                pos: pos.unwrap(),
+                ty,
+                scope: self.scope.clone(),
+                kind: TermKind::Import(*file_id),
+                meta: self.meta.take(),
+            }),
             Term::Op1(UnaryOp::StaticAccess(ident), _) => {
                 let x = self.access.get_or_insert(Vec::with_capacity(1));
                 x.push(ident.to_owned())
@@ -6,18 +6,24 @@ use log::debug;
 use lsp_server::Connection;
 
 mod cache;
+mod config;
 mod diagnostic;
+mod eval;
 mod files;
 mod linearization;
 mod requests;
 mod server;
+use lsp_types::InitializeParams;
 use server::Server;
 use structopt::StructOpt;
 
-use crate::trace::Trace;
+use crate::{config::Config, trace::Trace};
 
 mod term;
+#[cfg(test)]
+mod testing;
 mod trace;
+mod workspace;
 
 #[derive(StructOpt, Debug)]
 /// The LSP server of the Nickel language.
@@ -44,9 +50,26 @@ fn main() -> Result<()> {
 
     let capabilities = Server::capabilities();
 
-    connection.initialize(serde_json::to_value(&capabilities)?)?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    let config = params
+        .initialization_options
+        .map(Config::from_json)
+        .unwrap_or_default();
+    let roots: Vec<PathBuf> = match params.workspace_folders {
+        Some(folders) => folders
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect(),
+        None => params
+            .root_uri
+            .and_then(|uri| uri.to_file_path().ok())
+            .into_iter()
+            .collect(),
+    };
 
-    let _server = Server::new(connection).run();
+    let mut server = Server::new(connection, config);
+    server.index_workspace(&roots);
+    let _ = server.run();
 
     Ok(())
 }
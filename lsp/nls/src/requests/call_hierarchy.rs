@@ -0,0 +1,465 @@
+//! Call hierarchy of the functions bound by `let` bindings and record fields.
+//!
+//! The calls of a function are its usages. A call is attributed to the innermost named function
+//! whose body contains it, that is whose parameters are in the scope of the call, or to the file
+//! for the calls made at the top level.
+
+use codespan::{ByteIndex, FileId};
+use codespan_lsp::position_to_byte_index;
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+    CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams, Range,
+    SymbolKind,
+};
+use nickel_lang::typecheck::linearization::Scope;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::{
+    cache::CacheExt,
+    diagnostic::LocationCompat,
+    linearization::{
+        completed::{is_parameter, Completed},
+        imports::{resolve_usage, value_of, Located},
+        interface::{Resolved, TermKind},
+        LinearizationItem,
+    },
+    requests::goto::location_of,
+    server::Server,
+    trace::{Enrich, Trace},
+};
+
+/// The calls made by a function, or at the top level of a file, to another function.
+struct Calls<'a> {
+    file_id: FileId,
+    function: Option<&'a LinearizationItem<Resolved>>,
+    ranges: Vec<Range>,
+}
+
+pub fn handle_prepare(
+    params: CallHierarchyPrepareParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server
+        .cache
+        .id_of_uri(&params.text_document_position_params.text_document.uri)
+        .unwrap();
+
+    let start = position_to_byte_index(
+        server.cache.files(),
+        file_id,
+        &params.text_document_position_params.position,
+    )
+    .unwrap();
+
+    let locator = (file_id, ByteIndex(start as u32));
+    let linearization = server.lin_cache_get(&file_id)?;
+
+    Trace::enrich(&id, linearization);
+
+    let function = match linearization.item_at(&locator) {
+        Some(
+            item @ LinearizationItem {
+                kind: TermKind::Usage(_),
+                ..
+            },
+        ) => resolve_usage(&server.cache, &server.lin_cache, (file_id, item)),
+        Some(item) => Some((file_id, item)),
+        None => None,
+    }
+    .filter(|function| is_function_binding(server, *function))
+    .and_then(|function| to_item(server, function));
+
+    match function {
+        Some(function) => server.reply(Response::new_ok(id, vec![function])),
+        None => server.reply(Response::new_ok(id, Value::Null)),
+    }
+    Ok(())
+}
+
+pub fn handle_incoming_calls(
+    params: CallHierarchyIncomingCallsParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let (file_id, function) = match function_of_item(server, &params.item) {
+        Some((file_id, Some(function))) => (file_id, function),
+        // The top level of a file isn't called
+        _ => {
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+
+    let mut calls = Vec::new();
+    for (caller_file, linearization) in server.lin_cache.iter() {
+        let functions = functions(linearization);
+        for usage in linearization
+            .linearization
+            .iter()
+            .filter(|item| matches!(item.kind, TermKind::Usage(_)))
+        {
+            let callee = resolve_usage(&server.cache, &server.lin_cache, (*caller_file, usage))
+                .map(|(file_id, item)| (file_id, item.id));
+            if callee == Some((file_id, function.id)) {
+                add_call(
+                    &mut calls,
+                    (*caller_file, enclosing_function(&functions, usage)),
+                    range_of(server, *caller_file, usage),
+                );
+            }
+        }
+    }
+
+    let calls: Vec<CallHierarchyIncomingCall> = calls
+        .into_iter()
+        .filter_map(|calls| {
+            let from = match calls.function {
+                Some(caller) => to_item(server, (calls.file_id, caller)),
+                None => file_item(server, calls.file_id),
+            }?;
+            Some(CallHierarchyIncomingCall {
+                from,
+                from_ranges: calls.ranges,
+            })
+        })
+        .collect();
+
+    server.reply(Response::new_ok(id, calls));
+    Ok(())
+}
+
+pub fn handle_outgoing_calls(
+    params: CallHierarchyOutgoingCallsParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let (file_id, caller) = match function_of_item(server, &params.item) {
+        Some(caller) => caller,
+        None => {
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+    let linearization = server.lin_cache_get(&file_id)?;
+    let functions = functions(linearization);
+
+    let mut calls = Vec::new();
+    for usage in linearization
+        .linearization
+        .iter()
+        .filter(|item| matches!(item.kind, TermKind::Usage(_)))
+        .filter(|usage| {
+            enclosing_function(&functions, usage).map(|function| function.id)
+                == caller.map(|caller| caller.id)
+        })
+    {
+        match resolve_usage(&server.cache, &server.lin_cache, (file_id, usage)) {
+            Some((callee_file, callee)) if is_function_binding(server, (callee_file, callee)) => {
+                add_call(
+                    &mut calls,
+                    (callee_file, Some(callee)),
+                    range_of(server, file_id, usage),
+                )
+            }
+            _ => (),
+        }
+    }
+
+    let calls: Vec<CallHierarchyOutgoingCall> = calls
+        .into_iter()
+        .filter_map(|calls| {
+            Some(CallHierarchyOutgoingCall {
+                to: to_item(server, (calls.file_id, calls.function?))?,
+                from_ranges: calls.ranges,
+            })
+        })
+        .collect();
+
+    server.reply(Response::new_ok(id, calls));
+    Ok(())
+}
+
+/// Return if an item is a `let` binding or a record field whose value is a function, possibly
+/// through other bindings as in `let g = f`.
+fn is_function_binding(server: &Server, (file_id, item): Located) -> bool {
+    match item.kind {
+        TermKind::Declaration(..) if !is_parameter(item) => (),
+        TermKind::RecordField { .. } => (),
+        _ => return false,
+    }
+
+    matches!(
+        value_of(&server.cache, &server.lin_cache, (file_id, item)),
+        Some((value_file, value)) if server.lin_cache[&value_file].is_function(value)
+    )
+}
+
+/// Return the scope of the parameters of the function a binding is defined as, which is a prefix
+/// of the scope of the body of the function.
+fn function_scope<'a>(
+    linearization: &'a Completed,
+    item: &'a LinearizationItem<Resolved>,
+) -> Option<&'a Scope> {
+    let value = linearization.value_item(item);
+    if value.id != item.id && linearization.is_function(value) {
+        Some(&value.scope)
+    } else {
+        None
+    }
+}
+
+/// Return the named functions defined in a linearization, together with the scope of their
+/// parameters.
+fn functions(linearization: &Completed) -> Vec<(&Scope, &LinearizationItem<Resolved>)> {
+    linearization
+        .linearization
+        .iter()
+        .filter(|item| match item.kind {
+            TermKind::Declaration(..) => !is_parameter(item),
+            TermKind::RecordField { .. } => true,
+            _ => false,
+        })
+        .filter_map(|item| Some((function_scope(linearization, item)?, item)))
+        .collect()
+}
+
+/// Return the innermost named function whose body contains an item, if any.
+fn enclosing_function<'a>(
+    functions: &[(&Scope, &'a LinearizationItem<Resolved>)],
+    item: &LinearizationItem<Resolved>,
+) -> Option<&'a LinearizationItem<Resolved>> {
+    functions
+        .iter()
+        .filter(|(scope, _)| item.scope.len() > scope.len() && item.scope.starts_with(scope))
+        .max_by_key(|(scope, _)| scope.len())
+        .map(|(_, function)| *function)
+}
+
+/// Record a call, grouping the calls by caller or by callee.
+fn add_call<'a>(
+    calls: &mut Vec<Calls<'a>>,
+    (file_id, function): (FileId, Option<&'a LinearizationItem<Resolved>>),
+    range: Range,
+) {
+    let id = function.map(|function| function.id);
+    match calls
+        .iter_mut()
+        .find(|calls| calls.file_id == file_id && calls.function.map(|f| f.id) == id)
+    {
+        Some(calls) => calls.ranges.push(range),
+        None => calls.push(Calls {
+            file_id,
+            function,
+            ranges: vec![range],
+        }),
+    }
+}
+
+/// Return the function designated by an item of the call hierarchy, or `None` for the top level
+/// of a file.
+fn function_of_item<'a>(
+    server: &'a Server,
+    item: &CallHierarchyItem,
+) -> Option<(FileId, Option<&'a LinearizationItem<Resolved>>)> {
+    let file_id = server.cache.id_of_uri(&item.uri)?;
+    if item.kind == SymbolKind::File {
+        return Some((file_id, None));
+    }
+
+    let start = position_to_byte_index(server.cache.files(), file_id, &item.selection_range.start)
+        .unwrap_or_default();
+    let function = server.lin_cache.get(&file_id).and_then(|linearization| {
+        linearization.linearization.iter().find(|candidate| {
+            candidate.pos.start.to_usize() == start
+                && is_function_binding(server, (file_id, candidate))
+        })
+    });
+    Some((file_id, function))
+}
+
+/// Convert a named function to an item of the call hierarchy, whose range spans from the name of
+/// the function to the end of its body.
+fn to_item(server: &Server, (file_id, function): Located) -> Option<CallHierarchyItem> {
+    let name = match &function.kind {
+        TermKind::Declaration(name, _, _) => name,
+        TermKind::RecordField { ident, .. } => ident,
+        _ => return None,
+    };
+    let location = location_of(server, function.pos)?;
+    let linearization = server.lin_cache.get(&file_id)?;
+
+    let end = function_scope(linearization, function)
+        .and_then(|scope| {
+            linearization
+                .linearization
+                .iter()
+                .filter(|item| item.pos.src_id == file_id && item.scope.starts_with(scope))
+                .map(|item| item.pos.end)
+                .max()
+        })
+        .filter(|end| *end > function.pos.end)
+        .unwrap_or(function.pos.end);
+
+    Some(CallHierarchyItem {
+        name: name.to_string(),
+        kind: SymbolKind::Function,
+        tags: None,
+        detail: Some(format!("{}", function.ty)),
+        uri: location.uri,
+        range: Range::from_codespan(
+            &file_id,
+            &(function.pos.start.to_usize()..end.to_usize()),
+            server.cache.files(),
+        ),
+        selection_range: location.range,
+        data: None,
+    })
+}
+
+/// Return the item of the call hierarchy standing for the top level of a file.
+fn file_item(server: &Server, file_id: FileId) -> Option<CallHierarchyItem> {
+    let uri = server.cache.uri_of(file_id)?;
+    let name = Path::new(server.cache.name(file_id))
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    let len = server.cache.files().source(file_id).len();
+
+    Some(CallHierarchyItem {
+        name,
+        kind: SymbolKind::File,
+        tags: None,
+        detail: None,
+        uri,
+        range: Range::from_codespan(&file_id, &(0..len), server.cache.files()),
+        selection_range: Range::from_codespan(&file_id, &(0..0), server.cache.files()),
+        data: None,
+    })
+}
+
+fn range_of(server: &Server, file_id: FileId, item: &LinearizationItem<Resolved>) -> Range {
+    Range::from_codespan(
+        &file_id,
+        &(item.pos.start.to_usize()..item.pos.end.to_usize()),
+        server.cache.files(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        request::{CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare},
+        PartialResultParams, Position, TextDocumentPositionParams, WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    const SOURCE: &str = "let f = fun x => x + 1 in\nlet g = fun y => f y + f 2 in\ng 3";
+
+    /// Prepare the call hierarchy of the function at `(line, character)`.
+    fn prepare(server: &mut TestServer, (line, character): (u32, u32)) -> CallHierarchyItem {
+        let text_document = server.open("main.ncl", SOURCE);
+        let mut items = server
+            .request::<CallHierarchyPrepare>(CallHierarchyPrepareParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document,
+                    position: Position::new(line, character),
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        items.pop().unwrap()
+    }
+
+    /// The columns of ranges on a single line.
+    fn columns(ranges: &[Range]) -> Vec<(u32, u32, u32)> {
+        ranges
+            .iter()
+            .map(|range| {
+                assert_eq!(range.start.line, range.end.line);
+                (range.start.line, range.start.character, range.end.character)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incoming_calls() {
+        let mut server = TestServer::new();
+        let f = prepare(&mut server, (0, 4));
+        assert_eq!((f.name.as_str(), f.kind), ("f", SymbolKind::Function));
+
+        let calls: Vec<_> = server
+            .request::<CallHierarchyIncomingCalls>(CallHierarchyIncomingCallsParams {
+                item: f,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|call| (call.from.name, call.from.kind, columns(&call.from_ranges)))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![(
+                String::from("g"),
+                SymbolKind::Function,
+                vec![(1, 17, 18), (1, 23, 24)]
+            )]
+        );
+
+        // `g` is called at the top level of the file
+        let g = prepare(&mut server, (2, 0));
+        let calls: Vec<_> = server
+            .request::<CallHierarchyIncomingCalls>(CallHierarchyIncomingCallsParams {
+                item: g,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|call| (call.from.name, call.from.kind, columns(&call.from_ranges)))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![(String::from("main.ncl"), SymbolKind::File, vec![(2, 0, 1)])]
+        );
+    }
+
+    #[test]
+    fn outgoing_calls() {
+        let mut server = TestServer::new();
+        let g = prepare(&mut server, (1, 4));
+        assert_eq!((g.name.as_str(), g.kind), ("g", SymbolKind::Function));
+
+        let calls: Vec<_> = server
+            .request::<CallHierarchyOutgoingCalls>(CallHierarchyOutgoingCallsParams {
+                item: g,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|call| (call.to.name, columns(&call.from_ranges)))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![(String::from("f"), vec![(1, 17, 18), (1, 23, 24)])]
+        );
+
+        // `f` calls no other function
+        let f = prepare(&mut server, (0, 4));
+        let calls = server
+            .request::<CallHierarchyOutgoingCalls>(CallHierarchyOutgoingCallsParams {
+                item: f,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap();
+        assert!(calls.is_empty());
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+
+use codespan::{ByteIndex, FileId};
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, Diagnostic, TextEdit,
+    WorkspaceEdit,
+};
+use nickel_lang::{
+    cache::Cache,
+    error::FixData,
+    identifier::Ident,
+    position::{RawSpan, TermPos},
+    term::Term,
+    types::{AbsType, Types},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{diagnostic::LocationCompat, requests::rename::is_identifier, server::Server};
+
+/// A fix of an error, offered as a quick fix on its diagnostics.
+///
+/// Fixes are computed when the diagnostics are published, as some errors, such as contract
+/// violations, are only known after evaluating the document, and they are stored in the `data`
+/// field of the diagnostics. The client sends them back with a code action request.
+#[derive(Debug, Serialize, Deserialize)]
+struct Fix {
+    title: String,
+    edits: Vec<TextEdit>,
+}
+
+/// Compute the fixes of an error in a document from its fix data, and store them in its
+/// diagnostics. Fixes editing another file are not offered.
+pub fn attach_fixes(
+    diagnostics: &mut [Diagnostic],
+    cache: &Cache,
+    file_id: FileId,
+    data: &FixData,
+) {
+    let fixes = match data {
+        FixData::MisspelledField(field, candidates) => misspelled_field(field, candidates),
+        FixData::MissingFields(fields, pos) => missing_fields(cache, fields, *pos),
+        FixData::UnboundIdentifier(ident, pos) => unbound_identifier(cache, ident, *pos),
+        FixData::DynamicValue(ty, pos) => dynamic_value(cache, ty, *pos),
+    };
+    let fixes: Vec<Fix> = fixes
+        .into_iter()
+        .filter_map(|(title, edits)| {
+            let edits = edits
+                .into_iter()
+                .map(|(span, new_text)| {
+                    if span.src_id != file_id {
+                        return None;
+                    }
+                    let range = lsp_types::Range::from_codespan(
+                        &file_id,
+                        &(span.start.to_usize()..span.end.to_usize()),
+                        cache.files(),
+                    );
+                    Some(TextEdit { range, new_text })
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(Fix { title, edits })
+        })
+        .collect();
+
+    if !fixes.is_empty() {
+        let data = serde_json::to_value(fixes).unwrap();
+        for diagnostic in diagnostics.iter_mut() {
+            diagnostic.data = Some(data.clone());
+        }
+    }
+}
+
+/// Offer the fixes stored in the diagnostics of a range of a document as quick fixes. The first
+/// fix of a diagnostic is the preferred one.
+pub fn handle_code_action(
+    params: CodeActionParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let quick_fixes = match params.context.only {
+        Some(ref kinds) => kinds
+            .iter()
+            .any(|kind| *kind == CodeActionKind::QUICKFIX || *kind == CodeActionKind::EMPTY),
+        None => true,
+    };
+
+    let mut actions = Vec::new();
+    for diagnostic in params.context.diagnostics.iter().filter(|_| quick_fixes) {
+        let fixes: Vec<Fix> = match diagnostic.data.clone().map(serde_json::from_value) {
+            Some(Ok(fixes)) => fixes,
+            _ => continue,
+        };
+
+        for (index, fix) in fixes.into_iter().enumerate() {
+            let mut changes = HashMap::new();
+            changes.insert(params.text_document.uri.clone(), fix.edits);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: fix.title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(changes),
+                    ..Default::default()
+                }),
+                is_preferred: Some(index == 0),
+                ..Default::default()
+            }));
+        }
+    }
+
+    server.reply(Response::new_ok(id, actions));
+    Ok(())
+}
+
+/// A fix as a title and edits, before the spans are converted to LSP ranges.
+type SpanFix = (String, Vec<(RawSpan, String)>);
+
+/// Replace a misspelled field with each of the existing fields with a close name.
+fn misspelled_field(field: &Ident, candidates: &[Ident]) -> Vec<SpanFix> {
+    let span = match field.pos.into_opt() {
+        Some(span) => span,
+        None => return Vec::new(),
+    };
+
+    candidates
+        .iter()
+        .map(|candidate| {
+            (
+                format!("Replace `{}` with `{}`", field, candidate),
+                vec![(span, quote_if_needed(&candidate.label))],
+            )
+        })
+        .collect()
+}
+
+/// Insert a definition of the missing fields at the start of a record, with a stub value of the
+/// type of the field. In a record spanning several lines, each field is inserted on its own line.
+fn missing_fields(cache: &Cache, fields: &[(Ident, Option<Types>)], pos: TermPos) -> Vec<SpanFix> {
+    let span = match pos.into_opt() {
+        Some(span) => span,
+        None => return Vec::new(),
+    };
+    let source = cache.files().source(span.src_id);
+    let record = &source[span.start.to_usize()..span.end.to_usize()];
+    // The record may be a variable, or the merge of several records
+    if !record.starts_with('{') || !record.ends_with('}') {
+        return Vec::new();
+    }
+
+    let definitions: Vec<String> = fields
+        .iter()
+        .map(|(id, ty)| format!("{} = {}", quote_if_needed(&id.label), stub(ty.as_ref())))
+        .collect();
+
+    let content = &record[1..record.len() - 1];
+    let start = ByteIndex::from(span.start.to_usize() as u32 + 1);
+    let (end, new_text) = if content.trim().is_empty() {
+        (
+            ByteIndex::from(span.end.to_usize() as u32 - 1),
+            format!(" {} ", definitions.join(", ")),
+        )
+    } else if content.contains('\n') && content.split('\n').next().unwrap().trim().is_empty() {
+        let indent = content
+            .lines()
+            .skip(1)
+            .find(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .unwrap_or_default();
+        let new_text = definitions
+            .iter()
+            .map(|definition| format!("\n{}{},", indent, definition))
+            .collect();
+        (start, new_text)
+    } else if content.starts_with(char::is_whitespace) {
+        (start, format!(" {},", definitions.join(", ")))
+    } else {
+        (start, format!(" {}, ", definitions.join(", ")))
+    };
+
+    let names: Vec<String> = fields.iter().map(|(id, _)| format!("`{}`", id)).collect();
+    let title = if names.len() == 1 {
+        format!("Add missing field {}", names[0])
+    } else {
+        format!("Add missing fields {}", names.join(", "))
+    };
+
+    vec![(
+        title,
+        vec![(
+            RawSpan {
+                src_id: span.src_id,
+                start,
+                end,
+            },
+            new_text,
+        )],
+    )]
+}
+
+/// Qualify an unbound identifier with each module of the standard library having a member of the
+/// same name, such as `map` with `array.map`.
+fn unbound_identifier(cache: &Cache, ident: &Ident, pos: TermPos) -> Vec<SpanFix> {
+    let span = match pos.into_opt() {
+        Some(span) => span,
+        None => return Vec::new(),
+    };
+
+    stdlib_modules(cache, ident)
+        .into_iter()
+        .map(|module| {
+            let qualified = format!("{}.{}", module, quote_if_needed(&ident.label));
+            (
+                format!("Replace `{}` with `{}`", ident, qualified),
+                vec![(span, qualified)],
+            )
+        })
+        .collect()
+}
+
+/// Convert an expression of type `Dyn` to the expected type with a contract annotation.
+fn dynamic_value(cache: &Cache, ty: &Types, pos: TermPos) -> Vec<SpanFix> {
+    let span = match pos.into_opt() {
+        Some(span) => span,
+        None => return Vec::new(),
+    };
+    let source = cache.files().source(span.src_id);
+    let expression = &source[span.start.to_usize()..span.end.to_usize()];
+
+    vec![(
+        format!("Add a contract annotation `| {}`", ty),
+        vec![(span, format!("({} | {})", expression, ty))],
+    )]
+}
+
+/// Return the modules of the standard library having a member named `ident`.
+fn stdlib_modules(cache: &Cache, ident: &Ident) -> Vec<Ident> {
+    let mut modules = Vec::new();
+    for term in cache
+        .stdlib_ids()
+        .iter()
+        .filter_map(|file_id| cache.get_ref(*file_id))
+    {
+        let fields = match term.as_ref() {
+            Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields,
+            _ => continue,
+        };
+        for (module, value) in fields {
+            match value.as_ref() {
+                Term::Record(members, _) | Term::RecRecord(members, ..)
+                    if members.contains_key(ident) =>
+                {
+                    modules.push(module.clone())
+                }
+                _ => (),
+            }
+        }
+    }
+
+    modules.sort();
+    modules
+}
+
+/// A stub value of a type, to be replaced by the user.
+fn stub(ty: Option<&Types>) -> String {
+    match ty.map(|ty| &ty.0) {
+        Some(AbsType::Num()) => String::from("0"),
+        Some(AbsType::Str()) => String::from("\"\""),
+        Some(AbsType::Bool()) => String::from("false"),
+        Some(AbsType::Array(_)) => String::from("[]"),
+        Some(AbsType::StaticRecord(_)) | Some(AbsType::DynRecord(_)) => String::from("{}"),
+        Some(AbsType::Enum(row)) => match &row.0 {
+            AbsType::RowExtend(id, _, _) => format!("`{}", quote_if_needed(&id.label)),
+            _ => String::from("null"),
+        },
+        Some(AbsType::Flat(rt))
+            if matches!(rt.as_ref(), Term::Record(..) | Term::RecRecord(..)) =>
+        {
+            String::from("{}")
+        }
+        _ => String::from("null"),
+    }
+}
+
+/// Quote a field name which isn't a valid identifier, such as a keyword or a name with spaces,
+/// escaping it as a Nickel string.
+fn quote_if_needed(name: &str) -> String {
+    if is_identifier(name) {
+        return String::from(name);
+    }
+
+    let mut quoted = String::from("\"");
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '%' if chars.peek() == Some(&'{') => quoted.push_str("\\%"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        request::CodeActionRequest, CodeActionContext, PartialResultParams, Position, Range,
+        WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Open `source` and return the title and the edits of the quick fixes of its diagnostics.
+    fn quick_fixes(source: &str) -> Vec<(String, Vec<TextEdit>)> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        let diagnostics = server
+            .published_diagnostics()
+            .into_iter()
+            .flat_map(|params| params.diagnostics)
+            .collect();
+        let actions = server
+            .request::<CodeActionRequest>(CodeActionParams {
+                text_document,
+                range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                context: CodeActionContext {
+                    diagnostics,
+                    only: None,
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap_or_default();
+
+        actions
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(action) => {
+                    let mut changes = action.edit.unwrap().changes.unwrap();
+                    let edits = changes.remove(&TestServer::uri("main.ncl")).unwrap();
+                    (action.title, edits)
+                }
+                CodeActionOrCommand::Command(command) => panic!("unexpected command {:?}", command),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoted_names() {
+        assert_eq!(quote_if_needed("foo"), "foo");
+        assert_eq!(quote_if_needed("_foo-bar"), "_foo-bar");
+        assert_eq!(quote_if_needed("if"), "\"if\"");
+        assert_eq!(quote_if_needed("fun"), "\"fun\"");
+        assert_eq!(quote_if_needed("foo bar"), "\"foo bar\"");
+        assert_eq!(
+            quote_if_needed("a\"b\\c%{d}%e\n"),
+            "\"a\\\"b\\\\c\\%{d}%e\\n\""
+        );
+    }
+
+    #[test]
+    fn misspelled_fields() {
+        let edit = |start, end, new_text: &str| TextEdit {
+            range: Range::new(Position::new(0, start), Position::new(0, end)),
+            new_text: String::from(new_text),
+        };
+        assert_eq!(
+            quick_fixes("(let r = {value = 1} in r.valeu) : Num")[0],
+            (
+                String::from("Replace `valeu` with `value`"),
+                vec![edit(26, 31, "value")]
+            )
+        );
+        assert_eq!(
+            quick_fixes("(let r = {\"if\" = 1} in r.iff) : Num")[0],
+            (
+                String::from("Replace `iff` with `if`"),
+                vec![edit(25, 28, "\"if\"")]
+            )
+        );
+    }
+}
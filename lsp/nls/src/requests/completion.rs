@@ -1,16 +1,102 @@
-use codespan::ByteIndex;
+use std::{collections::HashMap, convert::Infallible};
+
+use codespan::{ByteIndex, FileId};
 use codespan_lsp::position_to_byte_index;
 use log::debug;
 use lsp_server::{RequestId, Response, ResponseError};
-use lsp_types::{CompletionItem, CompletionParams};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, Documentation, InsertTextFormat,
+};
+use nickel_lang::{
+    cache::Cache,
+    identifier::Ident,
+    term::{MergePriority, MetaValue, RichTerm, Term, TraverseOrder, UnaryOp},
+    types::{AbsType, Types},
+};
 use serde_json::Value;
 
 use crate::{
-    linearization::interface::TermKind,
+    cache::CacheExt,
+    linearization::{
+        completed::Completed,
+        imports::{field_of, resolve_usage, top_level_record, value_of, Located},
+        interface::{Resolved, TermKind},
+        LinearizationItem,
+    },
     server::Server,
     trace::{Enrich, Trace},
 };
 
+/// A record field offered for completion, described by the annotations of its declaration.
+struct FieldCompletion {
+    label: String,
+    /// The type and the contracts the field is annotated with.
+    contracts: Vec<Types>,
+    doc: Option<String>,
+    deprecated: bool,
+    /// The source of the default value of the field, if any.
+    default: Option<String>,
+}
+
+impl FieldCompletion {
+    fn new(label: String, meta: Option<&MetaValue>) -> Self {
+        FieldCompletion {
+            label,
+            contracts: meta
+                .map(|meta| {
+                    meta.types
+                        .iter()
+                        .chain(meta.contracts.iter())
+                        .map(|contract| contract.types.clone())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            doc: meta.and_then(|meta| meta.doc.clone()),
+            deprecated: meta.and_then(|meta| meta.deprecated.as_ref()).is_some(),
+            default: None,
+        }
+    }
+
+    /// Convert to a completion item. When completing the definition of a field, its default value
+    /// is proposed as a snippet.
+    fn into_item(self, definition: bool) -> CompletionItem {
+        let (insert_text, insert_text_format) = match self.default {
+            Some(default) if definition => (
+                Some(format!(
+                    "{} = ${{1:{}}}",
+                    self.label,
+                    escape_snippet(&default)
+                )),
+                Some(InsertTextFormat::Snippet),
+            ),
+            _ => (None, None),
+        };
+        let detail = self
+            .contracts
+            .iter()
+            .map(Types::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+
+        CompletionItem {
+            label: self.label,
+            kind: Some(CompletionItemKind::Field),
+            detail: Some(detail).filter(|detail| !detail.is_empty()),
+            documentation: self.doc.map(Documentation::String),
+            deprecated: Some(self.deprecated).filter(|deprecated| *deprecated),
+            insert_text,
+            insert_text_format,
+            ..Default::default()
+        }
+    }
+}
+
+fn escape_snippet(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('$', "\\$")
+        .replace('}', "\\}")
+}
+
 pub fn handle_completion(
     params: CompletionParams,
     id: RequestId,
@@ -18,7 +104,7 @@ pub fn handle_completion(
 ) -> Result<(), ResponseError> {
     let file_id = server
         .cache
-        .id_of(params.text_document_position.text_document.uri.as_str())
+        .id_of_uri(&params.text_document_position.text_document.uri)
         .unwrap();
 
     let start = position_to_byte_index(
@@ -29,10 +115,49 @@ pub fn handle_completion(
     .unwrap();
 
     let locator = (file_id, ByteIndex(start as u32));
-    let linearization = server.lin_cache_get(&file_id)?;
 
-    Trace::enrich(&id, linearization);
+    // Fields may be completed in a file which doesn't typecheck, hence without a linearization:
+    // typically, when fields required by a record type are missing.
+    if let Some(linearization) = server.lin_cache.get(&file_id) {
+        Trace::enrich(&id, linearization);
+    }
+
+    // After a `.`, offer the fields of the accessed record
+    let source = server.cache.files().source(file_id);
+    if let Some((root_start, path)) = access_path(&source[..start]) {
+        debug!("completing access to {:?}", path);
+
+        let items: Vec<_> = access_fields(
+            &server.cache,
+            &server.lin_cache,
+            (file_id, ByteIndex(root_start as u32)),
+            &path,
+        )
+        .into_iter()
+        .map(|field| field.into_item(false))
+        .collect();
+
+        server.reply(Response::new_ok(id, items));
+        return Ok(());
+    }
+
+    // Inside a record, offer the fields expected by its contracts which are not defined yet
+    if let Some(fields) = server.cache.get_ref(file_id).and_then(|term| {
+        let path = enclosing_terms(term, locator);
+        missing_fields(&server.cache, &server.lin_cache, &path)
+    }) {
+        debug!("completing record fields");
+
+        let items: Vec<_> = fields
+            .into_iter()
+            .map(|field| field.into_item(true))
+            .collect();
+
+        server.reply(Response::new_ok(id, items));
+        return Ok(());
+    }
 
+    let linearization = server.lin_cache_get(&file_id)?;
     let item = linearization.item_at(&locator);
 
     if item == None {
@@ -60,3 +185,438 @@ pub fn handle_completion(
     debug!("found closest item: {:?}", item);
     Ok(())
 }
+
+/// If the source before the cursor ends with a record access, such as `foo.bar.ba`, return the
+/// start of the accessed variable and the accessed path (`["foo", "bar"]`). The identifier being
+/// typed, if any, is ignored.
+fn access_path(before: &str) -> Option<(usize, Vec<String>)> {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let strip_ident = |text: &str| text.trim_end_matches(is_ident_char).len();
+
+    let mut end = strip_ident(before);
+    let mut path = Vec::new();
+
+    while before[..end].ends_with('.') {
+        let ident_end = end - 1;
+        let ident_start = strip_ident(&before[..ident_end]);
+        let ident = &before[ident_start..ident_end];
+
+        if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+
+        path.push(ident.to_owned());
+        end = ident_start;
+    }
+
+    path.reverse();
+    Some((end, path)).filter(|(_, path)| !path.is_empty())
+}
+
+/// Return the fields of the record accessed by `path`, whose root is a variable starting at
+/// `root_start`. As the access being typed may not parse, the linearization may be outdated: the
+/// root variable is looked up in the scope of the item found at its position, if any, or else in
+/// the whole file.
+fn access_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    (file_id, root_start): (FileId, ByteIndex),
+    path: &[String],
+) -> Vec<FieldCompletion> {
+    let linearization = match lin_cache.get(&file_id) {
+        Some(linearization) => linearization,
+        None => return Vec::new(),
+    };
+    let is_root = |item: &&LinearizationItem<Resolved>| match &item.kind {
+        TermKind::Declaration(ident, ..) | TermKind::RecordField { ident, .. } => {
+            ident.label == path[0]
+        }
+        _ => false,
+    };
+
+    let declaration = match linearization.item_at(&(file_id, root_start)) {
+        Some(item) => linearization
+            .get_in_scope(item)
+            .into_iter()
+            .rev()
+            .find(is_root),
+        None => linearization.linearization.iter().rev().find(is_root),
+    };
+
+    let mut value = declaration.and_then(|decl| value_of(cache, lin_cache, (file_id, decl)));
+    for field in path[1..].iter() {
+        value = value
+            .and_then(|value| field_of(cache, lin_cache, value, &Ident::from(field.as_str())))
+            .and_then(|field| value_of(cache, lin_cache, field));
+    }
+
+    match value.map(|value| value_fields(cache, lin_cache, value)) {
+        Some(fields) if !fields.is_empty() => fields,
+        // The value may not be a record literal, but still have a record type
+        _ => match (declaration, path.len()) {
+            (Some(declaration), 1) => type_fields(&declaration.ty),
+            _ => Vec::new(),
+        },
+    }
+}
+
+/// Return the fields of a value, if it is a record or an imported record.
+fn value_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    (file_id, value): Located,
+) -> Vec<FieldCompletion> {
+    let record = match value.kind {
+        TermKind::Import(imported) => top_level_record(cache, lin_cache, imported),
+        TermKind::Record(_) => Some((file_id, value)),
+        _ => None,
+    };
+
+    match record {
+        Some((file_id, record)) => record_fields(cache, lin_cache, (file_id, record)),
+        None => type_fields(&value.ty),
+    }
+}
+
+/// Return the fields of a record of the linearization, with their annotations. Default values
+/// are retrieved from the term of the record, which is looked up by the position of its fields:
+/// records which are the value of a field are not linearized at their actual position.
+fn record_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    (file_id, record): Located,
+) -> Vec<FieldCompletion> {
+    let (linearization, fields) = match (lin_cache.get(&file_id), &record.kind) {
+        (Some(linearization), TermKind::Record(fields)) => (linearization, fields),
+        _ => return Vec::new(),
+    };
+    let record_term = cache
+        .get_ref(file_id)
+        .zip(fields.keys().next())
+        .and_then(|(term, field)| find_record(term, field));
+
+    let mut fields: Vec<_> = fields
+        .iter()
+        .filter_map(|(ident, field)| {
+            let field = linearization.get_item(*field)?;
+            let mut completion = FieldCompletion::new(ident.label.clone(), field.meta.as_ref());
+            completion.default = record_term
+                .as_ref()
+                .and_then(|term| default_value(cache, term, ident));
+            Some(completion)
+        })
+        .collect();
+    fields.sort_by(|f1, f2| f1.label.cmp(&f2.label));
+    fields
+}
+
+/// Return the fields of a record type.
+fn type_fields(ty: &Types) -> Vec<FieldCompletion> {
+    let mut row = match &ty.0 {
+        AbsType::StaticRecord(row) => row.as_ref(),
+        _ => return Vec::new(),
+    };
+
+    let mut fields = Vec::new();
+    while let AbsType::RowExtend(ident, field_ty, tail) = &row.0 {
+        let mut completion = FieldCompletion::new(ident.label.clone(), None);
+        completion
+            .contracts
+            .extend(field_ty.iter().map(|ty| (**ty).clone()));
+        fields.push(completion);
+        row = tail.as_ref();
+    }
+    fields
+}
+
+/// Return the source of the default value of a field of a record term, if any.
+fn default_value(cache: &Cache, record: &RichTerm, field: &Ident) -> Option<String> {
+    let fields = match record.as_ref() {
+        Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields,
+        _ => return None,
+    };
+
+    match fields.get(field)?.as_ref() {
+        Term::MetaValue(MetaValue {
+            priority: MergePriority::Default,
+            value: Some(value),
+            ..
+        }) => {
+            let span = value.pos.into_opt()?;
+            let source = cache.files().source(span.src_id);
+            Some(source[span.start.to_usize()..span.end.to_usize()].to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Find the record term defining a given field.
+fn find_record(term: &RichTerm, field: &Ident) -> Option<RichTerm> {
+    let mut found = None;
+    let _ = term.clone().traverse::<_, _, Infallible>(
+        &mut |rt: RichTerm, found: &mut Option<RichTerm>| {
+            let defines_field = match rt.as_ref() {
+                Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields
+                    .keys()
+                    .any(|ident| ident == field && ident.pos == field.pos),
+                _ => false,
+            };
+
+            if found.is_none() && defines_field {
+                *found = Some(rt.clone());
+            }
+            Ok(rt)
+        },
+        &mut found,
+        TraverseOrder::TopDown,
+    );
+    found
+}
+
+/// Return the path of terms from the root of a term down to the innermost term containing a
+/// position. The cursor is considered inside a record only if it is between its braces.
+fn enclosing_terms(term: &RichTerm, (file_id, index): (FileId, ByteIndex)) -> Vec<RichTerm> {
+    let mut path = Vec::new();
+    let _ = term.clone().traverse::<_, _, Infallible>(
+        &mut |rt: RichTerm, path: &mut Vec<RichTerm>| {
+            let contains = match (rt.pos.into_opt(), rt.as_ref()) {
+                (Some(span), Term::Record(..)) | (Some(span), Term::RecRecord(..)) => {
+                    span.src_id == file_id && span.start < index && index < span.end
+                }
+                (Some(span), _) => {
+                    span.src_id == file_id && span.start <= index && index <= span.end
+                }
+                (None, _) => false,
+            };
+
+            if contains {
+                path.push(rt.clone());
+            }
+            Ok(rt)
+        },
+        &mut path,
+        TraverseOrder::TopDown,
+    );
+    path
+}
+
+/// If the innermost term of `path` is a record, return the fields expected by its contracts which
+/// are not defined yet.
+fn missing_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    path: &[RichTerm],
+) -> Option<Vec<FieldCompletion>> {
+    let defined = match path.last()?.as_ref() {
+        Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields,
+        _ => return None,
+    };
+
+    let mut fields = expected_fields(cache, lin_cache, path);
+    fields.retain(|field| !defined.contains_key(&Ident::from(field.label.as_str())));
+    Some(fields)
+}
+
+/// Return the fields expected by the contracts of the innermost record of `path`. If the record
+/// isn't annotated, but is the value of a field of another record, the fields are derived from the
+/// contracts of this field, as expected by the enclosing record.
+fn expected_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    path: &[RichTerm],
+) -> Vec<FieldCompletion> {
+    let (record, outer) = match path.split_last() {
+        Some((record, outer)) => (record, outer),
+        None => return Vec::new(),
+    };
+
+    let mut contracts: Vec<Types> = match outer.last().map(RichTerm::as_ref) {
+        Some(Term::MetaValue(
+            meta @ MetaValue {
+                value: Some(value), ..
+            },
+        )) if value.pos == record.pos => FieldCompletion::new(String::new(), Some(meta)).contracts,
+        _ => Vec::new(),
+    };
+
+    if contracts.is_empty() {
+        let parent = outer
+            .iter()
+            .rposition(|rt| matches!(rt.as_ref(), Term::Record(..) | Term::RecRecord(..)));
+
+        if let Some(parent) = parent {
+            let child_pos = path[parent + 1].pos;
+            let field = match path[parent].as_ref() {
+                Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields
+                    .iter()
+                    .find(|(_, value)| value.pos == child_pos)
+                    .map(|(ident, _)| ident.label.clone()),
+                _ => None,
+            };
+
+            if let Some(field) = field {
+                contracts = expected_fields(cache, lin_cache, &path[..=parent])
+                    .into_iter()
+                    .find(|expected| expected.label == field)
+                    .map(|expected| expected.contracts)
+                    .unwrap_or_default();
+            }
+        }
+    }
+
+    contracts
+        .iter()
+        .flat_map(|contract| contract_fields(cache, lin_cache, contract))
+        .collect()
+}
+
+/// Return the fields declared by a contract, which is either a record type or a record contract,
+/// possibly bound to a variable or defined in another file.
+fn contract_fields(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    contract: &Types,
+) -> Vec<FieldCompletion> {
+    let rt = match &contract.0 {
+        AbsType::Flat(rt) => rt,
+        _ => return type_fields(contract),
+    };
+    // Contracts are linearized by the importer. A contract bound to a variable, such as `Schema`
+    // or `Schemas.Server`, is represented by the usage of the last identifier.
+    let (span, is_usage) = match rt.as_ref() {
+        Term::Var(ident) | Term::Op1(UnaryOp::StaticAccess(ident), _) => (ident.pos, true),
+        Term::Record(..) | Term::RecRecord(..) => (rt.pos, false),
+        _ => return Vec::new(),
+    };
+    let item = span.into_opt().and_then(|span| {
+        lin_cache
+            .get(&span.src_id)?
+            .linearization
+            .iter()
+            .find(|item| item.pos == span && matches!(item.kind, TermKind::Usage(_)) == is_usage)
+            .map(|item| (span.src_id, item))
+    });
+
+    let value = item.and_then(|(file_id, item)| match item.kind {
+        TermKind::Usage(_) => resolve_usage(cache, lin_cache, (file_id, item))
+            .and_then(|declaration| value_of(cache, lin_cache, declaration)),
+        TermKind::Record(_) => Some((file_id, item)),
+        _ => None,
+    });
+
+    match value {
+        Some(value) => value_fields(cache, lin_cache, value),
+        // A record contract nested in another one is not linearized at its position
+        None if !is_usage => term_fields(cache, rt),
+        None => Vec::new(),
+    }
+}
+
+/// Return the fields of a record term, with their annotations.
+fn term_fields(cache: &Cache, record: &RichTerm) -> Vec<FieldCompletion> {
+    let fields = match record.as_ref() {
+        Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields,
+        _ => return Vec::new(),
+    };
+
+    let mut fields: Vec<_> = fields
+        .iter()
+        .map(|(ident, value)| {
+            let meta = match value.as_ref() {
+                Term::MetaValue(meta) => Some(meta),
+                _ => None,
+            };
+            let mut completion = FieldCompletion::new(ident.label.clone(), meta);
+            completion.default = default_value(cache, record, ident);
+            completion
+        })
+        .collect();
+    fields.sort_by(|f1, f2| f1.label.cmp(&f2.label));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        request::Completion, CompletionResponse, PartialResultParams, Position,
+        TextDocumentPositionParams, WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Return the completion items at `(line, character)` in `source`.
+    fn complete(source: &str, (line, character): (u32, u32)) -> Vec<CompletionItem> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        match server.request::<Completion>(CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document,
+                position: Position::new(line, character),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        }) {
+            Some(CompletionResponse::Array(items)) => items,
+            response => panic!("expected completion items, got {:?}", response),
+        }
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        let mut labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        labels.sort_unstable();
+        labels
+    }
+
+    #[test]
+    fn access_path_before_cursor() {
+        assert_eq!(
+            access_path("x + foo."),
+            Some((4, vec![String::from("foo")]))
+        );
+        assert_eq!(
+            access_path("foo.bar.ba"),
+            Some((0, vec![String::from("foo"), String::from("bar")]))
+        );
+        assert_eq!(access_path("foo"), None);
+        assert_eq!(access_path("1."), None);
+    }
+
+    #[test]
+    fn record_access() {
+        let source = "let r = {foo | Num | doc \"The foo\" = 1, bar = {baz = 2}} in r.foo";
+        let items = complete(source, (0, 64));
+        assert_eq!(labels(&items), vec!["bar", "foo"]);
+
+        let foo = items.iter().find(|item| item.label == "foo").unwrap();
+        assert_eq!(foo.detail.as_deref(), Some("Num"));
+        assert_eq!(
+            foo.documentation,
+            Some(Documentation::String(String::from("The foo")))
+        );
+        assert_eq!(foo.insert_text, None);
+    }
+
+    #[test]
+    fn nested_record_access() {
+        let source = "let r = {foo = 1, bar = {baz = 2, qux = 3}} in r.bar.baz";
+        assert_eq!(labels(&complete(source, (0, 53))), vec!["baz", "qux"]);
+    }
+
+    #[test]
+    fn fields_from_contracts() {
+        let source = "let C = {foo | Num, bar | Str | default = \"x\"} in\n{foo = 1, } | C";
+        let items = complete(source, (1, 10));
+        assert_eq!(labels(&items), vec!["bar"]);
+        assert_eq!(items[0].insert_text.as_deref(), Some("bar = ${1:\"x\"}"));
+        assert_eq!(items[0].insert_text_format, Some(InsertTextFormat::Snippet));
+
+        // The fields of a record which is the value of a field are given by the contracts of the
+        // enclosing record
+        let source = "{sub = {a = 1, }} | {sub | {a | Num, b | Str}}";
+        assert_eq!(labels(&complete(source, (0, 15))), vec!["b"]);
+        let source = "let Sub = {a | Num, b | Str} in\n{sub = {a = 1, }} | {sub | Sub}";
+        assert_eq!(labels(&complete(source, (1, 15))), vec!["b"]);
+    }
+}
@@ -0,0 +1,153 @@
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{FoldingRange, FoldingRangeKind, FoldingRangeParams};
+use nickel_lang::parser::lexer::{Lexer, MultiStringToken, NormalToken, StringToken, Token};
+
+use crate::{cache::CacheExt, server::Server};
+
+/// Compute the folding ranges of records, arrays, multiline strings and chains of `let` bindings.
+///
+/// Folding ranges are computed from the tokens of the document rather than from its analysis, so
+/// that they are available while the document doesn't parse. If the document can't be lexed, the
+/// ranges before the error are returned.
+pub fn handle_folding_ranges(
+    params: FoldingRangeParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+    let files = server.cache.files();
+    let line_of = |offset: usize| files.line_index(file_id, offset as u32).to_usize();
+
+    let mut ranges = Vec::new();
+    // The delimiters left open, with the line of the ones which are folded once closed.
+    let mut delimiters: Vec<Option<usize>> = Vec::new();
+    // The `let` bindings waiting for their `in`, with the depth of delimiters they are at and the
+    // start line of their chain.
+    let mut lets: Vec<(usize, usize)> = Vec::new();
+    // The start and the end lines of a chain of `let` bindings, if the previous token ended it.
+    // The chain continues if the next token is another `let`.
+    let mut chain = None;
+
+    for (start, token, _) in Lexer::new(files.source(file_id)).map_while(Result::ok) {
+        let line = line_of(start);
+
+        let chain_start = match chain.take() {
+            Some((chain_start, _)) if token == Token::Normal(NormalToken::Let) => Some(chain_start),
+            Some((chain_start, chain_end)) => {
+                ranges.extend(fold(chain_start, chain_end));
+                None
+            }
+            None => None,
+        };
+
+        match token {
+            Token::Normal(NormalToken::LBrace)
+            | Token::Normal(NormalToken::LBracket)
+            | Token::Normal(NormalToken::MultiStringStart(_)) => delimiters.push(Some(line)),
+            Token::Normal(NormalToken::LParen)
+            | Token::Normal(NormalToken::EnumOpen)
+            | Token::Str(StringToken::Interpolation)
+            | Token::MultiStr(MultiStringToken::Interpolation) => delimiters.push(None),
+            // The closing delimiter stays visible
+            Token::Normal(NormalToken::RBrace)
+            | Token::Normal(NormalToken::RBracket)
+            | Token::Normal(NormalToken::RParen)
+            | Token::Normal(NormalToken::EnumClose)
+            | Token::MultiStr(MultiStringToken::End) => {
+                if let Some(Some(open)) = delimiters.pop() {
+                    ranges.extend(fold(open, line.saturating_sub(1)));
+                }
+            }
+            Token::Normal(NormalToken::Let) => {
+                lets.push((delimiters.len(), chain_start.unwrap_or(line)));
+            }
+            Token::Normal(NormalToken::In) => {
+                if matches!(lets.last(), Some((depth, _)) if *depth == delimiters.len()) {
+                    let (_, chain_start) = lets.pop().unwrap();
+                    chain = Some((chain_start, line));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if let Some((chain_start, chain_end)) = chain {
+        ranges.extend(fold(chain_start, chain_end));
+    }
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+
+    server.reply(Response::new_ok(id, ranges));
+    Ok(())
+}
+
+fn fold(start: usize, end: usize) -> Option<FoldingRange> {
+    if end > start {
+        Some(FoldingRange {
+            start_line: start as u32,
+            start_character: None,
+            end_line: end as u32,
+            end_character: None,
+            kind: Some(FoldingRangeKind::Region),
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{request::FoldingRangeRequest, PartialResultParams, WorkDoneProgressParams};
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Return the start and end lines of the folding ranges of `source`.
+    fn folding_ranges(source: &str) -> Vec<(u32, u32)> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        server
+            .request::<FoldingRangeRequest>(FoldingRangeParams {
+                text_document,
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .map(|range| (range.start_line, range.end_line))
+            .collect()
+    }
+
+    #[test]
+    fn records_and_arrays() {
+        let source = "{\n  foo = [\n    1,\n    2,\n  ],\n  bar = { baz = 1 },\n}\n";
+        assert_eq!(folding_ranges(source), vec![(0, 5), (1, 3)]);
+    }
+
+    #[test]
+    fn parentheses_are_not_folded() {
+        let source = "(\n  1\n  + 2\n)\n";
+        assert!(folding_ranges(source).is_empty());
+    }
+
+    #[test]
+    fn multiline_strings() {
+        let source = "{\n  foo = m%\"\n    a\n    %{\"b\"}\n  \"%m,\n}\n";
+        assert_eq!(folding_ranges(source), vec![(0, 4), (1, 3)]);
+    }
+
+    #[test]
+    fn let_chains() {
+        let source = "let x = 1 in\nlet y = {\n  z = 2,\n} in\nlet w = 3 in\nx + y.z + w\n";
+        assert_eq!(folding_ranges(source), vec![(0, 4), (1, 2)]);
+
+        // A nested `let` doesn't end the chain of the enclosing one
+        let source = "let x =\n  let y = 1 in\n  y\nin\nx\n";
+        assert_eq!(folding_ranges(source), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn lexing_error() {
+        let source = "{\n  foo = 1,\n}\n\"unterminated\n";
+        assert_eq!(folding_ranges(source), vec![(0, 1)]);
+    }
+}
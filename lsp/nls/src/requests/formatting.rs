@@ -0,0 +1,202 @@
+use codespan_lsp::byte_index_to_position;
+use log::debug;
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{
+    DocumentFormattingParams, DocumentRangeFormattingParams, Position, Range, TextEdit,
+};
+use nickel_lang::format;
+use serde_json::Value;
+
+use crate::{cache::CacheExt, server::Server};
+
+/// Format a whole document. The document is replaced by a single edit, if it changes at all.
+pub fn handle_format_document(
+    params: DocumentFormattingParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+    let files = server.cache.files();
+    let source = files.source(file_id);
+
+    let formatted = match format::format(source) {
+        Ok(formatted) => formatted,
+        Err(err) => {
+            debug!("cannot format {}: {:?}", params.text_document.uri, err);
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+
+    let mut edits = Vec::new();
+    if formatted != *source {
+        edits.push(TextEdit {
+            range: Range {
+                start: Position::new(0, 0),
+                end: byte_index_to_position(files, file_id, source.len()).unwrap(),
+            },
+            new_text: formatted,
+        });
+    }
+
+    server.reply(Response::new_ok(id, edits));
+    Ok(())
+}
+
+/// Format the lines covered by a range of a document. As their indentation depends on the
+/// enclosing code, the whole document is formatted, but only the lines of the range are replaced.
+pub fn handle_format_range(
+    params: DocumentRangeFormattingParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+    let files = server.cache.files();
+    let source = files.source(file_id);
+
+    let lines = match format::format_lines(source) {
+        Ok(lines) => lines,
+        Err(err) => {
+            debug!("cannot format {}: {:?}", params.text_document.uri, err);
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+
+    // A range ending at the start of a line, such as a selection of whole lines, doesn't cover it
+    let start_line = params.range.start.line as usize;
+    let end_line = match params.range.end {
+        Position { line, character: 0 } if line > params.range.start.line => line as usize - 1,
+        Position { line, .. } => line as usize,
+    };
+    let end_line = end_line.min(lines.len() - 1);
+    if start_line > end_line {
+        server.reply(Response::new_ok(id, Vec::<TextEdit>::new()));
+        return Ok(());
+    }
+
+    let mut new_text = String::new();
+    for line in lines[start_line..=end_line].iter().flatten() {
+        new_text.push_str(line);
+        new_text.push('\n');
+    }
+
+    let line_start = |line: usize| {
+        files
+            .line_span(file_id, line as u32)
+            .unwrap()
+            .start()
+            .to_usize()
+    };
+    let start = line_start(start_line);
+    let (end, end_position) = if end_line + 1 < lines.len() {
+        (
+            line_start(end_line + 1),
+            Position::new(end_line as u32 + 1, 0),
+        )
+    } else {
+        (
+            source.len(),
+            byte_index_to_position(files, file_id, source.len()).unwrap(),
+        )
+    };
+
+    let mut edits = Vec::new();
+    if new_text != source[start..end] {
+        edits.push(TextEdit {
+            range: Range {
+                start: Position::new(start_line as u32, 0),
+                end: end_position,
+            },
+            new_text,
+        });
+    }
+
+    server.reply(Response::new_ok(id, edits));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{request::RangeFormatting, FormattingOptions, WorkDoneProgressParams};
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Format the lines of `source` covered by the range from `start` to `end`.
+    fn format_range(source: &str, start: (u32, u32), end: (u32, u32)) -> Vec<TextEdit> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        server
+            .request::<RangeFormatting>(DocumentRangeFormattingParams {
+                text_document,
+                range: Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+                options: FormattingOptions::default(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .unwrap_or_default()
+    }
+
+    const SOURCE: &str = "{\nfoo   = 1,\nbar =   2,\n}\n";
+
+    #[test]
+    fn lines_of_range() {
+        let edits = format_range(SOURCE, (1, 3), (1, 5));
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(1, 0), Position::new(2, 0)),
+                new_text: String::from("  foo = 1,\n"),
+            }]
+        );
+    }
+
+    #[test]
+    fn end_at_start_of_line() {
+        // The selection of the second line ends at the start of the third one
+        let edits = format_range(SOURCE, (1, 0), (2, 0));
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(1, 0), Position::new(2, 0)),
+                new_text: String::from("  foo = 1,\n"),
+            }]
+        );
+
+        // An empty range at the start of a line covers the line
+        let edits = format_range(SOURCE, (2, 0), (2, 0));
+        assert_eq!(edits[0].new_text, "  bar = 2,\n");
+    }
+
+    #[test]
+    fn range_past_end() {
+        let edits = format_range(SOURCE, (2, 0), (10, 0));
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(2, 0), Position::new(4, 0)),
+                new_text: String::from("  bar = 2,\n}\n"),
+            }]
+        );
+
+        assert!(format_range(SOURCE, (10, 0), (12, 0)).is_empty());
+    }
+
+    #[test]
+    fn without_final_line_break() {
+        // As when formatting the whole document, the last line ends with a line break
+        let edits = format_range("{\nfoo   = 1 }", (1, 0), (1, 0));
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(1, 0), Position::new(1, 11)),
+                new_text: String::from("  foo = 1 }\n"),
+            }]
+        );
+    }
+
+    #[test]
+    fn formatted_range() {
+        assert!(format_range("{\n  foo = 1,\n}\n", (0, 0), (3, 0)).is_empty());
+    }
+}
@@ -3,18 +3,42 @@ use codespan_lsp::position_to_byte_index;
 use log::debug;
 use lsp_server::{RequestId, Response, ResponseError};
 use lsp_types::{
-    GotoDefinitionParams, GotoDefinitionResponse, Location, Range, ReferenceParams, Url,
+    DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Location, Range, ReferenceParams,
 };
 use nickel_lang::position::RawSpan;
 use serde_json::Value;
 
 use crate::{
+    cache::CacheExt,
     diagnostic::LocationCompat,
-    linearization::interface::{TermKind, UsageState},
+    linearization::{
+        imports::{external_usages, resolve_usage},
+        interface::{Resolved, TermKind},
+        LinearizationItem,
+    },
     server::Server,
     trace::{Enrich, Trace},
 };
 
+/// Convert a span, which may belong to an imported file, to a location.
+pub fn location_of(server: &Server, span: RawSpan) -> Option<Location> {
+    match span {
+        RawSpan {
+            start: ByteIndex(start),
+            end: ByteIndex(end),
+            src_id,
+        } => Some(Location {
+            uri: server.cache.uri_of(src_id)?,
+            range: Range::from_codespan(
+                &src_id,
+                &(start as usize..end as usize),
+                server.cache.files(),
+            ),
+        }),
+    }
+}
+
 pub fn handle_to_definition(
     params: GotoDefinitionParams,
     id: RequestId,
@@ -22,13 +46,7 @@ pub fn handle_to_definition(
 ) -> Result<(), ResponseError> {
     let file_id = server
         .cache
-        .id_of(
-            params
-                .text_document_position_params
-                .text_document
-                .uri
-                .as_str(),
-        )
+        .id_of_uri(&params.text_document_position_params.text_document.uri)
         .unwrap();
 
     let start = position_to_byte_index(
@@ -55,24 +73,18 @@ pub fn handle_to_definition(
     debug!("found referencing item: {:?}", item);
 
     let location = match item.kind {
-        TermKind::Usage(UsageState::Resolved(usage_id)) => {
-            let definition = linearization.get_item(usage_id).unwrap();
-            let location = match definition.pos {
-                RawSpan {
-                    start: ByteIndex(start),
-                    end: ByteIndex(end),
-                    src_id,
-                } => Location {
-                    uri: Url::parse(&server.cache.name(src_id).to_string_lossy()).unwrap(),
-                    range: Range::from_codespan(
-                        &src_id,
-                        &(start as usize..end as usize),
-                        server.cache.files(),
-                    ),
-                },
-            };
-            Some(location)
-        }
+        // The definition may be located in an imported file
+        TermKind::Usage(_) => resolve_usage(&server.cache, &server.lin_cache, (file_id, item))
+            .and_then(|(_, definition)| location_of(server, definition.pos)),
+        // Go to the beginning of the imported file
+        TermKind::Import(imported) => location_of(
+            server,
+            RawSpan {
+                src_id: imported,
+                start: ByteIndex(0),
+                end: ByteIndex(0),
+            },
+        ),
         _ => None,
     };
 
@@ -93,7 +105,7 @@ pub fn handle_to_usages(
 ) -> Result<(), ResponseError> {
     let file_id = server
         .cache
-        .id_of(params.text_document_position.text_document.uri.as_str())
+        .id_of_uri(&params.text_document_position.text_document.uri)
         .unwrap();
 
     let start = position_to_byte_index(
@@ -123,21 +135,13 @@ pub fn handle_to_usages(
 
             for reference_id in usages.iter() {
                 let reference = linearization.get_item(*reference_id).unwrap();
-                let location = match reference.pos {
-                    RawSpan {
-                        start: ByteIndex(start),
-                        end: ByteIndex(end),
-                        src_id,
-                    } => Location {
-                        uri: Url::parse(&server.cache.name(src_id).to_string_lossy()).unwrap(),
-                        range: Range::from_codespan(
-                            &src_id,
-                            &(start as usize..end as usize),
-                            server.cache.files(),
-                        ),
-                    },
-                };
-                locations.push(location);
+                locations.extend(location_of(server, reference.pos));
+            }
+
+            // Record fields may also be accessed by the files importing this one
+            for (_, reference) in external_usages(&server.cache, &server.lin_cache, (file_id, item))
+            {
+                locations.extend(location_of(server, reference.pos));
             }
             Some(locations)
         }
@@ -153,3 +157,141 @@ pub fn handle_to_usages(
     }
     Ok(())
 }
+
+/// Highlight the binding under the cursor and its usages in the document. The binding may be
+/// declared in an imported file, in which case only its usages are highlighted.
+pub fn handle_document_highlight(
+    params: DocumentHighlightParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server
+        .cache
+        .id_of_uri(&params.text_document_position_params.text_document.uri)
+        .unwrap();
+
+    let start = position_to_byte_index(
+        server.cache.files(),
+        file_id,
+        &params.text_document_position_params.position,
+    )
+    .unwrap();
+
+    let locator = (file_id, ByteIndex(start as u32));
+    let linearization = server.lin_cache_get(&file_id)?;
+
+    Trace::enrich(&id, linearization);
+
+    let declaration = match linearization.item_at(&locator) {
+        Some(
+            item @ LinearizationItem {
+                kind: TermKind::Usage(_),
+                ..
+            },
+        ) => resolve_usage(&server.cache, &server.lin_cache, (file_id, item)),
+        Some(
+            item @ LinearizationItem {
+                kind: TermKind::Declaration(..) | TermKind::RecordField { .. },
+                ..
+            },
+        ) => Some((file_id, item)),
+        _ => None,
+    };
+
+    let (declaration_file, declaration) = match declaration {
+        Some(declaration) => declaration,
+        None => {
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+
+    let highlight = |item: &LinearizationItem<Resolved>, kind| DocumentHighlight {
+        range: Range::from_codespan(
+            &file_id,
+            &(item.pos.start.to_usize()..item.pos.end.to_usize()),
+            server.cache.files(),
+        ),
+        kind: Some(kind),
+    };
+
+    let mut highlights = Vec::new();
+    if declaration_file == file_id {
+        highlights.push(highlight(declaration, DocumentHighlightKind::Write));
+    }
+    // Scanning the usages of the document also covers the accesses to the fields of a record
+    for usage in linearization
+        .linearization
+        .iter()
+        .filter(|item| matches!(item.kind, TermKind::Usage(_)))
+    {
+        let resolved = resolve_usage(&server.cache, &server.lin_cache, (file_id, usage))
+            .map(|(file_id, item)| (file_id, item.id));
+        if resolved == Some((declaration_file, declaration.id)) {
+            highlights.push(highlight(usage, DocumentHighlightKind::Read));
+        }
+    }
+
+    debug!("highlights: {:?}", highlights);
+
+    server.reply(Response::new_ok(id, highlights));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        request::DocumentHighlightRequest, PartialResultParams, Position,
+        TextDocumentPositionParams, WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Return the kind and the columns of the highlights of the binding at `(line, character)` in
+    /// `source`.
+    fn highlights(
+        source: &str,
+        (line, character): (u32, u32),
+    ) -> Vec<(DocumentHighlightKind, u32, u32)> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        server
+            .request::<DocumentHighlightRequest>(DocumentHighlightParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document,
+                    position: Position::new(line, character),
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .map(|highlight| {
+                assert_eq!(highlight.range.start.line, highlight.range.end.line);
+                (
+                    highlight.kind.unwrap(),
+                    highlight.range.start.character,
+                    highlight.range.end.character,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shadowed_variable() {
+        let source = "let x = 1 in let y = x + 1 in let x = y in x + y";
+        let outer = vec![
+            (DocumentHighlightKind::Write, 4, 5),
+            (DocumentHighlightKind::Read, 21, 22),
+        ];
+        let inner = vec![
+            (DocumentHighlightKind::Write, 34, 35),
+            (DocumentHighlightKind::Read, 43, 44),
+        ];
+        assert_eq!(highlights(source, (0, 4)), outer);
+        assert_eq!(highlights(source, (0, 21)), outer);
+        assert_eq!(highlights(source, (0, 34)), inner);
+        assert_eq!(highlights(source, (0, 43)), inner);
+    }
+}
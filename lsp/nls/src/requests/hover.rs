@@ -6,7 +6,12 @@ use lsp_types::{Hover, HoverContents, HoverParams, LanguageString, MarkedString,
 use serde_json::Value;
 
 use crate::{
+    cache::CacheExt,
     diagnostic::LocationCompat,
+    linearization::{
+        imports::resolve_usage,
+        interface::{TermKind, UsageState},
+    },
     server::Server,
     trace::{Enrich, Trace},
 };
@@ -18,13 +23,7 @@ pub fn handle(
 ) -> Result<(), ResponseError> {
     let file_id = server
         .cache
-        .id_of(
-            params
-                .text_document_position_params
-                .text_document
-                .uri
-                .as_str(),
-        )
+        .id_of_uri(&params.text_document_position_params.text_document.uri)
         .unwrap();
 
     let start = position_to_byte_index(
@@ -52,7 +51,37 @@ pub fn handle(
 
     debug!("{:?}", item);
 
-    let (ty, meta) = linearization.resolve_item_type_meta(&item);
+    // Declarations, record fields and their usages are shown with their name
+    let name = match &item.kind {
+        TermKind::Declaration(ident, ..)
+        | TermKind::RecordField { ident, .. }
+        | TermKind::Usage(UsageState::Deferred { child: ident, .. }) => Some(ident.clone()),
+        TermKind::Usage(UsageState::Resolved(declaration)) => linearization
+            .get_item(*declaration)
+            .and_then(|declaration| match &declaration.kind {
+                TermKind::Declaration(ident, ..) | TermKind::RecordField { ident, .. } => {
+                    Some(ident.clone())
+                }
+                _ => None,
+            }),
+        _ => None,
+    };
+
+    // Accesses to the fields of an imported file are described by the linearization of the
+    // imported file
+    let (ty, meta) = match item.kind {
+        TermKind::Usage(UsageState::Deferred { .. }) => {
+            resolve_usage(&server.cache, &server.lin_cache, (file_id, &item))
+                .and_then(|(decl_file, decl)| {
+                    server
+                        .lin_cache
+                        .get(&decl_file)
+                        .map(|lin| lin.resolve_item_type_meta(decl))
+                })
+                .unwrap_or_else(|| linearization.resolve_item_type_meta(&item))
+        }
+        _ => linearization.resolve_item_type_meta(&item),
+    };
 
     let range = Range::from_codespan(
         &file_id,
@@ -66,7 +95,10 @@ pub fn handle(
             contents: HoverContents::Array(vec![
                 MarkedString::LanguageString(LanguageString {
                     language: "nickel".into(),
-                    value: ty.to_string(),
+                    value: match name {
+                        Some(name) => format!("{} : {}", name, ty),
+                        None => ty.to_string(),
+                    },
                 }),
                 MarkedString::LanguageString(LanguageString {
                     language: "plain".into(),
@@ -0,0 +1,164 @@
+use std::{convert::TryFrom, ops};
+
+use codespan::{FileId, Files};
+
+use codespan_lsp::{byte_index_to_position, position_to_byte_index};
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{request::Request, Position, Range, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    cache::CacheExt,
+    server::Server,
+    trace::{Enrich, Trace},
+};
+
+/// The `textDocument/inlayHint` request, introduced by the version 3.17 of the protocol, which is
+/// not covered by `lsp_types` yet.
+pub enum InlayHintRequest {}
+
+impl Request for InlayHintRequest {
+    type Params = InlayHintParams;
+    type Result = Option<Vec<InlayHint>>;
+    const METHOD: &'static str = "textDocument/inlayHint";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+    pub kind: InlayHintKind,
+    pub padding_left: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum InlayHintKind {
+    Type = 1,
+    Parameter = 2,
+}
+
+impl From<InlayHintKind> for u8 {
+    fn from(kind: InlayHintKind) -> Self {
+        kind as u8
+    }
+}
+
+impl TryFrom<u8> for InlayHintKind {
+    type Error = String;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            1 => Ok(InlayHintKind::Type),
+            2 => Ok(InlayHintKind::Parameter),
+            _ => Err(format!("invalid inlay hint kind {}", kind)),
+        }
+    }
+}
+
+/// Show the inferred types of `let` bindings, function parameters and record fields after their
+/// identifier, unless they are annotated with a type already.
+pub fn handle_inlay_hints(
+    params: InlayHintParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = match server.cache.id_of_uri(&params.text_document.uri) {
+        Some(file_id) if server.config.inlay_hints.enabled => file_id,
+        _ => {
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+
+    let files = server.cache.files();
+    let range = byte_range(files, file_id, &params.range);
+
+    let linearization = server.lin_cache_get(&file_id)?;
+    Trace::enrich(&id, linearization);
+
+    let hints: Vec<_> = linearization
+        .linearization
+        .iter()
+        .filter(|item| item.pos.src_id == file_id && range.contains(&item.pos.end.to_usize()))
+        .filter_map(|item| {
+            let ty = linearization.inferred_type(item)?;
+            let position = byte_index_to_position(files, file_id, item.pos.end.to_usize()).ok()?;
+            Some(InlayHint {
+                position,
+                label: format!(": {}", ty),
+                kind: InlayHintKind::Type,
+                padding_left: false,
+            })
+        })
+        .collect();
+
+    server.reply(Response::new_ok(id, hints));
+    Ok(())
+}
+
+/// Convert the range of a request to byte indices. Clients may request the hints of a range
+/// extending past the end of the document, such as the visible part of an editor.
+pub fn byte_range(files: &Files<String>, file_id: FileId, range: &Range) -> ops::Range<usize> {
+    let len = files.source(file_id).len();
+    let last_line = files.line_index(file_id, len as u32).to_usize();
+    let byte_index = |position: &Position, default: usize| {
+        if position.line as usize > last_line {
+            len
+        } else {
+            position_to_byte_index(files, file_id, position).unwrap_or(default)
+        }
+    };
+
+    byte_index(&range.start, 0)..byte_index(&range.end, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Return the position and the label of the inlay hints of the whole of `source`.
+    fn hints(source: &str) -> Vec<(Position, String)> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        server
+            .request::<InlayHintRequest>(InlayHintParams {
+                text_document,
+                range: Range::new(Position::new(0, 0), Position::new(u32::MAX, 0)),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|hint| (hint.position, hint.label))
+            .collect()
+    }
+
+    #[test]
+    fn inferred_types() {
+        assert_eq!(
+            hints("let x = 1 in\nlet s = \"a\" in\nx"),
+            vec![
+                (Position::new(0, 5), String::from(": Num")),
+                (Position::new(1, 5), String::from(": Str")),
+            ]
+        );
+    }
+
+    #[test]
+    fn annotated_types() {
+        assert_eq!(hints("let x : Num = 1 in x"), Vec::new());
+        assert_eq!(
+            hints("{a : Num = 1, b = \"b\"}"),
+            vec![(Position::new(0, 15), String::from(": Str"))]
+        );
+    }
+}
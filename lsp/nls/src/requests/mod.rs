@@ -1,4 +1,11 @@
+pub mod call_hierarchy;
+pub mod code_actions;
 pub mod completion;
+pub mod folding;
+pub mod formatting;
 pub mod goto;
 pub mod hover;
+pub mod inlay_hints;
+pub mod rename;
+pub mod semantic_tokens;
 pub mod symbols;
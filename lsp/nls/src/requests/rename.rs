@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use codespan::{ByteIndex, FileId};
+use codespan_lsp::position_to_byte_index;
+use log::debug;
+use lsp_server::{ErrorCode, RequestId, Response, ResponseError};
+use lsp_types::{
+    PrepareRenameResponse, Range, RenameParams, TextDocumentPositionParams, TextEdit, Url,
+    WorkspaceEdit,
+};
+use nickel_lang::{
+    cache::Cache,
+    parser::lexer::{Lexer, NormalToken, Token},
+    position::RawSpan,
+};
+use serde_json::Value;
+
+use crate::{
+    cache::CacheExt,
+    diagnostic::LocationCompat,
+    linearization::{
+        completed::Completed,
+        imports::{external_usages, resolve_usage, Located},
+        interface::{Resolved, TermKind, UsageState},
+        LinearizationItem,
+    },
+    server::Server,
+    term::RawSpanExt,
+    trace::{Enrich, Trace},
+};
+
+pub fn handle_prepare_rename(
+    params: TextDocumentPositionParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+
+    let start = position_to_byte_index(server.cache.files(), file_id, &params.position).unwrap();
+    let locator = (file_id, ByteIndex(start as u32));
+    let linearization = server.lin_cache_get(&file_id)?;
+
+    Trace::enrich(&id, linearization);
+
+    let response = linearization
+        .item_at(&locator)
+        .filter(|item| declaration_of(&server.cache, &server.lin_cache, (file_id, item)).is_some())
+        .map(|item| {
+            let (file_id, span) = item.pos.to_range();
+            PrepareRenameResponse::RangeWithPlaceholder {
+                range: Range::from_codespan(&file_id, &span, server.cache.files()),
+                placeholder: server.cache.files().source(file_id)[span].to_owned(),
+            }
+        });
+
+    debug!("prepare rename: {:?}", response);
+
+    match response {
+        Some(response) => server.reply(Response::new_ok(id, response)),
+        None => server.reply(Response::new_ok(id, Value::Null)),
+    }
+    Ok(())
+}
+
+pub fn handle_rename(
+    params: RenameParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server
+        .cache
+        .id_of_uri(&params.text_document_position.text_document.uri)
+        .unwrap();
+
+    let start = position_to_byte_index(
+        server.cache.files(),
+        file_id,
+        &params.text_document_position.position,
+    )
+    .unwrap();
+
+    if !is_identifier(&params.new_name) {
+        return Err(rename_error(
+            ErrorCode::InvalidParams,
+            format!("`{}` is not a valid identifier", params.new_name),
+        ));
+    }
+
+    let locator = (file_id, ByteIndex(start as u32));
+    let linearization = server.lin_cache_get(&file_id)?;
+
+    Trace::enrich(&id, linearization);
+
+    // The declaration may be located in an imported file
+    let (file_id, declaration) = match linearization
+        .item_at(&locator)
+        .and_then(|item| declaration_of(&server.cache, &server.lin_cache, (file_id, item)))
+    {
+        Some(declaration) => declaration,
+        None => {
+            server.reply(Response::new_ok(id, Value::Null));
+            return Ok(());
+        }
+    };
+    let linearization = server.lin_cache_get(&file_id)?;
+
+    let occurrences = occurrences(&server.cache, &server.lin_cache, (file_id, declaration));
+    let files = server.cache.files();
+    let source_of =
+        |span: &RawSpan| &files.source(span.src_id)[span.start.to_usize()..span.end.to_usize()];
+    let old_name = source_of(&declaration.pos).to_owned();
+    let source = files.source(file_id);
+
+    // Quoted fields, such as `"a field"`, are not handled: their occurrences may not be
+    // identifiers.
+    if let Some(span) = occurrences
+        .iter()
+        .find(|span| source_of(span) != old_name.as_str())
+    {
+        return Err(rename_error(
+            ErrorCode::InvalidRequest,
+            format!(
+                "cannot rename `{}`: it occurs as `{}`",
+                old_name,
+                source_of(span)
+            ),
+        ));
+    }
+
+    if let Some((conflict, span)) =
+        find_capture(linearization, declaration, &params.new_name, source)
+    {
+        let location = server
+            .cache
+            .files()
+            .location(file_id, span.start)
+            .map(|loc| format!(" (line {}, column {})", loc.line.0 + 1, loc.column.0 + 1))
+            .unwrap_or_default();
+        return Err(rename_error(
+            ErrorCode::InvalidRequest,
+            format!("{}{}", conflict, location),
+        ));
+    }
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for span in occurrences {
+        let (file_id, range) = span.to_range();
+        let uri = match server.cache.uri_of(file_id) {
+            Some(uri) => uri,
+            None => continue,
+        };
+        changes.entry(uri).or_default().push(TextEdit {
+            range: Range::from_codespan(&file_id, &range, server.cache.files()),
+            new_text: params.new_name.clone(),
+        });
+    }
+
+    debug!("rename edits: {:?}", changes);
+
+    server.reply(Response::new_ok(
+        id,
+        WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        },
+    ));
+    Ok(())
+}
+
+fn rename_error(code: ErrorCode, message: String) -> ResponseError {
+    ResponseError {
+        code: code as i32,
+        message,
+        data: None,
+    }
+}
+
+/// Check that a name is a single identifier, and not e.g. a keyword.
+pub fn is_identifier(name: &str) -> bool {
+    let mut lexer = Lexer::new(name);
+    matches!(
+        (lexer.next(), lexer.next()),
+        (
+            Some(Ok((_, Token::Normal(NormalToken::Identifier(_)), _))),
+            None
+        )
+    )
+}
+
+/// Return the declaration (a `let` binding, a function parameter or a record field) an item
+/// refers to, if any, together with the file it is declared in.
+fn declaration_of<'a>(
+    cache: &Cache,
+    lin_cache: &'a HashMap<FileId, Completed>,
+    (file_id, item): Located<'a>,
+) -> Option<Located<'a>> {
+    let (file_id, declaration) = match item.kind {
+        TermKind::Usage(_) => resolve_usage(cache, lin_cache, (file_id, item))?,
+        _ => (file_id, item),
+    };
+
+    match declaration.kind {
+        TermKind::Declaration(..) | TermKind::RecordField { .. } => Some((file_id, declaration)),
+        _ => None,
+    }
+}
+
+/// Return the spans of a declaration and all of its usages, including the accesses from the
+/// files importing it, without duplicates.
+fn occurrences(
+    cache: &Cache,
+    lin_cache: &HashMap<FileId, Completed>,
+    (file_id, declaration): Located,
+) -> Vec<RawSpan> {
+    let usages = match &declaration.kind {
+        TermKind::Declaration(_, usages, _) | TermKind::RecordField { usages, .. } => {
+            usages.as_slice()
+        }
+        _ => &[],
+    };
+    let linearization = &lin_cache[&file_id];
+
+    let mut spans: Vec<RawSpan> = std::iter::once(declaration.pos)
+        .chain(
+            usages
+                .iter()
+                .filter_map(|usage| linearization.get_item(*usage))
+                .map(|usage| usage.pos),
+        )
+        .chain(
+            external_usages(cache, lin_cache, (file_id, declaration))
+                .into_iter()
+                .map(|(_, usage)| usage.pos),
+        )
+        .collect();
+    spans.sort_by_key(|span| (span.src_id, span.start));
+    spans.dedup();
+    spans
+}
+
+/// Check if renaming a declaration to `new_name` would change the meaning of the program, by
+/// either:
+///
+/// - capturing a usage of another binding named `new_name` in the scope of the declaration, or
+/// - having the usages of the renamed declaration captured by another binding named `new_name`,
+///   or by a sibling field of the same name.
+///
+/// Return a description of the conflict and the position of the offending item, if any. All the
+/// items of the linearization are expected to come from `source`.
+fn find_capture(
+    linearization: &Completed,
+    declaration: &LinearizationItem<Resolved>,
+    new_name: &str,
+    source: &str,
+) -> Option<(String, RawSpan)> {
+    let source_of = |span: &RawSpan| &source[span.start.to_usize()..span.end.to_usize()];
+    // Field accesses such as `record.field` aren't subject to variable capture.
+    let is_variable = |item: &LinearizationItem<Resolved>| {
+        matches!(item.kind, TermKind::Usage(_))
+            && !source[..item.pos.start.to_usize()]
+                .trim_end()
+                .ends_with('.')
+    };
+    let is_named = |item: &LinearizationItem<Resolved>| match &item.kind {
+        TermKind::Declaration(ident, ..) | TermKind::RecordField { ident, .. } => {
+            ident.label == new_name
+        }
+        _ => false,
+    };
+
+    if let TermKind::RecordField { record, .. } = declaration.kind {
+        if let Some(TermKind::Record(fields)) = linearization.get_item(record).map(|r| &r.kind) {
+            if let Some(field) = fields.keys().find(|field| field.label == new_name) {
+                return Some((
+                    format!("the record already has a field `{}`", new_name),
+                    field.pos.into_opt().unwrap_or(declaration.pos),
+                ));
+            }
+        }
+    }
+
+    // Usages of the declaration itself, or of a binding declared in its scope, keep referring to
+    // the same binding once renamed. The bindings of a chain of `let` share the same scope: the
+    // ones declared later are in the scope of the earlier ones.
+    let resolves_inside = |item: &LinearizationItem<Resolved>| match item.kind {
+        TermKind::Usage(UsageState::Resolved(decl)) => matches!(
+            linearization.get_item(decl),
+            Some(decl) if decl.id == declaration.id
+                || (decl.scope.starts_with(&declaration.scope)
+                    && (decl.scope.len() > declaration.scope.len()
+                        || decl.pos.start > declaration.pos.start))
+        ),
+        _ => false,
+    };
+    let captured_usage = linearization.linearization.iter().find(|item| {
+        is_variable(item)
+            && item.scope.starts_with(&declaration.scope)
+            && source_of(&item.pos) == new_name
+            && !resolves_inside(item)
+    });
+    if let Some(usage) = captured_usage {
+        return Some((
+            format!("renaming would capture a usage of `{}`", new_name),
+            usage.pos,
+        ));
+    }
+
+    let usages = match &declaration.kind {
+        TermKind::Declaration(_, usages, _) | TermKind::RecordField { usages, .. } => usages,
+        _ => return None,
+    };
+    usages
+        .iter()
+        .filter_map(|usage| linearization.get_item(*usage))
+        .filter(|usage| is_variable(usage))
+        .find_map(|usage| {
+            // The bindings of a chain of `let` declared after a usage don't shadow it, while
+            // record fields are recursive.
+            linearization.get_in_scope(usage).into_iter().find(|item| {
+                item.id != declaration.id
+                    && is_named(item)
+                    && (matches!(item.kind, TermKind::RecordField { .. })
+                        || item.pos.start <= usage.pos.start)
+            })
+        })
+        .map(|shadowing| {
+            (
+                format!(
+                    "renamed usages would be shadowed by another binding of `{}`",
+                    new_name
+                ),
+                shadowing.pos,
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{request::Rename, Position, WorkDoneProgressParams};
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Rename the declaration or usage at `(line, character)` in `source`.
+    fn rename(
+        source: &str,
+        (line, character): (u32, u32),
+        new_name: &str,
+    ) -> Result<Option<WorkspaceEdit>, ResponseError> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        server.try_request::<Rename>(RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document,
+                position: Position::new(line, character),
+            },
+            new_name: String::from(new_name),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+    }
+
+    fn rename_error(source: &str, position: (u32, u32), new_name: &str) -> String {
+        match rename(source, position, new_name) {
+            Err(error) => error.message,
+            Ok(edit) => panic!("expected an error, got {:?}", edit),
+        }
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(is_identifier("foo"));
+        assert!(is_identifier("_foo_bar-baz"));
+        assert!(!is_identifier("let"));
+        assert!(!is_identifier("foo bar"));
+        assert!(!is_identifier("1"));
+        assert!(!is_identifier(""));
+    }
+
+    #[test]
+    fn rename_binding() {
+        let edit = rename("let x = 1 in x + x", (0, 4), "y").unwrap().unwrap();
+        let edits = &edit.changes.unwrap()[&TestServer::uri("main.ncl")];
+        let starts: Vec<_> = edits
+            .iter()
+            .map(|edit| edit.range.start.character)
+            .collect();
+        assert_eq!(starts, vec![4, 13, 17]);
+        assert!(edits.iter().all(|edit| edit.new_text == "y"));
+    }
+
+    #[test]
+    fn keyword_as_new_name() {
+        let message = rename_error("let x = 1 in x", (0, 4), "if");
+        assert_eq!(message, "`if` is not a valid identifier");
+    }
+
+    #[test]
+    fn capture_inner_usage() {
+        // `x` in the body would refer to the renamed `y`
+        let message = rename_error("let x = 1 in let y = 2 in x + y", (0, 17), "x");
+        assert!(message.starts_with("renaming would capture a usage of `x`"));
+    }
+
+    #[test]
+    fn shadowed_usages() {
+        // The usage of the renamed `x` would refer to `y`
+        let message = rename_error("let x = 1 in let y = 2 in x + y", (0, 4), "y");
+        assert!(
+            message.starts_with("renamed usages would be shadowed by another binding of `y`"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn shadowing_binding() {
+        // The usage of `x` in `z` is not in the scope of `y`, and the usage of `y` keeps
+        // referring to the inner binding
+        let edit = rename("let x = 1 in let z = x in let y = 2 in y + z", (0, 4), "y")
+            .unwrap()
+            .unwrap();
+        let edits = &edit.changes.unwrap()[&TestServer::uri("main.ncl")];
+        let starts: Vec<_> = edits
+            .iter()
+            .map(|edit| edit.range.start.character)
+            .collect();
+        assert_eq!(starts, vec![4, 21]);
+
+        // Bindings are recursive: `x` would refer to the new `y` itself
+        let message = rename_error("let x = 1 in let y = x in y", (0, 4), "y");
+        assert!(message.starts_with("renamed usages would be shadowed"));
+    }
+
+    #[test]
+    fn sibling_field() {
+        let message = rename_error("{a = 1, b = 2}", (0, 1), "b");
+        assert!(message.starts_with("the record already has a field `b`"));
+    }
+}
@@ -0,0 +1,540 @@
+use std::{collections::HashMap, ops};
+
+use codespan::FileId;
+use codespan_lsp::byte_index_to_position;
+use lsp_server::{RequestId, Response, ResponseError};
+use lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensLegend,
+    SemanticTokensParams, SemanticTokensRangeParams,
+};
+use nickel_lang::{
+    identifier::Ident,
+    parser::lexer::{Lexer, MultiStringToken, NormalToken, StringToken, Token},
+    term::MergePriority,
+    typecheck::Environment,
+    types::AbsType,
+};
+
+use crate::{
+    cache::CacheExt,
+    linearization::{
+        completed::{is_parameter, Completed},
+        interface::{Resolved, TermKind, UsageState},
+        LinearizationItem,
+    },
+    requests::inlay_hints::byte_range,
+    server::Server,
+    trace::{Enrich, Trace},
+};
+
+/// The types of the semantic tokens. The index of a type in the legend is its value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenType {
+    Namespace,
+    Type,
+    TypeParameter,
+    Parameter,
+    Variable,
+    Property,
+    EnumMember,
+    Function,
+    Macro,
+    Keyword,
+    Comment,
+    String,
+    Number,
+    Operator,
+}
+
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+];
+
+/// The modifiers of the semantic tokens, as bits of the set of modifiers of a token. The index of
+/// a modifier in the legend is the index of its bit.
+const DECLARATION: u32 = 1;
+const DEPRECATED: u32 = 1 << 1;
+const DEFAULT_LIBRARY: u32 = 1 << 2;
+/// A record field with the `default` merge priority.
+const DEFAULT_PRIORITY: u32 = 1 << 3;
+
+const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+    SemanticTokenModifier::new("defaultPriority"),
+];
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+pub fn handle_semantic_tokens_full(
+    params: SemanticTokensParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+    let len = server.cache.files().source(file_id).len();
+
+    let tokens = semantic_tokens(server, &id, file_id, 0..len);
+    server.reply(Response::new_ok(id, tokens));
+    Ok(())
+}
+
+pub fn handle_semantic_tokens_range(
+    params: SemanticTokensRangeParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
+    let range = byte_range(server.cache.files(), file_id, &params.range);
+
+    let tokens = semantic_tokens(server, &id, file_id, range);
+    server.reply(Response::new_ok(id, tokens));
+    Ok(())
+}
+
+/// A token of the document to highlight, spanning the bytes `start..end`.
+struct Highlight {
+    start: usize,
+    end: usize,
+    token_type: TokenType,
+    modifiers: u32,
+}
+
+/// Compute the semantic tokens of a document starting in a range of bytes.
+///
+/// The tokens are given by the lexer, which accepts documents which don't parse. The identifiers
+/// are then classified using the linearization of the document, if any. Contracts and types are
+/// recognized by their position in annotations, which are not recorded by the linearization.
+fn semantic_tokens(
+    server: &Server,
+    id: &RequestId,
+    file_id: FileId,
+    range: ops::Range<usize>,
+) -> SemanticTokens {
+    let linearization = server.lin_cache.get(&file_id);
+    if let Some(linearization) = linearization {
+        Trace::enrich(id, linearization);
+    }
+
+    let files = server.cache.files();
+    let source = files.source(file_id);
+    let classifier = Classifier::new(linearization, &server.global_env, source, file_id);
+    let highlights = highlights(source, &classifier);
+
+    let mut data = Vec::new();
+    let (mut previous_line, mut previous_start) = (0, 0);
+    for highlight in highlights
+        .into_iter()
+        .filter(|highlight| range.contains(&highlight.start))
+    {
+        // Clients may not support tokens spanning several lines, such as multiline strings
+        let mut start = highlight.start;
+        for line in source[highlight.start..highlight.end].split('\n') {
+            let end = start + line.len();
+            let length = line.trim_end_matches('\r').encode_utf16().count() as u32;
+            if length > 0 {
+                let position = byte_index_to_position(files, file_id, start).unwrap();
+                let delta_line = position.line - previous_line;
+                let delta_start = if delta_line == 0 {
+                    position.character - previous_start
+                } else {
+                    position.character
+                };
+                data.push(SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length,
+                    token_type: highlight.token_type as u32,
+                    token_modifiers_bitset: highlight.modifiers,
+                });
+                previous_line = position.line;
+                previous_start = position.character;
+            }
+            start = end + 1;
+        }
+    }
+
+    SemanticTokens {
+        result_id: None,
+        data,
+    }
+}
+
+/// Classify the tokens of a document. If the document can't be lexed, only the tokens before
+/// the error are classified.
+fn highlights(source: &str, classifier: &Classifier) -> Vec<Highlight> {
+    let tokens: Vec<_> = Lexer::with_comments(source).map_while(Result::ok).collect();
+
+    // The delimiters left open, which are `true` for the brackets of enum types.
+    let mut delimiters: Vec<bool> = Vec::new();
+    // The depths of delimiters of the type annotations and contracts being lexed.
+    let mut annotations: Vec<usize> = Vec::new();
+    // The type variables introduced by a `forall` in the current annotations.
+    let mut type_variables: Vec<&str> = Vec::new();
+    let mut in_forall = false;
+    // Whether the previous tokens are a module of the standard library, followed or not by a dot.
+    // The linearization doesn't resolve the accesses to the standard library.
+    let (mut after_stdlib, mut after_stdlib_dot) = (false, false);
+
+    let mut highlights = Vec::new();
+    let mut tokens = tokens.iter().peekable();
+    while let Some((start, token, end)) = tokens.next() {
+        let (start, mut end) = (*start, *end);
+        let in_enum = delimiters.last() == Some(&true);
+        let in_type = in_enum || annotations.last() == Some(&delimiters.len());
+        let next = tokens.peek().map(|(_, token, _)| token);
+
+        let highlight = match token {
+            Token::Normal(NormalToken::Identifier(name)) if in_forall => {
+                type_variables.push(name);
+                Some((TokenType::TypeParameter, DECLARATION))
+            }
+            Token::Normal(NormalToken::Identifier(name)) => {
+                let item = if after_stdlib_dot {
+                    Some((TokenType::Function, DEFAULT_LIBRARY))
+                } else {
+                    classifier.identifier(start, end, name)
+                };
+                let is_declaration =
+                    matches!(item, Some((_, modifiers)) if modifiers & DECLARATION != 0);
+
+                if in_type && !is_declaration {
+                    let modifiers = item.map_or(0, |(_, modifiers)| modifiers) & DEFAULT_LIBRARY;
+                    if in_enum {
+                        Some((TokenType::EnumMember, 0))
+                    } else if type_variables.contains(name) {
+                        Some((TokenType::TypeParameter, 0))
+                    } else {
+                        Some((TokenType::Type, modifiers))
+                    }
+                } else if item.is_none() && next == Some(&Token::Normal(NormalToken::Colon)) {
+                    // The fields of record types are not part of the linearization
+                    Some((TokenType::Property, DECLARATION))
+                } else {
+                    item
+                }
+            }
+            // An enum tag
+            Token::Normal(NormalToken::Backtick) => match tokens.peek() {
+                Some((tag_start, Token::Normal(NormalToken::Identifier(_)), tag_end))
+                    if *tag_start == end =>
+                {
+                    end = *tag_end;
+                    tokens.next();
+                    Some((TokenType::EnumMember, 0))
+                }
+                _ => None,
+            },
+            Token::Normal(normal) => classify_normal(normal, &source[start..end]),
+            Token::Str(StringToken::Interpolation)
+            | Token::MultiStr(MultiStringToken::Interpolation) => None,
+            Token::Str(_) | Token::MultiStr(_) => Some((TokenType::String, 0)),
+        };
+
+        after_stdlib_dot = after_stdlib && *token == Token::Normal(NormalToken::Dot);
+        after_stdlib = matches!(token, Token::Normal(NormalToken::Identifier(_)))
+            && matches!(highlight, Some((_, modifiers)) if modifiers & DEFAULT_LIBRARY != 0);
+
+        if let Some((token_type, modifiers)) = highlight {
+            highlights.push(Highlight {
+                start,
+                end,
+                token_type,
+                modifiers,
+            });
+        }
+
+        match token {
+            Token::Normal(NormalToken::Colon) | Token::Normal(NormalToken::Pipe) if !in_type => {
+                annotations.push(delimiters.len())
+            }
+            Token::Normal(NormalToken::Forall) => in_forall = true,
+            Token::Normal(NormalToken::Dot) => in_forall = false,
+            Token::Normal(NormalToken::EnumOpen) => delimiters.push(true),
+            Token::Normal(NormalToken::LBrace)
+            | Token::Normal(NormalToken::LBracket)
+            | Token::Normal(NormalToken::LParen)
+            | Token::Str(StringToken::Interpolation)
+            | Token::MultiStr(MultiStringToken::Interpolation) => delimiters.push(false),
+            Token::Normal(NormalToken::RBrace)
+            | Token::Normal(NormalToken::RBracket)
+            | Token::Normal(NormalToken::RParen)
+            | Token::Normal(NormalToken::EnumClose) => {
+                delimiters.pop();
+                while matches!(annotations.last(), Some(depth) if *depth > delimiters.len()) {
+                    annotations.pop();
+                }
+            }
+            // The end of an annotation
+            Token::Normal(NormalToken::Equals)
+            | Token::Normal(NormalToken::Comma)
+            | Token::Normal(NormalToken::Semicolon)
+            | Token::Normal(NormalToken::In)
+            | Token::Normal(NormalToken::Then)
+            | Token::Normal(NormalToken::Else)
+                if in_type && !in_enum =>
+            {
+                annotations.pop();
+            }
+            _ => (),
+        }
+
+        if annotations.is_empty() {
+            type_variables.clear();
+        }
+    }
+
+    highlights
+}
+
+/// Classify a token of the normal mode, except identifiers.
+fn classify_normal(token: &NormalToken, source: &str) -> Option<(TokenType, u32)> {
+    use NormalToken::*;
+
+    let token_type = match token {
+        LineComment => TokenType::Comment,
+        NumLiteral(_) => TokenType::Number,
+        DoubleQuote | MultiStringStart(_) => TokenType::String,
+        Dyn | Num | Bool | Str | Array => TokenType::Type,
+        If | Then | Else | Forall | In | Let | Rec | Switch | Fun | Import | Merge | Default
+        | Doc | Null | True | False => TokenType::Keyword,
+        Plus | Minus | Times | Div | Percent | DoublePlus | DoubleEq | NotEquals | DoubleAnd
+        | DoublePipe | Bang | Ampersand | RightPipe | LAngleBracket | RAngleBracket | LessOrEq
+        | GreaterOrEq => TokenType::Operator,
+        // The primitive operators, such as `%head%`
+        _ if source.len() > 1 && source.starts_with('%') && source.ends_with('%') => {
+            TokenType::Macro
+        }
+        _ => return None,
+    };
+
+    Some((token_type, 0))
+}
+
+/// Classify the identifiers of a document using its linearization.
+struct Classifier<'a> {
+    linearization: Option<&'a Completed>,
+    /// The items of the document indexed by their span.
+    items: HashMap<(usize, usize), &'a LinearizationItem<Resolved>>,
+    global_env: &'a Environment,
+    source: &'a str,
+}
+
+impl<'a> Classifier<'a> {
+    fn new(
+        linearization: Option<&'a Completed>,
+        global_env: &'a Environment,
+        source: &'a str,
+        file_id: FileId,
+    ) -> Self {
+        let items = linearization
+            .into_iter()
+            .flat_map(|linearization| linearization.linearization.iter())
+            .filter(|item| {
+                item.pos.src_id == file_id
+                    && matches!(
+                        item.kind,
+                        TermKind::Declaration(..)
+                            | TermKind::RecordField { .. }
+                            | TermKind::Usage(_)
+                    )
+            })
+            .map(|item| ((item.pos.start.to_usize(), item.pos.end.to_usize()), item))
+            .collect();
+
+        Classifier {
+            linearization,
+            items,
+            global_env,
+            source,
+        }
+    }
+
+    /// Classify an identifier spanning the bytes `start..end`.
+    fn identifier(&self, start: usize, end: usize, name: &str) -> Option<(TokenType, u32)> {
+        let linearization = self.linearization?;
+        let item = self.items.get(&(start, end))?;
+
+        match item.kind {
+            TermKind::Declaration(..) | TermKind::RecordField { .. } => {
+                let (token_type, modifiers) = self.declaration(item);
+                Some((token_type, modifiers | DECLARATION))
+            }
+            TermKind::Usage(UsageState::Resolved(declaration)) => linearization
+                .get_item(declaration)
+                .map(|declaration| self.declaration(declaration)),
+            // The modules of the standard library are not part of the linearization
+            TermKind::Usage(UsageState::Unbound) if self.is_stdlib(name) => {
+                Some((TokenType::Namespace, DEFAULT_LIBRARY))
+            }
+            TermKind::Usage(UsageState::Deferred { parent, .. }) => {
+                if self.is_stdlib_access(linearization, parent) {
+                    Some((TokenType::Function, DEFAULT_LIBRARY))
+                } else {
+                    Some((TokenType::Property, 0))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Classify a declaration or a record field, without the declaration modifier.
+    fn declaration(&self, item: &LinearizationItem<Resolved>) -> (TokenType, u32) {
+        let linearization = self.linearization.unwrap();
+        let value = linearization.value_item(item);
+
+        let token_type = match item.kind {
+            TermKind::RecordField { .. } => TokenType::Property,
+            TermKind::Declaration(..) => {
+                if is_parameter(item) {
+                    TokenType::Parameter
+                } else if matches!(item.ty.0, AbsType::Arrow(..))
+                    || matches!(value.ty.0, AbsType::Arrow(..))
+                    || linearization.is_function(value)
+                {
+                    TokenType::Function
+                } else {
+                    TokenType::Variable
+                }
+            }
+            _ => TokenType::Variable,
+        };
+
+        let mut modifiers = 0;
+        if let Some(meta) = linearization.item_meta(item) {
+            if meta.deprecated.is_some() {
+                modifiers |= DEPRECATED;
+            }
+            if meta.priority == MergePriority::Default {
+                modifiers |= DEFAULT_PRIORITY;
+            }
+        }
+
+        (token_type, modifiers)
+    }
+
+    fn is_stdlib(&self, name: &str) -> bool {
+        self.global_env.get(&Ident::from(name)).is_some()
+    }
+
+    /// Return if a chain of field accesses starts with a module of the standard library, as in
+    /// `array.map`.
+    fn is_stdlib_access(&self, linearization: &Completed, mut parent: usize) -> bool {
+        loop {
+            match linearization.get_item(parent) {
+                Some(LinearizationItem {
+                    kind: TermKind::Usage(UsageState::Deferred { parent: next, .. }),
+                    ..
+                }) => parent = *next,
+                Some(LinearizationItem {
+                    kind: TermKind::Usage(UsageState::Unbound),
+                    pos,
+                    ..
+                }) => {
+                    return self.is_stdlib(&self.source[pos.start.to_usize()..pos.end.to_usize()])
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        request::SemanticTokensFullRequest, PartialResultParams, SemanticTokensResult,
+        WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    /// Return the encoded semantic tokens of `source`, as tuples of the relative line, the
+    /// relative start, the length, the type and the modifiers.
+    fn tokens(source: &str) -> Vec<(u32, u32, u32, TokenType, u32)> {
+        let mut server = TestServer::new();
+        let text_document = server.open("main.ncl", source);
+        let tokens = match server.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            text_document,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }) {
+            Some(SemanticTokensResult::Tokens(tokens)) => tokens,
+            response => panic!("expected semantic tokens, got {:?}", response),
+        };
+
+        tokens
+            .data
+            .into_iter()
+            .map(|token| {
+                (
+                    token.delta_line,
+                    token.delta_start,
+                    token.length,
+                    TOKEN_TYPE_OF[token.token_type as usize],
+                    token.token_modifiers_bitset,
+                )
+            })
+            .collect()
+    }
+
+    const TOKEN_TYPE_OF: &[TokenType] = &[
+        TokenType::Namespace,
+        TokenType::Type,
+        TokenType::TypeParameter,
+        TokenType::Parameter,
+        TokenType::Variable,
+        TokenType::Property,
+        TokenType::EnumMember,
+        TokenType::Function,
+        TokenType::Macro,
+        TokenType::Keyword,
+        TokenType::Comment,
+        TokenType::String,
+        TokenType::Number,
+        TokenType::Operator,
+    ];
+
+    #[test]
+    fn encoded_tokens() {
+        let source = "let x = m%\"\n  é a\n\"%m in\nx ++ \"b\" # c";
+        assert_eq!(
+            tokens(source),
+            vec![
+                (0, 0, 3, TokenType::Keyword, 0),
+                (0, 4, 1, TokenType::Variable, DECLARATION),
+                (0, 4, 3, TokenType::String, 0),
+                // A multiline string is split into one token per line, and empty lines are
+                // skipped. Lengths count UTF-16 code units: `é` is two bytes long, but one unit.
+                (1, 0, 5, TokenType::String, 0),
+                (1, 0, 3, TokenType::String, 0),
+                (0, 4, 2, TokenType::Keyword, 0),
+                (1, 0, 1, TokenType::Variable, 0),
+                (0, 2, 2, TokenType::Operator, 0),
+                (0, 3, 1, TokenType::String, 0),
+                (0, 1, 1, TokenType::String, 0),
+                (0, 1, 1, TokenType::String, 0),
+                (0, 2, 3, TokenType::Comment, 0),
+            ]
+        );
+    }
+}
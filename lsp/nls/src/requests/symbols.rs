@@ -1,10 +1,14 @@
 use crate::{
-    linearization::interface::TermKind,
+    cache::CacheExt,
+    linearization::{completed::is_parameter, interface::TermKind},
+    requests::goto::location_of,
     term::RawSpanExt,
     trace::{Enrich, Trace},
 };
 use lsp_server::{RequestId, Response, ResponseError};
-use lsp_types::{DocumentSymbol, DocumentSymbolParams, SymbolKind};
+use lsp_types::{
+    DocumentSymbol, DocumentSymbolParams, SymbolInformation, SymbolKind, WorkspaceSymbolParams,
+};
 use serde_json::Value;
 
 use crate::server::Server;
@@ -14,10 +18,7 @@ pub fn handle_document_symbols(
     id: RequestId,
     server: &mut Server,
 ) -> Result<(), ResponseError> {
-    let file_id = server
-        .cache
-        .id_of(params.text_document.uri.to_string())
-        .unwrap();
+    let file_id = server.cache.id_of_uri(&params.text_document.uri).unwrap();
 
     if let Some(completed) = server.lin_cache.get(&file_id) {
         Trace::enrich(&id, completed);
@@ -56,3 +57,108 @@ pub fn handle_document_symbols(
 
     Ok(())
 }
+
+/// Search the bindings and the record fields of all the analyzed files, which include the files of
+/// the workspace folders once they have been indexed. Function parameters are left out.
+pub fn handle_workspace_symbols(
+    params: WorkspaceSymbolParams,
+    id: RequestId,
+    server: &mut Server,
+) -> Result<(), ResponseError> {
+    let mut symbols = Vec::new();
+
+    for linearization in server.lin_cache.values() {
+        for item in linearization.linearization.iter() {
+            let (name, kind) = match &item.kind {
+                TermKind::Declaration(name, _, _) if !is_parameter(item) => {
+                    let kind = if linearization.is_function(linearization.value_item(item)) {
+                        SymbolKind::Function
+                    } else {
+                        SymbolKind::Variable
+                    };
+                    (name, kind)
+                }
+                TermKind::RecordField { ident, .. } => (ident, SymbolKind::Field),
+                _ => continue,
+            };
+
+            if !matches_query(&name.label, &params.query) {
+                continue;
+            }
+
+            if let Some(location) = location_of(server, item.pos) {
+                // `deprecated` is a required field but causes a warning although we are not using it
+                #[allow(deprecated)]
+                symbols.push(SymbolInformation {
+                    name: name.to_string(),
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    location,
+                    container_name: None,
+                });
+            }
+        }
+    }
+
+    symbols.sort_by(|a, b| {
+        (a.location.uri.as_str(), a.location.range.start)
+            .cmp(&(b.location.uri.as_str(), b.location.range.start))
+    });
+
+    server.reply(Response::new_ok(id, symbols));
+    Ok(())
+}
+
+/// Return if the characters of a query appear in order in a name, ignoring case, such that
+/// `mkcfg` matches `make_config`.
+fn matches_query(name: &str, query: &str) -> bool {
+    let mut chars = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| chars.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{request::WorkspaceSymbol, PartialResultParams, WorkDoneProgressParams};
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[test]
+    fn workspace_symbols() {
+        let mut server = TestServer::new();
+        server.open(
+            "a.ncl",
+            "let make_config = fun x => x in {config = make_config 1}",
+        );
+        server.open("b.ncl", "let make_cfg = 1 in {mkcfg = make_cfg, other = 2}");
+
+        let symbols: Vec<_> = server
+            .request::<WorkspaceSymbol>(WorkspaceSymbolParams {
+                query: String::from("mkcfg"),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|symbol| {
+                let uri = symbol.location.uri.to_string();
+                (symbol.name, symbol.kind, uri)
+            })
+            .collect();
+
+        let a = TestServer::uri("a.ncl").to_string();
+        let b = TestServer::uri("b.ncl").to_string();
+        assert_eq!(
+            symbols,
+            vec![
+                (String::from("make_config"), SymbolKind::Function, a),
+                (String::from("make_cfg"), SymbolKind::Variable, b.clone()),
+                (String::from("mkcfg"), SymbolKind::Field, b),
+            ]
+        );
+    }
+}
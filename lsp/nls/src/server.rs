@@ -1,44 +1,100 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use codespan::FileId;
+use crossbeam_channel::{after, never, select};
 use log::{debug, trace, warn};
 use lsp_server::{
     Connection, ErrorCode, Message, Notification, RequestId, Response, ResponseError,
 };
 use lsp_types::{
     notification::Notification as _,
-    notification::{DidChangeTextDocument, DidOpenTextDocument},
+    notification::{
+        DidChangeConfiguration, DidChangeTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    },
     request::{Request as RequestTrait, *},
-    CompletionOptions, CompletionParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
-    DocumentSymbolParams, GotoDefinitionParams, HoverOptions, HoverParams, HoverProviderCapability,
-    OneOf, ReferenceParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
-    TextDocumentSyncOptions, WorkDoneProgressOptions,
+    CallHierarchyIncomingCallsParams, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CallHierarchyServerCapability, CodeActionKind, CodeActionOptions, CodeActionParams,
+    CodeActionProviderCapability, CompletionOptions, CompletionParams,
+    DidChangeConfigurationParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, DocumentFormattingParams, DocumentHighlightParams,
+    DocumentRangeFormattingParams, DocumentSymbolParams, FoldingRangeParams,
+    FoldingRangeProviderCapability, GotoDefinitionParams, HoverOptions, HoverParams,
+    HoverProviderCapability, OneOf, ReferenceParams, RenameOptions, RenameParams,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensServerCapabilities, ServerCapabilities,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind,
+    TextDocumentSyncOptions, TextDocumentSyncSaveOptions, WorkDoneProgressOptions,
+    WorkspaceSymbolParams,
 };
 
 use nickel_lang::cache::Cache;
 use nickel_lang::typecheck::Environment;
+use serde_json::Value;
 
 use crate::{
+    config::Config,
+    eval::{Evaluated, Evaluator},
     linearization::completed::Completed,
-    requests::{completion, goto, hover, symbols},
+    requests::{
+        call_hierarchy, code_actions, completion, folding, formatting, goto, hover,
+        inlay_hints::{self, InlayHintParams, InlayHintRequest},
+        rename, semantic_tokens, symbols,
+    },
     trace::Trace,
 };
 
+/// The delay without any change after which the files modified in the editor are analyzed again.
+const ANALYSIS_DEBOUNCE: Duration = Duration::from_millis(200);
+
 pub struct Server {
     pub connection: Connection,
     pub cache: Cache,
     pub lin_cache: HashMap<FileId, Completed>,
     pub global_env: Environment,
+    pub config: Config,
+    /// The files which have changed since their last analysis.
+    pending_analysis: HashSet<FileId>,
+    /// The time at which the pending files are analyzed, if no other change occurs before.
+    analysis_deadline: Option<Instant>,
+    /// The files of the workspace folders which remain to be indexed.
+    pending_index: Vec<PathBuf>,
+    /// The documents opened in the editor.
+    pub documents: HashSet<FileId>,
+    /// The evaluation thread, started by the first evaluation of a document.
+    evaluator: Option<Evaluator>,
+}
+
+/// The events waited for by the main loop.
+enum Event {
+    Message(Message),
+    Evaluated(Evaluated),
+    /// The deadline of the analysis has passed, or the server is idle.
+    Timeout,
+    /// The evaluation thread has stopped, and is started again by the next evaluation.
+    EvaluatorStopped,
+    Disconnected,
 }
 
 impl Server {
-    pub fn capabilities() -> ServerCapabilities {
+    /// The capabilities of the server, including the ones which are not covered by `lsp_types`.
+    pub fn capabilities() -> Value {
+        let mut capabilities = serde_json::to_value(Self::lsp_capabilities()).unwrap();
+        capabilities["inlayHintProvider"] = Value::Bool(true);
+        capabilities
+    }
+
+    fn lsp_capabilities() -> ServerCapabilities {
         ServerCapabilities {
             text_document_sync: Some(TextDocumentSyncCapability::Options(
                 TextDocumentSyncOptions {
                     open_close: Some(true),
-                    change: Some(TextDocumentSyncKind::Full),
+                    change: Some(TextDocumentSyncKind::Incremental),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
                     ..TextDocumentSyncOptions::default()
                 },
             )),
@@ -50,15 +106,42 @@ impl Server {
             definition_provider: Some(OneOf::Left(true)),
             references_provider: Some(OneOf::Left(true)),
             completion_provider: Some(CompletionOptions {
-                trigger_characters: Some(vec![]),
+                trigger_characters: Some(vec![".".to_owned()]),
                 ..Default::default()
             }),
             document_symbol_provider: Some(OneOf::Left(true)),
+            workspace_symbol_provider: Some(OneOf::Left(true)),
+            document_highlight_provider: Some(OneOf::Left(true)),
+            call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+            rename_provider: Some(OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: Some(false),
+                },
+            })),
+            document_formatting_provider: Some(OneOf::Left(true)),
+            document_range_formatting_provider: Some(OneOf::Left(true)),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: semantic_tokens::legend(),
+                    range: Some(true),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                    ..Default::default()
+                }),
+            ),
+            code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: Some(false),
+                },
+                resolve_provider: None,
+            })),
             ..ServerCapabilities::default()
         }
     }
 
-    pub fn new(connection: Connection) -> Server {
+    pub fn new(connection: Connection, config: Config) -> Server {
         let mut cache = Cache::new();
         cache.load_stdlib().unwrap();
         let global_env = cache.mk_types_env().unwrap();
@@ -68,6 +151,55 @@ impl Server {
             cache,
             lin_cache,
             global_env,
+            config,
+            pending_analysis: HashSet::new(),
+            analysis_deadline: None,
+            pending_index: Vec::new(),
+            documents: HashSet::new(),
+            evaluator: None,
+        }
+    }
+
+    /// Schedule the indexing of the Nickel files of the workspace folders, which is performed
+    /// while the server is idle.
+    pub fn index_workspace(&mut self, roots: &[PathBuf]) {
+        self.pending_index = crate::workspace::nickel_files(roots);
+        // Files are popped from the end of the queue
+        self.pending_index.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    /// Schedule the analysis of a file which has changed. The deadline is pushed back at each
+    /// change, such that a burst of changes only triggers one analysis.
+    pub(crate) fn schedule_analysis(&mut self, file_id: FileId) {
+        self.pending_analysis.insert(file_id);
+        self.analysis_deadline = Some(Instant::now() + ANALYSIS_DEBOUNCE);
+    }
+
+    /// Return the evaluation thread, which is started if needed.
+    pub(crate) fn evaluator(&mut self) -> &Evaluator {
+        self.evaluator.get_or_insert_with(Evaluator::new)
+    }
+
+    /// Cancel the evaluation in progress, if any, as a document has changed since.
+    pub(crate) fn cancel_evaluation(&self) {
+        if let Some(evaluator) = &self.evaluator {
+            evaluator.cancel();
+        }
+    }
+
+    /// Analyze the files which have changed since their last analysis.
+    pub(crate) fn analyze_pending(&mut self) {
+        self.analysis_deadline = None;
+
+        let pending = std::mem::take(&mut self.pending_analysis);
+        // A modified file has to be parsed again before its importers are typechecked.
+        for file_id in pending.iter() {
+            crate::files::prepare(self, *file_id);
+        }
+        for file_id in pending {
+            if let Err(err) = crate::files::analyze(self, file_id) {
+                warn!("{}", err);
+            }
         }
     }
 
@@ -105,9 +237,63 @@ impl Server {
         ));
     }
 
+    /// Wait for the next event. The workspace is indexed one file at a time, as long as no message
+    /// is waiting.
+    fn next_event(&self) -> Event {
+        let timeout = match self.analysis_deadline {
+            Some(deadline) => Some(deadline.saturating_duration_since(Instant::now())),
+            None if !self.pending_index.is_empty() => Some(Duration::ZERO),
+            None => None,
+        };
+        let timer = timeout.map(after).unwrap_or_else(never);
+        let evaluated = self
+            .evaluator
+            .as_ref()
+            .map(|evaluator| evaluator.results().clone())
+            .unwrap_or_else(never);
+
+        select! {
+            recv(self.connection.receiver) -> msg => match msg {
+                Ok(msg) => Event::Message(msg),
+                Err(_) => Event::Disconnected,
+            },
+            recv(evaluated) -> evaluated => match evaluated {
+                Ok(evaluated) => Event::Evaluated(evaluated),
+                Err(_) => Event::EvaluatorStopped,
+            },
+            recv(timer) -> _ => Event::Timeout,
+        }
+    }
+
     pub fn run(&mut self) -> Result<()> {
         trace!("Running...");
-        while let Ok(msg) = self.connection.receiver.recv() {
+        loop {
+            let msg = match self.next_event() {
+                Event::Message(msg) => msg,
+                Event::Evaluated(evaluated) => {
+                    crate::files::publish_evaluation(self, evaluated);
+                    continue;
+                }
+                Event::Timeout if self.analysis_deadline.is_some() => {
+                    self.analyze_pending();
+                    continue;
+                }
+                Event::Timeout => {
+                    if self.connection.receiver.is_empty() {
+                        if let Some(path) = self.pending_index.pop() {
+                            crate::workspace::index(self, path);
+                        }
+                    }
+                    continue;
+                }
+                Event::EvaluatorStopped => {
+                    warn!("the evaluation thread has stopped");
+                    self.evaluator = None;
+                    continue;
+                }
+                Event::Disconnected => break,
+            };
+
             trace!("Message: {:#?}", msg);
             match msg {
                 Message::Request(req) => {
@@ -135,7 +321,7 @@ impl Server {
         Ok(())
     }
 
-    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+    pub(crate) fn handle_notification(&mut self, notification: Notification) -> Result<()> {
         match notification.method.as_str() {
             DidOpenTextDocument::METHOD => {
                 trace!("handle open notification");
@@ -145,17 +331,36 @@ impl Server {
                 )
             }
             DidChangeTextDocument::METHOD => {
+                trace!("handle change notification");
+                crate::files::handle_change(
+                    self,
+                    serde_json::from_value::<DidChangeTextDocumentParams>(notification.params)?,
+                )
+            }
+            DidSaveTextDocument::METHOD => {
                 trace!("handle save notification");
+                // Evaluation is performed on the latest version of the document
+                self.analyze_pending();
                 crate::files::handle_save(
                     self,
-                    serde_json::from_value::<DidChangeTextDocumentParams>(notification.params)?,
+                    serde_json::from_value::<DidSaveTextDocumentParams>(notification.params)?,
                 )
             }
+            DidChangeConfiguration::METHOD => {
+                trace!("handle configuration change notification");
+                let params: DidChangeConfigurationParams =
+                    serde_json::from_value(notification.params)?;
+                self.config = Config::from_json(params.settings);
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
 
-    fn handle_request(&mut self, req: lsp_server::Request) -> Result<()> {
+    pub(crate) fn handle_request(&mut self, req: lsp_server::Request) -> Result<()> {
+        // Requests are answered based on the linearization, which must be up to date.
+        self.analyze_pending();
+
         Trace::receive(req.id.clone(), req.method.clone());
 
         let res = match req.method.as_str() {
@@ -188,6 +393,95 @@ impl Server {
                 symbols::handle_document_symbols(params, req.id.clone(), self)
             }
 
+            WorkspaceSymbol::METHOD => {
+                debug!("handle workspace symbols");
+                let params: WorkspaceSymbolParams = serde_json::from_value(req.params).unwrap();
+                symbols::handle_workspace_symbols(params, req.id.clone(), self)
+            }
+
+            DocumentHighlightRequest::METHOD => {
+                debug!("handle document highlights");
+                let params: DocumentHighlightParams = serde_json::from_value(req.params).unwrap();
+                goto::handle_document_highlight(params, req.id.clone(), self)
+            }
+
+            CallHierarchyPrepare::METHOD => {
+                debug!("handle call hierarchy preparation");
+                let params: CallHierarchyPrepareParams =
+                    serde_json::from_value(req.params).unwrap();
+                call_hierarchy::handle_prepare(params, req.id.clone(), self)
+            }
+
+            CallHierarchyIncomingCalls::METHOD => {
+                debug!("handle incoming calls");
+                let params: CallHierarchyIncomingCallsParams =
+                    serde_json::from_value(req.params).unwrap();
+                call_hierarchy::handle_incoming_calls(params, req.id.clone(), self)
+            }
+
+            CallHierarchyOutgoingCalls::METHOD => {
+                debug!("handle outgoing calls");
+                let params: CallHierarchyOutgoingCallsParams =
+                    serde_json::from_value(req.params).unwrap();
+                call_hierarchy::handle_outgoing_calls(params, req.id.clone(), self)
+            }
+
+            PrepareRenameRequest::METHOD => {
+                debug!("handle prepare rename");
+                let params: TextDocumentPositionParams =
+                    serde_json::from_value(req.params).unwrap();
+                rename::handle_prepare_rename(params, req.id.clone(), self)
+            }
+
+            Rename::METHOD => {
+                debug!("handle rename");
+                let params: RenameParams = serde_json::from_value(req.params).unwrap();
+                rename::handle_rename(params, req.id.clone(), self)
+            }
+
+            InlayHintRequest::METHOD => {
+                debug!("handle inlay hints");
+                let params: InlayHintParams = serde_json::from_value(req.params).unwrap();
+                inlay_hints::handle_inlay_hints(params, req.id.clone(), self)
+            }
+
+            Formatting::METHOD => {
+                debug!("handle formatting");
+                let params: DocumentFormattingParams = serde_json::from_value(req.params).unwrap();
+                formatting::handle_format_document(params, req.id.clone(), self)
+            }
+
+            RangeFormatting::METHOD => {
+                debug!("handle range formatting");
+                let params: DocumentRangeFormattingParams =
+                    serde_json::from_value(req.params).unwrap();
+                formatting::handle_format_range(params, req.id.clone(), self)
+            }
+
+            FoldingRangeRequest::METHOD => {
+                debug!("handle folding ranges");
+                let params: FoldingRangeParams = serde_json::from_value(req.params).unwrap();
+                folding::handle_folding_ranges(params, req.id.clone(), self)
+            }
+
+            SemanticTokensFullRequest::METHOD => {
+                debug!("handle semantic tokens");
+                let params: SemanticTokensParams = serde_json::from_value(req.params).unwrap();
+                semantic_tokens::handle_semantic_tokens_full(params, req.id.clone(), self)
+            }
+
+            SemanticTokensRangeRequest::METHOD => {
+                debug!("handle semantic tokens of a range");
+                let params: SemanticTokensRangeParams = serde_json::from_value(req.params).unwrap();
+                semantic_tokens::handle_semantic_tokens_range(params, req.id.clone(), self)
+            }
+
+            CodeActionRequest::METHOD => {
+                debug!("handle code actions");
+                let params: CodeActionParams = serde_json::from_value(req.params).unwrap();
+                code_actions::handle_code_action(params, req.id.clone(), self)
+            }
+
             _ => Ok(()),
         };
 
@@ -209,3 +503,124 @@ impl Server {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use lsp_types::{
+        DocumentSymbolResponse, PublishDiagnosticsParams, TextDocumentContentChangeEvent,
+        TextDocumentItem, Url, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+    use crate::testing::TestServer;
+
+    fn change(uri: &Url, version: i32, text: &str) -> DidChangeTextDocumentParams {
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version,
+            },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: String::from(text),
+            }],
+        }
+    }
+
+    #[test]
+    fn changes_are_analyzed_before_requests() {
+        let mut server = TestServer::new();
+        let doc = server.open("main.ncl", "let a = 1 in a");
+        assert_eq!(server.published_diagnostics().len(), 1);
+
+        server
+            .notify::<DidChangeTextDocument>(change(&doc.uri, 1, "let a = 1 + in a"))
+            .unwrap();
+        server
+            .notify::<DidChangeTextDocument>(change(&doc.uri, 2, "let b = 1 + 1 in b"))
+            .unwrap();
+        assert!(server.published_diagnostics().is_empty());
+
+        let symbols = server.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: doc,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let published = server.published_diagnostics();
+        assert_eq!(published.len(), 1);
+        assert!(published[0].diagnostics.is_empty());
+        match symbols {
+            Some(DocumentSymbolResponse::Nested(symbols)) => {
+                let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+                assert_eq!(names, vec!["b"]);
+            }
+            symbols => panic!("unexpected symbols {:?}", symbols),
+        }
+    }
+
+    #[test]
+    fn changes_are_debounced() {
+        let (connection, client) = Connection::memory();
+        let server = thread::spawn(move || Server::new(connection, Config::default()).run());
+        let receive_diagnostics = || loop {
+            match client
+                .receiver
+                .recv_timeout(Duration::from_secs(30))
+                .unwrap()
+            {
+                Message::Notification(notification)
+                    if notification.method == "textDocument/publishDiagnostics" =>
+                {
+                    break serde_json::from_value::<PublishDiagnosticsParams>(notification.params)
+                        .unwrap()
+                }
+                _ => (),
+            }
+        };
+        let notify = |method: &str, params| {
+            client
+                .sender
+                .send(Message::Notification(Notification::new(
+                    String::from(method),
+                    params,
+                )))
+                .unwrap()
+        };
+
+        let uri = TestServer::uri("main.ncl");
+        notify(
+            DidOpenTextDocument::METHOD,
+            serde_json::to_value(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: String::from("nickel"),
+                    version: 0,
+                    text: String::from("1"),
+                },
+            })
+            .unwrap(),
+        );
+        assert!(receive_diagnostics().diagnostics.is_empty());
+
+        let sent = Instant::now();
+        for (version, text) in [(1, "1 + 1"), (2, "1 +"), (3, "1 + )")] {
+            notify(
+                DidChangeTextDocument::METHOD,
+                serde_json::to_value(change(&uri, version, text)).unwrap(),
+            );
+        }
+        // Only the last version is analyzed, once no change has been received for a while
+        let diagnostics = receive_diagnostics();
+        assert!(sent.elapsed() >= ANALYSIS_DEBOUNCE);
+        assert!(!diagnostics.diagnostics.is_empty());
+        assert!(client.receiver.recv_timeout(ANALYSIS_DEBOUNCE * 2).is_err());
+
+        let shutdown = lsp_server::Request::new(RequestId::from(1), Shutdown::METHOD.into(), ());
+        client.sender.send(Message::Request(shutdown)).unwrap();
+        notify("exit", Value::Null);
+        server.join().unwrap().unwrap();
+    }
+}
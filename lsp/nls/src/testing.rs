@@ -0,0 +1,112 @@
+//! Helpers to test the server. Documents are opened and requests are sent as an editor would do,
+//! and the messages of the server are read back from an in-memory connection.
+
+use anyhow::Result;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, ResponseError};
+use lsp_types::{
+    notification::{DidOpenTextDocument, Notification as NotificationTrait},
+    request::Request as RequestTrait,
+    DidOpenTextDocumentParams, PublishDiagnosticsParams, TextDocumentIdentifier, TextDocumentItem,
+    Url,
+};
+
+use crate::{config::Config, server::Server};
+
+pub struct TestServer {
+    pub server: Server,
+    /// The other end of the connection of the server, playing the role of the editor.
+    client: Connection,
+    /// The messages received while waiting for the response to a request.
+    received: Vec<Message>,
+    next_id: i32,
+}
+
+impl TestServer {
+    pub fn new() -> TestServer {
+        let (connection, client) = Connection::memory();
+        TestServer {
+            server: Server::new(connection, Config::default()),
+            client,
+            received: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Return the URI of an in-memory document, which doesn't need to exist on disk.
+    pub fn uri(name: &str) -> Url {
+        Url::parse(&format!("file:///nls-test/{}", name)).unwrap()
+    }
+
+    /// Send a notification to the server, and return the result of handling it.
+    pub fn notify<N: NotificationTrait>(&mut self, params: N::Params) -> Result<()> {
+        self.server
+            .handle_notification(Notification::new(N::METHOD.to_owned(), params))
+    }
+
+    /// Open a document in the server, and return its identifier.
+    pub fn open(&mut self, name: &str, text: &str) -> TextDocumentIdentifier {
+        let uri = Self::uri(name);
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: String::from("nickel"),
+                version: 0,
+                text: String::from(text),
+            },
+        })
+        .unwrap();
+        TextDocumentIdentifier { uri }
+    }
+
+    /// Send a request to the server and return the result of its response. Panic if the server
+    /// answers with an error or doesn't answer.
+    pub fn request<R: RequestTrait>(&mut self, params: R::Params) -> R::Result {
+        self.try_request::<R>(params)
+            .unwrap_or_else(|error| panic!("{} failed: {}", R::METHOD, error.message))
+    }
+
+    /// Send a request to the server and return its response, which may be an error. Panic if the
+    /// server doesn't answer.
+    pub fn try_request<R: RequestTrait>(
+        &mut self,
+        params: R::Params,
+    ) -> Result<R::Result, ResponseError> {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        self.server
+            .handle_request(Request::new(id.clone(), R::METHOD.to_owned(), params))
+            .unwrap();
+
+        while let Ok(message) = self.client.receiver.try_recv() {
+            match message {
+                Message::Response(response) if response.id == id => {
+                    if let Some(error) = response.error {
+                        return Err(error);
+                    }
+                    let result = response.result.unwrap_or(serde_json::Value::Null);
+                    return Ok(serde_json::from_value(result).unwrap());
+                }
+                message => self.received.push(message),
+            }
+        }
+        panic!("no response to {}", R::METHOD)
+    }
+
+    /// Return the diagnostics published by the server since the last call, discarding the other
+    /// messages.
+    pub fn published_diagnostics(&mut self) -> Vec<PublishDiagnosticsParams> {
+        let received = std::mem::take(&mut self.received);
+        received
+            .into_iter()
+            .chain(self.client.receiver.try_iter())
+            .filter_map(|message| match message {
+                Message::Notification(notification)
+                    if notification.method == "textDocument/publishDiagnostics" =>
+                {
+                    serde_json::from_value(notification.params).ok()
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,71 @@
+//! Indexing of the workspace folders.
+//!
+//! The Nickel files of the workspace folders are analyzed in the background, one at a time when
+//! no message is waiting, such that workspace-wide requests also cover the files which are not
+//! opened in the editor.
+
+use std::{fs, path::PathBuf};
+
+use log::{debug, warn};
+use nickel_lang::cache::CacheOp;
+
+use crate::{cache::CacheExt, server::Server};
+
+/// Return the Nickel files contained in a list of folders. Hidden files and folders, such as
+/// `.git`, are skipped.
+pub fn nickel_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut folders = roots.to_vec();
+
+    while let Some(folder) = folders.pop() {
+        let entries = match fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Cannot index {}: {}", folder.display(), err);
+                continue;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && !hidden => folders.push(path),
+                Ok(file_type)
+                    if file_type.is_file()
+                        && !hidden
+                        && path.extension().and_then(|ext| ext.to_str()) == Some("ncl") =>
+                {
+                    files.push(path)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    files
+}
+
+/// Load and analyze a file of the workspace, without publishing its diagnostics. Files which have
+/// already been analyzed, such as the documents opened in the editor or the files they import,
+/// are skipped.
+pub fn index(server: &mut Server, path: PathBuf) {
+    let file_id = match server.cache.get_or_add_file(&path) {
+        Ok(CacheOp::Done(file_id)) | Ok(CacheOp::Cached(file_id)) => file_id,
+        Err(err) => {
+            warn!("Cannot index {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    if server.lin_cache.contains_key(&file_id) {
+        return;
+    }
+
+    debug!("Indexing {}", path.display());
+    crate::files::prepare(server, file_id);
+    let _ =
+        server
+            .cache
+            .typecheck_with_analysis(file_id, &server.global_env, &mut server.lin_cache);
+}
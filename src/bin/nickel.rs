@@ -1,13 +1,19 @@
 //! Entry point of the program.
+use nickel_lang::dap;
+use nickel_lang::diagnostic::ErrorFormat;
+use nickel_lang::diff::{self, DiffFormat};
 use nickel_lang::error::{Error, IOError};
-use nickel_lang::program::Program;
+use nickel_lang::error_codes;
+use nickel_lang::identifier::Ident;
+use nickel_lang::lint::{Lint, LintConfig};
+use nickel_lang::program::{Program, ProgramArg};
 use nickel_lang::repl::query_print;
 #[cfg(feature = "repl")]
 use nickel_lang::repl::rustyline_frontend;
 use nickel_lang::term::{RichTerm, Term};
 use nickel_lang::{serialize, serialize::ExportFormat};
 use std::path::PathBuf;
-use std::{fs, process};
+use std::{fs, io, process};
 // use std::ffi::OsStr;
 use directories::BaseDirs;
 use structopt::StructOpt;
@@ -19,6 +25,29 @@ struct Opt {
     #[structopt(short = "f", long, global = true, parse(from_os_str))]
     file: Option<PathBuf>,
 
+    /// Format of the reported errors. Available formats: `text, json, sarif`. Default format:
+    /// `text`.
+    #[structopt(long, global = true)]
+    error_format: Option<ErrorFormat>,
+
+    /// Allows the program to read this environment variable. Can be repeated
+    #[structopt(long = "allow-env", global = true, number_of_values = 1)]
+    allow_env: Vec<String>,
+
+    /// Turns warnings, such as the use of deprecated fields, into errors
+    #[structopt(long, global = true)]
+    deny_warnings: bool,
+
+    /// Silences the traces printed by `builtin.trace` and `builtin.trace_value`, for example for
+    /// production exports
+    #[structopt(long, global = true)]
+    silence_traces: bool,
+
+    /// Writes the environment variables read during evaluation, together with their value, to this
+    /// file as a JSON object
+    #[structopt(long, global = true, parse(from_os_str))]
+    env_deps: Option<PathBuf>,
+
     #[cfg(debug_assertions)]
     /// Skips the standard library import. For debugging only. This does not affect REPL
     #[structopt(long)]
@@ -43,10 +72,44 @@ enum Command {
         /// Available formats: `raw, json, yaml, toml`. Default format: `json`.
         #[structopt(long)]
         format: Option<ExportFormat>,
-        /// Output file. Standard output by default
+        /// Output file, or output directory with `--multi-file`. Standard output, respectively
+        /// the current directory, by default
         #[structopt(short = "o", long)]
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
+        /// Exports a top-level array as a stream of YAML documents separated by `---`. Implies
+        /// `--format yaml`
+        #[structopt(long, conflicts_with_all = &["format", "multi-file"])]
+        multi_doc: bool,
+        /// Exports a top-level record mapping relative paths to values as multiple files. The
+        /// format of each file is inferred from its extension: `.json`, `.yaml`, `.yml` and
+        /// `.toml` are recognized, and other files are exported in the raw format
+        #[structopt(long, conflicts_with = "format")]
+        multi_file: bool,
+        /// Argument of a top-level function program, given as `<name>=<Nickel expression>`. Can
+        /// be repeated
+        #[structopt(long = "arg", number_of_values = 1, parse(try_from_str = parse_arg))]
+        args: Vec<(String, String)>,
+        /// String argument of a top-level function program, given as `<name>=<string>`. Can be
+        /// repeated
+        #[structopt(long = "arg-str", number_of_values = 1, parse(try_from_str = parse_arg))]
+        str_args: Vec<(String, String)>,
+        /// Profiles the evaluation: prints the time spent and the number of calls, thunk forcings
+        /// and contract applications per source location, and writes the sampled stacks in the
+        /// folded format used by flamegraph tools to the file given by `--profile-output`
+        #[structopt(long)]
+        profile: bool,
+        /// Output file of the folded stacks with `--profile`. `nickel.folded` by default
+        #[structopt(long, requires = "profile", parse(from_os_str))]
+        profile_output: Option<PathBuf>,
+        /// Reports the contract violations, missing definitions and merge conflicts of all the
+        /// fields of the result, instead of stopping at the first one. The fields are evaluated
+        /// independently, and the export still fails if any error is found
+        #[structopt(long)]
+        all_errors: bool,
+        /// The maximum number of errors reported with `--all-errors`, at least 1. 20 by default
+        #[structopt(long, requires = "all-errors", parse(try_from_str = parse_max_errors))]
+        max_errors: Option<usize>,
     },
     /// Prints the metadata attached to an attribute, given as a path
     Query {
@@ -61,9 +124,47 @@ enum Command {
         default: bool,
         #[structopt(long)]
         value: bool,
+        #[structopt(long)]
+        deprecated: bool,
     },
     /// Typechecks the program but do not run it
     Typecheck,
+    /// Reports suspicious code as warnings, without running the program. Available rules:
+    /// `unused-binding, unused-import, shadowing, unreachable-arm, duplicate-field`. All rules
+    /// except `shadowing` are enabled by default
+    Lint {
+        /// Enables a rule. Can be repeated
+        #[structopt(long, number_of_values = 1)]
+        enable: Vec<Lint>,
+        /// Disables a rule. Can be repeated
+        #[structopt(long, number_of_values = 1)]
+        disable: Vec<Lint>,
+    },
+    /// Evaluates two programs and prints the differences between their results
+    Diff {
+        /// The original program
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        /// The modified program
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+        /// Compare the elements of arrays of records by the value of this field instead of by
+        /// index
+        #[structopt(long)]
+        key: Option<String>,
+        /// Available formats: `text, json`. Default format: `text`.
+        #[structopt(long)]
+        format: Option<DiffFormat>,
+    },
+    /// Prints a detailed explanation of an error code
+    Explain {
+        /// The error code, such as `E0301`
+        code: String,
+    },
+    /// Starts a debug adapter, communicating with an editor over the standard input and output
+    /// using the Debug Adapter Protocol. The program to debug is given by the `launch` request, or
+    /// by `--file`
+    Debug,
     /// Starts an REPL session
     Repl {
         #[structopt(long)]
@@ -74,8 +175,41 @@ enum Command {
     Doc {},
 }
 
+/// The options of the command line which configure the evaluation of a program, shared by all the
+/// subcommands evaluating programs.
+struct EvalOptions {
+    allow_env: Vec<String>,
+    deny_warnings: bool,
+    silence_traces: bool,
+    #[cfg(debug_assertions)]
+    nostdlib: bool,
+}
+
+impl EvalOptions {
+    fn apply(&self, program: &mut Program) {
+        for name in &self.allow_env {
+            program.allow_env(name.clone());
+        }
+        program.set_deny_warnings(self.deny_warnings);
+        program.set_silence_traces(self.silence_traces);
+
+        #[cfg(debug_assertions)]
+        if self.nostdlib {
+            program.set_skip_stdlib();
+        }
+    }
+}
+
 fn main() {
     let opts = Opt::from_args();
+    let error_format = opts.error_format.unwrap_or(ErrorFormat::Text);
+    let eval_options = EvalOptions {
+        allow_env: opts.allow_env,
+        deny_warnings: opts.deny_warnings,
+        silence_traces: opts.silence_traces,
+        #[cfg(debug_assertions)]
+        nostdlib: opts.nostdlib,
+    };
 
     if let Some(Command::Repl { history_file }) = opts.command {
         let histfile = if let Some(h) = history_file {
@@ -93,6 +227,34 @@ fn main() {
 
         #[cfg(not(feature = "repl"))]
         eprintln!("error: this executable was not compiled with REPL support");
+    } else if let Some(Command::Diff {
+        old,
+        new,
+        key,
+        format,
+    }) = opts.command
+    {
+        diff(
+            old,
+            new,
+            key,
+            format.unwrap_or(DiffFormat::Text),
+            error_format,
+            &eval_options,
+        );
+    } else if let Some(Command::Explain { code }) = opts.command {
+        match error_codes::explain(&code) {
+            Some(explanation) => println!("{}", explanation),
+            None => {
+                eprintln!("error: unknown error code {}", code);
+                process::exit(1)
+            }
+        }
+    } else if let Some(Command::Debug) = opts.command {
+        if let Err(err) = dap::run(io::stdin(), io::stdout(), opts.file) {
+            eprintln!("error: {}", err);
+            process::exit(1)
+        }
     } else {
         let mut program = opts
             .file
@@ -103,17 +265,61 @@ fn main() {
                 process::exit(1)
             });
 
-        #[cfg(debug_assertions)]
-        if opts.nostdlib {
-            program.set_skip_stdlib();
-        }
+        eval_options.apply(&mut program);
+        let deny_warnings = eval_options.deny_warnings;
 
         let result = match opts.command {
             Some(Command::PprintAst { transform }) => program.expand(
                 &mut std::io::BufWriter::new(Box::new(std::io::stdout())),
                 transform,
             ),
-            Some(Command::Export { format, output }) => export(&mut program, format, output),
+            Some(Command::Export {
+                format,
+                output,
+                multi_doc,
+                multi_file,
+                args,
+                str_args,
+                profile,
+                profile_output,
+                all_errors,
+                max_errors,
+            }) => {
+                let args = args
+                    .into_iter()
+                    .map(|(name, expr)| (Ident::from(name), ProgramArg::Expr(expr)))
+                    .chain(
+                        str_args
+                            .into_iter()
+                            .map(|(name, s)| (Ident::from(name), ProgramArg::Str(s))),
+                    )
+                    .collect();
+                // The arguments are checked even if there are none, such that a function program
+                // reports its missing arguments
+                program.set_args(args);
+
+                let mode = if multi_doc {
+                    ExportMode::MultiDoc
+                } else if multi_file {
+                    ExportMode::MultiFile
+                } else {
+                    ExportMode::Single(format.unwrap_or_default())
+                };
+
+                if profile {
+                    program.enable_profiling();
+                }
+                let max_errors = all_errors.then(|| max_errors.unwrap_or(DEFAULT_MAX_ERRORS));
+                let result = export(&mut program, mode, output, max_errors);
+
+                // The profile of a failed evaluation is still worth looking at.
+                if profile {
+                    let output = profile_output.unwrap_or_else(|| PathBuf::from("nickel.folded"));
+                    result.and(write_profile(&program, output))
+                } else {
+                    result
+                }
+            }
             Some(Command::Query {
                 path,
                 doc,
@@ -121,10 +327,12 @@ fn main() {
                 types,
                 default,
                 value,
+                deprecated,
             }) => {
                 program.query(path).map(|term| {
                     // Print a default selection of attributes if no option is specified
-                    let attrs = if !doc && !contract && !types && !default && !value {
+                    let attrs = if !doc && !contract && !types && !default && !value && !deprecated
+                    {
                         query_print::Attributes::default()
                     } else {
                         query_print::Attributes {
@@ -133,6 +341,7 @@ fn main() {
                             types,
                             default,
                             value,
+                            deprecated,
                         }
                     };
 
@@ -140,7 +349,25 @@ fn main() {
                 })
             }
             Some(Command::Typecheck) => program.typecheck().map(|_| ()),
-            Some(Command::Repl { .. }) => unreachable!(),
+            Some(Command::Lint { enable, disable }) => {
+                let mut config = LintConfig::default();
+                enable.into_iter().for_each(|lint| config.enable(lint));
+                disable.into_iter().for_each(|lint| config.disable(lint));
+
+                program.lint(&config).map(|warnings| {
+                    if !warnings.is_empty() {
+                        program.report_as(warnings, error_format);
+
+                        if deny_warnings {
+                            process::exit(1)
+                        }
+                    }
+                })
+            }
+            Some(Command::Repl { .. })
+            | Some(Command::Diff { .. })
+            | Some(Command::Explain { .. })
+            | Some(Command::Debug) => unreachable!(),
             #[cfg(feature = "doc")]
             Some(Command::Doc { .. }) => program.output_doc(),
             None => program
@@ -148,29 +375,174 @@ fn main() {
                 .map(|t| println!("{}", Term::from(t).deep_repr())),
         };
 
+        let result = match (result, opts.env_deps) {
+            (Ok(()), Some(file)) => write_env_deps(&program, file),
+            (result, _) => result,
+        };
+
+        let warnings = program.warnings();
+        if !warnings.is_empty() && !matches!(result, Err(Error::DeniedWarnings(_))) {
+            program.report_as(warnings, error_format);
+        }
+
         if let Err(err) = result {
-            program.report(err);
+            program.report_as(err, error_format);
             process::exit(1)
         }
     }
 }
 
+/// Parse a `<name>=<value>` program argument.
+fn parse_arg(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!("expected `<name>=<value>`, got `{}`", s)),
+    }
+}
+
+fn parse_max_errors(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err(String::from("at least one error must be reported")),
+        Ok(max_errors) => Ok(max_errors),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// The default maximum number of errors reported by `export --all-errors`.
+const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// The shape of the output of an export.
+enum ExportMode {
+    /// A single output in the given format.
+    Single(ExportFormat),
+    /// A stream of YAML documents, one for each element of the top-level array.
+    MultiDoc,
+    /// Multiple files, one for each field of the top-level record.
+    MultiFile,
+}
+
+/// Export the result of a program. If `max_errors` is set, evaluation collects up to this number of
+/// contract violations instead of stopping at the first one.
 fn export(
     program: &mut Program,
-    format: Option<ExportFormat>,
+    mode: ExportMode,
     output: Option<PathBuf>,
+    max_errors: Option<usize>,
 ) -> Result<(), Error> {
-    let rt = program.eval_full().map(RichTerm::from)?;
-    let format = format.unwrap_or_default();
+    let rt = match max_errors {
+        Some(max_errors) => program.eval_full_collect(max_errors)?,
+        None => program.eval_full()?,
+    };
 
-    serialize::validate(format, &rt)?;
+    match mode {
+        ExportMode::Single(format) => {
+            serialize::validate(format, &rt)?;
 
-    if let Some(file) = output {
-        let file = fs::File::create(&file).map_err(IOError::from)?;
-        serialize::to_writer(file, format, &rt)?;
-    } else {
-        serialize::to_writer(std::io::stdout(), format, &rt)?;
+            if let Some(file) = output {
+                let file = fs::File::create(&file).map_err(IOError::from)?;
+                serialize::to_writer(file, format, &rt)?;
+            } else {
+                serialize::to_writer(std::io::stdout(), format, &rt)?;
+            }
+        }
+        ExportMode::MultiDoc => {
+            if let Some(file) = output {
+                let file = fs::File::create(&file).map_err(IOError::from)?;
+                serialize::to_writer_multi_doc(file, &rt)?;
+            } else {
+                serialize::to_writer_multi_doc(std::io::stdout(), &rt)?;
+            }
+        }
+        ExportMode::MultiFile => {
+            let dir = output.unwrap_or_else(|| PathBuf::from("."));
+
+            // All the files are validated before writing anything, to avoid partial outputs
+            for (path, format, rt) in serialize::split_files(&rt)? {
+                let path = dir.join(path);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).map_err(IOError::from)?;
+                }
+                let file = fs::File::create(&path).map_err(IOError::from)?;
+                serialize::to_writer(file, format, &rt)?;
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Write the environment variables read by the program as a JSON object.
+fn write_env_deps(program: &Program, file: PathBuf) -> Result<(), Error> {
+    let file = fs::File::create(&file).map_err(IOError::from)?;
+    serde_json::to_writer_pretty(file, program.accessed_env())
+        .map_err(|err| IOError(err.to_string()))?;
+    Ok(())
+}
+
+/// Print the profile report on the standard error, and write the folded stacks to `file`.
+fn write_profile(program: &Program, file: PathBuf) -> Result<(), Error> {
+    if let Some(profile) = program.profile() {
+        profile
+            .write_report(program.files(), &mut std::io::stderr())
+            .map_err(IOError::from)?;
+        let mut file = fs::File::create(&file).map_err(IOError::from)?;
+        profile
+            .write_folded(program.files(), &mut file)
+            .map_err(IOError::from)?;
+    }
+    Ok(())
+}
+
+/// Load and fully evaluate a program, or report the error and exit. Warnings are reported as for
+/// the other subcommands.
+fn load_and_eval(
+    path: PathBuf,
+    error_format: ErrorFormat,
+    eval_options: &EvalOptions,
+) -> (Program, RichTerm) {
+    let mut program = Program::new_from_file(&path).unwrap_or_else(|err| {
+        eprintln!("Error when reading input {}: {}", path.display(), err);
+        process::exit(1)
+    });
+    eval_options.apply(&mut program);
+
+    let result = program.eval_full();
+
+    let warnings = program.warnings();
+    if !warnings.is_empty() && !matches!(result, Err(Error::DeniedWarnings(_))) {
+        program.report_as(warnings, error_format);
+    }
+
+    match result {
+        Ok(rt) => (program, rt),
+        Err(err) => {
+            program.report_as(err, error_format);
+            process::exit(1)
+        }
+    }
+}
+
+fn diff(
+    old: PathBuf,
+    new: PathBuf,
+    key: Option<String>,
+    format: DiffFormat,
+    error_format: ErrorFormat,
+    eval_options: &EvalOptions,
+) {
+    let (old_program, old_rt) = load_and_eval(old, error_format, eval_options);
+    let (new_program, new_rt) = load_and_eval(new, error_format, eval_options);
+
+    let changes = diff::diff(&old_rt, &new_rt, key.map(Ident::from).as_ref());
+
+    if let Err(err) = diff::write_changes(
+        &mut std::io::stdout(),
+        format,
+        &changes,
+        old_program.files(),
+        new_program.files(),
+    ) {
+        eprintln!("Error when writing the diff: {}", err);
+        process::exit(1)
+    }
+}
@@ -8,16 +8,18 @@ use crate::term::{RichTerm, SharedTerm, Term};
 use crate::transform::import_resolution;
 use crate::typecheck;
 use crate::typecheck::{linearization::StubHost, type_check};
-use crate::types::UnboundTypeVariableError;
+use crate::types::{AbsType, Types, UnboundTypeVariableError};
 use crate::{eval, parser, transform};
 use codespan::{FileId, Files};
 use io::Read;
 use std::collections::hash_map;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
 use std::result::Result;
 use std::time::SystemTime;
 use void::Void;
@@ -58,14 +60,23 @@ impl InputFormat {
 /// is, the operations that have been performed on this term) is stored in an [EntryState].
 #[derive(Debug, Clone)]
 pub struct Cache {
-    /// The content of the program sources plus imports.
-    files: Files<String>,
+    /// The content of the program sources plus imports. The database is shared with the trace
+    /// handler of the evaluator (see [Self::files_weak]), and is only copied on write if still in use.
+    files: Rc<Files<String>>,
     /// The name-id table, holding file ids stored in the database indexed by source names.
     file_ids: HashMap<OsString, NameIdEntry>,
-    /// Map containing for each FileIDs a list of files they import.
+    /// Map containing for each FileIDs a list of files they import. Only the import which loaded a
+    /// file for the first time is recorded, such that this map doesn't contain cycles.
     imports: HashMap<FileId, HashSet<FileId>>,
+    /// Map containing for each FileIDs the list of all the files importing them. Contrary to
+    /// `imports`, this map is complete and may contain cycles: it is used to typecheck the imports
+    /// of an entry before the entry itself (see [Self::get_all_imports]).
+    importers: HashMap<FileId, HashSet<FileId>>,
     /// The table storing parsed terms corresponding to the entries of the file database.
     terms: HashMap<FileId, CachedTerm>,
+    /// The types of the entries which have been typechecked, used to typecheck their importers.
+    /// Entries whose type couldn't be determined more precisely than `Dyn` are absent.
+    types: HashMap<FileId, Types>,
     /// The list of ids corresponding to the stdlib modules
     stdlib_ids: Option<Vec<FileId>>,
 
@@ -137,9 +148,12 @@ pub enum EntryState {
     ImportsResolving,
     /// The imports of the entry and its transitive dependencies has been resolved.
     ImportsResolved,
-    /// The entry have been typechecked, and its (transitive) imports are being typechecked.
+    /// The entry and its (transitive) imports are being typechecked. Contrary to other stages,
+    /// imports are processed before the entry itself, such that their type is known when
+    /// typechecking the entry.
     Typechecking,
-    /// The entry and its transitive imports have been typechecked.
+    /// The entry and its transitive imports have been typechecked, and the type of the entry has
+    /// been cached.
     Typechecked,
     /// The entry have been transformed, and its (transitive) imports are being transformed.
     Transforming,
@@ -202,10 +216,12 @@ pub enum ResolvedTerm {
 impl Cache {
     pub fn new() -> Self {
         Cache {
-            files: Files::new(),
+            files: Rc::new(Files::new()),
             file_ids: HashMap::new(),
             terms: HashMap::new(),
+            types: HashMap::new(),
             imports: HashMap::new(),
+            importers: HashMap::new(),
             stdlib_ids: None,
 
             #[cfg(debug_assertions)]
@@ -219,7 +235,7 @@ impl Cache {
         let mut buffer = String::new();
         fs::File::open(&path)
             .and_then(|mut file| file.read_to_string(&mut buffer))
-            .map(|_| self.files.add(path, buffer))
+            .map(|_| Rc::make_mut(&mut self.files).add(path, buffer))
     }
 
     /// Same as [Self::add_file], but assume that the path is already normalized, and take the
@@ -264,6 +280,15 @@ impl Cache {
         let path = path.into();
         if let Some(file_id) = self.id_of_file_(&path, timestamp) {
             Ok(CacheOp::Cached(file_id))
+        }
+        // A source added as a string under the name of a file, such as a document opened in an
+        // editor, takes precedence over the content of the file on disk.
+        else if let Some(NameIdEntry {
+            id,
+            timestamp: None,
+        }) = self.file_ids.get(&path)
+        {
+            Ok(CacheOp::Cached(*id))
         } else {
             self.add_file_(path, timestamp).map(CacheOp::Done)
         }
@@ -298,7 +323,7 @@ impl Cache {
     /// will override the old entry in the name-id table.
     pub fn add_string(&mut self, source_name: impl Into<OsString>, s: String) -> FileId {
         let source_name = source_name.into();
-        let id = self.files.add(source_name.clone(), s);
+        let id = Rc::make_mut(&mut self.files).add(source_name.clone(), s);
         self.file_ids.insert(
             source_name,
             NameIdEntry {
@@ -309,6 +334,24 @@ impl Cache {
         id
     }
 
+    /// Load a source as a string, replacing the content of the source with the same name if any,
+    /// such as a file previously loaded by an import. The entry is then considered as a
+    /// stand-alone source, which takes precedence over the file on disk when resolving imports.
+    ///
+    /// The cached term of the source and of its importers are left untouched: see
+    /// [Self::invalidate].
+    pub fn replace_string(&mut self, source_name: impl Into<OsString>, s: String) -> FileId {
+        let source_name = source_name.into();
+        match self.file_ids.get_mut(&source_name) {
+            Some(entry) => {
+                entry.timestamp = None;
+                Rc::make_mut(&mut self.files).update(entry.id, s);
+                entry.id
+            }
+            None => self.add_string(source_name, s),
+        }
+    }
+
     /// Load a temporary source. If a source with the same name exists, clear the corresponding
     /// term cache entry, and destructively update not only the name-id table entry, but also the
     /// content of the source itself.
@@ -318,11 +361,11 @@ impl Cache {
     pub fn add_tmp(&mut self, source_name: impl Into<OsString>, s: String) -> FileId {
         let source_name = source_name.into();
         if let Some(file_id) = self.id_of(&source_name) {
-            self.files.update(file_id, s);
+            Rc::make_mut(&mut self.files).update(file_id, s);
             self.terms.remove(&file_id);
             file_id
         } else {
-            let file_id = self.files.add(source_name.clone(), s);
+            let file_id = Rc::make_mut(&mut self.files).add(source_name.clone(), s);
             self.file_ids.insert(
                 source_name,
                 NameIdEntry {
@@ -353,6 +396,17 @@ impl Cache {
         }
     }
 
+    /// Same as [Self::parse], but fail if the source has syntax errors, including the ones the
+    /// parser recovered from. To be used before any processing that requires a valid program,
+    /// since the term of a source with errors is only partial.
+    pub fn parse_strict(&mut self, file_id: FileId) -> Result<CacheOp<()>, ParseErrors> {
+        match self.parse(file_id)? {
+            CacheOp::Done(e) | CacheOp::Cached(e) if !e.no_errors() => Err(e),
+            CacheOp::Done(_) => Ok(CacheOp::Done(())),
+            CacheOp::Cached(_) => Ok(CacheOp::Cached(())),
+        }
+    }
+
     /// Parse a source and populate the corresponding entry in the cache, or do nothing if the
     /// entry has already been parsed. Support multiple formats.
     pub fn parse_multi(
@@ -392,7 +446,7 @@ impl Cache {
         match format {
             InputFormat::Nickel => {
                 let (t, parse_errs) = parser::grammar::TermParser::new()
-                    .parse_term_tolerant(file_id, Lexer::new(buf))?;
+                    .parse_term_tolerant(file_id, Lexer::new(buf));
 
                 Ok((t, parse_errs))
             }
@@ -410,26 +464,25 @@ impl Cache {
 
     /// Typecheck an entry of the cache and update its state accordingly, or do nothing if the
     /// entry has already been typechecked. Require that the corresponding source has been parsed.
-    /// If the source contains imports, recursively typecheck on the imports too.
+    /// If the source contains imports, recursively typecheck the imports first, and cache the
+    /// type of each entry, which is then used to typecheck its importers (see
+    /// [ImportResolver::get_type]).
+    ///
+    /// In the case of cyclic imports, the entry whose typechecking is still in progress when it is
+    /// imported again is considered to be of type `Dyn` by its importer.
     pub fn typecheck(
         &mut self,
         file_id: FileId,
         global_env: &typecheck::Environment,
     ) -> Result<CacheOp<()>, CacheError<TypecheckError>> {
-        match self.terms.get(&file_id) {
-            Some(CachedTerm { state, .. }) if *state >= EntryState::Typechecked => {
-                Ok(CacheOp::Cached(()))
-            }
-            Some(CachedTerm { term, state, .. }) if *state >= EntryState::Parsed => {
-                if *state < EntryState::Typechecking {
-                    type_check(term, global_env, self, StubHost::<(), (), _>::new())?;
-                    self.update_state(file_id, EntryState::Typechecking);
-                }
+        match self.entry_state(file_id) {
+            Some(state) if state >= EntryState::Typechecking => Ok(CacheOp::Cached(())),
+            Some(state) if state >= EntryState::Parsed => {
+                self.update_state(file_id, EntryState::Typechecking);
 
-                if let Some(imports) = self.imports.get(&file_id).cloned() {
-                    for f in imports.into_iter() {
-                        self.typecheck(f, global_env)?;
-                    }
+                if let Err(err) = self.typecheck_entry(file_id, global_env) {
+                    self.update_state(file_id, state);
+                    return Err(err);
                 }
 
                 self.update_state(file_id, EntryState::Typechecked);
@@ -439,6 +492,37 @@ impl Cache {
         }
     }
 
+    /// Typecheck the imports of an entry, then the entry itself, and cache its type.
+    fn typecheck_entry(
+        &mut self,
+        file_id: FileId,
+        global_env: &typecheck::Environment,
+    ) -> Result<(), CacheError<TypecheckError>> {
+        for f in self.get_all_imports(file_id) {
+            self.typecheck(f, global_env)?;
+        }
+
+        let term = self.terms.get(&file_id).unwrap().term.clone();
+        type_check(&term, global_env, self, StubHost::<(), (), _>::new())?;
+        self.cache_type(file_id);
+
+        Ok(())
+    }
+
+    /// Infer the type of an entry which has been typechecked, and store it to be used when
+    /// typechecking its importers.
+    pub fn cache_type(&mut self, file_id: FileId) {
+        let ty = self
+            .terms
+            .get(&file_id)
+            .map(|cached| typecheck::infer_type(cached.term.as_ref()).try_into());
+
+        match ty {
+            Some(Ok(Types(AbsType::Dyn()))) | Some(Err(_)) | None => self.types.remove(&file_id),
+            Some(Ok(ty)) => self.types.insert(file_id, ty),
+        };
+    }
+
     /// Apply program transformations to an entry of the cache, and update its state accordingly,
     /// or do nothing if the entry has already been transformed. Require that the corresponding
     /// source has been parsed.
@@ -581,7 +665,21 @@ impl Cache {
                     let CachedTerm {
                         term, parse_errs, ..
                     } = self.terms.remove(&file_id).unwrap();
-                    let (term, pending) = import_resolution::resolve_imports(term, self)?;
+                    let (term, pending) =
+                        match import_resolution::resolve_imports(term.clone(), self) {
+                            Ok(result) => result,
+                            Err(err) => {
+                                self.terms.insert(
+                                    file_id,
+                                    CachedTerm {
+                                        term,
+                                        state,
+                                        parse_errs,
+                                    },
+                                );
+                                return Err(err.into());
+                            }
+                        };
                     self.terms.insert(
                         file_id,
                         CachedTerm {
@@ -617,15 +715,7 @@ impl Cache {
         file_id: FileId,
         global_env: &typecheck::Environment,
     ) -> Result<CacheOp<()>, Error> {
-        let mut result = CacheOp::Cached(());
-
-        match self.parse(file_id)? {
-            CacheOp::Done(e) | CacheOp::Cached(e) if !e.no_errors() => return Err(e.into()),
-            CacheOp::Done(_) => {
-                result = CacheOp::Done(());
-            }
-            _ => {}
-        };
+        let mut result = self.parse_strict(file_id)?;
 
         let import_res = self.resolve_imports(file_id).map_err(|cache_err| {
             cache_err.unwrap_error(
@@ -683,6 +773,75 @@ impl Cache {
         Ok((term, pending))
     }
 
+    /// Invalidate an entry whose source has changed, such that it is parsed again the next time it
+    /// is processed. The entries importing it, directly or transitively, are typechecked again
+    /// as well, since the type of the changed entry may not be the same anymore.
+    ///
+    /// The invalidated entry must be parsed again, and its imports resolved, before processing its
+    /// importers. Return the importers which have been invalidated.
+    pub fn invalidate(&mut self, file_id: FileId) -> Vec<FileId> {
+        self.terms.remove(&file_id);
+        self.types.remove(&file_id);
+        self.imports.remove(&file_id);
+        // The imports of the new source are recorded again when they are resolved
+        for importers in self.importers.values_mut() {
+            importers.remove(&file_id);
+        }
+
+        let mut pending = vec![file_id];
+        let mut visited = HashSet::new();
+
+        while let Some(id) = pending.pop() {
+            let importers: Vec<FileId> = self
+                .importers
+                .get(&id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|importer| visited.insert(*importer))
+                .collect();
+
+            for importer in importers {
+                match self.entry_state(importer) {
+                    // The term has been transformed, and can't be typechecked anymore: it has to be
+                    // parsed again.
+                    Some(state) if state >= EntryState::Transforming => {
+                        self.terms.remove(&importer);
+                    }
+                    Some(state) if state >= EntryState::Typechecking => {
+                        self.update_state(importer, EntryState::ImportsResolved);
+                    }
+                    _ => (),
+                }
+
+                self.types.remove(&importer);
+                pending.push(importer);
+            }
+        }
+
+        visited.into_iter().collect()
+    }
+
+    /// Retrieve the files imported by an entry. Only the imports which loaded a file for the first
+    /// time are returned.
+    pub fn get_imports(&self, file_id: FileId) -> impl Iterator<Item = FileId> + '_ {
+        self.imports.get(&file_id).into_iter().flatten().copied()
+    }
+
+    /// Retrieve all the files imported by an entry, including the ones which have been loaded for
+    /// the first time by another importer. Contrary to [Self::get_imports], the imports may form
+    /// cycles. The files are returned in the order of their ids.
+    pub fn get_all_imports(&self, file_id: FileId) -> Vec<FileId> {
+        let mut imports: Vec<FileId> = self
+            .importers
+            .iter()
+            .filter(|(_, importers)| importers.contains(&file_id))
+            .map(|(import, _)| *import)
+            .collect();
+        imports.sort();
+        imports
+    }
+
     /// Retrieve the name of a source given an id.
     pub fn name(&self, file_id: FileId) -> &OsStr {
         self.files.name(file_id)
@@ -728,7 +887,14 @@ impl Cache {
     /// Get a mutable reference to the underlying files. Required by
     /// [crate::error::ToDiagnostic::to_diagnostic].
     pub fn files_mut(&mut self) -> &mut Files<String> {
-        &mut self.files
+        Rc::make_mut(&mut self.files)
+    }
+
+    /// Get a weak reference to the underlying files, which doesn't prevent the cache from loading
+    /// or updating sources later on: the reference is then invalidated instead of the files being
+    /// copied. Used to report the position of traces during evaluation.
+    pub fn files_weak(&self) -> Weak<Files<String>> {
+        Rc::downgrade(&self.files)
     }
 
     /// Get a mutable reference to the cached term roots
@@ -783,15 +949,18 @@ impl Cache {
             .collect();
 
         for file_id in file_ids.iter() {
-            let errs = self.parse(*file_id)?.inner();
-            if !errs.no_errors() {
-                return Err(errs.into());
-            }
+            self.parse_strict(*file_id)?;
         }
         self.stdlib_ids.replace(file_ids);
         Ok(CacheOp::Done(()))
     }
 
+    /// Return the ids of the standard library modules, or an empty slice if the standard library
+    /// hasn't been loaded.
+    pub fn stdlib_ids(&self) -> &[FileId] {
+        self.stdlib_ids.as_deref().unwrap_or(&[])
+    }
+
     /// Typecheck the standard library. Currently only used in the test suite.
     pub fn typecheck_stdlib(&mut self) -> Result<CacheOp<()>, CacheError<TypecheckError>> {
         // We have a small bootstraping problem: to typecheck the global environment, we already
@@ -925,6 +1094,10 @@ pub trait ImportResolver {
     /// Get a resolved import from the term cache.
     fn get(&self, file_id: FileId) -> Option<RichTerm>;
 
+    /// Get the type of a resolved import, if it has already been typechecked and its type is
+    /// known more precisely than `Dyn`.
+    fn get_type(&self, file_id: FileId) -> Option<Types>;
+
     fn get_path(&self, file_id: FileId) -> &OsStr;
 }
 
@@ -944,8 +1117,24 @@ impl ImportResolver for Cache {
                 *pos,
             )
         })?;
+        if let Some(parent_id) = parent.as_ref().and_then(|parent| self.id_of(parent)) {
+            self.importers
+                .entry(id_op.inner())
+                .or_default()
+                .insert(parent_id);
+        }
+
         let file_id = match id_op {
-            CacheOp::Cached(id) => return Ok((ResolvedTerm::FromCache(), id)),
+            // The entry may have been invalidated since it was loaded, in which case it needs to
+            // be parsed again.
+            CacheOp::Cached(id) if self.terms.contains_key(&id) => {
+                let parse_errs = &self.terms[&id].parse_errs;
+                if !parse_errs.no_errors() {
+                    return Err(ImportError::ParseErrors(parse_errs.clone(), *pos));
+                }
+                return Ok((ResolvedTerm::FromCache(), id));
+            }
+            CacheOp::Cached(id) => id,
             CacheOp::Done(id) => {
                 if let Some(parent) = parent {
                     let parent_id = self.id_of(parent).unwrap();
@@ -961,9 +1150,15 @@ impl ImportResolver for Cache {
             }
         };
 
-        // We ignore non fatal parse errors while importing.
-        self.parse_multi(file_id, format)
-            .map_err(|err| ImportError::ParseErrors(err.into(), *pos))?;
+        // The parser recovers from syntax errors, but the term of an imported file with errors is
+        // only partial: the import fails as well.
+        let parse_errs = self
+            .parse_multi(file_id, format)
+            .map_err(|err| ImportError::ParseErrors(err.into(), *pos))?
+            .inner();
+        if !parse_errs.no_errors() {
+            return Err(ImportError::ParseErrors(parse_errs, *pos));
+        }
 
         Ok((ResolvedTerm::FromFile { path: path_buf }, file_id))
     }
@@ -977,6 +1172,10 @@ impl ImportResolver for Cache {
             })
     }
 
+    fn get_type(&self, file_id: FileId) -> Option<Types> {
+        self.types.get(&file_id).cloned()
+    }
+
     fn get_path(&self, file_id: FileId) -> &OsStr {
         self.files.name(file_id)
     }
@@ -1024,6 +1223,10 @@ pub mod resolvers {
             panic!("cache::resolvers: dummy resolver should not have been invoked");
         }
 
+        fn get_type(&self, _file_id: FileId) -> Option<Types> {
+            panic!("cache::resolvers: dummy resolver should not have been invoked");
+        }
+
         fn get_path(&self, _file_id: FileId) -> &OsStr {
             panic!("cache::resolvers: dummy resolver should not have been invoked");
         }
@@ -1094,6 +1297,10 @@ pub mod resolvers {
             self.term_cache.get(&file_id).cloned()
         }
 
+        fn get_type(&self, _file_id: FileId) -> Option<Types> {
+            None
+        }
+
         fn get_path(&self, file_id: FileId) -> &OsStr {
             self.files.name(file_id)
         }
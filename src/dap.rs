@@ -0,0 +1,968 @@
+//! A debug adapter for Nickel, implementing the [Debug Adapter
+//! Protocol](https://microsoft.github.io/debug-adapter-protocol/) (DAP).
+//!
+//! The adapter communicates with a client, typically an editor, over a pair of streams: the
+//! standard input and output for `nickel debug`. Once the client is done with the configuration,
+//! the adapter evaluates the program given by the `launch` request with a
+//! [debugger][crate::eval::debugger] installed. The debugger pauses evaluation on breakpoints,
+//! after steps or on errors, and then answers the requests of the client about the state of the
+//! abstract machine until evaluation is resumed.
+//!
+//! # Supported features
+//!
+//! - Breakpoints on source lines. A breakpoint is hit when evaluation reaches a term starting on
+//!   this line, coming from another line.
+//! - Stepping. Step in stops at the next term starting on another line. Step over additionally
+//!   waits for the thunks forced and the operands evaluated by the current term to be done, and
+//!   step out waits for the evaluation of the current thunk or operand to be done. The code of the
+//!   standard library is skipped.
+//! - Pausing on errors before they interrupt evaluation: on contract violations (the `blame`
+//!   exception filter, enabled by default) or on any error raised by a primitive operation (the
+//!   `error` filter).
+//! - The call stack, as reconstructed from the [callstack][crate::eval::callstack].
+//! - The variables of the environment of the current term. Records and arrays can be expanded,
+//!   but values which haven't been evaluated yet are not forced, since this could change the
+//!   behavior of the program.
+use crate::error::{EvalError, ToDiagnostic};
+use crate::eval::callstack::CallStack;
+use crate::eval::debugger::{Debugger, MachineState};
+use crate::eval::lazy::{Thunk, ThunkState};
+use crate::eval::{Closure, Environment};
+use crate::position::{RawSpan, TermPos};
+use crate::program::{self, Program};
+use crate::term::Term;
+use codespan::{FileId, Files};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// The id of the only thread of a Nickel program.
+const THREAD_ID: u64 = 1;
+
+/// Read a message framed by a `Content-Length` header. Return `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Write a message framed by a `Content-Length` header.
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+/// The connection with the client.
+struct Connection {
+    /// The requests of the client, read by a separate thread.
+    requests: Receiver<Value>,
+    /// The requests received while evaluation was running, which can only be answered once
+    /// evaluation is paused.
+    deferred: VecDeque<Value>,
+    output: Box<dyn Write>,
+    seq: u64,
+}
+
+impl Connection {
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        // If the client is gone, this is detected when reading the next request.
+        let _ = write_message(&mut self.output, &message);
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn respond_error(&mut self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+
+    fn output(&mut self, category: &str, output: String) {
+        self.event("output", json!({"category": category, "output": output}));
+    }
+
+    /// Wait for the next request. Return `None` if the client is gone.
+    fn next_request(&mut self) -> Option<Value> {
+        self.deferred
+            .pop_front()
+            .or_else(|| self.requests.recv().ok())
+    }
+}
+
+/// The exception filters, selecting the errors on which evaluation is paused.
+#[derive(Clone, Copy)]
+struct ExceptionFilters {
+    blame: bool,
+    error: bool,
+}
+
+/// The state of a debugging session shared by the adapter and the debugger.
+struct Session {
+    connection: Connection,
+    /// The lines with a breakpoint (starting at 1), by canonical path.
+    breakpoints: HashMap<PathBuf, Vec<usize>>,
+    filters: ExceptionFilters,
+    /// If the client disconnected while evaluation was running.
+    disconnected: bool,
+}
+
+impl Session {
+    /// Handle the requests which can be answered at any time. Return `false` if the request is
+    /// not one of them.
+    fn handle_config(&mut self, request: &Value) -> bool {
+        let args = &request["arguments"];
+
+        match command(request) {
+            "setBreakpoints" => {
+                let lines: Vec<usize> = args["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|bp| bp["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect();
+                let breakpoints: Vec<Value> = lines
+                    .iter()
+                    .map(|line| json!({"verified": true, "line": line}))
+                    .collect();
+
+                if let Some(path) = args["source"]["path"].as_str() {
+                    let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+                    self.breakpoints.insert(path, lines);
+                }
+                self.connection
+                    .respond(request, json!({ "breakpoints": breakpoints }));
+            }
+            "setExceptionBreakpoints" => {
+                let filters: Vec<&str> = args["filters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                self.filters = ExceptionFilters {
+                    blame: filters.contains(&"blame"),
+                    error: filters.contains(&"error"),
+                };
+                self.connection.respond(request, json!({}));
+            }
+            "threads" => self.connection.respond(
+                request,
+                json!({"threads": [{"id": THREAD_ID, "name": "main"}]}),
+            ),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// A source location, as a file and a line (starting at 0).
+type Location = (FileId, usize);
+
+/// When to pause evaluation next, besides breakpoints and errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Continue,
+    /// Pause at the next location, with the given reason.
+    Pause(&'static str),
+    StepIn {
+        from: Location,
+    },
+    StepOver {
+        from: Location,
+        depth: usize,
+    },
+    StepOut {
+        depth: usize,
+    },
+}
+
+/// A value which can be expanded in the variables view of the client.
+enum Handle {
+    /// The bindings of an environment.
+    Env(Environment),
+    /// The fields of a record or the elements of an array.
+    Value(Closure),
+}
+
+/// The state of the machine when evaluation is paused.
+struct Stop<'a> {
+    reason: &'static str,
+    /// The error which caused the pause, if any, as a short message and as a full report.
+    error: Option<(String, String)>,
+    closure: Option<&'a Closure>,
+    call_stack: &'a CallStack,
+    location: Option<Location>,
+    depth: usize,
+}
+
+/// The debugger driven by the client.
+struct DapDebugger {
+    session: Rc<RefCell<Session>>,
+    files: Files<String>,
+    stdlib_ids: Vec<FileId>,
+    contracts_id: Option<FileId>,
+    /// The canonical path of each file, or `None` if it isn't a file on disk.
+    paths: HashMap<FileId, Option<PathBuf>>,
+    mode: Mode,
+    /// The location of the last step with a location.
+    last_location: Option<Location>,
+    /// The closure of the last step.
+    last_closure: Option<Closure>,
+    handles: Vec<Handle>,
+}
+
+impl DapDebugger {
+    fn new(session: Rc<RefCell<Session>>, stop_on_entry: bool) -> Self {
+        DapDebugger {
+            session,
+            files: Files::new(),
+            stdlib_ids: Vec::new(),
+            contracts_id: None,
+            paths: HashMap::new(),
+            mode: if stop_on_entry {
+                Mode::Pause("entry")
+            } else {
+                Mode::Continue
+            },
+            last_location: None,
+            last_closure: None,
+            handles: Vec::new(),
+        }
+    }
+
+    fn path(&mut self, file_id: FileId) -> Option<&PathBuf> {
+        let files = &self.files;
+        self.paths
+            .entry(file_id)
+            .or_insert_with(|| std::fs::canonicalize(files.name(file_id)).ok())
+            .as_ref()
+    }
+
+    /// Return the location of a position outside of the standard library, if any. Inherited
+    /// positions are ignored, as they don't correspond to the term being evaluated.
+    fn location(&self, pos: &TermPos) -> Option<Location> {
+        match pos {
+            TermPos::Original(span) if !self.stdlib_ids.contains(&span.src_id) => self
+                .files
+                .location(span.src_id, span.start)
+                .ok()
+                .map(|loc| (span.src_id, loc.line.to_usize())),
+            _ => None,
+        }
+    }
+
+    fn has_breakpoint(&mut self, (file_id, line): Location) -> bool {
+        let path = match self.path(file_id) {
+            Some(path) => path.clone(),
+            None => return false,
+        };
+
+        self.session
+            .borrow()
+            .breakpoints
+            .get(&path)
+            .map(|lines| lines.contains(&(line + 1)))
+            .unwrap_or(false)
+    }
+
+    /// Handle the requests received while evaluation is running.
+    fn poll(&mut self) -> Result<(), EvalError> {
+        let mut session = self.session.borrow_mut();
+
+        while let Ok(request) = session.connection.requests.try_recv() {
+            match command(&request) {
+                // Requests are handled in order: once one is deferred, so are the following ones.
+                _ if !session.connection.deferred.is_empty() => {
+                    session.connection.deferred.push_back(request)
+                }
+                "pause" => {
+                    self.mode = Mode::Pause("pause");
+                    session.connection.respond(&request, json!({}));
+                }
+                "disconnect" => {
+                    session.connection.respond(&request, json!({}));
+                    session.disconnected = true;
+                    return Err(disconnected());
+                }
+                _ if session.handle_config(&request) => (),
+                _ => session.connection.deferred.push_back(request),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pause evaluation and answer the requests of the client until evaluation is resumed.
+    fn stop(&mut self, stop: Stop<'_>) -> Result<(), EvalError> {
+        self.handles.clear();
+        let session = Rc::clone(&self.session);
+        let mut session = session.borrow_mut();
+
+        let mut event = json!({
+            "reason": stop.reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some((message, _)) = &stop.error {
+            event["text"] = json!(message);
+        }
+        session.connection.event("stopped", event);
+
+        loop {
+            let request = match session.connection.next_request() {
+                Some(request) => request,
+                None => {
+                    session.disconnected = true;
+                    return Err(disconnected());
+                }
+            };
+            let args = &request["arguments"];
+
+            let mode = match command(&request) {
+                "continue" => Some(Mode::Continue),
+                "next" => stop.location.map(|from| Mode::StepOver {
+                    from,
+                    depth: stop.depth,
+                }),
+                "stepIn" => stop.location.map(|from| Mode::StepIn { from }),
+                "stepOut" => Some(Mode::StepOut { depth: stop.depth }),
+                _ => None,
+            };
+
+            match command(&request) {
+                "continue" | "next" | "stepIn" | "stepOut" => {
+                    // Without a location, as when paused on an error, stepping resumes until
+                    // the next location.
+                    self.mode = mode.unwrap_or(Mode::Pause("step"));
+                    session
+                        .connection
+                        .respond(&request, json!({"allThreadsContinued": true}));
+                    return Ok(());
+                }
+                "stackTrace" => {
+                    let frames = self.stack_frames(stop.closure, stop.call_stack);
+                    let total = frames.len();
+                    session.connection.respond(
+                        &request,
+                        json!({"stackFrames": frames, "totalFrames": total}),
+                    );
+                }
+                "scopes" => {
+                    let scopes = match (args["frameId"].as_u64(), stop.closure) {
+                        (Some(0), Some(closure)) => {
+                            let reference = self.handle(Handle::Env(closure.env.clone()));
+                            vec![json!({
+                                "name": "Locals",
+                                "variablesReference": reference,
+                                "expensive": false,
+                            })]
+                        }
+                        // The environment of the callers isn't recorded.
+                        _ => Vec::new(),
+                    };
+                    session
+                        .connection
+                        .respond(&request, json!({ "scopes": scopes }));
+                }
+                "variables" => {
+                    let reference = args["variablesReference"].as_u64().unwrap_or_default();
+                    let variables = self.variables(reference as usize);
+                    session
+                        .connection
+                        .respond(&request, json!({ "variables": variables }));
+                }
+                "exceptionInfo" => match &stop.error {
+                    Some((message, details)) => session.connection.respond(
+                        &request,
+                        json!({
+                            "exceptionId": message,
+                            "description": details,
+                            "breakMode": "always",
+                        }),
+                    ),
+                    None => session
+                        .connection
+                        .respond_error(&request, "not paused on an error"),
+                },
+                "pause" => session.connection.respond(&request, json!({})),
+                "disconnect" => {
+                    session.connection.respond(&request, json!({}));
+                    session.disconnected = true;
+                    return Err(disconnected());
+                }
+                _ if session.handle_config(&request) => (),
+                cmd => session
+                    .connection
+                    .respond_error(&request, &format!("unsupported request `{}`", cmd)),
+            }
+        }
+    }
+
+    fn stack_frames(&mut self, closure: Option<&Closure>, call_stack: &CallStack) -> Vec<Value> {
+        let calls = match self.contracts_id {
+            Some(contracts_id) => call_stack.group_by_calls(contracts_id).0,
+            None => Vec::new(),
+        };
+
+        // The first frame is at the current position, in the most recent call. Each following
+        // frame is at the position of the call of the previous one.
+        let names = calls
+            .iter()
+            .map(|call| {
+                call.head
+                    .as_ref()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| String::from("<func>"))
+            })
+            .chain(std::iter::once(String::from("<main>")));
+        let spans = std::iter::once(closure.and_then(|c| c.body.pos.into_opt()))
+            .chain(calls.iter().map(|call| Some(call.span)));
+
+        names
+            .zip(spans)
+            .enumerate()
+            .map(|(id, (name, span))| self.stack_frame(id, name, span))
+            .collect()
+    }
+
+    fn stack_frame(&mut self, id: usize, name: String, span: Option<RawSpan>) -> Value {
+        let mut frame = json!({"id": id, "name": name, "line": 0, "column": 0});
+
+        if let Some(span) = span {
+            if let Ok(loc) = self.files.location(span.src_id, span.start) {
+                frame["line"] = json!(loc.line.number().to_usize());
+                frame["column"] = json!(loc.column.to_usize() + 1);
+                frame["source"] = self.source(span.src_id);
+            }
+        }
+
+        frame
+    }
+
+    fn source(&mut self, file_id: FileId) -> Value {
+        let name = self.files.name(file_id).to_string_lossy().into_owned();
+
+        match self.path(file_id) {
+            Some(path) => json!({"name": name, "path": path}),
+            None => json!({"name": name, "presentationHint": "deemphasize"}),
+        }
+    }
+
+    /// Register a handle and return the corresponding variables reference. References start at 1,
+    /// as 0 means that a variable can't be expanded.
+    fn handle(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn variables(&mut self, reference: usize) -> Vec<Value> {
+        let children: Vec<(String, Closure)> = match reference
+            .checked_sub(1)
+            .and_then(|index| self.handles.get(index))
+        {
+            Some(Handle::Env(env)) => {
+                let mut bindings: Vec<_> = env
+                    .iter()
+                    .filter(|(id, _)| !id.is_generated())
+                    .map(|(id, thunk)| (id.to_string(), thunk.clone()))
+                    .collect();
+                bindings.sort_by(|(id1, _), (id2, _)| id1.cmp(id2));
+
+                return bindings
+                    .into_iter()
+                    .map(|(name, thunk)| {
+                        let (value, reference) = self.describe_thunk(&thunk);
+                        json!({"name": name, "value": value, "variablesReference": reference})
+                    })
+                    .collect();
+            }
+            Some(Handle::Value(closure)) => match closure.body.as_ref() {
+                Term::Record(fields, _) | Term::RecRecord(fields, ..) => {
+                    let mut fields: Vec<_> = fields
+                        .iter()
+                        .map(|(id, t)| {
+                            (
+                                id.to_string(),
+                                Closure {
+                                    body: t.clone(),
+                                    env: closure.env.clone(),
+                                },
+                            )
+                        })
+                        .collect();
+                    fields.sort_by(|(id1, _), (id2, _)| id1.cmp(id2));
+                    fields
+                }
+                Term::Array(elts) => elts
+                    .iter()
+                    .enumerate()
+                    .map(|(index, t)| {
+                        (
+                            index.to_string(),
+                            Closure {
+                                body: t.clone(),
+                                env: closure.env.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+
+        children
+            .into_iter()
+            .map(|(name, closure)| {
+                let (value, reference) = self.describe(closure);
+                json!({"name": name, "value": value, "variablesReference": reference})
+            })
+            .collect()
+    }
+
+    /// Describe the value of a thunk, without forcing it.
+    fn describe_thunk(&mut self, thunk: &Thunk) -> (String, usize) {
+        match thunk.state() {
+            ThunkState::Evaluated => self.describe(thunk.get_owned()),
+            ThunkState::Blackholed => (String::from("<being evaluated>"), 0),
+            ThunkState::Suspended => (String::from("<not evaluated>"), 0),
+        }
+    }
+
+    /// Describe a value, and register a handle if it can be expanded.
+    fn describe(&mut self, closure: Closure) -> (String, usize) {
+        match closure.body.as_ref() {
+            Term::Var(id) => match closure.env.get(id) {
+                Some(thunk) => self.describe_thunk(&thunk),
+                None => (String::from("<unbound>"), 0),
+            },
+            Term::MetaValue(meta) => match &meta.value {
+                Some(value) => self.describe(Closure {
+                    body: value.clone(),
+                    env: closure.env,
+                }),
+                None => (String::from("<no value>"), 0),
+            },
+            Term::Record(fields, _) | Term::RecRecord(fields, ..) => {
+                let value = format!("{{ … }} ({} fields)", fields.len());
+                (value, self.handle(Handle::Value(closure)))
+            }
+            Term::Array(elts) => {
+                let value = format!("[ … ] ({} elements)", elts.len());
+                (value, self.handle(Handle::Value(closure)))
+            }
+            t if t.is_whnf() => (t.shallow_repr(), 0),
+            _ => (String::from("<not evaluated>"), 0),
+        }
+    }
+}
+
+impl Debugger for DapDebugger {
+    fn load_sources(&mut self, files: &Files<String>, stdlib_ids: &[FileId]) {
+        self.files = files.clone();
+        self.stdlib_ids = stdlib_ids.to_vec();
+        self.contracts_id = stdlib_ids
+            .iter()
+            .find(|id| files.name(**id) == "<stdlib/contract.ncl>")
+            .copied();
+        self.paths.clear();
+    }
+
+    fn step(&mut self, state: &MachineState<'_>) -> Result<(), EvalError> {
+        self.poll()?;
+        self.last_closure = Some(state.closure.clone());
+
+        let location = match self.location(&state.closure.body.pos) {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+
+        let at_breakpoint = self.last_location != Some(location) && self.has_breakpoint(location);
+        let reason = match self.mode {
+            Mode::Pause(reason) => Some(reason),
+            Mode::StepIn { from } if location != from => Some("step"),
+            Mode::StepOver { from, depth } if location != from && state.depth <= depth => {
+                Some("step")
+            }
+            Mode::StepOut { depth } if state.depth < depth => Some("step"),
+            _ if at_breakpoint => Some("breakpoint"),
+            _ => None,
+        };
+        self.last_location = Some(location);
+
+        match reason {
+            Some(reason) => {
+                self.mode = Mode::Continue;
+                self.stop(Stop {
+                    reason,
+                    error: None,
+                    closure: Some(state.closure),
+                    call_stack: state.call_stack,
+                    location: Some(location),
+                    depth: state.depth,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn error(&mut self, error: &EvalError, call_stack: &CallStack) {
+        let filters = self.session.borrow().filters;
+        let is_blame = matches!(error, EvalError::BlameError(..));
+        if !(filters.error || filters.blame && is_blame) {
+            return;
+        }
+
+        let diagnostics = error
+            .clone()
+            .to_diagnostic(&mut self.files, self.contracts_id);
+        let message = diagnostics
+            .first()
+            .map(|d| d.message.clone())
+            .unwrap_or_default();
+        let details = program::render_diagnostics(&self.files, &diagnostics);
+
+        let closure = self.last_closure.take();
+        // The error interrupts evaluation anyway once resumed, even if the client disconnected.
+        let _ = self.stop(Stop {
+            reason: "exception",
+            error: Some((message, details)),
+            closure: closure.as_ref(),
+            call_stack,
+            location: None,
+            depth: 0,
+        });
+    }
+}
+
+fn disconnected() -> EvalError {
+    EvalError::Other(
+        String::from("evaluation interrupted by the debugger"),
+        TermPos::None,
+    )
+}
+
+/// Run a debugging session with a client communicating over `input` and `output`. The program to
+/// debug is given by the `launch` request, or is `default_program` if the request doesn't specify
+/// any.
+pub fn run<R, W>(input: R, output: W, default_program: Option<PathBuf>) -> io::Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write + 'static,
+{
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let session = Rc::new(RefCell::new(Session {
+        connection: Connection {
+            requests,
+            deferred: VecDeque::new(),
+            output: Box::new(output),
+            seq: 0,
+        },
+        breakpoints: HashMap::new(),
+        filters: ExceptionFilters {
+            blame: true,
+            error: false,
+        },
+        disconnected: false,
+    }));
+
+    let mut path = default_program;
+    let mut stop_on_entry = false;
+
+    // Configuration, until the client sends `configurationDone`.
+    loop {
+        let mut session = session.borrow_mut();
+        let request = match session.connection.next_request() {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        match command(&request) {
+            "initialize" => {
+                session.connection.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsExceptionInfoRequest": true,
+                        "exceptionBreakpointFilters": [
+                            {"filter": "blame", "label": "Contract violations", "default": true},
+                            {"filter": "error", "label": "All errors", "default": false},
+                        ],
+                    }),
+                );
+                session.connection.event("initialized", json!({}));
+            }
+            "launch" => {
+                let args = &request["arguments"];
+                if let Some(program) = args["program"].as_str() {
+                    path = Some(PathBuf::from(program));
+                }
+                stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                session.connection.respond(&request, json!({}));
+            }
+            "configurationDone" => {
+                session.connection.respond(&request, json!({}));
+                break;
+            }
+            "disconnect" => {
+                session.connection.respond(&request, json!({}));
+                return Ok(());
+            }
+            _ if session.handle_config(&request) => (),
+            cmd => session
+                .connection
+                .respond_error(&request, &format!("unsupported request `{}`", cmd)),
+        }
+    }
+
+    let exit_code = match path.map(Program::new_from_file) {
+        Some(Ok(mut program)) => {
+            program.set_debugger(DapDebugger::new(Rc::clone(&session), stop_on_entry));
+            let result = program.eval_full();
+
+            if session.borrow().disconnected {
+                return Ok(());
+            }
+
+            match result {
+                Ok(t) => {
+                    let output = format!("{}\n", Term::from(t).deep_repr());
+                    session.borrow_mut().connection.output("stdout", output);
+                    0
+                }
+                Err(err) => {
+                    let output = program.render(err);
+                    session.borrow_mut().connection.output("stderr", output);
+                    1
+                }
+            }
+        }
+        Some(Err(err)) => {
+            let output = format!("error when reading the program: {}\n", err);
+            session.borrow_mut().connection.output("stderr", output);
+            1
+        }
+        None => {
+            let output = String::from("no program to debug\n");
+            session.borrow_mut().connection.output("stderr", output);
+            1
+        }
+    };
+
+    let mut session = session.borrow_mut();
+    session
+        .connection
+        .event("exited", json!({ "exitCode": exit_code }));
+    session.connection.event("terminated", json!({}));
+
+    // Answer the remaining requests until the client disconnects.
+    while let Some(request) = session.connection.next_request() {
+        match command(&request) {
+            "disconnect" => {
+                session.connection.respond(&request, json!({}));
+                break;
+            }
+            _ if session.handle_config(&request) => (),
+            _ => session
+                .connection
+                .respond_error(&request, "the program has terminated"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// An output buffer which can be read once the session is over.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a session on `source` with the given requests, and return the messages sent by the
+    /// adapter.
+    fn run_session(name: &str, source: &str, requests: Vec<Value>) -> Vec<Value> {
+        let path =
+            std::env::temp_dir().join(format!("nickel-dap-{}-{}.ncl", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+
+        let mut input = Vec::new();
+        let requests = std::iter::once(json!({"command": "initialize", "arguments": {}}))
+            .chain(std::iter::once(json!({
+                "command": "launch",
+                "arguments": {"program": path},
+            })))
+            .chain(requests.into_iter().map(|request| match command(&request) {
+                "setBreakpoints" => {
+                    let mut request = request;
+                    request["arguments"]["source"] = json!({ "path": path });
+                    request
+                }
+                _ => request,
+            }));
+        for (seq, mut request) in requests.enumerate() {
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+
+        let output = SharedBuffer::default();
+        run(Cursor::new(input), output.clone(), None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let output = output.0.borrow();
+        let mut output = Cursor::new(output.as_slice());
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    fn find<'a>(messages: &'a [Value], kind: &str, name: &str) -> Option<&'a Value> {
+        messages
+            .iter()
+            .find(|msg| msg["type"] == kind && (msg["command"] == name || msg["event"] == name))
+    }
+
+    #[test]
+    fn breakpoint() {
+        let messages = run_session(
+            "breakpoint",
+            "let x = 1 + 1 in\nlet y = x * 2 in\ny + 1",
+            vec![
+                json!({"command": "setBreakpoints", "arguments": {"breakpoints": [{"line": 3}]}}),
+                json!({"command": "configurationDone"}),
+                json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+                json!({"command": "scopes", "arguments": {"frameId": 0}}),
+                json!({"command": "variables", "arguments": {"variablesReference": 1}}),
+                json!({"command": "continue", "arguments": {"threadId": 1}}),
+                // The breakpoint is hit again when coming back from the evaluation of `y`.
+                json!({"command": "continue", "arguments": {"threadId": 1}}),
+                json!({"command": "disconnect"}),
+            ],
+        );
+
+        let stopped = find(&messages, "event", "stopped").unwrap();
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+        let trace = find(&messages, "response", "stackTrace").unwrap();
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 3);
+
+        let variables = find(&messages, "response", "variables").unwrap();
+        let names: Vec<&str> = variables["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| var["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"x"));
+        assert!(names.contains(&"y"));
+
+        let output = find(&messages, "event", "output").unwrap();
+        assert_eq!(output["body"]["category"], "stdout");
+        assert_eq!(output["body"]["output"], "5\n");
+        let exited = find(&messages, "event", "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn pause_on_blame() {
+        let messages = run_session(
+            "blame",
+            "let x = 1 in\n(x | Str)",
+            vec![
+                json!({"command": "configurationDone"}),
+                json!({"command": "exceptionInfo", "arguments": {"threadId": 1}}),
+                json!({"command": "continue", "arguments": {"threadId": 1}}),
+                json!({"command": "disconnect"}),
+            ],
+        );
+
+        let stopped = find(&messages, "event", "stopped").unwrap();
+        assert_eq!(stopped["body"]["reason"], "exception");
+
+        let info = find(&messages, "response", "exceptionInfo").unwrap();
+        assert_eq!(info["success"], true);
+        assert!(info["body"]["description"]
+            .as_str()
+            .unwrap()
+            .contains("contract"));
+
+        let exited = find(&messages, "event", "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], 1);
+    }
+}
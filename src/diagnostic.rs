@@ -0,0 +1,318 @@
+//! Structured diagnostics.
+//!
+//! Errors are reported as [codespan_reporting] diagnostics (see [`crate::error::ToDiagnostic`]),
+//! which refer to source locations as byte offsets in a file database. This module converts them
+//! to a self-contained and serializable representation, with file names and line/column
+//! positions, which can be consumed by other tools. The CLI uses it to emit diagnostics either as
+//! JSON lines or as a [SARIF](https://sarifweb.azurewebsites.net/) log, and the WASM REPL to pass
+//! errors to the JavaScript side.
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::{self as cs, Diagnostic};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The severity of a diagnostic.
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Bug,
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl From<cs::Severity> for Severity {
+    fn from(s: cs::Severity) -> Severity {
+        match s {
+            cs::Severity::Bug => Severity::Bug,
+            cs::Severity::Error => Severity::Error,
+            cs::Severity::Warning => Severity::Warning,
+            cs::Severity::Note => Severity::Note,
+            cs::Severity::Help => Severity::Help,
+        }
+    }
+}
+
+/// The style of a label. Primary labels point to the cause of a diagnostic, while secondary
+/// labels give additional context.
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelStyle {
+    Primary,
+    Secondary,
+}
+
+impl From<cs::LabelStyle> for LabelStyle {
+    fn from(style: cs::LabelStyle) -> LabelStyle {
+        match style {
+            cs::LabelStyle::Primary => LabelStyle::Primary,
+            cs::LabelStyle::Secondary => LabelStyle::Secondary,
+        }
+    }
+}
+
+/// A label, attaching a message to a region of a source file. Lines and columns start at 1, and
+/// the end position is exclusive.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StructuredLabel {
+    pub message: String,
+    pub style: LabelStyle,
+    pub file: String,
+    pub line_start: usize,
+    pub col_start: usize,
+    pub line_end: usize,
+    pub col_end: usize,
+}
+
+impl StructuredLabel {
+    pub fn from_codespan(files: &Files<String>, label: &cs::Label<FileId>) -> Self {
+        let location = |offset: usize| {
+            files
+                .location(label.file_id, offset as u32)
+                .map(|loc| (loc.line.to_usize() + 1, loc.column.to_usize() + 1))
+        };
+
+        let (start, end) = match (location(label.range.start), location(label.range.end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Ok(loc), _) | (_, Ok(loc)) => (loc, loc),
+            _ => ((1, 1), (1, 1)),
+        };
+
+        StructuredLabel {
+            message: label.message.clone(),
+            style: label.style.into(),
+            file: files.name(label.file_id).to_string_lossy().into_owned(),
+            line_start: start.0,
+            col_start: start.1,
+            line_end: end.0,
+            col_end: end.1,
+        }
+    }
+}
+
+/// A self-contained diagnostic.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StructuredDiagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub labels: Vec<StructuredLabel>,
+    pub notes: Vec<String>,
+}
+
+impl StructuredDiagnostic {
+    pub fn from_codespan(files: &Files<String>, diag: &Diagnostic<FileId>) -> Self {
+        StructuredDiagnostic {
+            severity: diag.severity.into(),
+            code: diag.code.clone(),
+            message: diag.message.clone(),
+            labels: diag
+                .labels
+                .iter()
+                .map(|label| StructuredLabel::from_codespan(files, label))
+                .collect(),
+            notes: diag.notes.clone(),
+        }
+    }
+}
+
+/// Available formats for reporting errors.
+// If you add or remove variants, remember to update the CLI docs in `src/bin/nickel.rs'
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorFormat {
+    /// Human-readable diagnostics, rendered by `codespan-reporting`.
+    Text,
+    /// One JSON object per diagnostic and per line.
+    Json,
+    /// A SARIF log.
+    Sarif,
+}
+
+impl fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+            Self::Sarif => write!(f, "sarif"),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseErrorFormatError(String);
+
+impl fmt::Display for ParseErrorFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported error format {}", self.0)
+    }
+}
+
+impl FromStr for ErrorFormat {
+    type Err = ParseErrorFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            "sarif" => Ok(ErrorFormat::Sarif),
+            _ => Err(ParseErrorFormatError(String::from(s))),
+        }
+    }
+}
+
+/// Write diagnostics as JSON lines, that is one JSON object per line.
+pub fn write_json_lines<W>(mut out: W, diags: &[StructuredDiagnostic]) -> io::Result<()>
+where
+    W: Write,
+{
+    for diag in diags {
+        serde_json::to_writer(&mut out, diag)?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Convert diagnostics to a [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/)
+/// log, with one result per diagnostic. Primary labels become the locations of the result and
+/// secondary labels its related locations. Notes are appended to the message.
+pub fn to_sarif(diags: &[StructuredDiagnostic]) -> serde_json::Value {
+    use serde_json::json;
+
+    let location = |label: &StructuredLabel| {
+        json!({
+            "physicalLocation": {
+                "artifactLocation": { "uri": label.file },
+                "region": {
+                    "startLine": label.line_start,
+                    "startColumn": label.col_start,
+                    "endLine": label.line_end,
+                    "endColumn": label.col_end,
+                },
+            },
+            "message": { "text": label.message },
+        })
+    };
+
+    let results: Vec<serde_json::Value> = diags
+        .iter()
+        .map(|diag| {
+            let level = match diag.severity {
+                Severity::Bug | Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Note | Severity::Help => "note",
+            };
+            let text = std::iter::once(diag.message.as_str())
+                .chain(diag.notes.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let (primary, secondary): (Vec<_>, Vec<_>) = diag
+                .labels
+                .iter()
+                .partition(|label| label.style == LabelStyle::Primary);
+
+            let mut result = json!({
+                "level": level,
+                "message": { "text": text },
+                "locations": primary.into_iter().map(location).collect::<Vec<_>>(),
+                "relatedLocations": secondary.into_iter().map(location).collect::<Vec<_>>(),
+            });
+            if let Some(code) = &diag.code {
+                result["ruleId"] = json!(code);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "nickel",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://nickel-lang.org",
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Write diagnostics as a SARIF log. See [`to_sarif`].
+pub fn write_sarif<W>(out: W, diags: &[StructuredDiagnostic]) -> io::Result<()>
+where
+    W: Write,
+{
+    serde_json::to_writer_pretty(out, &to_sarif(diags))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic() -> (Files<String>, Diagnostic<FileId>) {
+        let mut files = Files::new();
+        let file_id = files.add("<test>", String::from("let x = 1 in\nx + \"a\""));
+        let diag = Diagnostic::error()
+            .with_message("incompatible types")
+            .with_labels(vec![
+                cs::Label::primary(file_id, 17..20).with_message("this expression"),
+                cs::Label::secondary(file_id, 4..5).with_message("bound here"),
+            ])
+            .with_notes(vec![String::from("expected type `Num`")]);
+        (files, diag)
+    }
+
+    #[test]
+    fn from_codespan() {
+        let (files, diag) = diagnostic();
+        let diag = StructuredDiagnostic::from_codespan(&files, &diag);
+
+        assert_eq!(diag.severity, Severity::Error);
+        assert_eq!(diag.code, None);
+        assert_eq!(
+            diag.labels[0],
+            StructuredLabel {
+                message: String::from("this expression"),
+                style: LabelStyle::Primary,
+                file: String::from("<test>"),
+                line_start: 2,
+                col_start: 5,
+                line_end: 2,
+                col_end: 8,
+            }
+        );
+        assert_eq!(
+            (diag.labels[1].line_start, diag.labels[1].col_start),
+            (1, 5)
+        );
+    }
+
+    #[test]
+    fn sarif() {
+        let (files, diag) = diagnostic();
+        let sarif = to_sarif(&[StructuredDiagnostic::from_codespan(&files, &diag)]);
+        let result = &sarif["runs"][0]["results"][0];
+
+        assert_eq!(result["level"], "error");
+        assert_eq!(
+            result["message"]["text"],
+            "incompatible types\nexpected type `Num`"
+        );
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"]["startLine"],
+            2
+        );
+        assert_eq!(
+            result["relatedLocations"][0]["message"]["text"],
+            "bound here"
+        );
+        assert!(result.get("ruleId").is_none());
+    }
+}
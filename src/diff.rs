@@ -0,0 +1,468 @@
+//! Structural comparison of evaluated Nickel values.
+//!
+//! This module implements the `diff` command of the CLI. Rather than comparing the textual output
+//! of an export, both programs are fully evaluated (see [`crate::program::Program::eval_full`])
+//! and the resulting terms are compared structurally:
+//!
+//! - records are compared field by field,
+//! - arrays are compared element by element, either by index or, if a key field is provided and
+//!   all the elements are records defining this field, by the value of this field,
+//! - other values are compared as constants.
+//!
+//! The result is a list of [changes][Change], each one being located by a [path][DiffPath] inside
+//! the compared values and carrying the terms involved, which retain their position in the
+//! original sources.
+use crate::identifier::Ident;
+use crate::position::TermPos;
+use crate::term::{MetaValue, RichTerm, Term};
+use codespan::{FileId, Files};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// An element of a path inside a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathElem {
+    /// A record field.
+    Field(Ident),
+    /// An array element, identified by its index.
+    Index(usize),
+    /// An array element, identified by the value of its key field.
+    Key(String),
+}
+
+/// A path inside a value, from the root to the changed sub-value.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DiffPath(pub Vec<PathElem>);
+
+impl DiffPath {
+    fn push(&self, elem: PathElem) -> Self {
+        let mut path = self.0.clone();
+        path.push(elem);
+        DiffPath(path)
+    }
+
+    /// Convert the path to a JSON array: fields and keys are represented as strings and indices as
+    /// numbers.
+    pub fn to_json(&self) -> Value {
+        Value::Array(
+            self.0
+                .iter()
+                .map(|elem| match elem {
+                    PathElem::Field(id) => json!(id.label),
+                    PathElem::Index(i) => json!(i),
+                    PathElem::Key(k) => json!(k),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for DiffPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<root>");
+        }
+
+        for (i, elem) in self.0.iter().enumerate() {
+            match elem {
+                PathElem::Field(id) if i == 0 => write!(f, "{}", id)?,
+                PathElem::Field(id) => write!(f, ".{}", id)?,
+                PathElem::Index(idx) => write!(f, "[{}]", idx)?,
+                PathElem::Key(key) => write!(f, "[{}]", key)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A difference between the old and the new value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A field or an array element only present in the new value.
+    Added { path: DiffPath, new: RichTerm },
+    /// A field or an array element only present in the old value.
+    Removed { path: DiffPath, old: RichTerm },
+    /// A value present on both sides, but which differs.
+    Changed {
+        path: DiffPath,
+        old: RichTerm,
+        new: RichTerm,
+    },
+}
+
+impl Change {
+    pub fn path(&self) -> &DiffPath {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. } => path,
+        }
+    }
+}
+
+/// Available output formats for the result of a diff.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DiffFormat {
+    /// A human-readable list of changes.
+    Text,
+    /// A JSON array of changes, for consumption by other tools.
+    Json,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseDiffFormatError(String);
+
+impl fmt::Display for ParseDiffFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported diff format {}", self.0)
+    }
+}
+
+impl FromStr for DiffFormat {
+    type Err = ParseDiffFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            _ => Err(ParseDiffFormatError(String::from(s))),
+        }
+    }
+}
+
+/// Strip the metavalues wrapping a value, if any. Fully evaluated records may still contain
+/// metavalues (whose value has been evaluated) as fields.
+fn unwrap_meta(rt: &RichTerm) -> &RichTerm {
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue {
+            value: Some(inner), ..
+        }) => unwrap_meta(inner),
+        _ => rt,
+    }
+}
+
+/// Compare two values which are not records nor arrays.
+///
+/// Functions, labels and other values that cannot be inspected are compared by their shallow
+/// representation: two functions are always considered to be equal.
+fn same_value(t1: &Term, t2: &Term) -> bool {
+    match (t1, t2) {
+        (Term::Null, Term::Null) => true,
+        (Term::Bool(b1), Term::Bool(b2)) => b1 == b2,
+        (Term::Num(n1), Term::Num(n2)) => n1 == n2,
+        (Term::Str(s1), Term::Str(s2)) => s1 == s2,
+        (Term::Enum(id1), Term::Enum(id2)) => id1 == id2,
+        (t1, t2) => t1.type_of() == t2.type_of() && t1.shallow_repr() == t2.shallow_repr(),
+    }
+}
+
+/// Extract the key of an array element, if it is a record with a constant key field.
+fn element_key(rt: &RichTerm, key: &Ident) -> Option<String> {
+    match unwrap_meta(rt).as_ref() {
+        Term::Record(fields, _) => fields.get(key).and_then(|t| match unwrap_meta(t).as_ref() {
+            Term::Str(s) => Some(s.clone()),
+            t @ Term::Num(_) | t @ Term::Bool(_) | t @ Term::Enum(_) => Some(t.shallow_repr()),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Index the elements of an array by the value of their key field. Return `None` if one of the
+/// element doesn't have a key, or if two elements have the same key.
+fn index_by_key<'a>(elts: &'a [RichTerm], key: &Ident) -> Option<Vec<(String, &'a RichTerm)>> {
+    let mut seen = BTreeSet::new();
+
+    elts.iter()
+        .map(|elt| {
+            let k = element_key(elt, key)?;
+            if seen.insert(k.clone()) {
+                Some((k, elt))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Compute the list of changes between an old and a new value.
+///
+/// If `array_key` is provided, arrays whose elements are all records with a distinct constant
+/// value for this field are compared by matching elements with the same key, rather than by
+/// index.
+pub fn diff(old: &RichTerm, new: &RichTerm, array_key: Option<&Ident>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_(&DiffPath::default(), old, new, array_key, &mut changes);
+    changes
+}
+
+fn diff_(
+    path: &DiffPath,
+    old: &RichTerm,
+    new: &RichTerm,
+    array_key: Option<&Ident>,
+    changes: &mut Vec<Change>,
+) {
+    let old = unwrap_meta(old);
+    let new = unwrap_meta(new);
+
+    match (old.as_ref(), new.as_ref()) {
+        (Term::Record(old_fields, _), Term::Record(new_fields, _)) => {
+            let ids: BTreeSet<&Ident> = old_fields.keys().chain(new_fields.keys()).collect();
+
+            for id in ids {
+                let path = path.push(PathElem::Field(id.clone()));
+
+                match (old_fields.get(id), new_fields.get(id)) {
+                    (Some(old), Some(new)) => diff_(&path, old, new, array_key, changes),
+                    (Some(old), None) => changes.push(Change::Removed {
+                        path,
+                        old: unwrap_meta(old).clone(),
+                    }),
+                    (None, Some(new)) => changes.push(Change::Added {
+                        path,
+                        new: unwrap_meta(new).clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Term::Array(old_elts), Term::Array(new_elts)) => {
+            let keyed = array_key
+                .and_then(|key| Some((index_by_key(old_elts, key)?, index_by_key(new_elts, key)?)));
+
+            match keyed {
+                Some((old_keyed, new_keyed)) => {
+                    let new_map: HashMap<&String, &RichTerm> =
+                        new_keyed.iter().map(|(k, t)| (k, *t)).collect();
+                    let old_map: HashMap<&String, &RichTerm> =
+                        old_keyed.iter().map(|(k, t)| (k, *t)).collect();
+
+                    for (k, old) in old_keyed.iter() {
+                        let path = path.push(PathElem::Key(k.clone()));
+
+                        match new_map.get(k) {
+                            Some(new) => diff_(&path, old, new, array_key, changes),
+                            None => changes.push(Change::Removed {
+                                path,
+                                old: unwrap_meta(old).clone(),
+                            }),
+                        }
+                    }
+
+                    for (k, new) in new_keyed.iter().filter(|(k, _)| !old_map.contains_key(k)) {
+                        changes.push(Change::Added {
+                            path: path.push(PathElem::Key(k.clone())),
+                            new: unwrap_meta(new).clone(),
+                        })
+                    }
+                }
+                None => {
+                    for i in 0..std::cmp::max(old_elts.len(), new_elts.len()) {
+                        let path = path.push(PathElem::Index(i));
+
+                        match (old_elts.get(i), new_elts.get(i)) {
+                            (Some(old), Some(new)) => diff_(&path, old, new, array_key, changes),
+                            (Some(old), None) => changes.push(Change::Removed {
+                                path,
+                                old: unwrap_meta(old).clone(),
+                            }),
+                            (None, Some(new)) => changes.push(Change::Added {
+                                path,
+                                new: unwrap_meta(new).clone(),
+                            }),
+                            (None, None) => unreachable!(),
+                        }
+                    }
+                }
+            }
+        }
+        (t_old, t_new) if same_value(t_old, t_new) => (),
+        _ => changes.push(Change::Changed {
+            path: path.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+    }
+}
+
+/// Return a human-readable location `file:line:column` of a position, if it is defined.
+fn location(files: &Files<String>, pos: TermPos) -> Option<(String, usize, usize)> {
+    let span = pos.into_opt()?;
+    location_of(files, span.src_id, span.start)
+}
+
+fn location_of(
+    files: &Files<String>,
+    file_id: FileId,
+    index: codespan::ByteIndex,
+) -> Option<(String, usize, usize)> {
+    let loc = files.location(file_id, index).ok()?;
+    Some((
+        files.name(file_id).to_string_lossy().into_owned(),
+        loc.line.to_usize() + 1,
+        loc.column.to_usize() + 1,
+    ))
+}
+
+fn fmt_location(files: &Files<String>, pos: TermPos) -> String {
+    location(files, pos)
+        .map(|(file, line, col)| format!("{}:{}:{}", file, line, col))
+        .unwrap_or_else(|| String::from("<generated>"))
+}
+
+fn location_to_json(files: &Files<String>, pos: TermPos) -> Value {
+    location(files, pos)
+        .map(|(file, line, column)| json!({"file": file, "line": line, "column": column}))
+        .unwrap_or(Value::Null)
+}
+
+/// Convert a value to JSON, falling back to its shallow representation for values that can't be
+/// serialized (functions, etc.).
+fn value_to_json(rt: &RichTerm) -> Value {
+    serde_json::to_value(rt).unwrap_or_else(|_| Value::String(rt.as_ref().shallow_repr()))
+}
+
+/// Write a list of changes in the given format.
+///
+/// `old_files` and `new_files` are the file databases of the old and the new program
+/// respectively, used to report the positions of the values in their original source.
+pub fn write_changes(
+    out: &mut impl Write,
+    format: DiffFormat,
+    changes: &[Change],
+    old_files: &Files<String>,
+    new_files: &Files<String>,
+) -> io::Result<()> {
+    match format {
+        DiffFormat::Text => {
+            for change in changes {
+                match change {
+                    Change::Added { path, new } => writeln!(
+                        out,
+                        "+ {}: {} ({})",
+                        path,
+                        new.as_ref().deep_repr(),
+                        fmt_location(new_files, new.pos)
+                    )?,
+                    Change::Removed { path, old } => writeln!(
+                        out,
+                        "- {}: {} ({})",
+                        path,
+                        old.as_ref().deep_repr(),
+                        fmt_location(old_files, old.pos)
+                    )?,
+                    Change::Changed { path, old, new } => writeln!(
+                        out,
+                        "~ {}: {} -> {} ({} -> {})",
+                        path,
+                        old.as_ref().deep_repr(),
+                        new.as_ref().deep_repr(),
+                        fmt_location(old_files, old.pos),
+                        fmt_location(new_files, new.pos)
+                    )?,
+                }
+            }
+
+            Ok(())
+        }
+        DiffFormat::Json => {
+            let entries: Vec<Value> = changes
+                .iter()
+                .map(|change| match change {
+                    Change::Added { path, new } => json!({
+                        "kind": "added",
+                        "path": path.to_json(),
+                        "new": value_to_json(new),
+                        "new_pos": location_to_json(new_files, new.pos),
+                    }),
+                    Change::Removed { path, old } => json!({
+                        "kind": "removed",
+                        "path": path.to_json(),
+                        "old": value_to_json(old),
+                        "old_pos": location_to_json(old_files, old.pos),
+                    }),
+                    Change::Changed { path, old, new } => json!({
+                        "kind": "changed",
+                        "path": path.to_json(),
+                        "old": value_to_json(old),
+                        "new": value_to_json(new),
+                        "old_pos": location_to_json(old_files, old.pos),
+                        "new_pos": location_to_json(new_files, new.pos),
+                    }),
+                })
+                .collect();
+
+            serde_json::to_writer_pretty(&mut *out, &entries)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::program::Program;
+    use std::io::Cursor;
+
+    fn eval_full(s: &str) -> Result<RichTerm, Error> {
+        Program::new_from_source(Cursor::new(s), "<test>")
+            .unwrap()
+            .eval_full()
+    }
+
+    fn diff_str(old: &str, new: &str, key: Option<&str>) -> Vec<String> {
+        let old = eval_full(old).unwrap();
+        let new = eval_full(new).unwrap();
+        diff(&old, &new, key.map(Ident::from).as_ref())
+            .iter()
+            .map(|change| {
+                let sign = match change {
+                    Change::Added { .. } => "+",
+                    Change::Removed { .. } => "-",
+                    Change::Changed { .. } => "~",
+                };
+                format!("{}{}", sign, change.path())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records() {
+        assert!(diff_str("{a = 1, b = {c = \"s\"}}", "{a = 1, b.c = \"s\"}", None).is_empty());
+        assert_eq!(
+            diff_str(
+                "{a = 1, b = {c = \"s\", d = true}}",
+                "{a = 2, b = {c = \"s\", e = null}}",
+                None
+            ),
+            vec!["~a", "-b.d", "+b.e"]
+        );
+        assert_eq!(
+            diff_str("{a | default = 1}", "{a | Num = 1 + 1}", None),
+            vec!["~a"]
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(diff_str("[1, 2, 3]", "[1, 3]", None), vec!["~[1]", "-[2]"]);
+
+        let old = "[{name = \"a\", v = 1}, {name = \"b\", v = 2}]";
+        let new = "[{name = \"b\", v = 3}, {name = \"c\", v = 4}]";
+        assert_eq!(
+            diff_str(old, new, Some("name")),
+            vec!["-[a]", "~[b].v", "+[c]"]
+        );
+        // Fallback to indices when some elements don't have a key
+        assert_eq!(
+            diff_str("[{v = 1}]", "[{v = 2}]", Some("name")),
+            vec!["~[0].v"]
+        );
+    }
+}
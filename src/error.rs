@@ -5,10 +5,11 @@
 use std::fmt::Write;
 
 use codespan::{FileId, Files};
-use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle};
+use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle, Severity};
 use lalrpop_util::ErrorRecovery;
 
 use crate::{
+    eval,
     eval::callstack::CallStack,
     identifier::Ident,
     label,
@@ -22,8 +23,8 @@ use crate::{
     position::{RawSpan, TermPos},
     repl,
     serialize::ExportFormat,
-    term::RichTerm,
-    types::Types,
+    term::{MetaValue, RichTerm, StrChunk, Term},
+    types::{AbsType, Types},
 };
 
 /// A general error occurring during either parsing or evaluation.
@@ -36,6 +37,30 @@ pub enum Error {
     SerializationError(SerializationError),
     IOError(IOError),
     ReplError(ReplError),
+    ArgError(ArgError),
+    /// Warnings emitted during evaluation while warnings are denied (see
+    /// [`crate::program::Program::set_deny_warnings`]).
+    DeniedWarnings(Vec<EvalWarning>),
+    /// Several errors collected during evaluation (see
+    /// [`crate::program::Program::eval_full_collect`]).
+    EvalErrors(EvalErrors),
+}
+
+/// An evaluation error raised by a field or an element of the result of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// The path of the field or element, such as `foo.bar[2]`. Empty for the program itself.
+    pub path: String,
+    pub error: EvalError,
+}
+
+/// The errors collected during the evaluation of a program, when evaluation doesn't stop at the
+/// first contract violation.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EvalErrors {
+    pub errors: Vec<FieldError>,
+    /// If evaluation stopped after reaching the maximum number of errors.
+    pub truncated: bool,
 }
 
 /// An error occurring during evaluation.
@@ -91,6 +116,11 @@ pub enum EvalError {
         String,  /* error message */
         TermPos, /* position of the call to deserialize */
     ),
+    /// Tried to read an environment variable which has not been allowed.
+    EnvAccessDenied(
+        /* variable name */ String,
+        /* position of the access */ TermPos,
+    ),
     /// An unexpected internal error.
     InternalError(String, TermPos),
     /// Errors occurring rarely enough to not deserve a dedicated variant.
@@ -309,6 +339,8 @@ pub enum ParseError {
     /// A recursive let pattern was encountered. They are not currently supported because we
     /// decided it was too involved to implement them.
     RecursiveLetPattern(RawSpan),
+    /// The message of a deprecation annotation is not a literal string.
+    InterpolatedDeprecationMessage(RawSpan),
 }
 
 /// An error occurring during the resolution of an import.
@@ -336,6 +368,12 @@ pub enum SerializationError {
     NotAString(RichTerm),
     /// A term contains constructs that cannot be serialized.
     NonSerializable(RichTerm),
+    /// Tried exporting something else than an `Array` as multiple YAML documents.
+    NotAnArray(RichTerm),
+    /// Tried exporting something else than a `Record` as multiple files.
+    NotARecord(RichTerm),
+    /// A field of a multi-file export is not a relative path inside the output directory.
+    InvalidPath(Ident),
     Other(String),
 }
 
@@ -343,6 +381,43 @@ pub enum SerializationError {
 #[derive(Debug, PartialEq, Clone)]
 pub struct IOError(pub String);
 
+/// An error occurring when applying a top-level function program to the arguments provided on the
+/// command line.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArgError {
+    /// Arguments were provided, but the program is not a function.
+    NotAFunction(/* provided arguments */ Vec<Ident>, TermPos),
+    /// Some fields of the argument pattern have no default value and were not provided.
+    MissingArgs(
+        /* missing fields, with their documentation */ Vec<(Ident, Option<String>)>,
+        /* pattern position */ RawSpan,
+    ),
+    /// Some arguments don't correspond to any field of a closed argument pattern.
+    UnknownArgs(
+        /* unknown arguments */ Vec<Ident>,
+        /* pattern position */ RawSpan,
+    ),
+    /// Some arguments were provided more than once.
+    DuplicateArgs(/* duplicate arguments */ Vec<Ident>),
+}
+
+/// A warning emitted during evaluation. Warnings don't interrupt evaluation, and are collected in
+/// the [evaluation context][crate::eval::context::EvalContext].
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalWarning {
+    /// A deprecated value was accessed, or a deprecated field was given a value by merging.
+    Deprecated {
+        /// The accessed field, if the value was accessed as a record field.
+        field: Option<Ident>,
+        /// The deprecation message.
+        message: String,
+        /// The position of the deprecated definition.
+        definition: TermPos,
+        /// The position of the access, or of the value set by merging.
+        usage: TermPos,
+    },
+}
+
 /// An error occurring during an REPL session.
 #[derive(Debug, PartialEq, Clone)]
 pub enum ReplError {
@@ -397,6 +472,12 @@ impl From<IOError> for Error {
     }
 }
 
+impl From<ArgError> for Error {
+    fn from(error: ArgError) -> Error {
+        Error::ArgError(error)
+    }
+}
+
 impl From<std::io::Error> for IOError {
     fn from(error: std::io::Error) -> IOError {
         IOError(error.to_string())
@@ -409,6 +490,287 @@ impl From<SerializationError> for EvalError {
     }
 }
 
+// Stable error codes. Each error is identified by a code of the form `Exxyy`, where `xx` is the
+// phase (`01` for parsing, `02` for typechecking, and so on) and `yy` identifies the error inside
+// the phase. Codes must never be reused nor reassigned: when an error variant is removed, its code
+// is retired. Each code has a long-form explanation in `crate::error_codes`.
+
+impl ParseError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnexpectedEOF(..) => "E0101",
+            ParseError::UnexpectedToken(..) => "E0102",
+            ParseError::ExtraToken(..) => "E0103",
+            ParseError::UnmatchedCloseBrace(..) => "E0104",
+            ParseError::InvalidEscapeSequence(..) => "E0105",
+            ParseError::InvalidAsciiEscapeCode(..) => "E0106",
+            ParseError::ExternalFormatError(..) => "E0107",
+            ParseError::UnboundTypeVariables(..) => "E0108",
+            ParseError::InvalidUniRecord(..) => "E0109",
+            ParseError::RecursiveLetPattern(..) => "E0110",
+            ParseError::InterpolatedDeprecationMessage(..) => "E0111",
+        }
+    }
+}
+
+impl TypecheckError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypecheckError::UnboundIdentifier(..) => "E0201",
+            TypecheckError::IllformedType(..) => "E0202",
+            TypecheckError::MissingRow(..) => "E0203",
+            TypecheckError::MissingDynTail(..) => "E0204",
+            TypecheckError::ExtraRow(..) => "E0205",
+            TypecheckError::ExtraDynTail(..) => "E0206",
+            TypecheckError::UnboundTypeVariable(..) => "E0207",
+            TypecheckError::TypeMismatch(..) => "E0208",
+            TypecheckError::RowKindMismatch(..) => "E0209",
+            TypecheckError::RowMismatch(..) => "E0210",
+            TypecheckError::RowConflict(..) => "E0211",
+            TypecheckError::ArrowTypeMismatch(..) => "E0212",
+        }
+    }
+}
+
+impl EvalError {
+    /// The stable code identifying this error. A serialization error raised by the `serialize`
+    /// builtin has the code of the underlying [`SerializationError`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::BlameError(..) => "E0301",
+            EvalError::MissingFieldDef(..) => "E0302",
+            EvalError::TypeError(..) => "E0303",
+            EvalError::NotAFunc(..) => "E0304",
+            EvalError::FieldMissing(..) => "E0305",
+            EvalError::NotEnoughArgs(..) => "E0306",
+            EvalError::MergeIncompatibleArgs(..) => "E0307",
+            EvalError::UnboundIdentifier(..) => "E0308",
+            EvalError::InfiniteRecursion(..) => "E0309",
+            EvalError::SerializationError(err) => err.code(),
+            EvalError::DeserializationError(..) => "E0310",
+            EvalError::EnvAccessDenied(..) => "E0311",
+            EvalError::InternalError(..) => "E0312",
+            EvalError::Other(..) => "E0313",
+        }
+    }
+}
+
+impl ImportError {
+    /// The stable code identifying this error. A parse error in an imported file has the code of
+    /// the underlying [`ParseError`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            ImportError::IOError(..) => "E0401",
+            ImportError::ParseErrors(errs, _) => {
+                errs.errors.first().map(ParseError::code).unwrap_or("E0102")
+            }
+        }
+    }
+}
+
+impl SerializationError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SerializationError::UnsupportedNull(..) => "E0501",
+            SerializationError::NotAString(..) => "E0502",
+            SerializationError::NonSerializable(..) => "E0503",
+            SerializationError::NotAnArray(..) => "E0504",
+            SerializationError::NotARecord(..) => "E0505",
+            SerializationError::InvalidPath(..) => "E0506",
+            SerializationError::Other(..) => "E0507",
+        }
+    }
+}
+
+impl IOError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        "E0601"
+    }
+}
+
+impl ReplError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReplError::UnknownCommand(..) => "E0701",
+            ReplError::MissingArg { .. } => "E0702",
+        }
+    }
+}
+
+impl ArgError {
+    /// The stable code identifying this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ArgError::NotAFunction(..) => "E0801",
+            ArgError::MissingArgs(..) => "E0802",
+            ArgError::UnknownArgs(..) => "E0803",
+            ArgError::DuplicateArgs(..) => "E0804",
+        }
+    }
+}
+
+impl EvalWarning {
+    /// The name identifying this kind of warning. Warnings are identified by a name rather than by
+    /// a numbered error code, as lints (see [`crate::lint::Lint::name`]).
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalWarning::Deprecated { .. } => "deprecated",
+        }
+    }
+}
+
+/// Structured data about an error which has a mechanical fix, such as a misspelled field. The
+/// fixes themselves are edits of the source, which are computed from this data by tools such as
+/// the language server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixData {
+    /// An access to a field which doesn't exist. The position of the identifier is the one of the
+    /// misspelled field.
+    MisspelledField(
+        Ident,
+        /* the existing fields with a close name, from the closest one */ Vec<Ident>,
+    ),
+    /// Fields required by a contract which are missing from a record.
+    MissingFields(
+        /* the missing fields, with their type if any */ Vec<(Ident, Option<Types>)>,
+        /* the position of the record */ TermPos,
+    ),
+    /// An unbound identifier, which may be the name of a member of the standard library.
+    UnboundIdentifier(Ident, TermPos),
+    /// An expression of type `Dyn` used where a value of a static type is expected. It can be
+    /// converted to the expected type by a contract annotation.
+    DynamicValue(
+        /* the expected type, where unknown parts are `Dyn` */ Types,
+        TermPos,
+    ),
+}
+
+impl TypecheckError {
+    /// The data needed to fix this error, if it has a mechanical fix.
+    pub fn fix_data(&self) -> Option<FixData> {
+        match self {
+            TypecheckError::UnboundIdentifier(id, pos) => {
+                Some(FixData::UnboundIdentifier(id.clone(), *pos))
+            }
+            TypecheckError::MissingRow(id, _, actual, _) if id.pos.as_opt_ref().is_some() => {
+                let candidates = close_fields(id, actual);
+                if candidates.is_empty() {
+                    None
+                } else {
+                    Some(FixData::MisspelledField(id.clone(), candidates))
+                }
+            }
+            TypecheckError::TypeMismatch(expd, Types(AbsType::Dyn()), pos) => {
+                Some(FixData::DynamicValue(unknowns_to_dyn(expd), *pos))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl EvalError {
+    /// The data needed to fix this error, if it has a mechanical fix.
+    pub fn fix_data(&self) -> Option<FixData> {
+        match self {
+            EvalError::BlameError(l, _) => {
+                let ty = ty_path::subtype(&l.path, &l.types)?;
+                let value = l.arg_thunk.as_ref()?.get_owned().body;
+                let RecordFieldsDiff { missing, .. } = record_fields_diff(ty, value.as_ref())?;
+                if missing.is_empty() {
+                    return None;
+                }
+
+                // The argument of a contract nested in a record contract is a variable of the
+                // builtin contract, while its value has the position of the record
+                let pos = if value.pos.is_def() {
+                    value.pos
+                } else {
+                    l.arg_pos
+                };
+                let fields = missing
+                    .into_iter()
+                    .map(|(id, ty)| (id.clone(), ty.cloned()))
+                    .collect();
+                Some(FixData::MissingFields(fields, pos))
+            }
+            EvalError::MissingFieldDef(label, callstack) => {
+                let (field, pos_record, _) = missing_field_def(callstack);
+                let ty = label.as_ref().map(|l| l.types.as_ref().clone());
+                Some(FixData::MissingFields(
+                    vec![(Ident::from(field?), ty)],
+                    pos_record,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The fields of a record type `ty` whose name is close enough to `id` to be what a misspelled
+/// `id` was meant to be, from the closest one.
+fn close_fields(id: &Ident, ty: &Types) -> Vec<Ident> {
+    let mut row = match &ty.0 {
+        AbsType::StaticRecord(rows) => rows.as_ref(),
+        _ => return Vec::new(),
+    };
+
+    // Allow one edit, and one more for every three characters, as `port` for `prot`
+    let max_distance = 1 + id.label.chars().count() / 3;
+    let mut candidates = Vec::new();
+    while let AbsType::RowExtend(field, _, tail) = &row.0 {
+        let distance = edit_distance(&id.label, &field.label);
+        if distance <= max_distance {
+            candidates.push((distance, field.clone()));
+        }
+        row = tail.as_ref();
+    }
+
+    candidates.sort_by_key(|(distance, _)| *distance);
+    candidates.into_iter().map(|(_, field)| field).collect()
+}
+
+/// The Levenshtein distance between two strings, that is the number of characters to insert,
+/// delete or substitute to transform one into the other.
+fn edit_distance(s1: &str, s2: &str) -> usize {
+    let s2: Vec<char> = s2.chars().collect();
+    // The distances between the prefix of `s1` processed so far and each prefix of `s2`
+    let mut distances: Vec<usize> = (0..=s2.len()).collect();
+
+    for (i, c1) in s1.chars().enumerate() {
+        let mut previous_diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, c2) in s2.iter().enumerate() {
+            let substitution = previous_diagonal + usize::from(c1 != *c2);
+            previous_diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(distances[j + 1] + 1);
+        }
+    }
+
+    distances[s2.len()]
+}
+
+/// Replace the unknown parts of a type reported by the typechecker, which are free unification
+/// variables named `_a`, `_b`, and so on, by `Dyn`.
+fn unknowns_to_dyn(ty: &Types) -> Types {
+    match &ty.0 {
+        AbsType::Var(id) if id.label.starts_with('_') => Types(AbsType::Dyn()),
+        abs_ty => Types(abs_ty.clone().map(|ty| Box::new(unknowns_to_dyn(&ty)))),
+    }
+}
+
+/// Attach an error code to the main diagnostic of an error, which is the first one.
+fn with_code(mut diags: Vec<Diagnostic<FileId>>, code: &str) -> Vec<Diagnostic<FileId>> {
+    if let Some(diag) = diags.first_mut() {
+        diag.code = Some(String::from(code));
+    }
+    diags
+}
+
 /// Return an escaped version of a string. Used to sanitize strings before inclusion in error
 /// messages, which can contain ASCII code sequences, and in particular ANSI escape codes, that
 /// could alter Nickel's error messages.
@@ -421,6 +783,18 @@ pub fn escape(s: &str) -> String {
     .expect("escape(): converting from a string should give back a valid UTF8 string")
 }
 
+impl From<Vec<EvalWarning>> for Error {
+    fn from(warnings: Vec<EvalWarning>) -> Error {
+        Error::DeniedWarnings(warnings)
+    }
+}
+
+impl From<EvalErrors> for Error {
+    fn from(errors: EvalErrors) -> Error {
+        Error::EvalErrors(errors)
+    }
+}
+
 impl From<ReplError> for Error {
     fn from(error: ReplError) -> Error {
         Error::ReplError(error)
@@ -468,6 +842,9 @@ impl ParseError {
                 InternalParseError::RecursiveLetPattern(pos) => {
                     ParseError::RecursiveLetPattern(pos)
                 }
+                InternalParseError::InterpolatedDeprecationMessage(pos) => {
+                    ParseError::InterpolatedDeprecationMessage(pos)
+                }
             },
         }
     }
@@ -566,15 +943,29 @@ pub trait ToDiagnostic<FileId> {
     ) -> Vec<Diagnostic<FileId>>;
 }
 
+/// Report several errors or warnings at once, which matters for the machine-readable formats of
+/// [`crate::diagnostic`] that group the diagnostics of a run in one document.
+impl<T: ToDiagnostic<FileId>> ToDiagnostic<FileId> for Vec<T> {
+    fn to_diagnostic(
+        &self,
+        files: &mut Files<String>,
+        contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        self.iter()
+            .flat_map(|e| e.to_diagnostic(files, contract_id))
+            .collect()
+    }
+}
+
 // Helpers for the creation of codespan `Label`s
 
 /// Create a primary label from a span.
-fn primary(span: &RawSpan) -> Label<FileId> {
+pub(crate) fn primary(span: &RawSpan) -> Label<FileId> {
     Label::primary(span.src_id, span.start.to_usize()..span.end.to_usize())
 }
 
 /// Create a secondary label from a span.
-fn secondary(span: &RawSpan) -> Label<FileId> {
+pub(crate) fn secondary(span: &RawSpan) -> Label<FileId> {
     Label::secondary(span.src_id, span.start.to_usize()..span.end.to_usize())
 }
 
@@ -789,6 +1180,289 @@ fn blame_label_note(l: &label::Label) -> Diagnostic<FileId> {
     .with_message("bound here")])
 }
 
+/// The maximum number of nested records, arrays and variables unfolded when rendering the value
+/// responsible for a blame.
+const VALUE_MAX_DEPTH: usize = 8;
+/// The maximum number of lines of the rendering of the value responsible for a blame.
+const VALUE_MAX_LINES: usize = 12;
+/// The width used to render the value responsible for a blame. Longer lines are truncated.
+const VALUE_WIDTH: usize = 80;
+
+/// Substitute the variables of a value with their content, as [crate::eval::subst], but without
+/// going deeper than `depth` nested records, arrays and variables. Deeper values are elided as
+/// `…`, which also guards against cyclic values such as recursive records. Expressions which are
+/// not evaluated yet are elided as well.
+fn unfold_value(rt: RichTerm, env: &eval::Environment, depth: usize) -> RichTerm {
+    let elided = || RichTerm::from(Term::Var(Ident::from("…")));
+    let pos = rt.pos;
+
+    match rt.as_ref() {
+        Term::Var(id) => match env.get(id) {
+            Some(_) if depth == 0 => elided(),
+            Some(thunk) => {
+                let closure = thunk.get_owned();
+                unfold_value(closure.body, &closure.env, depth - 1)
+            }
+            None => rt,
+        },
+        Term::MetaValue(MetaValue {
+            value: Some(value), ..
+        }) => unfold_value(value.clone(), env, depth),
+        Term::Record(..) | Term::RecRecord(..) | Term::Array(..) if depth == 0 => elided(),
+        Term::Record(fields, attrs) | Term::RecRecord(fields, _, attrs, _) => {
+            let fields = fields
+                .iter()
+                .map(|(id, t)| (id.clone(), unfold_value(t.clone(), env, depth - 1)))
+                .collect();
+            RichTerm::new(Term::Record(fields, *attrs), pos)
+        }
+        Term::Array(ts) => {
+            let ts = ts
+                .iter()
+                .map(|t| unfold_value(t.clone(), env, depth - 1))
+                .collect();
+            RichTerm::new(Term::Array(ts), pos)
+        }
+        Term::Let(id, t1, t2, _) => {
+            let mut env = env.clone();
+            let closure = eval::Closure {
+                body: t1.clone(),
+                env: env.clone(),
+            };
+            env.insert(
+                id.clone(),
+                eval::lazy::Thunk::new(closure, eval::IdentKind::Let),
+            );
+            unfold_value(t2.clone(), &env, depth)
+        }
+        // Applications, primitive operations and the like are not evaluated yet and may be
+        // arbitrarily large internal expressions (e.g. lazily applied contracts): elide them.
+        Term::App(..)
+        | Term::Op1(..)
+        | Term::Op2(..)
+        | Term::OpN(..)
+        | Term::Switch(..)
+        | Term::LetPattern(..)
+        | Term::Wrapped(..) => elided(),
+        Term::StrChunks(chunks)
+            if chunks
+                .iter()
+                .any(|chunk| matches!(chunk, StrChunk::Expr(..))) =>
+        {
+            elided()
+        }
+        _ => rt,
+    }
+}
+
+/// Pretty-print a value responsible for a blame, truncating long lines and large values.
+fn render_value(value: RichTerm) -> String {
+    use crate::pretty::*;
+    use pretty::BoxAllocator;
+
+    let allocator = BoxAllocator;
+    let doc: DocBuilder<_, ()> = value.pretty(&allocator);
+    let mut out = Vec::new();
+    doc.render(VALUE_WIDTH, &mut out).unwrap();
+    let rendered = String::from_utf8_lossy(&out);
+
+    let mut lines: Vec<String> = rendered
+        .trim()
+        .lines()
+        .map(|line| match line.char_indices().nth(VALUE_WIDTH) {
+            Some((index, _)) => format!("{}…", &line[..index]),
+            None => String::from(line),
+        })
+        .collect();
+    if lines.len() > VALUE_MAX_LINES {
+        lines.truncate(VALUE_MAX_LINES);
+        lines.push(String::from("…"));
+    }
+
+    lines.join("\n")
+}
+
+/// Determine the name of a field missing a definition, the position of its record and the
+/// position of the access which required it, from the call stack of a
+/// [`EvalError::MissingFieldDef`].
+fn missing_field_def(callstack: &CallStack) -> (Option<String>, TermPos, TermPos) {
+    use crate::eval::callstack::StackElem;
+
+    // The following code determines what was the last accessed record field by looking at the
+    // call stack. Because of recursive records though, the fields may actually be accessed via a
+    // variable:
+    //
+    // ```
+    //  {
+    //    foo | Dyn
+    //        | doc "Oops, undefined :(",
+    //    bar = 1 + foo,
+    //  }.bar
+    //  ```
+    //
+    // Here, the missing field doesn't correspond to a field access, but to a variable occurrence
+    // `foo`. Thus, we take the last non-generated identifier accessed (either variable or field)
+    // as the name of the missing field.
+    let mut field: Option<String> = None;
+    let mut pos_record = TermPos::None;
+    let mut pos_access = TermPos::None;
+
+    for elt in callstack.as_ref().iter().rev() {
+        match elt {
+            StackElem::Var { id, pos, .. } if !id.is_generated() && field.is_none() => {
+                field = Some(id.to_string());
+                pos_access = *pos;
+            }
+            StackElem::Field {
+                id,
+                pos_record: pos_rec,
+                pos_access: pos_acc,
+                ..
+            } => {
+                field = Some(id.to_string());
+                pos_access = *pos_acc;
+                pos_record = *pos_rec;
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    (field, pos_record, pos_access)
+}
+
+/// The difference between the fields of a record and the fields of a record contract.
+struct RecordFieldsDiff<'a> {
+    /// The fields required by the contract which are missing from the record, with their type if
+    /// any.
+    missing: Vec<(&'a Ident, Option<&'a Types>)>,
+    /// The fields of the record which aren't allowed by the contract.
+    extra: Vec<&'a Ident>,
+}
+
+/// Compare the fields of `value` with the fields of the record contract `ty`. Return `None` if
+/// `ty` isn't a record contract or `value` isn't a record.
+fn record_fields_diff<'a>(ty: &'a Types, value: &'a Term) -> Option<RecordFieldsDiff<'a>> {
+    let actual: Vec<&Ident> = match value {
+        Term::Record(fields, _) | Term::RecRecord(fields, ..) => fields.keys().collect(),
+        _ => return None,
+    };
+
+    // The fields of the contract, their type, whether they are required, and if the contract is
+    // open.
+    let (expected, open) = match &ty.0 {
+        AbsType::StaticRecord(rows) => {
+            let mut expected = Vec::new();
+            let mut row = rows.as_ref();
+            let open = loop {
+                match &row.0 {
+                    AbsType::RowExtend(id, ty, tail) => {
+                        expected.push((id, ty.as_deref(), true));
+                        row = tail.as_ref();
+                    }
+                    AbsType::RowEmpty() => break false,
+                    _ => break true,
+                }
+            };
+            (expected, open)
+        }
+        AbsType::Flat(rt) => match rt.as_ref() {
+            Term::Record(fields, attrs) | Term::RecRecord(fields, _, attrs, _) => {
+                let expected = fields
+                    .iter()
+                    .map(|(id, t)| match t.as_ref() {
+                        Term::MetaValue(meta) => {
+                            let ty = meta.types.as_ref().or_else(|| meta.contracts.first());
+                            (id, ty.map(|ctr| &ctr.types), meta.value.is_none())
+                        }
+                        _ => (id, None, false),
+                    })
+                    .collect::<Vec<_>>();
+                (expected, attrs.open)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let missing = expected
+        .iter()
+        .filter(|(id, _, required)| *required && !actual.contains(id))
+        .map(|(id, ty, _)| (*id, *ty))
+        .collect();
+    let extra = actual
+        .iter()
+        .filter(|id| !open && !expected.iter().any(|(other, _, _)| other == *id))
+        .copied()
+        .collect();
+
+    Some(RecordFieldsDiff { missing, extra })
+}
+
+/// For a record contract, list the fields required by the contract which are missing from
+/// `value`, and the fields of `value` which aren't allowed by the contract.
+fn report_record_fields(ty: &Types, value: &Term) -> Vec<String> {
+    let RecordFieldsDiff { missing, extra } = match record_fields_diff(ty, value) {
+        Some(diff) => diff,
+        None => return Vec::new(),
+    };
+
+    let list = |mut ids: Vec<&Ident>| {
+        ids.sort();
+        ids.into_iter()
+            .map(|id| format!("`{}`", id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut notes = Vec::new();
+    if !missing.is_empty() {
+        notes.push(format!(
+            "missing fields: {}",
+            list(missing.into_iter().map(|(id, _)| id).collect())
+        ));
+    }
+    if !extra.is_empty() {
+        notes.push(format!("extra fields: {}", list(extra)));
+    }
+    notes
+}
+
+/// Generate the notes describing the value responsible for a blame: the part of the type it
+/// failed to respect, the value itself, and the missing and extra fields for record contracts.
+fn report_blame_value(l: &label::Label) -> Vec<String> {
+    let mut notes = Vec::new();
+    let subtype = ty_path::subtype(&l.path, &l.types);
+
+    if !l.path.is_empty() {
+        match (subtype, ty_path::data_path(&l.path)) {
+            (Some(ty), Some(path)) if ty_path::has_no_arrow(&l.path) => {
+                notes.push(format!("expected a value of type `{}` at `{}`", ty, path))
+            }
+            (Some(ty), _) => notes.push(format!("expected a value of type `{}`", ty)),
+            _ => (),
+        }
+    }
+
+    if let Some(thunk) = &l.arg_thunk {
+        let closure = thunk.get_owned();
+        let value = unfold_value(closure.body, &closure.env, VALUE_MAX_DEPTH);
+
+        if let Some(ty) = subtype {
+            notes.extend(report_record_fields(ty, value.as_ref()));
+        }
+
+        // The shallow representation is already shown by a label.
+        let shallow = value.as_ref().shallow_repr();
+        let rendered = render_value(value);
+        if rendered != shallow {
+            notes.push(format!("value:\n{}", rendered));
+        }
+    }
+
+    notes
+}
+
 impl ToDiagnostic<FileId> for Error {
     fn to_diagnostic(
         &self,
@@ -808,7 +1482,53 @@ impl ToDiagnostic<FileId> for Error {
             Error::SerializationError(err) => err.to_diagnostic(files, contract_id),
             Error::IOError(err) => err.to_diagnostic(files, contract_id),
             Error::ReplError(err) => err.to_diagnostic(files, contract_id),
+            Error::ArgError(err) => err.to_diagnostic(files, contract_id),
+            Error::DeniedWarnings(warnings) => warnings
+                .to_diagnostic(files, contract_id)
+                .into_iter()
+                .map(|mut diag| {
+                    diag.severity = Severity::Error;
+                    diag.with_notes(vec![String::from("warnings are denied")])
+                })
+                .collect(),
+            Error::EvalErrors(errors) => errors.to_diagnostic(files, contract_id),
+        }
+    }
+}
+
+impl ToDiagnostic<FileId> for FieldError {
+    fn to_diagnostic(
+        &self,
+        files: &mut Files<String>,
+        contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let mut diags = self.error.to_diagnostic(files, contract_id);
+
+        if let Some(diag) = diags.first_mut().filter(|_| !self.path.is_empty()) {
+            diag.notes
+                .push(format!("raised by the field `{}`", self.path));
         }
+
+        diags
+    }
+}
+
+impl ToDiagnostic<FileId> for EvalErrors {
+    fn to_diagnostic(
+        &self,
+        files: &mut Files<String>,
+        contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let mut diags = self.errors.to_diagnostic(files, contract_id);
+
+        if self.truncated {
+            diags.push(Diagnostic::note().with_message(format!(
+                "evaluation stopped after {} errors",
+                self.errors.len()
+            )));
+        }
+
+        diags
     }
 }
 
@@ -818,7 +1538,7 @@ impl ToDiagnostic<FileId> for EvalError {
         files: &mut Files<String>,
         contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let diags = match self {
             EvalError::BlameError(l, call_stack) => {
                 let mut msg = String::new();
 
@@ -838,8 +1558,10 @@ impl ToDiagnostic<FileId> for EvalError {
                     write!(&mut msg, ": {}", &escape(&l.tag)).unwrap();
                 }
 
-                let (path_label, notes) = report_ty_path(l, files);
+                let (path_label, ty_path_notes) = report_ty_path(l, files);
                 let mut labels = vec![path_label];
+                let mut notes = report_blame_value(l);
+                notes.extend(ty_path_notes);
 
                 if let Some(ref arg_pos) = l.arg_pos.into_opt() {
                     // In some cases, if the blame error is located in an argument or return value
@@ -938,47 +1660,7 @@ impl ToDiagnostic<FileId> for EvalError {
                 diagnostics
             }
             EvalError::MissingFieldDef(label, callstack) => {
-                use crate::eval::callstack::StackElem;
-
-                // The following code determines what was the last accessed record field by looking
-                // at the call stack. Because of recursive records though, the fields may actually
-                // be accessed via a variable:
-                //
-                // ```
-                //  {
-                //    foo | Dyn
-                //        | doc "Oops, undefined :(",
-                //    bar = 1 + foo,
-                //  }.bar
-                //  ```
-                //
-                // Here, the missing field doesn't correspond to a field access, but to a variable
-                // occurrence `foo`. Thus, we take the last non-generated identifier accessed
-                // (either variable or field) as the name of the missing field.
-                let mut field: Option<String> = None;
-                let mut pos_record = TermPos::None;
-                let mut pos_access = TermPos::None;
-
-                for elt in callstack.as_ref().iter().rev() {
-                    match elt {
-                        StackElem::Var { id, pos, .. } if !id.is_generated() && field.is_none() => {
-                            field = Some(id.to_string());
-                            pos_access = *pos;
-                        }
-                        StackElem::Field {
-                            id,
-                            pos_record: pos_rec,
-                            pos_access: pos_acc,
-                            ..
-                        } => {
-                            field = Some(id.to_string());
-                            pos_access = *pos_acc;
-                            pos_record = *pos_rec;
-                            break;
-                        }
-                        _ => (),
-                    }
-                }
+                let (field, pos_record, pos_access) = missing_field_def(callstack);
 
                 let mut labels = vec![];
 
@@ -1133,6 +1815,23 @@ impl ToDiagnostic<FileId> for EvalError {
 
                 vec![Diagnostic::error().with_message(msg).with_labels(labels)]
             }
+            EvalError::EnvAccessDenied(name, span_opt) => {
+                let labels = span_opt
+                    .as_opt_ref()
+                    .map(|span| vec![primary(span).with_message("accessed here")])
+                    .unwrap_or_default();
+
+                vec![Diagnostic::error()
+                    .with_message(format!(
+                        "access to the environment variable `{}` is not allowed",
+                        name
+                    ))
+                    .with_labels(labels)
+                    .with_notes(vec![format!(
+                        "evaluation is hermetic by default: use `--allow-env {}` to allow it",
+                        name
+                    )])]
+            }
             EvalError::InternalError(msg, span_opt) => {
                 let labels = span_opt
                     .as_opt_ref()
@@ -1156,7 +1855,9 @@ impl ToDiagnostic<FileId> for EvalError {
                     .with_labels(labels)
                     .with_notes(vec![String::from(INTERNAL_ERROR_MSG)])]
             }
-        }
+        };
+
+        with_code(diags, self.code())
     }
 }
 
@@ -1235,9 +1936,15 @@ impl ToDiagnostic<FileId> for ParseError {
                     String::from("A destructuring let-binding can't be recursive. Try removing the `rec` from `let rec`."),
                     String::from("Note: you can reference other fields of a record recursively from within a field, so you might not need the recursive let."),
                 ]),
+            ParseError::InterpolatedDeprecationMessage(span) => Diagnostic::error()
+                .with_message("the message of a deprecation must be a literal string")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "Remove the interpolated expressions from the message, as in `| deprecated \"use `new` instead\"`.",
+                )]),
         };
 
-        vec![diagnostic]
+        vec![diagnostic.with_code(self.code())]
     }
 }
 
@@ -1254,7 +1961,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
                 .unwrap_or_default()
         }
 
-        match self {
+        let diags = match self {
             TypecheckError::UnboundIdentifier(ident, pos_opt) =>
             // Use the same diagnostic as `EvalError::UnboundIdentifier` for consistency.
                 {
@@ -1449,7 +2156,9 @@ impl ToDiagnostic<FileId> for TypecheckError {
 
                 diags
             }
-        }
+        };
+
+        with_code(diags, self.code())
     }
 }
 
@@ -1459,7 +2168,7 @@ impl ToDiagnostic<FileId> for ImportError {
         files: &mut Files<String>,
         contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let diags = match self {
             ImportError::IOError(path, error, span_opt) => {
                 let labels = span_opt
                     .as_opt_ref()
@@ -1486,7 +2195,9 @@ impl ToDiagnostic<FileId> for ImportError {
 
                 diagnostic
             }
-        }
+        };
+
+        with_code(diags, self.code())
     }
 }
 
@@ -1496,7 +2207,7 @@ impl ToDiagnostic<FileId> for SerializationError {
         files: &mut Files<String>,
         _contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let diags = match self {
             SerializationError::NotAString(rt) => vec![Diagnostic::error()
                 .with_message(format!(
                     "raw export only supports `Str`, got {}",
@@ -1511,10 +2222,42 @@ impl ToDiagnostic<FileId> for SerializationError {
             SerializationError::NonSerializable(rt) => vec![Diagnostic::error()
                 .with_message("non serializable term")
                 .with_labels(vec![primary_term(rt, files)])],
+            SerializationError::NotAnArray(rt) => vec![Diagnostic::error()
+                .with_message(format!(
+                    "multi-document export only supports `Array`, got {}",
+                    rt.as_ref()
+                        .type_of()
+                        .unwrap_or_else(|| String::from("<unevaluated>"))
+                ))
+                .with_labels(vec![primary_term(rt, files)])],
+            SerializationError::NotARecord(rt) => vec![Diagnostic::error()
+                .with_message(format!(
+                    "multi-file export only supports `Record`, got {}",
+                    rt.as_ref()
+                        .type_of()
+                        .unwrap_or_else(|| String::from("<unevaluated>"))
+                ))
+                .with_labels(vec![primary_term(rt, files)])],
+            SerializationError::InvalidPath(id) => {
+                let labels = id
+                    .pos
+                    .as_opt_ref()
+                    .map(|span| vec![primary(span).with_message("defined here")])
+                    .unwrap_or_default();
+
+                vec![Diagnostic::error()
+                    .with_message(format!("invalid output path `{}`", escape(&id.label)))
+                    .with_labels(labels)
+                    .with_notes(vec![String::from(
+                        "paths must be relative and stay inside the output directory",
+                    )])]
+            }
             SerializationError::Other(msg) => vec![Diagnostic::error()
                 .with_message("error during serialization")
                 .with_notes(vec![msg.clone()])],
-        }
+        };
+
+        with_code(diags, self.code())
     }
 }
 
@@ -1524,9 +2267,116 @@ impl ToDiagnostic<FileId> for IOError {
         _files: &mut Files<String>,
         _contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let diags = match self {
             IOError(msg) => vec![Diagnostic::error().with_message(msg.clone())],
-        }
+        };
+
+        with_code(diags, self.code())
+    }
+}
+
+impl ToDiagnostic<FileId> for ArgError {
+    fn to_diagnostic(
+        &self,
+        _files: &mut Files<String>,
+        _contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let list = |ids: &Vec<Ident>| {
+            ids.iter()
+                .map(|id| format!("`{}`", id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let diags = match self {
+            ArgError::NotAFunction(args, pos) => {
+                let labels = pos
+                    .as_opt_ref()
+                    .map(|span| vec![primary(span).with_message("this is not a function")])
+                    .unwrap_or_default();
+
+                vec![Diagnostic::error()
+                    .with_message("arguments were provided, but the program is not a function")
+                    .with_labels(labels)
+                    .with_notes(vec![format!("provided arguments: {}", list(args))])]
+            }
+            ArgError::MissingArgs(missing, span) => {
+                let mut notes: Vec<String> = missing
+                    .iter()
+                    .map(|(id, doc)| match doc {
+                        Some(doc) => format!("`{}`: {}", id, doc.trim()),
+                        None => format!("`{}`", id),
+                    })
+                    .collect();
+                notes.push(String::from(
+                    "provide them with `--arg <name>=<expression>` or `--arg-str <name>=<string>`",
+                ));
+
+                vec![Diagnostic::error()
+                    .with_message(format!(
+                        "missing argument{} to the program",
+                        if missing.len() > 1 { "s" } else { "" }
+                    ))
+                    .with_labels(vec![primary(span).with_message("required by this pattern")])
+                    .with_notes(notes)]
+            }
+            ArgError::UnknownArgs(unknown, span) => vec![Diagnostic::error()
+                .with_message(format!(
+                    "unknown argument{} {}",
+                    if unknown.len() > 1 { "s" } else { "" },
+                    list(unknown)
+                ))
+                .with_labels(vec![
+                    primary(span).with_message("expected fields are listed here")
+                ])
+                .with_notes(vec![String::from(
+                    "this pattern is closed: add `, ..` at the end to accept additional arguments",
+                )])],
+            ArgError::DuplicateArgs(duplicates) => vec![Diagnostic::error().with_message(format!(
+                "argument{} {} provided more than once",
+                if duplicates.len() > 1 { "s" } else { "" },
+                list(duplicates)
+            ))],
+        };
+
+        with_code(diags, self.code())
+    }
+}
+
+impl ToDiagnostic<FileId> for EvalWarning {
+    fn to_diagnostic(
+        &self,
+        _files: &mut Files<String>,
+        _contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let diags = match self {
+            EvalWarning::Deprecated {
+                field,
+                message,
+                definition,
+                usage,
+            } => {
+                let mut labels = Vec::new();
+                if let Some(span) = usage.as_opt_ref() {
+                    labels.push(primary(span).with_message("used here"));
+                }
+                if let Some(span) = definition.as_opt_ref() {
+                    labels.push(secondary(span).with_message("deprecated here"));
+                }
+
+                let msg = match field {
+                    Some(field) => format!("use of deprecated field `{}`", field),
+                    None => String::from("use of deprecated value"),
+                };
+
+                vec![Diagnostic::warning()
+                    .with_message(msg)
+                    .with_labels(labels)
+                    .with_notes(vec![message.clone()])]
+            }
+        };
+
+        with_code(diags, self.code())
     }
 }
 
@@ -1536,7 +2386,7 @@ impl ToDiagnostic<FileId> for ReplError {
         _files: &mut Files<String>,
         _contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let diags = match self {
             ReplError::UnknownCommand(s) => vec![Diagnostic::error()
                 .with_message(format!("unknown command `{}`", s))
                 .with_notes(vec![String::from(
@@ -1556,6 +2406,8 @@ impl ToDiagnostic<FileId> for ReplError {
                     .with_message(format!("{}: missing argument", cmd))
                     .with_notes(notes)]
             }
-        }
+        };
+
+        with_code(diags, self.code())
     }
 }
@@ -0,0 +1,579 @@
+//! Long-form explanations of error codes.
+//!
+//! Each error reported by Nickel carries a stable code of the form `Exxyy` (see the `code()`
+//! methods of the error types in [`crate::error`]), which is displayed together with the error
+//! message. This module holds a longer explanation for each code, usually with a minimal example
+//! triggering the error and a description of how to fix it. The explanations are printed by
+//! `nickel explain <code>`.
+
+/// The explanations of all error codes, sorted by code.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0101",
+        r#"Unexpected end of file.
+
+The parser reached the end of the input while it was still expecting more
+tokens, for example the body of a `let` binding:
+
+    let x = 1 in
+
+Complete the unfinished expression, or look for an unclosed delimiter (brace,
+bracket, parenthesis or string) earlier in the file."#,
+    ),
+    (
+        "E0102",
+        r#"Unexpected token.
+
+The parser found a token that can't appear at this position. For example, the
+bound expression of a `let` is missing:
+
+    let x = in x
+
+The error message lists the tokens that were expected instead. Typical causes
+are a missing operand, a missing separator between record fields, or a keyword
+used as an identifier."#,
+    ),
+    (
+        "E0103",
+        r#"Superfluous token.
+
+The parser successfully parsed a complete expression, but the input goes on
+with additional tokens. A Nickel file must consist of exactly one expression.
+
+Remove the extra tokens, or combine the expressions, for example in a record
+or an array."#,
+    ),
+    (
+        "E0104",
+        r#"Unmatched closing brace.
+
+A closing brace `}` doesn't match any opening brace:
+
+    }
+
+Remove the brace, or add the missing opening brace."#,
+    ),
+    (
+        "E0105",
+        r#"Invalid escape sequence.
+
+A string literal contains a backslash followed by a character which doesn't
+form a valid escape sequence:
+
+    "\q"
+
+Use `\\` for a literal backslash. The valid escape sequences are `\"`, `\\`,
+`\%`, `\n`, `\r`, `\t` and `\xHH` for an ASCII character code."#,
+    ),
+    (
+        "E0106",
+        r#"Invalid ASCII escape code.
+
+A `\xHH` escape sequence in a string literal denotes a code outside of the
+ASCII range, which stops at `\x7F`:
+
+    "\x80"
+
+Write the character directly in the string instead, since Nickel strings are
+UTF-8."#,
+    ),
+    (
+        "E0107",
+        r#"Parse error in a JSON, YAML or TOML file.
+
+An imported file in one of the supported data formats is not valid. For
+example, importing a file `bad.json` containing `{"a": }`:
+
+    import "bad.json"
+
+Fix the syntax of the imported file. The format is determined by the
+extension of the file."#,
+    ),
+    (
+        "E0108",
+        r#"Unbound type variables.
+
+A type annotation uses type variables which are not introduced by a `forall`.
+Quantify the variables explicitly, as in `forall a. a -> a`, or replace them
+with concrete types."#,
+    ),
+    (
+        "E0109",
+        r#"Invalid record with a polymorphic tail.
+
+A record literal ends with a polymorphic tail, which makes it a record type,
+but it also contains constructs that are only allowed in record values, such
+as a field definition:
+
+    ({foo : Num = 1; a} : Dyn)
+
+Either remove the tail to get a record value, or remove the definitions to get
+a record type."#,
+    ),
+    (
+        "E0110",
+        r#"Recursive destructuring.
+
+A destructuring let-binding is declared recursive:
+
+    let rec {a} = {a = 1} in a
+
+This is not supported. Remove the `rec`. Note that the fields of a record can
+already refer to each other recursively, so the recursive binding is often not
+needed."#,
+    ),
+    (
+        "E0111",
+        r#"Interpolated deprecation message.
+
+The message of a deprecation annotation contains interpolated expressions:
+
+    {old | deprecated "use %{name} instead" = 1}
+
+The message must be a literal string, since it is read when parsing the
+program. Write the message without interpolation."#,
+    ),
+    (
+        "E0201",
+        r#"Unbound identifier.
+
+A variable is used but isn't defined in the current scope:
+
+    let f : Num -> Num = fun x => y in f 1
+
+Check the spelling of the variable, define it with a `let`, or import the
+file defining it."#,
+    ),
+    (
+        "E0202",
+        r#"Ill-formed type.
+
+A type doesn't have a valid shape, for example because a row type contains
+something else than rows. Check the syntax of the type annotation."#,
+    ),
+    (
+        "E0203",
+        r#"Missing row.
+
+A record is expected to have a field that its type lacks:
+
+    ({a = 1} : {a : Num, b : Num})
+
+Add the missing field to the record, or remove it from the expected type. If
+the field is optional, consider using a contract with a default value
+instead of a static type."#,
+    ),
+    (
+        "E0204",
+        r#"Missing dynamic tail.
+
+A record type is expected to be open, with a dynamic tail `; Dyn`, but the
+actual type is closed:
+
+    let r : {a : Num} = {a = 1} in (r : {a : Num; Dyn})
+
+Make the two types agree, either by adding `; Dyn` to the type of the
+expression or by removing it from the expected type."#,
+    ),
+    (
+        "E0205",
+        r#"Extra row.
+
+A record has a field which is not part of its expected type:
+
+    ({a = 1, b = 2} : {a : Num})
+
+Remove the field from the record, add it to the expected type, or use an open
+record type such as `{a : Num; Dyn}` if additional fields are allowed."#,
+    ),
+    (
+        "E0206",
+        r#"Extra dynamic tail.
+
+A record type is expected to be closed, but the actual type has a dynamic
+tail `; Dyn`. Make the two types agree, either by removing `; Dyn` from the
+type of the expression or by adding it to the expected type."#,
+    ),
+    (
+        "E0207",
+        r#"Unbound type variable.
+
+A type variable is used but isn't introduced by any enclosing `forall`.
+Add the variable to a `forall`, or replace it with a concrete type."#,
+    ),
+    (
+        "E0208",
+        r#"Type mismatch.
+
+The type of an expression, inferred or annotated, is incompatible with the
+type expected by its context:
+
+    (true : Num)
+
+Fix the expression or the annotation. If the value is only known at run time,
+consider a contract annotation `|` instead of a static type annotation `:`."#,
+    ),
+    (
+        "E0209",
+        r#"Row kind mismatch.
+
+The same identifier is used both as a record field and as an enum tag, and
+the typechecker can't reconcile the two kinds of rows. Check that records and
+enums are not mixed up, for example that a value expected to be an enum tag
+isn't a record."#,
+    ),
+    (
+        "E0210",
+        r#"Row mismatch.
+
+A field of a record has a type which is incompatible with the type expected
+for this field:
+
+    ({a = 1} : {a : Str})
+
+The error following this one describes why the types of the field don't
+match. Fix the value of the field or its expected type."#,
+    ),
+    (
+        "E0211",
+        r#"Conflicting row declarations.
+
+The type of a record is required to both have and not have a given field,
+or to have this field with two different types. This typically arises from
+polymorphic record functions used with records that already define the field
+with another type. Make the declarations of the field agree."#,
+    ),
+    (
+        "E0212",
+        r#"Function types mismatch.
+
+A function is used with a type which doesn't match its declared type, either
+on the domain or on the codomain:
+
+    let f : Num -> Num = fun x => x in (f : Str -> Num)
+
+The error points to the part of the function types which differs, and the
+following error, if any, describes why. Fix the function or the expected
+type."#,
+    ),
+    (
+        "E0301",
+        r#"Contract broken.
+
+A value doesn't satisfy a contract that it is annotated with:
+
+    let x | Num = "a" in x
+
+The error shows the contract, the value, and where the contract was attached.
+For a function contract, the error tells whether the function or its caller
+is to blame. Fix the value, or the contract if it is too strict."#,
+    ),
+    (
+        "E0302",
+        r#"Missing field definition.
+
+A field that is declared, for example by a record contract, is accessed but
+never given a value:
+
+    ({a | Num} | {a | Num}).a
+
+Provide a value for the field, for example by merging a record that defines
+it, or give it a default value in the contract with `| default = ...`."#,
+    ),
+    (
+        "E0303",
+        r#"Dynamic type error.
+
+A primitive operation was applied to a value of the wrong type at run time:
+
+    1 + "a"
+
+Convert the value to the expected type first, for example with
+`string.from_num`, or fix the expression producing it. Adding type annotations
+lets the typechecker catch these errors before evaluation."#,
+    ),
+    (
+        "E0304",
+        r#"Not a function.
+
+A value which isn't a function is applied to an argument:
+
+    1 2
+
+Check for a missing operator or a missing separator between two expressions,
+for example in an array literal, where elements must be separated by commas."#,
+    ),
+    (
+        "E0305",
+        r#"Missing field.
+
+A field is accessed, or required by a record operation, but the record
+doesn't have it:
+
+    {a = 1}.b
+
+Check the spelling of the field, or use `record.has_field` to test for its
+presence before accessing it."#,
+    ),
+    (
+        "E0306",
+        r#"Not enough arguments.
+
+A builtin operation was called with fewer arguments than it requires. This
+usually means that a primitive operator, written between `%` signs, is
+partially applied. Provide all the arguments, or use the equivalent function
+of the standard library, which can be partially applied."#,
+    ),
+    (
+        "E0307",
+        r#"Non mergeable terms.
+
+Two values can't be merged with `&`, for example because they are two
+different numbers:
+
+    {a = 1} & {a = 2}
+
+Only records are merged recursively. For other values, make sure that at most
+one of the two sides is not a default value, by marking the other one with
+`| default`."#,
+    ),
+    (
+        "E0308",
+        r#"Unbound identifier during evaluation.
+
+A variable was not found in the environment at run time. This is normally
+caught by the typechecker (see E0201): check the spelling of the variable, or
+define it."#,
+    ),
+    (
+        "E0309",
+        r#"Infinite recursion.
+
+The evaluation of a value depends on itself:
+
+    let rec x = x + 1 in x
+
+The same happens in records when a field is defined in terms of itself, for
+example `{a = a}`. Break the cycle, for example by renaming one of the
+variables so that it refers to an outer binding."#,
+    ),
+    (
+        "E0310",
+        r#"Deserialization error.
+
+The string given to `builtin.deserialize` is not valid in the requested
+format:
+
+    builtin.deserialize `Json "{"
+
+Fix the string, or check that the format matches its content."#,
+    ),
+    (
+        "E0311",
+        r#"Environment variable access not allowed.
+
+The program reads an environment variable with `builtin.env`, but the
+variable has not been allowed:
+
+    builtin.env "HOME"
+
+Evaluation is hermetic by default. Allow the variable explicitly with
+`--allow-env HOME` on the command line, or `Program::allow_env` when using
+Nickel as a library."#,
+    ),
+    (
+        "E0312",
+        r#"Internal error.
+
+An unexpected error occurred inside the interpreter. This is a bug in Nickel
+rather than in your program: please report it, together with the program
+which triggered it, at https://github.com/tweag/nickel/issues."#,
+    ),
+    (
+        "E0313",
+        r#"Evaluation error.
+
+An error which doesn't have a dedicated code occurred during evaluation, for
+example an invalid argument given to a function of the standard library. The
+error message describes the problem."#,
+    ),
+    (
+        "E0401",
+        r#"Import failed.
+
+An imported file couldn't be read:
+
+    import "nonexistent.ncl"
+
+Check the path of the import. Relative paths are resolved with respect to the
+directory of the importing file."#,
+    ),
+    (
+        "E0501",
+        r#"Null value not supported by the export format.
+
+The value to export contains `null`, which TOML can't represent. Remove the
+null values, or export to JSON or YAML instead."#,
+    ),
+    (
+        "E0502",
+        r#"Raw export of a non-string value.
+
+The raw export format writes a string as-is, but the value to export is not a
+string. Convert the value to a string, or use another export format."#,
+    ),
+    (
+        "E0503",
+        r#"Non serializable value.
+
+The value to export contains something that can't be represented in a data
+format, typically a function:
+
+    {f = fun x => x}
+
+Remove the offending value, or apply the function to get a serializable
+result."#,
+    ),
+    (
+        "E0504",
+        r#"Multi-document export of a non-array value.
+
+A multi-document YAML export (`--multi-doc`) requires the program to evaluate
+to an array, each element of which becomes a document. Wrap the value in an
+array, or export it normally."#,
+    ),
+    (
+        "E0505",
+        r#"Multi-file export of a non-record value.
+
+A multi-file export (`--multi-file`) requires the program to evaluate to a
+record mapping paths to the content of the corresponding files."#,
+    ),
+    (
+        "E0506",
+        r#"Invalid output path.
+
+A field of a multi-file export is not a relative path that stays inside the
+output directory, for example `"../secrets.json"` or `"/etc/passwd"`. Use
+relative paths without `..` components."#,
+    ),
+    (
+        "E0507",
+        r#"Serialization error.
+
+The serializer of the export format reported an error, for example because
+TOML requires the top-level value to be a record. The error message gives the
+details."#,
+    ),
+    (
+        "E0601",
+        r#"I/O error.
+
+Reading or writing a file failed. Check that the file exists and that you have
+the required permissions."#,
+    ),
+    (
+        "E0701",
+        r#"Unknown REPL command.
+
+The input starts with `:` but isn't a known command. Type `:help` to get the
+list of available commands."#,
+    ),
+    (
+        "E0702",
+        r#"Missing REPL command argument.
+
+A REPL command requires an argument which was not given, as in `:load` without
+a file. Type `:help <command>` to see how to use the command."#,
+    ),
+    (
+        "E0801",
+        r#"Arguments given to a program which is not a function.
+
+Arguments were provided with `--arg` or `--arg-str`, but the program doesn't
+evaluate to a function. Remove the arguments, or turn the program into a
+function, for example `fun {env, ..} => ...`."#,
+    ),
+    (
+        "E0802",
+        r#"Missing program arguments.
+
+The program is a function whose argument pattern has fields without default
+values, which were not provided. For a program starting with:
+
+    fun {env, region ? "eu"} => ...
+
+`env` must be provided with `--arg env=<expression>` or
+`--arg-str env=<string>`. The documentation of each missing field, if any, is
+shown in the error."#,
+    ),
+    (
+        "E0803",
+        r#"Unknown program arguments.
+
+Arguments were provided which don't correspond to any field of the argument
+pattern of the program, and the pattern is closed. Check the spelling of the
+arguments, or add `..` at the end of the pattern to accept additional
+arguments."#,
+    ),
+    (
+        "E0804",
+        r#"Duplicate program arguments.
+
+The same argument was provided more than once, with `--arg` or `--arg-str`.
+Each argument must be given a single value."#,
+    ),
+];
+
+/// Return the explanation of an error code, or `None` if the code doesn't exist. The code is
+/// case-insensitive.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, ToDiagnostic};
+    use crate::program::Program;
+    use codespan::Files;
+    use std::io::Cursor;
+
+    #[test]
+    fn codes_are_sorted_and_unique() {
+        for (code, _) in EXPLANATIONS {
+            assert!(code.len() == 5 && code.starts_with('E'));
+        }
+
+        for pair in EXPLANATIONS.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+        }
+    }
+
+    #[test]
+    fn diagnostics_have_explained_codes() {
+        let error = |s: &str| -> (Files<String>, Error) {
+            let mut program = Program::new_from_source(Cursor::new(s), "<test>").unwrap();
+            let error = program.eval_full().unwrap_err();
+            (program.files().clone(), error)
+        };
+
+        for (source, code) in &[
+            ("let x = 1 in", "E0101"),
+            ("(true : Num)", "E0208"),
+            ("let x | Num = \"a\" in x", "E0301"),
+            ("{a = 1}.b", "E0305"),
+        ] {
+            let (mut files, error) = error(source);
+            let diags = error.to_diagnostic(&mut files, None);
+            assert_eq!(diags[0].code.as_deref(), Some(*code));
+            assert!(explain(code).is_some());
+        }
+
+        assert_eq!(explain("e0101"), explain("E0101"));
+        assert!(explain("E9999").is_none());
+    }
+}
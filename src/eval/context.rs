@@ -0,0 +1,214 @@
+//! Evaluation context.
+//!
+//! The evaluation context holds the settings and the state of an evaluation which live outside of
+//! the abstract machine itself. It is created by the caller (typically a
+//! [`Program`][crate::program::Program] or the REPL) and threaded through the main loop down to
+//! the implementation of primitive operations.
+//!
+//! # Environment variables
+//!
+//! To keep evaluation hermetic by default, Nickel code can only read the environment variables
+//! which have been explicitly allowed (see [`EvalContext::allow_env`]). The variables read during
+//! evaluation are recorded together with their value, such that a caller caching the result of
+//! an evaluation can know what it depends on.
+//!
+//! # Warnings
+//!
+//! Warnings, such as the use of deprecated values, don't interrupt evaluation. They are collected
+//! in the context, and it is up to the caller to report them once evaluation is done.
+//!
+//! # Traces
+//!
+//! Traces, emitted by the `trace` builtin, are handed over to a [trace handler][TraceHandler] as
+//! soon as they are emitted, such that they are visible even if evaluation fails or doesn't
+//! terminate afterwards. By default, they are printed on the standard error. Traces can also be
+//! silenced altogether, for example for production exports.
+//!
+//! # Profiling
+//!
+//! When profiling is enabled, the context holds the [profile][super::profile] of the evaluations
+//! performed with it.
+//!
+//! # Debugging
+//!
+//! A [debugger][super::debugger] can be installed in the context to observe, and possibly pause,
+//! evaluation.
+use super::debugger::Debugger;
+use super::profile::Profile;
+use crate::error::EvalWarning;
+use crate::position::TermPos;
+use crate::term::RichTerm;
+use codespan::FileId;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// The evaluation context. See the [module documentation][self].
+#[derive(Debug, Default)]
+pub struct EvalContext {
+    /// The environment variables that Nickel code is allowed to read.
+    allowed_env: HashSet<String>,
+    /// The environment variables read during evaluation, together with their value (or `None` if
+    /// they were not set).
+    accessed_env: BTreeMap<String, Option<String>>,
+    /// The warnings emitted during evaluation.
+    warnings: Vec<EvalWarning>,
+    /// If traces are silenced.
+    silence_traces: bool,
+    /// The handler of traces. Traces are printed on the standard error if there is none.
+    trace_handler: Option<TraceHandler>,
+    /// The profile being recorded, if profiling is enabled.
+    profile: Option<Profile>,
+    /// The debugger observing evaluation, if any.
+    debugger: Option<Box<dyn Debugger>>,
+}
+
+/// A trace emitted by the `trace` builtin.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// The message of the trace.
+    pub message: String,
+    /// The deeply evaluated value being traced, if it was requested.
+    pub value: Option<RichTerm>,
+    /// The position of the primitive operation.
+    pub pos: TermPos,
+    /// The positions of the applications whose body was being evaluated when the trace was
+    /// emitted, from the most recent to the least recent.
+    pub callers: Vec<TermPos>,
+}
+
+impl Trace {
+    /// Return the position of the trace, which is the position of the primitive operation or of
+    /// the most recent caller that doesn't belong to a file in `skipped`.
+    ///
+    /// The `trace` primitive is meant to be called through its stdlib wrapper: skipping the
+    /// standard library gives the position of the call to the wrapper, which is what the user
+    /// wrote, instead of the one of the primitive operation inside the wrapper.
+    pub fn pos_outside(&self, skipped: &[FileId]) -> TermPos {
+        std::iter::once(&self.pos)
+            .chain(self.callers.iter())
+            .find(|pos| matches!(pos.into_opt(), Some(span) if !skipped.contains(&span.src_id)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Pretty-print the traced value, if any. Strings are printed as quoted and escaped string
+    /// literals, so that `"1"` and `1` can be told apart.
+    pub fn value_to_string(&self) -> Option<String> {
+        use crate::pretty::*;
+        use pretty::BoxAllocator;
+
+        self.value.as_ref().map(|value| {
+            let allocator = BoxAllocator;
+            let doc: DocBuilder<_, ()> = value.clone().pretty(&allocator);
+            let mut out = Vec::new();
+            doc.render(80, &mut out).unwrap();
+            String::from_utf8_lossy(&out).into_owned()
+        })
+    }
+}
+
+/// A function called on each trace emitted during evaluation. See [`EvalContext::set_trace_handler`].
+pub struct TraceHandler(Box<dyn FnMut(&Trace)>);
+
+impl fmt::Debug for TraceHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TraceHandler")
+    }
+}
+
+/// Error returned when trying to read an environment variable which has not been allowed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvAccessDenied;
+
+impl EvalContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allow Nickel code to read the environment variable `name`.
+    pub fn allow_env(&mut self, name: impl Into<String>) {
+        self.allowed_env.insert(name.into());
+    }
+
+    /// Return the environment variables read during evaluation, together with their value.
+    pub fn accessed_env(&self) -> &BTreeMap<String, Option<String>> {
+        &self.accessed_env
+    }
+
+    /// Read the environment variable `name` if it has been allowed, and record the access. Return
+    /// `Ok(None)` if the variable is allowed but is not set or is not valid unicode.
+    pub fn get_env(&mut self, name: &str) -> Result<Option<String>, EnvAccessDenied> {
+        if !self.allowed_env.contains(name) {
+            return Err(EnvAccessDenied);
+        }
+
+        let value = self
+            .accessed_env
+            .entry(String::from(name))
+            .or_insert_with(|| std::env::var(name).ok());
+        Ok(value.clone())
+    }
+
+    /// Record a warning. A warning which has already been recorded, that is with the same
+    /// definition and usage positions, is ignored, such that each site is reported only once.
+    pub fn warn(&mut self, warning: EvalWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Return the warnings emitted during evaluation, in the order they were emitted.
+    pub fn warnings(&self) -> &[EvalWarning] {
+        &self.warnings
+    }
+
+    /// Silence traces, or print them again.
+    pub fn set_silence_traces(&mut self, silence: bool) {
+        self.silence_traces = silence;
+    }
+
+    /// Set the function called on each trace, in place of printing it on the standard error. The
+    /// handler isn't called when traces are silenced.
+    pub fn set_trace_handler(&mut self, handler: impl FnMut(&Trace) + 'static) {
+        self.trace_handler = Some(TraceHandler(Box::new(handler)));
+    }
+
+    /// Emit a trace.
+    pub fn trace(&mut self, trace: Trace) {
+        if self.silence_traces {
+            return;
+        }
+
+        match &mut self.trace_handler {
+            Some(TraceHandler(handler)) => handler(&trace),
+            None => match trace.value_to_string() {
+                Some(value) => eprintln!("trace: {}\n{}", trace.message, value),
+                None => eprintln!("trace: {}", trace.message),
+            },
+        }
+    }
+
+    /// Enable profiling. Profiling stays enabled for the subsequent evaluations, whose profiles
+    /// are accumulated.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::new);
+    }
+
+    /// Return the profile recorded so far, or `None` if profiling is not enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub(crate) fn profile_mut(&mut self) -> Option<&mut Profile> {
+        self.profile.as_mut()
+    }
+
+    /// Install a debugger, which is called during the subsequent evaluations.
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    pub(crate) fn debugger_mut(&mut self) -> Option<&mut (dyn Debugger + 'static)> {
+        self.debugger.as_deref_mut()
+    }
+}
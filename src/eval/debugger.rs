@@ -0,0 +1,45 @@
+//! Debugger hooks into the abstract machine.
+//!
+//! A [`Debugger`] installed in the [evaluation context][super::context::EvalContext] is called
+//! before each step of the main loop with the current state of the machine, and when a primitive
+//! operation fails, before the error interrupts evaluation. The debugger may block in these hooks,
+//! for example to let a user inspect the state of the machine, which effectively pauses
+//! evaluation. A debugger may also interrupt evaluation altogether.
+//!
+//! See [`crate::dap`] for a debugger implementing the Debug Adapter Protocol.
+use super::{CallStack, Closure};
+use crate::error::EvalError;
+use codespan::{FileId, Files};
+use std::fmt;
+
+/// The state of the abstract machine before an evaluation step.
+pub struct MachineState<'a> {
+    /// The closure about to be evaluated.
+    pub closure: &'a Closure,
+    /// The call stack.
+    pub call_stack: &'a CallStack,
+    /// The size of the evaluation stack. Forcing a thunk or evaluating the operands of a
+    /// primitive operation increases it, and it goes back to its previous size once this is done.
+    pub depth: usize,
+}
+
+/// A debugger observing evaluation. See the [module documentation][self].
+pub trait Debugger {
+    /// Called before evaluation with the sources of the program, including imports and the
+    /// standard library, such that positions can be mapped back to source lines.
+    fn load_sources(&mut self, files: &Files<String>, stdlib_ids: &[FileId]);
+
+    /// Called before each evaluation step. Returning an error interrupts evaluation with this
+    /// error.
+    fn step(&mut self, state: &MachineState<'_>) -> Result<(), EvalError>;
+
+    /// Called when a primitive operation, such as a contract check, fails, before the error
+    /// interrupts evaluation.
+    fn error(&mut self, error: &EvalError, call_stack: &CallStack);
+}
+
+impl fmt::Debug for dyn Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Debugger")
+    }
+}
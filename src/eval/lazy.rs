@@ -296,4 +296,13 @@ impl ThunkUpdateFrame {
             false
         }
     }
+
+    /// Reset the corresponding thunk to the suspended state, when its evaluation has been
+    /// interrupted by an error. The thunk can then be forced again, instead of raising an infinite
+    /// recursion error.
+    pub fn reset(self) {
+        if let Some(data) = Weak::upgrade(&self.data) {
+            data.borrow_mut().state = ThunkState::Suspended;
+        }
+    }
 }
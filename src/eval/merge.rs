@@ -52,7 +52,8 @@
 //! - *Contract check*: merging a `Contract` or a `ContractDefault` with a simple value `t`
 //! evaluates to a contract check, that is an `Assume(..., t)`
 use super::*;
-use crate::error::EvalError;
+use crate::error::{EvalError, EvalWarning};
+use crate::eval::context::EvalContext;
 use crate::label::Label;
 use crate::position::TermPos;
 use crate::term::{
@@ -91,6 +92,7 @@ pub fn merge(
     mut env2: Environment,
     pos_op: TermPos,
     mode: MergeMode,
+    ctx: &mut EvalContext,
 ) -> Result<Closure, EvalError> {
     // Merging a simple value and a metavalue is equivalent to first wrapping the simple value in a
     // new metavalue (with no attribute set excepted the value), and then merging the two
@@ -233,6 +235,7 @@ pub fn merge(
 
             let MetaValue {
                 doc: doc1,
+                deprecated: deprecated1,
                 types: types1,
                 contracts: contracts1,
                 priority: priority1,
@@ -240,6 +243,7 @@ pub fn merge(
             } = meta1;
             let MetaValue {
                 doc: doc2,
+                deprecated: deprecated2,
                 types: types2,
                 contracts: contracts2,
                 priority: priority2,
@@ -248,6 +252,31 @@ pub fn merge(
 
             let doc = merge_doc(doc1, doc2);
 
+            // Giving a value to a deprecated field is a use of this field
+            if let (Some(message), Some(v2)) = (&deprecated1, &value2) {
+                ctx.warn(EvalWarning::Deprecated {
+                    field: None,
+                    message: message.clone(),
+                    definition: pos1,
+                    usage: v2.pos,
+                });
+            }
+            if let (Some(message), Some(v1)) = (&deprecated2, &value1) {
+                ctx.warn(EvalWarning::Deprecated {
+                    field: None,
+                    message: message.clone(),
+                    definition: pos2,
+                    usage: v1.pos,
+                });
+            }
+            // Keep pointing to the deprecated definition, which is reported on later accesses
+            let pos = match (&deprecated1, &deprecated2) {
+                (Some(_), _) => pos1,
+                (None, Some(_)) => pos2,
+                (None, None) => pos_op.into_inherited(),
+            };
+            let deprecated = deprecated1.or(deprecated2);
+
             // If:
             // 1. meta1 has a value
             // 2. meta2 has a contract
@@ -337,6 +366,7 @@ pub fn merge(
                 .collect();
             let meta = MetaValue {
                 doc,
+                deprecated,
                 types,
                 contracts,
                 priority,
@@ -344,7 +374,7 @@ pub fn merge(
             };
 
             Ok(Closure {
-                body: RichTerm::new(Term::MetaValue(meta), pos_op.into_inherited()),
+                body: RichTerm::new(Term::MetaValue(meta), pos),
                 env,
             })
         }
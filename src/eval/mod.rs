@@ -91,23 +91,30 @@
 use crate::{
     cache::ImportResolver,
     environment::Environment as GenericEnvironment,
-    error::EvalError,
-    identifier::Ident,
+    error::{EvalError, EvalErrors, EvalWarning, FieldError},
+    identifier::{Ident, GEN_PREFIX},
     match_sharedterm, mk_app,
+    position::TermPos,
     term::{
         make as mk_term, BinaryOp, BindingType, LetAttrs, MetaValue, RichTerm, SharedTerm,
         StrChunk, Term, UnaryOp,
     },
 };
 
+use std::collections::HashMap;
+
 pub mod callstack;
+pub mod context;
+pub mod debugger;
 pub mod fixpoint;
 pub mod lazy;
 pub mod merge;
 pub mod operation;
+pub mod profile;
 pub mod stack;
 
 use callstack::*;
+use context::EvalContext;
 use lazy::*;
 use operation::{continuate_operation, OperationCont};
 use stack::Stack;
@@ -183,11 +190,12 @@ pub fn eval<R>(
     t0: RichTerm,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
 ) -> Result<RichTerm, EvalError>
 where
     R: ImportResolver,
 {
-    eval_closure(Closure::atomic_closure(t0), global_env, resolver, true).map(|(term, _)| term)
+    eval_closure(Closure::atomic_closure(t0), global_env, resolver, ctx, true).map(|(term, _)| term)
 }
 
 /// Fully evaluate a Nickel term: the result is not a WHNF but to a value with all variables substituted.
@@ -195,11 +203,13 @@ pub fn eval_full<R>(
     t0: RichTerm,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
 ) -> Result<RichTerm, EvalError>
 where
     R: ImportResolver,
 {
-    eval_deep_closure(t0, global_env, resolver).map(|(term, env)| subst(term, global_env, &env))
+    eval_deep_closure(t0, global_env, resolver, ctx)
+        .map(|(term, env)| subst(term, global_env, &env))
 }
 
 /// Fully evaluates a Nickel term like `eval_full`, but does not substitute all variables.
@@ -207,17 +217,19 @@ pub fn eval_deep<R>(
     t0: RichTerm,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
 ) -> Result<RichTerm, EvalError>
 where
     R: ImportResolver,
 {
-    eval_deep_closure(t0, global_env, resolver).map(|(term, _)| term)
+    eval_deep_closure(t0, global_env, resolver, ctx).map(|(term, _)| term)
 }
 
 fn eval_deep_closure<R>(
     t0: RichTerm,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
 ) -> Result<(RichTerm, Environment), EvalError>
 where
     R: ImportResolver,
@@ -234,7 +246,180 @@ where
             Term::Var(var)
         ),
     );
-    eval_closure(Closure::atomic_closure(wrapper), global_env, resolver, true)
+    eval_closure(
+        Closure::atomic_closure(wrapper),
+        global_env,
+        resolver,
+        ctx,
+        true,
+    )
+}
+
+/// Fully evaluate a Nickel term like [eval_full], but evaluate the fields of records and the
+/// elements of arrays separately, such that a contract violation doesn't prevent the evaluation of
+/// independent fields. Contract violations, missing field definitions and merge conflicts are
+/// collected, together with the path of the field or element which raised them. Evaluation stops
+/// at the first other error, or once `max_errors` errors have been collected. At least one error
+/// is collected, even if `max_errors` is zero.
+pub fn eval_full_collect<R>(
+    t0: RichTerm,
+    global_env: &Environment,
+    resolver: &mut R,
+    ctx: &mut EvalContext,
+    max_errors: usize,
+) -> Result<RichTerm, EvalErrors>
+where
+    R: ImportResolver,
+{
+    let mut collector = Collector {
+        global_env,
+        resolver,
+        ctx,
+        max_errors: max_errors.max(1),
+        errors: EvalErrors::default(),
+        stopped: false,
+    };
+
+    match collector.eval(Closure::atomic_closure(t0), &mut String::new()) {
+        Some(result) if collector.errors.errors.is_empty() => Ok(result),
+        _ => Err(collector.errors),
+    }
+}
+
+/// The state of [eval_full_collect].
+struct Collector<'a, R> {
+    global_env: &'a Environment,
+    resolver: &'a mut R,
+    ctx: &'a mut EvalContext,
+    max_errors: usize,
+    errors: EvalErrors,
+    /// If evaluation must stop, either because of an error which isn't a contract violation or
+    /// because the maximum number of errors has been reached.
+    stopped: bool,
+}
+
+impl<'a, R: ImportResolver> Collector<'a, R> {
+    /// Fully evaluate a closure, whose path is `path`. Return `None` if an error was raised by the
+    /// closure or by one of its subterms.
+    fn eval(&mut self, clos: Closure, path: &mut String) -> Option<RichTerm> {
+        let (term, env) = match eval_closure(clos, self.global_env, self.resolver, self.ctx, true) {
+            Ok(result) => result,
+            Err(error) => {
+                self.push(error, path);
+                return None;
+            }
+        };
+
+        let pos = term.pos;
+        match term.term.into_owned() {
+            Term::Record(map, attrs) => {
+                let mut fields: Vec<_> = map.into_iter().collect();
+                // Fields are evaluated in the order of the source, such that the errors are
+                // reported in this order too.
+                fields
+                    .sort_by_key(|(id, _)| (id.pos.into_opt().map(|span| span.start), id.clone()));
+
+                let mut result = HashMap::with_capacity(fields.len());
+                let mut failed = false;
+                for (id, t) in fields {
+                    let len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(&id.label);
+                    let value = self.eval_field(t, &env, path);
+                    path.truncate(len);
+
+                    match value {
+                        Some(value) => {
+                            result.insert(id, value);
+                        }
+                        None => failed = true,
+                    }
+                    if self.stopped {
+                        return None;
+                    }
+                }
+
+                (!failed).then(|| RichTerm::new(Term::Record(result, attrs), pos))
+            }
+            Term::Array(ts) => {
+                let mut result = Vec::with_capacity(ts.len());
+                let mut failed = false;
+                for (index, t) in ts.into_iter().enumerate() {
+                    let len = path.len();
+                    path.push_str(&format!("[{}]", index));
+                    let value = self.eval_field(t, &env, path);
+                    path.truncate(len);
+
+                    match value {
+                        Some(value) => result.push(value),
+                        None => failed = true,
+                    }
+                    if self.stopped {
+                        return None;
+                    }
+                }
+
+                (!failed).then(|| RichTerm::new(Term::Array(result), pos))
+            }
+            t => Some(subst(RichTerm::new(t, pos), self.global_env, &env)),
+        }
+    }
+
+    fn eval_field(
+        &mut self,
+        t: RichTerm,
+        env: &Environment,
+        path: &mut String,
+    ) -> Option<RichTerm> {
+        self.eval(
+            Closure {
+                body: t,
+                env: env.clone(),
+            },
+            path,
+        )
+    }
+
+    fn push(&mut self, error: EvalError, path: &str) {
+        // A field depending on a field which violates a contract raises the same violation.
+        if let EvalError::BlameError(label, _) = &error {
+            let duplicate = self
+                .errors
+                .errors
+                .iter()
+                .any(|e| matches!(&e.error, EvalError::BlameError(other, _) if other == label));
+            if duplicate {
+                return;
+            }
+        }
+
+        if self.errors.errors.len() >= self.max_errors {
+            self.errors.truncated = true;
+            self.stopped = true;
+            return;
+        }
+
+        if !is_collected(&error) {
+            self.stopped = true;
+        }
+        self.errors.errors.push(FieldError {
+            path: String::from(path),
+            error,
+        });
+    }
+}
+
+/// If an error only affects the field or element which raised it, such that evaluation can proceed
+/// with the other ones in [eval_full_collect].
+fn is_collected(error: &EvalError) -> bool {
+    matches!(
+        error,
+        EvalError::BlameError(..)
+            | EvalError::MissingFieldDef(..)
+            | EvalError::MergeIncompatibleArgs(..)
+    )
 }
 
 /// Evaluate a Nickel Term, stopping when a meta value is encountered at the top-level without
@@ -246,17 +431,18 @@ pub fn eval_meta<R>(
     t: RichTerm,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
 ) -> Result<RichTerm, EvalError>
 where
     R: ImportResolver,
 {
-    let (mut rt, env) = eval_closure(Closure::atomic_closure(t), global_env, resolver, false)?;
+    let (mut rt, env) = eval_closure(Closure::atomic_closure(t), global_env, resolver, ctx, false)?;
 
     match *SharedTerm::make_mut(&mut rt.term) {
         Term::MetaValue(ref mut meta) => {
             if let Some(t) = meta.value.take() {
                 let (evaluated, env) =
-                    eval_closure(Closure { body: t, env }, global_env, resolver, true)?;
+                    eval_closure(Closure { body: t, env }, global_env, resolver, ctx, true)?;
                 let substituted = subst(evaluated, global_env, &env);
 
                 meta.value = Some(substituted);
@@ -268,6 +454,35 @@ where
     Ok(rt)
 }
 
+/// Record the use of a deprecated value if it is forced by a record field access, that is if the
+/// last element of the call stack, generated variables aside, is a field access written in the
+/// source. Variables written by the user, such as a reference to a recursive field, mean that the
+/// value is not used through the field access but through the variable. Forcing the fields of a
+/// record, for example to serialize it, is not a use either. Setting the value by merging is
+/// detected in [merge].
+fn warn_deprecated(
+    ctx: &mut EvalContext,
+    call_stack: &CallStack,
+    message: &str,
+    definition: TermPos,
+) {
+    let last = call_stack.0.iter().rev().find(
+        |elem| !matches!(elem, StackElem::Var { id, .. } if id.label.starts_with(GEN_PREFIX)),
+    );
+
+    match last {
+        Some(StackElem::Field { id, pos_access, .. }) if pos_access.is_def() => {
+            ctx.warn(EvalWarning::Deprecated {
+                field: Some(id.clone()),
+                message: String::from(message),
+                definition,
+                usage: *pos_access,
+            })
+        }
+        _ => (),
+    }
+}
+
 /// The main loop of evaluation.
 ///
 /// Implement the evaluation of the core language, which includes application, thunk update,
@@ -280,6 +495,8 @@ where
 /// - `global_env`: the global environment containing the builtin functions of the language. Accessible from anywhere in the
 /// program.
 /// - `resolver`: the interface to fetch imports.
+/// - `ctx`: the evaluation context, holding the settings and the state of evaluation which are
+///   not part of the abstract machine, such as the environment variables accessible to the program.
 /// - `enriched_strict`: if evaluation is strict with respect to enriched values (metavalues).
 ///   Standard evaluation should be strict, but set to false when extracting the metadata of value.
 ///
@@ -292,6 +509,7 @@ pub fn eval_closure<R>(
     mut clos: Closure,
     global_env: &Environment,
     resolver: &mut R,
+    ctx: &mut EvalContext,
     mut enriched_strict: bool,
 ) -> Result<(RichTerm, Environment), EvalError>
 where
@@ -300,7 +518,23 @@ where
     let mut call_stack = CallStack::new();
     let mut stack = Stack::new();
 
+    if let Some(profile) = ctx.profile_mut() {
+        profile.resume();
+    }
+
     loop {
+        if let Some(profile) = ctx.profile_mut() {
+            profile.step(&call_stack);
+        }
+
+        if let Some(debugger) = ctx.debugger_mut() {
+            debugger.step(&debugger::MachineState {
+                closure: &clos,
+                call_stack: &call_stack,
+                depth: stack.len(),
+            })?;
+        }
+
         let Closure {
             body: RichTerm {
                 term: shared_term,
@@ -321,7 +555,8 @@ where
                     .ok_or_else(|| EvalError::UnboundIdentifier(x.clone(), pos))?;
                 std::mem::drop(env); // thunk may be a 1RC pointer
 
-                if thunk.state() != ThunkState::Evaluated {
+                let forced = thunk.state() != ThunkState::Evaluated;
+                if forced {
                     if thunk.should_update() {
                         match thunk.mk_update_frame() {
                             Ok(thunk_upd) => stack.push_thunk(thunk_upd),
@@ -336,6 +571,11 @@ where
                     }
                 }
                 call_stack.enter_var(thunk.ident_kind(), x.clone(), pos);
+
+                if let Some(profile) = ctx.profile_mut().filter(|_| forced) {
+                    profile.enter_thunk(x, thunk.borrow().body.pos, &call_stack);
+                }
+
                 thunk.into_closure()
             }
             Term::App(t1, t2) => {
@@ -560,6 +800,10 @@ where
             }
             // Unwrapping of enriched terms
             Term::MetaValue(meta) if enriched_strict => {
+                if let Some(message) = &meta.deprecated {
+                    warn_deprecated(ctx, &call_stack, message, pos);
+                }
+
                 if meta.value.is_some() {
                     /* Since we are forcing a metavalue, we are morally evaluating `force t` rather
                      * than `t` iteself.  Updating a thunk after having performed this forcing may
@@ -625,13 +869,27 @@ where
                     update_thunks(&mut stack, &clos);
                     clos
                 } else {
-                    continuate_operation(clos, &mut stack, &mut call_stack)?
+                    let result = continuate_operation(clos, &mut stack, &mut call_stack, ctx);
+                    if let Err(ref err) = result {
+                        if let Some(debugger) = ctx.debugger_mut() {
+                            debugger.error(err, &call_stack);
+                        }
+                    }
+                    result?
                 }
             }
             // Function call
             Term::Fun(x, t) => {
                 if let Some((thunk, pos_app)) = stack.pop_arg_as_thunk() {
                     call_stack.enter_fun(pos_app);
+
+                    // The body of a multi-ary function `fun x y => body` is only entered once
+                    // all the arguments have been provided.
+                    let saturated = !matches!(t.as_ref(), Term::Fun(..)) || t.pos != pos;
+                    if let Some(profile) = ctx.profile_mut().filter(|_| saturated) {
+                        profile.enter_fun(pos, pos_app, &call_stack);
+                    }
+
                     env.insert(x.clone(), thunk);
                     Closure {
                         body: t.clone(),
@@ -7,7 +7,9 @@
 //! On the other hand, the functions `process_unary_operation` and `process_binary_operation`
 //! receive evaluated operands and implement the actual semantics of operators.
 use super::{
-    callstack, merge,
+    callstack,
+    context::{EnvAccessDenied, EvalContext, Trace},
+    merge,
     merge::{merge, MergeMode},
     stack::Stack,
     subst, CallStack, Closure, Environment,
@@ -94,12 +96,13 @@ pub fn continuate_operation(
     mut clos: Closure,
     stack: &mut Stack,
     call_stack: &mut CallStack,
+    ctx: &mut EvalContext,
 ) -> Result<Closure, EvalError> {
     let (cont, cs_len, pos) = stack.pop_op_cont().expect("Condition already checked");
     call_stack.truncate(cs_len);
     match cont {
         OperationCont::Op1(u_op, arg_pos) => {
-            process_unary_operation(u_op, clos, arg_pos, stack, call_stack, pos)
+            process_unary_operation(u_op, clos, arg_pos, stack, call_stack, ctx, pos)
         }
         OperationCont::Op2First(b_op, mut snd_clos, fst_pos) => {
             std::mem::swap(&mut clos, &mut snd_clos);
@@ -111,7 +114,7 @@ pub fn continuate_operation(
             Ok(clos)
         }
         OperationCont::Op2Second(b_op, fst_clos, fst_pos, snd_pos) => process_binary_operation(
-            b_op, fst_clos, fst_pos, clos, snd_pos, stack, call_stack, pos,
+            b_op, fst_clos, fst_pos, clos, snd_pos, stack, call_stack, ctx, pos,
         ),
         OperationCont::OpN {
             op,
@@ -136,7 +139,7 @@ pub fn continuate_operation(
 
                 Ok(next)
             } else {
-                process_nary_operation(op, evaluated, stack, pos)
+                process_nary_operation(op, evaluated, stack, ctx, pos)
             }
         }
     }
@@ -152,6 +155,7 @@ fn process_unary_operation(
     arg_pos: TermPos,
     stack: &mut Stack,
     call_stack: &mut CallStack,
+    ctx: &mut EvalContext,
     pos_op: TermPos,
 ) -> Result<Closure, EvalError> {
     let Closure {
@@ -982,6 +986,25 @@ fn process_unary_operation(
                 ))
             }
         }
+        UnaryOp::EnvVar() => {
+            if let Term::Str(name) = &*t {
+                let result = match ctx.get_env(name) {
+                    Ok(Some(value)) => Term::Str(value),
+                    Ok(None) => Term::Null,
+                    Err(EnvAccessDenied) => {
+                        return Err(EvalError::EnvAccessDenied(name.clone(), pos_op))
+                    }
+                };
+                Ok(Closure::atomic_closure(RichTerm::new(result, pos_op_inh)))
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("env"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
+            }
+        }
     }
 }
 
@@ -997,6 +1020,7 @@ fn process_binary_operation(
     snd_pos: TermPos,
     stack: &mut Stack,
     call_stack: &mut CallStack,
+    ctx: &mut EvalContext,
     pos_op: TermPos,
 ) -> Result<Closure, EvalError> {
     let Closure {
@@ -1242,6 +1266,10 @@ fn process_binary_operation(
                 l.arg_pos = thunk.borrow().body.pos;
                 l.arg_thunk = Some(thunk);
 
+                if let Some(profile) = ctx.profile_mut() {
+                    profile.enter_contract(&l, call_stack);
+                }
+
                 stack.push_arg(
                     Closure::atomic_closure(RichTerm::new(Term::Lbl(l), pos2.into_inherited())),
                     pos2.into_inherited(),
@@ -1821,6 +1849,7 @@ fn process_binary_operation(
             env2,
             pos_op,
             MergeMode::Standard,
+            ctx,
         ),
 
         BinaryOp::Hash() => {
@@ -2115,6 +2144,47 @@ fn process_binary_operation(
                 )),
             }
         }
+        BinaryOp::Trace(with_value) => {
+            if let Term::Str(message) = &*t1 {
+                let value = RichTerm {
+                    term: t2,
+                    pos: pos2,
+                };
+                let traced = with_value.then(|| subst(value.clone(), &Environment::new(), &env2));
+
+                let callers = call_stack
+                    .0
+                    .iter()
+                    .rev()
+                    .filter_map(|elem| match elem {
+                        callstack::StackElem::Fun(pos) => Some(*pos),
+                        _ => None,
+                    })
+                    .collect();
+
+                ctx.trace(Trace {
+                    message: message.clone(),
+                    value: traced,
+                    pos: pos_op,
+                    callers,
+                });
+
+                Ok(Closure {
+                    body: value,
+                    env: env2,
+                })
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("trace, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
     }
 }
 
@@ -2126,6 +2196,7 @@ fn process_nary_operation(
     n_op: NAryOp,
     args: Vec<(Closure, TermPos)>,
     _stack: &mut Stack,
+    ctx: &mut EvalContext,
     pos_op: TermPos,
 ) -> Result<Closure, EvalError> {
     let pos_op_inh = pos_op.into_inherited();
@@ -2293,6 +2364,7 @@ fn process_nary_operation(
                             env3,
                             pos_op,
                             MergeMode::Contract(lbl),
+                            ctx,
                         )
                     }
                 } else {
@@ -2414,7 +2486,8 @@ mod tests {
         stack.push_op_cont(cont, 0, TermPos::None);
         let mut call_stack = CallStack::new();
 
-        clos = continuate_operation(clos, &mut stack, &mut call_stack).unwrap();
+        clos = continuate_operation(clos, &mut stack, &mut call_stack, &mut EvalContext::new())
+            .unwrap();
 
         assert_eq!(
             clos,
@@ -2445,7 +2518,8 @@ mod tests {
         stack.push_op_cont(cont, 0, TermPos::None);
         let mut call_stack = CallStack::new();
 
-        clos = continuate_operation(clos, &mut stack, &mut call_stack).unwrap();
+        clos = continuate_operation(clos, &mut stack, &mut call_stack, &mut EvalContext::new())
+            .unwrap();
 
         assert_eq!(
             clos,
@@ -2493,7 +2567,8 @@ mod tests {
         stack.push_op_cont(cont, 0, TermPos::None);
         let mut call_stack = CallStack::new();
 
-        clos = continuate_operation(clos, &mut stack, &mut call_stack).unwrap();
+        clos = continuate_operation(clos, &mut stack, &mut call_stack, &mut EvalContext::new())
+            .unwrap();
 
         assert_eq!(
             clos,
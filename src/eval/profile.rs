@@ -0,0 +1,363 @@
+//! Profiling of evaluation.
+//!
+//! When profiling is enabled (see [`EvalContext::enable_profiling`][super::context::EvalContext::enable_profiling]),
+//! the abstract machine reports to a [`Profile`] the function calls, thunk forcings and contract
+//! applications it performs, which are counted per source location.
+//!
+//! Because evaluation is lazy, there are no well delimited stack frames in Nickel, and thus no
+//! exact notion of the time spent in a function or in a contract. The profile maintains instead
+//! a stack of frames, each being attached to an element of the [call stack][CallStack]: a frame is
+//! considered active as long as this element is still on the call stack. Every
+//! [`SAMPLE_PERIOD`] evaluation steps, the time elapsed since the previous sample is attributed to
+//! the active frames. This gives, for each source location, the time spent evaluating it either
+//! directly (self time) or through nested calls (total time), as well as a folded stack
+//! representation which can be turned into a flamegraph by standard tools, such as `inferno` or
+//! `flamegraph.pl`.
+//!
+//! The implementation of builtin contracts is usually not relevant to the user: the frames located
+//! in a [hidden file][Profile::hide_file] are ignored, and their time is attributed to the
+//! enclosing frames instead.
+use super::callstack::{CallStack, StackElem};
+use crate::identifier::Ident;
+use crate::label::Label;
+use crate::position::{RawSpan, TermPos};
+use codespan::{FileId, Files};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// The number of evaluation steps between two samples of the active frames.
+pub const SAMPLE_PERIOD: u32 = 16;
+
+/// The kind of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FrameKind {
+    /// The body of a function, located at the definition of the function. The count is the number
+    /// of calls.
+    Function,
+    /// The content of a binding or a record field, located at its definition. The count is the
+    /// number of times the corresponding thunk was forced.
+    Thunk,
+    /// A contract, located at the annotation. The count is the number of contract applications.
+    Contract,
+}
+
+impl FrameKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FrameKind::Function => "call",
+            FrameKind::Thunk => "force",
+            FrameKind::Contract => "contract",
+        }
+    }
+}
+
+/// A source location being evaluated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The name of the function, field or binding, or the type of the contract.
+    pub name: String,
+    pub span: RawSpan,
+}
+
+/// The statistics gathered for a frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// The number of calls, thunk forcings or contract applications, depending on the kind of the
+    /// frame.
+    pub count: u64,
+    /// The time sampled while this frame was the most recent active frame.
+    pub self_time: Duration,
+    /// The time sampled while this frame was active.
+    pub total_time: Duration,
+}
+
+/// An active frame, valid as long as the call stack has the same element at the same depth as
+/// when the frame was entered.
+#[derive(Debug, Clone)]
+struct Marker {
+    frame: usize,
+    depth: usize,
+    top: Option<StackElem>,
+}
+
+impl Marker {
+    fn new(frame: usize, call_stack: &CallStack) -> Self {
+        Marker {
+            frame,
+            depth: call_stack.len(),
+            top: call_stack.0.last().cloned(),
+        }
+    }
+
+    fn is_active(&self, call_stack: &CallStack) -> bool {
+        match self.depth.checked_sub(1) {
+            Some(index) => call_stack.0.get(index) == self.top.as_ref(),
+            None => true,
+        }
+    }
+}
+
+/// The profile of an evaluation. See the [module documentation][self].
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// The frames encountered so far. Frames are referred to by their index in this vector.
+    frames: Vec<Frame>,
+    /// The index of each frame in `frames`.
+    indices: HashMap<Frame, usize>,
+    /// The statistics of each frame, indexed as `frames`.
+    stats: Vec<FrameStats>,
+    /// The active frames, from the least recent to the most recent.
+    active: Vec<Marker>,
+    /// The time sampled for each distinct stack of active frames.
+    folded: HashMap<Vec<usize>, Duration>,
+    /// The number of steps since the last sample.
+    steps: u32,
+    /// The time of the last sample.
+    last_sample: Instant,
+    /// The files whose frames are ignored.
+    hidden: HashSet<FileId>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            frames: Vec::new(),
+            indices: HashMap::new(),
+            stats: Vec::new(),
+            active: Vec::new(),
+            folded: HashMap::new(),
+            steps: 0,
+            last_sample: Instant::now(),
+            hidden: HashSet::new(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Ignore the frames located in `file_id`.
+    pub fn hide_file(&mut self, file_id: FileId) {
+        self.hidden.insert(file_id);
+    }
+
+    /// Restart the clock, such that the time elapsed since the last sample isn't attributed to
+    /// the active frames. Called at the beginning of each evaluation.
+    pub fn resume(&mut self) {
+        self.steps = 0;
+        self.last_sample = Instant::now();
+    }
+
+    /// Record an evaluation step, and sample the active frames every [`SAMPLE_PERIOD`] steps.
+    pub fn step(&mut self, call_stack: &CallStack) {
+        self.steps += 1;
+        if self.steps < SAMPLE_PERIOD {
+            return;
+        }
+
+        self.steps = 0;
+        let now = Instant::now();
+        let elapsed = now - self.last_sample;
+        self.last_sample = now;
+
+        self.prune(call_stack);
+        let stack: Vec<usize> = self.active.iter().map(|marker| marker.frame).collect();
+
+        if let Some(top) = stack.last() {
+            self.stats[*top].self_time += elapsed;
+        }
+        let mut seen = HashSet::new();
+        for frame in stack.iter() {
+            // Recursive frames appear several times on the stack, but are only counted once.
+            if seen.insert(*frame) {
+                self.stats[*frame].total_time += elapsed;
+            }
+        }
+
+        *self.folded.entry(stack).or_default() += elapsed;
+    }
+
+    /// Record that the body of a function defined at `pos_def` is entered, following the
+    /// application at `pos_app`. `call_stack` must already include the corresponding
+    /// [`StackElem::Fun`].
+    pub fn enter_fun(&mut self, pos_def: TermPos, pos_app: TermPos, call_stack: &CallStack) {
+        if let Some(span) = pos_def.into_opt() {
+            // The names used in hidden files are meaningless to the user.
+            let name = Some(pos_app)
+                .filter(|pos| !self.is_hidden(pos))
+                .and_then(|pos| call_name(call_stack, pos))
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| String::from("<fun>"));
+            self.enter(FrameKind::Function, name, span, call_stack);
+        }
+    }
+
+    /// Record that the thunk bound to `id`, whose content is at `pos`, is forced. `call_stack`
+    /// must already include the corresponding [`StackElem::Var`].
+    pub fn enter_thunk(&mut self, id: &Ident, pos: TermPos, call_stack: &CallStack) {
+        // Record fields are bound to generated variables: we use the name of the field being
+        // accessed, if any, and ignore other generated variables.
+        let name = match call_stack.0.last() {
+            Some(StackElem::Var { pos, .. }) if self.is_hidden(pos) => String::from("<thunk>"),
+            _ if id.is_generated() => match call_stack.0.iter().rev().nth(1) {
+                Some(StackElem::Field { id, .. }) => id.to_string(),
+                _ => return,
+            },
+            _ => id.to_string(),
+        };
+
+        if let Some(span) = pos.into_opt() {
+            self.enter(FrameKind::Thunk, name, span, call_stack);
+        }
+    }
+
+    /// Record that the contract of `label` is applied.
+    pub fn enter_contract(&mut self, label: &Label, call_stack: &CallStack) {
+        self.enter(
+            FrameKind::Contract,
+            label.types.to_string(),
+            label.span,
+            call_stack,
+        );
+    }
+
+    fn enter(&mut self, kind: FrameKind, name: String, span: RawSpan, call_stack: &CallStack) {
+        if self.hidden.contains(&span.src_id) {
+            return;
+        }
+
+        let frame = Frame { kind, name, span };
+        let index = match self.indices.get(&frame) {
+            Some(index) => *index,
+            None => {
+                let index = self.frames.len();
+                self.indices.insert(frame.clone(), index);
+                self.frames.push(frame);
+                self.stats.push(FrameStats::default());
+                index
+            }
+        };
+
+        self.stats[index].count += 1;
+        self.prune(call_stack);
+        self.active.push(Marker::new(index, call_stack));
+    }
+
+    /// Return `true` if `pos` is located in a hidden file.
+    fn is_hidden(&self, pos: &TermPos) -> bool {
+        matches!(pos.into_opt(), Some(span) if self.hidden.contains(&span.src_id))
+    }
+
+    /// Drop the frames which aren't active anymore.
+    fn prune(&mut self, call_stack: &CallStack) {
+        while matches!(self.active.last(), Some(marker) if !marker.is_active(call_stack)) {
+            self.active.pop();
+        }
+    }
+
+    /// Return the frames together with their statistics, by decreasing total time.
+    pub fn frames(&self) -> Vec<(&Frame, &FrameStats)> {
+        let mut frames: Vec<_> = self.frames.iter().zip(self.stats.iter()).collect();
+        frames.sort_by(|(f1, s1), (f2, s2)| {
+            s2.total_time
+                .cmp(&s1.total_time)
+                .then(s2.count.cmp(&s1.count))
+                .then(f1.kind.cmp(&f2.kind))
+                .then(f1.name.cmp(&f2.name))
+        });
+        frames
+    }
+
+    /// Write a human-readable report with the statistics of each frame, by decreasing total time.
+    pub fn write_report(&self, files: &Files<String>, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:>10} {:>10} {:>10} {:>8}  {:<40} location",
+            "total (ms)", "self (ms)", "count", "kind", "name"
+        )?;
+
+        for (frame, stats) in self.frames() {
+            writeln!(
+                out,
+                "{:>10.3} {:>10.3} {:>10} {:>8}  {:<40} {}",
+                stats.total_time.as_secs_f64() * 1000.0,
+                stats.self_time.as_secs_f64() * 1000.0,
+                stats.count,
+                frame.kind.as_str(),
+                frame.name,
+                location(files, &frame.span),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the sampled stacks in the folded format, one stack per line with the time in
+    /// microseconds, as expected by flamegraph tools.
+    pub fn write_folded(&self, files: &Files<String>, out: &mut impl Write) -> io::Result<()> {
+        let mut lines: Vec<(String, u128)> = self
+            .folded
+            .iter()
+            .map(|(stack, time)| {
+                let names: Vec<String> = std::iter::once(String::from("<main>"))
+                    .chain(stack.iter().map(|index| {
+                        let frame = &self.frames[*index];
+                        // Semicolons separate frames in the folded format.
+                        format!(
+                            "{} {} ({})",
+                            frame.kind.as_str(),
+                            frame.name,
+                            location(files, &frame.span)
+                        )
+                        .replace(';', ",")
+                    }))
+                    .collect();
+                (names.join(";"), time.as_micros())
+            })
+            .filter(|(_, time)| *time > 0)
+            .collect();
+        lines.sort();
+
+        for (stack, time) in lines {
+            writeln!(out, "{} {}", stack, time)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the name of the function called by the application at `pos_app`, which is the most
+/// recent variable or field accessed since the application was entered.
+fn call_name(call_stack: &CallStack, pos_app: TermPos) -> Option<&Ident> {
+    let span_app = pos_app.into_opt()?;
+
+    for elem in call_stack.0.iter().rev() {
+        match elem {
+            StackElem::Var { id, pos, .. }
+            | StackElem::Field {
+                id,
+                pos_access: pos,
+                ..
+            } if !id.is_generated() => {
+                return pos.into_opt().filter(|span| *span <= span_app).map(|_| id);
+            }
+            StackElem::App(pos) if *pos == pos_app => return None,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Render a span as `file:line:column`.
+fn location(files: &Files<String>, span: &RawSpan) -> String {
+    let name = files.name(span.src_id).to_string_lossy();
+    match files.location(span.src_id, span.start) {
+        Ok(loc) => format!("{}:{}:{}", name, loc.line.number(), loc.column.number()),
+        Err(_) => name.into_owned(),
+    }
+}
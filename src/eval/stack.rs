@@ -100,8 +100,21 @@ impl IntoIterator for Stack {
     type Item = Marker;
     type IntoIter = ::std::vec::IntoIter<Marker>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    fn into_iter(mut self) -> Self::IntoIter {
+        std::mem::take(&mut self.0).into_iter()
+    }
+}
+
+/// The thunks still waiting for an update when the stack is dropped are the ones whose evaluation
+/// was interrupted by an error. They are reset, such that they can be forced again later, for
+/// example in the REPL or when collecting errors (see [super::eval_full_collect]).
+impl Drop for Stack {
+    fn drop(&mut self) {
+        for marker in self.0.drain(..) {
+            if let Marker::Thunk(thunk) = marker {
+                thunk.reset();
+            }
+        }
     }
 }
 
@@ -126,6 +139,15 @@ impl Stack {
         count
     }
 
+    /// Return the number of elements of the stack.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Count the number of arguments at the top of the stack.
     pub fn count_args(&self) -> usize {
         Stack::count(self, Marker::is_arg)
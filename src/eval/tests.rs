@@ -11,7 +11,13 @@ use codespan::Files;
 
 /// Evaluate a term without import support.
 fn eval_no_import(t: RichTerm) -> Result<Term, EvalError> {
-    eval(t, &Environment::new(), &mut DummyResolver {}).map(Term::from)
+    eval(
+        t,
+        &Environment::new(),
+        &mut DummyResolver {},
+        &mut EvalContext::new(),
+    )
+    .map(Term::from)
 }
 
 fn parse(s: &str) -> Option<RichTerm> {
@@ -195,7 +201,8 @@ fn imports() {
         eval(
             mk_import("x", "two", mk_term::var("x"), &mut resolver).unwrap(),
             &Environment::new(),
-            &mut resolver
+            &mut resolver,
+            &mut EvalContext::new()
         )
         .map(Term::from)
         .unwrap(),
@@ -213,7 +220,8 @@ fn imports() {
             )
             .unwrap(),
             &Environment::new(),
-            &mut resolver
+            &mut resolver,
+            &mut EvalContext::new()
         )
         .map(Term::from)
         .unwrap(),
@@ -298,24 +306,146 @@ fn global_env() {
 
     let t = mk_term::let_in("x", Term::Num(2.0), mk_term::var("x"));
     assert_eq!(
-        eval(t, &global_env, &mut resolver).map(Term::from),
+        eval(t, &global_env, &mut resolver, &mut EvalContext::new()).map(Term::from),
         Ok(Term::Num(2.0))
     );
 
     let t = mk_term::let_in("x", Term::Num(2.0), mk_term::var("g"));
     assert_eq!(
-        eval(t, &global_env, &mut resolver).map(Term::from),
+        eval(t, &global_env, &mut resolver, &mut EvalContext::new()).map(Term::from),
         Ok(Term::Num(1.0))
     );
 
     // Shadowing of global environment
     let t = mk_term::let_in("g", Term::Num(2.0), mk_term::var("g"));
     assert_eq!(
-        eval(t, &global_env, &mut resolver).map(Term::from),
+        eval(t, &global_env, &mut resolver, &mut EvalContext::new()).map(Term::from),
         Ok(Term::Num(2.0))
     );
 }
 
+#[test]
+fn env_access() {
+    std::env::set_var("NICKEL_TEST_ENV_ACCESS", "foo");
+    let mut ctx = EvalContext::new();
+    let env_var = |name: &str, ctx: &mut EvalContext| -> Result<Term, EvalError> {
+        eval(
+            mk_term::op1(UnaryOp::EnvVar(), mk_term::string(name)),
+            &Environment::new(),
+            &mut DummyResolver {},
+            ctx,
+        )
+        .map(Term::from)
+    };
+
+    assert!(matches!(
+        env_var("NICKEL_TEST_ENV_ACCESS", &mut ctx),
+        Err(EvalError::EnvAccessDenied(..))
+    ));
+    assert!(ctx.accessed_env().is_empty());
+
+    ctx.allow_env("NICKEL_TEST_ENV_ACCESS");
+    ctx.allow_env("NICKEL_TEST_ENV_UNSET");
+    assert_eq!(
+        env_var("NICKEL_TEST_ENV_ACCESS", &mut ctx),
+        Ok(Term::Str(String::from("foo")))
+    );
+    assert_eq!(env_var("NICKEL_TEST_ENV_UNSET", &mut ctx), Ok(Term::Null));
+    assert_eq!(
+        ctx.accessed_env().iter().collect::<Vec<_>>(),
+        vec![
+            (
+                &String::from("NICKEL_TEST_ENV_ACCESS"),
+                &Some(String::from("foo"))
+            ),
+            (&String::from("NICKEL_TEST_ENV_UNSET"), &None)
+        ]
+    );
+}
+
+#[test]
+fn trace() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let traces = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = EvalContext::new();
+    let traces_handler = Rc::clone(&traces);
+    ctx.set_trace_handler(move |trace| {
+        traces_handler
+            .borrow_mut()
+            .push((trace.message.clone(), trace.value_to_string()))
+    });
+
+    let mut eval_trace = |s: &str| {
+        eval(
+            parse(s).unwrap(),
+            &Environment::new(),
+            &mut DummyResolver {},
+            &mut ctx,
+        )
+        .map(Term::from)
+    };
+
+    assert_eq!(eval_trace("%trace% \"msg\" (1 + 1)"), Ok(Term::Num(2.0)));
+    assert_eq!(
+        eval_trace("let x = %trace_value% \"value\" 2 in x + x"),
+        Ok(Term::Num(4.0))
+    );
+    assert_eq!(
+        eval_trace("%trace_value% \"string\" \"a \\\"b\\\"\""),
+        Ok(Term::Str(String::from("a \"b\"")))
+    );
+    assert!(eval_trace("%trace% 1 2").is_err());
+    assert_eq!(
+        *traces.borrow(),
+        vec![
+            (String::from("msg"), None),
+            (String::from("value"), Some(String::from("2"))),
+            (
+                String::from("string"),
+                Some(String::from("\"a \\\"b\\\"\""))
+            )
+        ]
+    );
+
+    ctx.set_silence_traces(true);
+    eval(
+        parse("%trace% \"silenced\" 1").unwrap(),
+        &Environment::new(),
+        &mut DummyResolver {},
+        &mut ctx,
+    )
+    .unwrap();
+    assert_eq!(traces.borrow().len(), 3);
+}
+
+#[test]
+fn failed_thunk_can_be_forced_again() {
+    let global_env = mk_env(vec![("x", parse("1 + true").unwrap())]);
+    let mut ctx = EvalContext::new();
+    let mut eval_x = || {
+        eval(
+            mk_term::var("x"),
+            &global_env,
+            &mut DummyResolver {},
+            &mut ctx,
+        )
+    };
+
+    // The thunk of `x` must not stay blackholed after the first failure, or forcing it again
+    // would report an infinite recursion instead of the original error.
+    for _ in 0..2 {
+        match eval_x() {
+            Err(EvalError::TypeError(..)) => (),
+            result => panic!("expected a type error, got {:?}", result),
+        }
+    }
+
+    let thunk = global_env.get(&Ident::from("x")).unwrap();
+    assert_eq!(thunk.state(), ThunkState::Suspended);
+}
+
 fn mk_env(bindings: Vec<(&str, RichTerm)>) -> Environment {
     bindings
         .into_iter()
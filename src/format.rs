@@ -0,0 +1,332 @@
+//! Source formatting.
+//!
+//! Contrary to the pretty printer of [`crate::pretty`], which prints a term and thus loses the
+//! comments and the layout of the original source, the formatter works on the tokens of the
+//! source. It keeps comments and line breaks where they are, and only normalizes whitespace:
+//!
+//! - lines are indented by [`INDENT`] spaces for each delimiter (`{`, `[`, `(`, `[|` or the start
+//!   of an interpolated expression) left open by the previous lines. A line starting with a
+//!   closing delimiter is aligned with the line which opened it.
+//! - a line continuing the expression of the previous line, such as the body of a function or
+//!   the branches of an `if`, keeps its original indentation relative to the line which started
+//!   the expression. An expression starts after an opening delimiter, a `,`, a `;` or an `in`.
+//! - spaces between tokens are collapsed to a single one, and trailing whitespace is removed.
+//! - consecutive blank lines are collapsed to a single one, and blank lines at the beginning and
+//!   at the end of the source are removed.
+//!
+//! The content of strings is never modified: the lines starting inside a multiline string are
+//! left as is.
+use crate::parser::error::ParseError;
+use crate::parser::lexer::{Lexer, MultiStringToken, NormalToken, StringToken, Token};
+
+/// The number of spaces of an indentation level.
+pub const INDENT: usize = 2;
+
+/// Format a source. Fail if the source can't be lexed.
+pub fn format(source: &str) -> Result<String, ParseError> {
+    let mut result = String::new();
+    for line in format_lines(source)?.into_iter().flatten() {
+        result.push_str(&line);
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+/// Format a source line by line. Return the formatted content of each line of the source, without
+/// the line break, or `None` for a blank line which is removed. Fail if the source can't be lexed.
+///
+/// As the formatter preserves line breaks, a part of a source can be formatted by replacing its
+/// lines with their formatted content.
+pub fn format_lines(source: &str) -> Result<Vec<Option<String>>, ParseError> {
+    let tokens = Lexer::with_comments(source).collect::<Result<Vec<_>, _>>()?;
+
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(index, _)| index + 1));
+    let line_of = |offset: usize| match starts.binary_search(&offset) {
+        Ok(line) => line,
+        Err(line) => line - 1,
+    };
+
+    // Whether the line break ending each line is part of a string.
+    let mut in_string = vec![false; starts.len()];
+    for (start, token, end) in tokens.iter() {
+        if matches!(token, Token::Str(_) | Token::MultiStr(_)) {
+            for (index, _) in source[*start..*end].match_indices('\n') {
+                in_string[line_of(start + index)] = true;
+            }
+        }
+    }
+
+    let mut formatter = Formatter::new();
+    let mut tokens = tokens.into_iter().peekable();
+    let mut lines = Vec::with_capacity(starts.len());
+
+    for (index, start) in starts.iter().copied().enumerate() {
+        let end = starts
+            .get(index + 1)
+            .map(|next| next - 1)
+            .unwrap_or_else(|| source.len());
+        let line = &source[start..end];
+
+        let mut line_tokens = Vec::new();
+        while let Some((token_start, _, _)) = tokens.peek() {
+            if *token_start > end {
+                break;
+            }
+            line_tokens.push(tokens.next().unwrap());
+        }
+
+        let verbatim = index > 0 && in_string[index - 1];
+        if verbatim {
+            let original = line.len() - line.trim_start().len();
+            formatter.update(&line_tokens, original);
+            lines.push(Line::Code(if in_string[index] {
+                line.to_owned()
+            } else {
+                line.trim_end().to_owned()
+            }));
+        } else if line_tokens.is_empty() {
+            lines.push(Line::Blank);
+        } else {
+            let indent = formatter.indent(line, &line_tokens);
+            formatter.update(&line_tokens, indent);
+
+            let mut text = " ".repeat(indent);
+            let mut previous_end = None;
+            for (token_start, _, token_end) in line_tokens.iter() {
+                if matches!(previous_end, Some(previous_end) if *token_start > previous_end) {
+                    text.push(' ');
+                }
+                text.push_str(&source[*token_start..(*token_end).min(end)]);
+                previous_end = Some(*token_end);
+            }
+            // Comments may end with whitespace
+            if !in_string[index] {
+                text.truncate(text.trim_end().len());
+            }
+            lines.push(Line::Code(text));
+        }
+    }
+
+    // Remove the blank lines at the end of the source.
+    while let Some(Line::Blank) = lines.last() {
+        lines.pop();
+    }
+
+    let mut result = Vec::with_capacity(starts.len());
+    let mut previous_blank = true;
+    for line in lines {
+        match line {
+            Line::Blank if previous_blank => result.push(None),
+            Line::Blank => {
+                previous_blank = true;
+                result.push(Some(String::new()));
+            }
+            Line::Code(text) => {
+                previous_blank = false;
+                result.push(Some(text));
+            }
+        }
+    }
+    result.resize(starts.len(), None);
+
+    Ok(result)
+}
+
+/// A line of the formatted source.
+enum Line {
+    Blank,
+    Code(String),
+}
+
+/// A delimiter left open by the previous lines, or the whole source.
+struct Frame {
+    /// The indentation of the content of the delimiter.
+    content: usize,
+    /// The indentation of the line closing the delimiter.
+    closing: usize,
+    /// The original and formatted indentation of the line starting the current expression inside
+    /// the delimiter, if any.
+    expression: Option<(usize, usize)>,
+}
+
+struct Formatter {
+    frames: Vec<Frame>,
+    /// Whether the last token, excluding comments, starts a new expression.
+    expression_start: bool,
+}
+
+impl Formatter {
+    fn new() -> Self {
+        Formatter {
+            frames: vec![Frame {
+                content: 0,
+                closing: 0,
+                expression: None,
+            }],
+            expression_start: true,
+        }
+    }
+
+    /// Compute the indentation of a line which doesn't start inside a string.
+    fn indent(&mut self, line: &str, tokens: &[(usize, Token, usize)]) -> usize {
+        let original = line.len() - line.trim_start().len();
+        let is_comment = tokens
+            .iter()
+            .all(|(_, token, _)| *token == Token::Normal(NormalToken::LineComment));
+        let can_close = self.frames.len() > 1;
+        let frame = self.frames.last_mut().unwrap();
+
+        if can_close && is_closing(&tokens[0].1) {
+            frame.closing
+        } else if self.expression_start {
+            // A comment before an expression doesn't start it
+            if !is_comment {
+                frame.expression = Some((original, frame.content));
+            }
+            frame.content
+        } else {
+            match frame.expression {
+                Some((expr_original, expr_indent)) => {
+                    expr_indent + original.saturating_sub(expr_original)
+                }
+                None => frame.content,
+            }
+        }
+    }
+
+    /// Update the open delimiters with the tokens of a line, indented by `indent` spaces.
+    fn update(&mut self, tokens: &[(usize, Token, usize)], indent: usize) {
+        for (_, token, _) in tokens.iter() {
+            if *token == Token::Normal(NormalToken::LineComment) {
+                continue;
+            }
+
+            if is_opening(token) {
+                self.frames.push(Frame {
+                    content: indent + INDENT,
+                    closing: indent,
+                    expression: None,
+                });
+            } else if is_closing(token) && self.frames.len() > 1 {
+                self.frames.pop();
+            }
+
+            self.expression_start = is_opening(token)
+                || matches!(
+                    token,
+                    Token::Normal(NormalToken::Comma)
+                        | Token::Normal(NormalToken::Semicolon)
+                        | Token::Normal(NormalToken::In)
+                );
+        }
+    }
+}
+
+fn is_opening(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Normal(NormalToken::LBrace)
+            | Token::Normal(NormalToken::LBracket)
+            | Token::Normal(NormalToken::LParen)
+            | Token::Normal(NormalToken::EnumOpen)
+            | Token::Str(StringToken::Interpolation)
+            | Token::MultiStr(MultiStringToken::Interpolation)
+    )
+}
+
+fn is_closing(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Normal(NormalToken::RBrace)
+            | Token::Normal(NormalToken::RBracket)
+            | Token::Normal(NormalToken::RParen)
+            | Token::Normal(NormalToken::EnumClose)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::error::LexicalError;
+
+    /// Format a source, and check that formatting is idempotent.
+    fn fmt(s: &str) -> String {
+        let formatted = format(s).unwrap();
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn indentation() {
+        assert_eq!(
+            fmt("{\nfoo = [\n      1,\n2,\n    ],\n      bar = {\n  baz = 1 }\n   }"),
+            "{\n  foo = [\n    1,\n    2,\n  ],\n  bar = {\n    baz = 1 }\n}\n"
+        );
+        assert_eq!(
+            fmt("let x = [{\n    a = 1\n}] in\n  x"),
+            "let x = [{\n  a = 1\n}] in\nx\n"
+        );
+        assert_eq!(
+            fmt("{\n    a = \"%{\n    1 + 1\n}\",\n}"),
+            "{\n  a = \"%{\n    1 + 1\n  }\",\n}\n"
+        );
+    }
+
+    #[test]
+    fn continuation() {
+        assert_eq!(
+            fmt("{\n      f = fun x =>\n        x + 1,\n      g = fun x =>\n        if x then\n          1\n        else\n          2,\n}"),
+            "{\n  f = fun x =>\n    x + 1,\n  g = fun x =>\n    if x then\n      1\n    else\n      2,\n}\n"
+        );
+        assert_eq!(
+            fmt("let x =\n      1\n  in\n    let y = 2 in\n  x + y"),
+            "let x =\n      1\n  in\nlet y = 2 in\nx + y\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            fmt("# header\n{\n      # the name\n  name = \"foo\",   # trailing   \n    # closing\n}"),
+            "# header\n{\n  # the name\n  name = \"foo\", # trailing\n  # closing\n}\n"
+        );
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            fmt("\n\n{   a  =  1,\t\n\n\n\n  b = 2,  \n\n}\n\n\n"),
+            "{ a = 1,\n\n  b = 2,\n\n}\n"
+        );
+        assert_eq!(
+            format_lines("\n1 +\n\n\n  1\n\n").unwrap(),
+            vec![
+                None,
+                Some(String::from("1 +")),
+                Some(String::new()),
+                None,
+                Some(String::from("  1")),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            fmt("{\n     text = m%\"\n        first   \n          second %{ \"a  b\" }\n     \"%m,\n  other = \"x  \ny\",\n}"),
+            "{\n  text = m%\"\n        first   \n          second %{ \"a  b\" }\n     \"%m,\n  other = \"x  \ny\",\n}\n"
+        );
+    }
+
+    #[test]
+    fn lexical_error() {
+        assert_eq!(
+            format("{ a = 1 }}"),
+            Err(ParseError::Lexical(LexicalError::UnmatchedCloseBrace(9)))
+        );
+    }
+}
@@ -67,6 +67,61 @@ pub mod ty_path {
             .any(|elt| matches!(*elt, Elem::Domain | Elem::Codomain))
     }
 
+    /// Return the subtype of `ty` designated by a type path, or `None` if the path doesn't match
+    /// the structure of the type.
+    pub fn subtype<'a>(path: &[Elem], mut ty: &'a Types) -> Option<&'a Types> {
+        for elem in path {
+            while let AbsType::Forall(_, body) = &ty.0 {
+                ty = body.as_ref();
+            }
+
+            ty = match (&ty.0, elem) {
+                (AbsType::Arrow(dom, _), Elem::Domain) => dom.as_ref(),
+                (AbsType::Arrow(_, codom), Elem::Codomain) => codom.as_ref(),
+                (AbsType::Array(elt), Elem::Array) => elt.as_ref(),
+                (AbsType::StaticRecord(rows), Elem::Field(ident)) => {
+                    let mut row = rows.as_ref();
+                    loop {
+                        match &row.0 {
+                            AbsType::RowExtend(id, Some(ty), _) if id == ident => {
+                                break ty.as_ref()
+                            }
+                            AbsType::RowExtend(_, _, tail) => row = tail.as_ref(),
+                            _ => return None,
+                        }
+                    }
+                }
+                _ => return None,
+            };
+        }
+
+        Some(ty)
+    }
+
+    /// Render the part of a type path going through records and arrays, such as `foo.bar[_]`, or
+    /// return `None` if the path doesn't go through any record or array.
+    pub fn data_path(path: &[Elem]) -> Option<String> {
+        let mut result = String::new();
+
+        for elem in path {
+            match elem {
+                Elem::Field(id) if result.is_empty() => result.push_str(&id.label),
+                Elem::Field(id) => {
+                    result.push('.');
+                    result.push_str(&id.label);
+                }
+                Elem::Array => result.push_str("[_]"),
+                Elem::Domain | Elem::Codomain => (),
+            }
+        }
+
+        if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
     /// Return the position span encoded by a type path in the string representation of the
     /// corresponding type.
     ///
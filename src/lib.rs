@@ -1,10 +1,16 @@
 pub mod cache;
+pub mod dap;
 pub mod destruct;
+pub mod diagnostic;
+pub mod diff;
 pub mod environment;
 pub mod error;
+pub mod error_codes;
 pub mod eval;
+pub mod format;
 pub mod identifier;
 pub mod label;
+pub mod lint;
 pub mod parser;
 pub mod position;
 pub mod pretty;
@@ -0,0 +1,759 @@
+//! Static lints.
+//!
+//! The linter walks the term produced by the parser, before any program transformation, and
+//! reports suspicious but valid code as warnings: let-bindings and imports which are never used,
+//! bindings shadowing another one, switch arms that can never be taken, and fields defined several
+//! times in the same record literal. Each kind of warning corresponds to a [`Lint`], which can be
+//! enabled or disabled individually through a [`LintConfig`].
+use crate::destruct::{Destruct, Match};
+use crate::error::{primary, secondary, ToDiagnostic};
+use crate::identifier::{Ident, GEN_PREFIX};
+use crate::position::TermPos;
+use crate::term::{BinaryOp, Contract, MetaValue, RichTerm, StrChunk, Term};
+use crate::types::{AbsType, Types};
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A lint rule.
+// If you add or remove variants, remember to update the CLI docs in `src/bin/nickel.rs'
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Lint {
+    /// A let-binding which is never used.
+    UnusedBinding,
+    /// An imported file bound to a variable which is never used.
+    UnusedImport,
+    /// A binding with the same name as an enclosing one, which becomes inaccessible.
+    Shadowing,
+    /// A switch arm which can never be taken.
+    UnreachableArm,
+    /// A field defined several times in the same record literal with values that can't be merged.
+    DuplicateField,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedBinding,
+        Lint::UnusedImport,
+        Lint::Shadowing,
+        Lint::UnreachableArm,
+        Lint::DuplicateField,
+    ];
+
+    /// The name of the rule, as used on the command line and as the code of the diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedBinding => "unused-binding",
+            Lint::UnusedImport => "unused-import",
+            Lint::Shadowing => "shadowing",
+            Lint::UnreachableArm => "unreachable-arm",
+            Lint::DuplicateField => "duplicate-field",
+        }
+    }
+
+    /// Is the rule enabled in the default configuration? Shadowing is common and intended in
+    /// Nickel, where `let` is not recursive, so it is only reported on demand.
+    pub fn is_default(&self) -> bool {
+        !matches!(self, Lint::Shadowing)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseLintError(String);
+
+impl fmt::Display for ParseLintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown lint {}", self.0)
+    }
+}
+
+impl FromStr for Lint {
+    type Err = ParseLintError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .iter()
+            .find(|lint| lint.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| ParseLintError(String::from(s)))
+    }
+}
+
+/// The set of enabled lints.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LintConfig {
+    enabled: HashSet<Lint>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            enabled: Lint::ALL.iter().copied().filter(Lint::is_default).collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn enable(&mut self, lint: Lint) {
+        self.enabled.insert(lint);
+    }
+
+    pub fn disable(&mut self, lint: Lint) {
+        self.enabled.remove(&lint);
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled.contains(&lint)
+    }
+}
+
+/// A warning reported by the linter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintWarning {
+    /// A let-bound variable is never used.
+    UnusedBinding(Ident),
+    /// A variable bound to an import is never used.
+    UnusedImport(Ident),
+    /// A binding shadows an enclosing binding with the same name.
+    Shadowing {
+        /// The new binding.
+        id: Ident,
+        /// The binding which is shadowed.
+        shadowed: Ident,
+    },
+    /// A switch arm is never taken, because the matched value is a known enum tag.
+    UnreachableArm {
+        /// The tag of the unreachable arm, or `None` for the default arm.
+        arm: Option<Ident>,
+        /// The position of the unreachable arm.
+        arm_pos: TermPos,
+        /// The tag which is always matched.
+        value: Ident,
+        /// The position of the matched expression.
+        value_pos: TermPos,
+    },
+    /// A field is defined twice with values that can't be merged.
+    DuplicateField {
+        id: Ident,
+        first: TermPos,
+        second: TermPos,
+    },
+}
+
+impl LintWarning {
+    /// The lint rule which produced this warning.
+    pub fn lint(&self) -> Lint {
+        match self {
+            LintWarning::UnusedBinding(_) => Lint::UnusedBinding,
+            LintWarning::UnusedImport(_) => Lint::UnusedImport,
+            LintWarning::Shadowing { .. } => Lint::Shadowing,
+            LintWarning::UnreachableArm { .. } => Lint::UnreachableArm,
+            LintWarning::DuplicateField { .. } => Lint::DuplicateField,
+        }
+    }
+
+    /// The main position of the warning, used to sort warnings.
+    fn pos(&self) -> TermPos {
+        match self {
+            LintWarning::UnusedBinding(id)
+            | LintWarning::UnusedImport(id)
+            | LintWarning::Shadowing { id, .. } => id.pos,
+            LintWarning::UnreachableArm { arm_pos, .. } => *arm_pos,
+            LintWarning::DuplicateField { second, .. } => *second,
+        }
+    }
+}
+
+impl ToDiagnostic<FileId> for LintWarning {
+    fn to_diagnostic(
+        &self,
+        _files: &mut Files<String>,
+        _contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let label = |pos: &TermPos, primary_label: bool, msg: &str| -> Option<Label<FileId>> {
+            let span = pos.as_opt_ref()?;
+            let label = if primary_label {
+                primary(span)
+            } else {
+                secondary(span)
+            };
+            Some(label.with_message(msg))
+        };
+
+        let (message, labels, notes) = match self {
+            LintWarning::UnusedBinding(id) => (
+                format!("unused variable `{}`", id),
+                vec![label(&id.pos, true, "this variable is never used")],
+                vec![format!(
+                    "If this is intentional, prefix the variable with an underscore: `_{}`",
+                    id
+                )],
+            ),
+            LintWarning::UnusedImport(id) => (
+                format!("unused import `{}`", id),
+                vec![label(&id.pos, true, "this import is never used")],
+                vec![String::from("Remove the import if it is not needed anymore.")],
+            ),
+            LintWarning::Shadowing { id, shadowed } => (
+                format!("`{}` shadows an existing variable", id),
+                vec![
+                    label(&id.pos, true, "this binding"),
+                    label(&shadowed.pos, false, "shadows this binding"),
+                ],
+                vec![String::from(
+                    "The shadowed variable is not accessible in the scope of the new binding.",
+                )],
+            ),
+            LintWarning::UnreachableArm {
+                arm,
+                arm_pos,
+                value,
+                value_pos,
+            } => (
+                match arm {
+                    Some(arm) => format!("unreachable switch arm `{}", arm),
+                    None => String::from("unreachable default switch arm"),
+                },
+                vec![
+                    label(arm_pos, true, "this arm is never taken"),
+                    label(
+                        value_pos,
+                        false,
+                        &format!("the matched value is always `{}", value),
+                    ),
+                ],
+                Vec::new(),
+            ),
+            LintWarning::DuplicateField { id, first, second } => (
+                format!("field `{}` is defined twice", id),
+                vec![
+                    label(second, true, "redefined here"),
+                    label(first, false, "first definition"),
+                ],
+                vec![String::from(
+                    "Both definitions are merged, which fails at evaluation unless the values are equal.",
+                )],
+            ),
+        };
+
+        vec![Diagnostic::warning()
+            .with_message(message)
+            .with_code(self.lint().name())
+            .with_labels(labels.into_iter().flatten().collect())
+            .with_notes(notes)]
+    }
+}
+
+/// Lint a parsed term, and return the warnings of the lints enabled in `config`, sorted by
+/// position.
+pub fn lint(rt: &RichTerm, config: &LintConfig) -> Vec<LintWarning> {
+    let mut linter = Linter {
+        scope: Vec::new(),
+        warnings: Vec::new(),
+    };
+    linter.walk(rt);
+
+    let mut warnings: Vec<LintWarning> = linter
+        .warnings
+        .into_iter()
+        .filter(|w| config.is_enabled(w.lint()))
+        .collect();
+    warnings.sort_by_key(|w| w.pos().into_opt().map(|span| (span.src_id, span.start)));
+    warnings
+}
+
+/// The kind of a variable in scope.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum BindingKind {
+    Let,
+    Import,
+    Param,
+    Field,
+}
+
+/// A variable in scope.
+struct Binding {
+    id: Ident,
+    kind: BindingKind,
+    used: bool,
+    /// The enum tag the variable is bound to, if it is a literal.
+    tag: Option<Ident>,
+}
+
+struct Linter {
+    scope: Vec<Binding>,
+    warnings: Vec<LintWarning>,
+}
+
+/// Variables prefixed with an underscore are unused on purpose, and generated variables don't
+/// appear in the source.
+fn is_ignored(id: &Ident) -> bool {
+    id.label.starts_with('_') || id.label.starts_with(GEN_PREFIX)
+}
+
+impl Linter {
+    fn bind(&mut self, id: &Ident, kind: BindingKind, tag: Option<Ident>) {
+        if kind != BindingKind::Field && !is_ignored(id) {
+            let shadowed = self
+                .scope
+                .iter()
+                .rev()
+                .find(|b| b.kind != BindingKind::Field && b.id == *id);
+
+            if let Some(shadowed) = shadowed {
+                self.warnings.push(LintWarning::Shadowing {
+                    id: id.clone(),
+                    shadowed: shadowed.id.clone(),
+                });
+            }
+        }
+
+        self.scope.push(Binding {
+            id: id.clone(),
+            kind,
+            used: false,
+            tag,
+        });
+    }
+
+    /// Bind the variables of a pattern and return their number.
+    fn bind_pattern(&mut self, pat: &Destruct, kind: BindingKind) -> usize {
+        match pat {
+            Destruct::Record { matches, rest, .. } => {
+                let bound: usize = matches.iter().map(|m| self.bind_match(m, kind)).sum();
+                if let Some(rest) = rest {
+                    self.bind(rest, kind, None);
+                    bound + 1
+                } else {
+                    bound
+                }
+            }
+            Destruct::Array { matches, .. } => {
+                matches.iter().map(|m| self.bind_match(m, kind)).sum()
+            }
+            Destruct::Empty => 0,
+        }
+    }
+
+    fn bind_match(&mut self, m: &Match, kind: BindingKind) -> usize {
+        match m {
+            Match::Assign(_, _, (id, sub_pat)) => {
+                let bound = self.bind_pattern(sub_pat, kind);
+                if let Some(id) = id {
+                    self.bind(id, kind, None);
+                    bound + 1
+                } else {
+                    bound
+                }
+            }
+            Match::Simple(id, _) => {
+                self.bind(id, kind, None);
+                1
+            }
+        }
+    }
+
+    /// Pop the last `count` bindings from the scope, reporting the unused ones.
+    fn unbind(&mut self, count: usize) {
+        for _ in 0..count {
+            let binding = self.scope.pop().unwrap();
+
+            if binding.used || is_ignored(&binding.id) {
+                continue;
+            }
+
+            match binding.kind {
+                BindingKind::Let => self.warnings.push(LintWarning::UnusedBinding(binding.id)),
+                BindingKind::Import => self.warnings.push(LintWarning::UnusedImport(binding.id)),
+                BindingKind::Param | BindingKind::Field => (),
+            }
+        }
+    }
+
+    fn lookup(&mut self, id: &Ident) -> Option<&mut Binding> {
+        self.scope.iter_mut().rev().find(|b| b.id == *id)
+    }
+
+    fn walk(&mut self, rt: &RichTerm) {
+        match rt.term.as_ref() {
+            Term::Var(id) => {
+                if let Some(binding) = self.lookup(id) {
+                    binding.used = true;
+                }
+            }
+            Term::ParseError
+            | Term::Null
+            | Term::Bool(_)
+            | Term::Num(_)
+            | Term::Str(_)
+            | Term::Lbl(_)
+            | Term::Sym(_)
+            | Term::Enum(_)
+            | Term::Import(_)
+            | Term::ResolvedImport(_) => (),
+            Term::Fun(id, body) => {
+                self.bind(id, BindingKind::Param, None);
+                self.walk(body);
+                self.unbind(1);
+            }
+            Term::FunPattern(id, pat, body) => {
+                self.walk_pattern_meta(pat);
+
+                let mut bound = self.bind_pattern(pat, BindingKind::Param);
+                if let Some(id) = id {
+                    self.bind(id, BindingKind::Param, None);
+                    bound += 1;
+                }
+
+                self.walk(body);
+                self.unbind(bound);
+            }
+            Term::Let(id, t1, t2, attrs) => {
+                let kind = match t1.term.as_ref() {
+                    Term::Import(_) | Term::ResolvedImport(_) => BindingKind::Import,
+                    _ => BindingKind::Let,
+                };
+                let tag = match t1.term.as_ref() {
+                    Term::Enum(tag) => Some(tag.clone()),
+                    _ => None,
+                };
+
+                if attrs.rec {
+                    self.bind(id, kind, tag);
+                    self.walk(t1);
+                } else {
+                    self.walk(t1);
+                    self.bind(id, kind, tag);
+                }
+
+                self.walk(t2);
+                self.unbind(1);
+            }
+            Term::LetPattern(id, pat, t1, t2) => {
+                self.walk(t1);
+                self.walk_pattern_meta(pat);
+
+                let mut bound = self.bind_pattern(pat, BindingKind::Let);
+                if let Some(id) = id {
+                    self.bind(id, BindingKind::Let, None);
+                    bound += 1;
+                }
+
+                self.walk(t2);
+                self.unbind(bound);
+            }
+            Term::App(t1, t2) | Term::Op2(_, t1, t2) => {
+                self.walk(t1);
+                self.walk(t2);
+            }
+            Term::Switch(t, cases, default) => {
+                self.walk(t);
+                self.check_switch(t, cases, default);
+
+                for t in cases.values().chain(default.iter()) {
+                    self.walk(t);
+                }
+            }
+            Term::Op1(_, t) | Term::Wrapped(_, t) => self.walk(t),
+            Term::OpN(_, ts) | Term::Array(ts) => {
+                for t in ts {
+                    self.walk(t);
+                }
+            }
+            Term::Record(map, _) => {
+                self.check_duplicates(map);
+
+                for t in map.values() {
+                    self.walk(t);
+                }
+            }
+            Term::RecRecord(map, dyn_fields, _, _) => {
+                self.check_duplicates(map);
+
+                // As in `free_vars`, the names of dynamic fields are not in the scope of the
+                // record.
+                for (t, _) in dyn_fields {
+                    self.walk(t);
+                }
+
+                for id in map.keys() {
+                    self.bind(id, BindingKind::Field, None);
+                }
+
+                for t in map.values().chain(dyn_fields.iter().map(|(_, t)| t)) {
+                    self.walk(t);
+                }
+
+                self.unbind(map.len());
+            }
+            Term::StrChunks(chunks) => {
+                for chunk in chunks {
+                    if let StrChunk::Expr(t, _) = chunk {
+                        self.walk(t);
+                    }
+                }
+            }
+            Term::MetaValue(meta) => self.walk_meta(meta),
+        }
+    }
+
+    fn walk_meta(&mut self, meta: &MetaValue) {
+        for Contract { types, .. } in meta.types.iter().chain(meta.contracts.iter()) {
+            self.walk_type(types);
+        }
+
+        if let Some(t) = &meta.value {
+            self.walk(t);
+        }
+    }
+
+    /// Walk the contracts and the default values of a pattern.
+    fn walk_pattern_meta(&mut self, pat: &Destruct) {
+        if let Destruct::Record { matches, .. } | Destruct::Array { matches, .. } = pat {
+            for m in matches {
+                match m {
+                    Match::Assign(_, meta, (_, sub_pat)) => {
+                        self.walk_meta(meta);
+                        self.walk_pattern_meta(sub_pat);
+                    }
+                    Match::Simple(_, meta) => self.walk_meta(meta),
+                }
+            }
+        }
+    }
+
+    /// Walk the terms (custom contracts) inside a type.
+    fn walk_type(&mut self, ty: &Types) {
+        match &ty.0 {
+            AbsType::Dyn()
+            | AbsType::Num()
+            | AbsType::Bool()
+            | AbsType::Str()
+            | AbsType::Sym()
+            | AbsType::Var(_)
+            | AbsType::RowEmpty() => (),
+            AbsType::Forall(_, ty)
+            | AbsType::Enum(ty)
+            | AbsType::StaticRecord(ty)
+            | AbsType::DynRecord(ty)
+            | AbsType::Array(ty) => self.walk_type(ty),
+            AbsType::Arrow(ty1, ty2) => {
+                self.walk_type(ty1);
+                self.walk_type(ty2);
+            }
+            AbsType::RowExtend(_, ty_opt, tail) => {
+                if let Some(ty) = ty_opt {
+                    self.walk_type(ty);
+                }
+                self.walk_type(tail);
+            }
+            AbsType::Flat(rt) => self.walk(rt),
+        }
+    }
+
+    /// Report the arms of a switch which can't be taken because the matched value is an enum tag
+    /// known statically, either a literal or a variable bound to a literal.
+    fn check_switch(
+        &mut self,
+        t: &RichTerm,
+        cases: &HashMap<Ident, RichTerm>,
+        default: &Option<RichTerm>,
+    ) {
+        let value = match t.term.as_ref() {
+            Term::Enum(tag) => Some(tag.clone()),
+            Term::Var(id) => self.lookup(id).and_then(|b| b.tag.clone()),
+            _ => None,
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None => return,
+        };
+
+        for arm in cases.keys().filter(|arm| **arm != value) {
+            self.warnings.push(LintWarning::UnreachableArm {
+                arm: Some(arm.clone()),
+                arm_pos: arm.pos,
+                value: value.clone(),
+                value_pos: t.pos,
+            });
+        }
+
+        match default {
+            Some(default) if cases.contains_key(&value) => {
+                self.warnings.push(LintWarning::UnreachableArm {
+                    arm: None,
+                    arm_pos: default.pos,
+                    value,
+                    value_pos: t.pos,
+                })
+            }
+            _ => (),
+        }
+    }
+
+    /// Report the fields of a record literal which are defined several times with values that
+    /// can't be merged. The parser combines multiple definitions of a field using a merge without
+    /// position, which distinguishes them from a merge written explicitly.
+    fn check_duplicates(&mut self, map: &HashMap<Ident, RichTerm>) {
+        fn definitions<'a>(rt: &'a RichTerm, acc: &mut Vec<&'a RichTerm>) {
+            match rt.term.as_ref() {
+                Term::Op2(BinaryOp::Merge(), t1, t2) if rt.pos == TermPos::None => {
+                    definitions(t1, acc);
+                    definitions(t2, acc);
+                }
+                _ => acc.push(rt),
+            }
+        }
+
+        for (id, t) in map {
+            let value = match t.term.as_ref() {
+                Term::MetaValue(meta) => meta.value.as_ref(),
+                _ => Some(t),
+            };
+
+            let mut defs = Vec::new();
+            if let Some(value) = value {
+                definitions(value, &mut defs);
+            }
+
+            let mut unmergeable = defs.into_iter().filter(|t| {
+                matches!(
+                    t.term.as_ref(),
+                    Term::Null
+                        | Term::Bool(_)
+                        | Term::Num(_)
+                        | Term::Str(_)
+                        | Term::StrChunks(_)
+                        | Term::Enum(_)
+                        | Term::Array(_)
+                        | Term::Fun(..)
+                        | Term::FunPattern(..)
+                )
+            });
+
+            if let (Some(first), Some(second)) = (unmergeable.next(), unmergeable.next()) {
+                self.warnings.push(LintWarning::DuplicateField {
+                    id: id.clone(),
+                    first: first.pos,
+                    second: second.pos,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+
+    fn lint_str(s: &str, config: &LintConfig) -> Vec<LintWarning> {
+        let id = Files::new().add("<test>", String::from(s));
+        let rt = grammar::TermParser::new()
+            .parse_term(id, lexer::Lexer::new(s))
+            .unwrap();
+        lint(&rt, config)
+    }
+
+    fn lints(s: &str) -> Vec<(Lint, String)> {
+        let mut config = LintConfig::default();
+        config.enable(Lint::Shadowing);
+
+        lint_str(s, &config)
+            .into_iter()
+            .map(|w| {
+                let name = match &w {
+                    LintWarning::UnusedBinding(id)
+                    | LintWarning::UnusedImport(id)
+                    | LintWarning::Shadowing { id, .. }
+                    | LintWarning::DuplicateField { id, .. } => id.label.clone(),
+                    LintWarning::UnreachableArm { arm, .. } => arm
+                        .as_ref()
+                        .map(|arm| arm.label.clone())
+                        .unwrap_or_else(|| String::from("_")),
+                };
+                (w.lint(), name)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unused() {
+        assert_eq!(
+            lints("let x = 1 in let y = 2 in let _z = 3 in y"),
+            vec![(Lint::UnusedBinding, String::from("x"))]
+        );
+        assert_eq!(
+            lints("let lib = import \"lib.ncl\" in let {a, b} = {a = 1, b = 2} in a"),
+            vec![
+                (Lint::UnusedImport, String::from("lib")),
+                (Lint::UnusedBinding, String::from("b"))
+            ]
+        );
+        assert_eq!(
+            lints("let C = fun l x => x in let y = 1 in {foo | C = y, bar = foo}"),
+            vec![]
+        );
+        assert_eq!(lints("fun x y => x"), vec![]);
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            lints("let x = 1 in let f = fun x => x in f x"),
+            vec![(Lint::Shadowing, String::from("x"))]
+        );
+        assert_eq!(
+            lints("fun x => let {a = x} = {a = x} in x"),
+            vec![(Lint::Shadowing, String::from("x"))]
+        );
+        // Fields are in scope in recursive records, so the outer `a` is unused.
+        assert_eq!(
+            lints("let a = 1 in {a = a, b = 2}"),
+            vec![(Lint::UnusedBinding, String::from("a"))]
+        );
+        assert!(lint_str("let x = 1 in let x = x + 1 in x", &LintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn unreachable_arms() {
+        assert_eq!(
+            lints("switch { `a => 1, `b => 2 } `a"),
+            vec![(Lint::UnreachableArm, String::from("b"))]
+        );
+        assert_eq!(
+            lints("let mode = `debug in switch { `debug => 1, _ => 2 } mode"),
+            vec![(Lint::UnreachableArm, String::from("_"))]
+        );
+        assert_eq!(lints("fun x => switch { `a => 1, `b => 2 } x"), vec![]);
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        assert_eq!(
+            lints("{a = 1, b = 2, a = 3}"),
+            vec![(Lint::DuplicateField, String::from("a"))]
+        );
+        assert_eq!(lints("{a.b = 1, a.c = 2, d | Num, d = 1}"), vec![]);
+        assert_eq!(lints("{a = 1 & 1}"), vec![]);
+    }
+
+    #[test]
+    fn config() {
+        let mut config = LintConfig::default();
+        config.disable(Lint::UnusedBinding);
+        assert!(lint_str("let x = 1 in 2", &config).is_empty());
+        assert_eq!("unused-import".parse(), Ok(Lint::UnusedImport));
+        assert!("unused".parse::<Lint>().is_err());
+    }
+}
@@ -36,4 +36,7 @@ pub enum ParseError {
     /// A recursive let pattern was encountered. They are not currently supported because we
     /// decided it was too involved to implement them.
     RecursiveLetPattern(RawSpan),
+    /// The message of a deprecation annotation `| deprecated "<message>"` contains interpolated
+    /// expressions, while it must be a literal string.
+    InterpolatedDeprecationMessage(RawSpan),
 }
@@ -261,6 +261,12 @@ pub enum NormalToken<'input> {
     NumFromStr,
     #[token("%enum_from_str%")]
     EnumFromStr,
+    #[token("%env%")]
+    EnvVar,
+    #[token("%trace%")]
+    Trace,
+    #[token("%trace_value%")]
+    TraceValue,
 
     #[token("{")]
     LBrace,
@@ -422,6 +428,9 @@ pub struct Lexer<'input> {
     /// made necessary by an issue of Logos (<https://github.com/maciejhirsz/logos/issues/200>). See
     /// [`MultiStringToken::QuotesCandidateInterpolation`].
     pub buffer: Option<(Token<'input>, Range<usize>)>,
+    /// If line comments are returned as tokens instead of being skipped. The parser doesn't
+    /// expect comments, but tools working on the source itself, such as the formatter, do.
+    pub comments: bool,
 }
 
 impl<'input> Lexer<'input> {
@@ -431,6 +440,15 @@ impl<'input> Lexer<'input> {
             stack: Vec::new(),
             count: 0,
             buffer: None,
+            comments: false,
+        }
+    }
+
+    /// Create a lexer which returns line comments as [`NormalToken::LineComment`] tokens.
+    pub fn with_comments(s: &'input str) -> Self {
+        Lexer {
+            comments: true,
+            ..Lexer::new(s)
         }
     }
 
@@ -679,7 +697,7 @@ impl<'input> Iterator for Lexer<'input> {
                 ))))
             }
             // Ignore comment
-            Some(Normal(NormalToken::LineComment)) => return self.next(),
+            Some(Normal(NormalToken::LineComment)) if !self.comments => return self.next(),
             _ => (),
         };
 
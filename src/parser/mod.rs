@@ -1,8 +1,8 @@
 use crate::error::{ParseError, ParseErrors};
 use crate::identifier::Ident;
-use crate::term::RichTerm;
+use crate::term::{RichTerm, Term};
 use codespan::FileId;
-use lalrpop_util::lalrpop_mod;
+use lalrpop_util::{lalrpop_mod, ErrorRecovery};
 
 lalrpop_mod!(
     #[allow(clippy::all)]
@@ -30,22 +30,42 @@ pub enum ExtendedTerm {
     ToplevelLet(Ident, RichTerm),
 }
 
+/// Gather the errors of a parse: the errors the parser recovered from and, if the parser couldn't
+/// recover, the error which stopped it. In the latter case, the term is replaced by
+/// `Term::ParseError`, but the errors recovered from before are still reported.
+fn with_recovered_errors<T>(
+    result: Result<T, lalrpop_util::ParseError<usize, lexer::Token, error::ParseError>>,
+    recovered: Vec<ErrorRecovery<usize, lexer::Token, error::ParseError>>,
+    file_id: FileId,
+    on_failure: impl FnOnce() -> T,
+) -> (T, ParseErrors) {
+    let mut parse_errors = ParseErrors::from_recoverable(recovered, file_id);
+
+    match result {
+        Ok(t) => (t, parse_errors),
+        Err(err) => {
+            parse_errors
+                .errors
+                .push(ParseError::from_lalrpop(err, file_id));
+            (on_failure(), parse_errors)
+        }
+    }
+}
+
 impl grammar::ExtendedTermParser {
+    /// Parse a term, recovering from syntax errors when possible. Always return a term, possibly
+    /// partial, together with all the syntax errors encountered.
     pub fn parse_term_tolerant(
         &self,
         file_id: FileId,
         lexer: lexer::Lexer,
-    ) -> Result<(ExtendedTerm, ParseErrors), ParseError> {
+    ) -> (ExtendedTerm, ParseErrors) {
         let mut parse_errors = Vec::new();
-        let result = self
-            .parse(file_id, &mut parse_errors, lexer)
-            .map_err(|err| ParseError::from_lalrpop(err, file_id));
+        let result = self.parse(file_id, &mut parse_errors, lexer);
 
-        let parse_errors = ParseErrors::from_recoverable(parse_errors, file_id);
-        match result {
-            Ok(t) => Ok((t, parse_errors)),
-            Err(e) => Err(e),
-        }
+        with_recovered_errors(result, parse_errors, file_id, || {
+            ExtendedTerm::RichTerm(RichTerm::from(Term::ParseError))
+        })
     }
 
     pub fn parse_term(
@@ -54,29 +74,26 @@ impl grammar::ExtendedTermParser {
         lexer: lexer::Lexer,
     ) -> Result<ExtendedTerm, ParseErrors> {
         match self.parse_term_tolerant(file_id, lexer) {
-            Ok((t, e)) if e.no_errors() => Ok(t),
-            Ok((_, e)) => Err(e),
-            Err(e) => Err(e.into()),
+            (t, e) if e.no_errors() => Ok(t),
+            (_, e) => Err(e),
         }
     }
 }
 
 impl grammar::TermParser {
+    /// Parse a term, recovering from syntax errors when possible. Always return a term, possibly
+    /// partial, together with all the syntax errors encountered.
     pub fn parse_term_tolerant(
         &self,
         file_id: FileId,
         lexer: lexer::Lexer,
-    ) -> Result<(RichTerm, ParseErrors), ParseError> {
+    ) -> (RichTerm, ParseErrors) {
         let mut parse_errors = Vec::new();
-        let result = self
-            .parse(file_id, &mut parse_errors, lexer)
-            .map_err(|err| ParseError::from_lalrpop(err, file_id));
+        let result = self.parse(file_id, &mut parse_errors, lexer);
 
-        let parse_errors = ParseErrors::from_recoverable(parse_errors, file_id);
-        match result {
-            Ok(t) => Ok((t, parse_errors)),
-            Err(e) => Err(e),
-        }
+        with_recovered_errors(result, parse_errors, file_id, || {
+            RichTerm::from(Term::ParseError)
+        })
     }
 
     pub fn parse_term(
@@ -85,9 +102,8 @@ impl grammar::TermParser {
         lexer: lexer::Lexer,
     ) -> Result<RichTerm, ParseErrors> {
         match self.parse_term_tolerant(file_id, lexer) {
-            Ok((t, e)) if e.no_errors() => Ok(t),
-            Ok((_, e)) => Err(e),
-            Err(e) => Err(e.into()),
+            (t, e) if e.no_errors() => Ok(t),
+            (_, e) => Err(e),
         }
     }
 }
@@ -372,3 +372,87 @@ fn line_comments() {
         parse_without_pos("{field = foo}")
     );
 }
+
+/// Parse a term, recovering from syntax errors, and return the partial term with all the errors.
+fn parse_tolerant(s: &str) -> (RichTerm, Vec<ParseError>) {
+    let id = Files::new().add("<test>", String::from(s));
+
+    let (t, errs) = super::grammar::TermParser::new().parse_term_tolerant(id, Lexer::new(s));
+    (t, errs.errors)
+}
+
+#[test]
+fn error_recovery() {
+    let has_fields = |t: &RichTerm, fields: &[&str]| match t.as_ref() {
+        RecRecord(map, ..) => fields
+            .iter()
+            .all(|field| map.contains_key(&Ident::from(*field))),
+        _ => false,
+    };
+
+    // Errors in a field name, an annotation, and a field value
+    for s in [
+        "{ = 1, b = 1 +, c = 2 }",
+        "{ a | = 1, b = 1 +, c = 2 }",
+        "{ \"a\" 1, b = +, c = 2 }",
+    ] {
+        let (t, errs) = parse_tolerant(s);
+        assert_eq!(errs.len(), 2, "{}", s);
+        assert!(has_fields(&t, &["b", "c"]), "{}", s);
+    }
+
+    // An error in the last field
+    let (t, errs) = parse_tolerant("{ a = 1, b c = 2 }");
+    assert_eq!(errs.len(), 1);
+    assert!(has_fields(&t, &["a"]));
+
+    // Errors in a let-binding and an interpolated expression
+    let (t, errs) = parse_tolerant("{ a = let x = in x, b = \"%{ 1 + }\", c = [1, , 2] }");
+    assert_eq!(errs.len(), 3);
+    assert!(has_fields(&t, &["a", "b", "c"]));
+
+    // The errors recovered from are still reported when the parser eventually fails
+    let (t, errs) = parse_tolerant("{ a = (1 + 2 }, b = 1 + }");
+    assert!(errs.len() >= 2);
+    assert_matches!(errs.last(), Some(ParseError::UnmatchedCloseBrace(..)));
+    assert_matches!(t.as_ref(), crate::term::Term::ParseError);
+}
+
+#[test]
+fn deprecated_is_not_a_keyword() {
+    use crate::term::MetaValue as Meta;
+
+    let field = |s: &str, name: &str| match parse_without_pos(s).as_ref() {
+        RecRecord(map, ..) => map.get(&Ident::from(name)).cloned().unwrap(),
+        _ => panic!("expected a record"),
+    };
+
+    // As a field name and as a variable
+    assert_eq!(
+        field("{deprecated = true}", "deprecated"),
+        Bool(true).into()
+    );
+    assert_eq!(
+        parse_without_pos("let deprecated = 1 in deprecated"),
+        mk_term::let_in("deprecated", Num(1.0), mk_term::var("deprecated"))
+    );
+
+    // As an annotation, when applied to a string literal
+    assert_matches!(
+        field("{a | deprecated \"use b\" = 1}", "a").as_ref(),
+        MetaValue(Meta { deprecated: Some(message), contracts, .. })
+            if message == "use b" && contracts.is_empty()
+    );
+
+    // Otherwise, as a contract
+    assert_matches!(
+        field("{a | deprecated = 1}", "a").as_ref(),
+        MetaValue(Meta { deprecated: None, contracts, .. }) if contracts.len() == 1
+    );
+
+    // The message of the annotation must be a literal string
+    assert_matches!(
+        parse("{a | deprecated \"use %{b}\" = 1}"),
+        Err(ParseError::InterpolatedDeprecationMessage(..))
+    );
+}
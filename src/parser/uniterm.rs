@@ -208,6 +208,7 @@ impl UniRecord {
                                 match rt.term.into_owned() {
                                     Term::MetaValue(MetaValue {
                                         doc: None,
+                                        deprecated: None,
                                         types: Some(ctrt),
                                         contracts,
                                         priority: MergePriority::Normal,
@@ -16,7 +16,7 @@ use crate::{
     mk_app, mk_fun,
     position::{RawSpan, TermPos},
     term::{make as mk_term, BinaryOp, MetaValue, RecordAttrs, RichTerm, StrChunk, Term, UnaryOp},
-    types::Types,
+    types::{AbsType, Types},
 };
 
 /// Distinguish between the standard string separators `"`/`"` and the multi-line string separators
@@ -58,11 +58,13 @@ pub enum ChunkLiteralPart<'input> {
     Char(char),
 }
 
-/// The last field of a record, that can either be a normal field declaration or an ellipsis.
+/// The last field of a record, that can either be a normal field declaration, an ellipsis, or a
+/// syntax error from which the parser recovered.
 #[derive(Clone, Debug)]
 pub enum RecordLastField {
     Field((FieldPath, RichTerm)),
     Ellipsis,
+    Error,
 }
 
 /// An infix operator that is not applied. Used for the curried operator syntax (e.g `(==)`)
@@ -317,6 +319,34 @@ pub fn mk_label(types: Types, src_id: FileId, l: usize, r: usize) -> Label {
     }
 }
 
+/// Return the message of a deprecation annotation `| deprecated "<message>"`, if `types` is one.
+/// Fail if the message is not a literal string, as in `| deprecated "use %{name}"`.
+///
+/// `deprecated` isn't a keyword, as it is a common field name: the annotation is parsed as the
+/// application of a contract `deprecated` to a string, and recognized afterwards.
+pub fn deprecation_message(types: &Types, span: RawSpan) -> Result<Option<String>, ParseError> {
+    let (function, arg) = match &types.0 {
+        AbsType::Flat(rt) => match rt.as_ref() {
+            Term::App(function, arg) => (function, arg),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    match (function.as_ref(), arg.as_ref()) {
+        (Term::Var(id), Term::StrChunks(chunks)) if id.label == "deprecated" => chunks
+            .iter()
+            .rev()
+            .map(|chunk| match chunk {
+                StrChunk::Literal(s) => Ok(s.as_str()),
+                StrChunk::Expr(..) => Err(ParseError::InterpolatedDeprecationMessage(span)),
+            })
+            .collect::<Result<String, _>>()
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
 /// Generate a `Let` or a `LetPattern` (depending on `pat` being empty or not) from a the parsing
 /// of a let definition. This function fails if the definition has both a non-empty pattern and
 /// is recursive (`pat != Destruct::Empty && rec`), because recursive let-patterns are currently
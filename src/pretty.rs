@@ -85,6 +85,19 @@ where
         } else {
             self.nil()
         })
+        .append(
+            mv.deprecated
+                .as_ref()
+                .map(|msg| {
+                    self.text("|")
+                        .append(self.space())
+                        .append(self.text("deprecated"))
+                        .append(self.space())
+                        .append(self.escaped_string(msg).double_quotes())
+                        .append(self.line())
+                })
+                .unwrap_or(self.nil()),
+        )
         .append(self.intersperse(
             mv.contracts.iter().map(|c| {
                 self.text("|")
@@ -176,6 +189,8 @@ where
 
             DynAccess() => allocator.text("."),
             ArrayElemAt() => allocator.text("%elem_at%"),
+            Trace(false) => allocator.text("%trace%"),
+            Trace(true) => allocator.text("%trace_value%"),
 
             op => allocator.as_string(format!("%{:?}%", op).to_lowercase()),
         }
@@ -227,7 +242,8 @@ where
             Null => allocator.text("null"),
             Bool(v) => allocator.as_string(v),
             Num(v) => allocator.as_string(v),
-            Str(v) => allocator.escaped_string(v),
+            // Strings only appear after evaluation, and are printed back as string literals
+            Str(v) => allocator.escaped_string(v).double_quotes(),
             StrChunks(chunks) => {
                 let multiline = chunks.len() > 1;
                 let nb_perc = chunks
@@ -21,13 +21,21 @@
 //! functions in [`crate::cache`] (see [`crate::cache::Cache::mk_eval_env`]).
 //! Each such value is added to the global environment before the evaluation of the program.
 use crate::cache::*;
-use crate::error::{Error, ToDiagnostic};
+use crate::destruct::{Destruct, Match};
+use crate::diagnostic::{self, ErrorFormat, StructuredDiagnostic};
+use crate::error::{primary, ArgError, Error, EvalWarning, ToDiagnostic};
+use crate::eval::context::{EvalContext, Trace};
+use crate::eval::debugger::Debugger;
+use crate::eval::profile::Profile;
 use crate::identifier::Ident;
+use crate::lint::{self, LintConfig, LintWarning};
 use crate::parser::lexer::Lexer;
-use crate::term::{RichTerm, Term};
-use crate::{eval, parser};
-use codespan::FileId;
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use crate::term::{make as mk_term, MetaValue, RecordAttrs, RichTerm, Term};
+use crate::{eval, mk_app, parser, typecheck};
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::Diagnostic;
+use codespan_reporting::term::termcolor::{ColorChoice, NoColor, StandardStream};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::io::{self, Read};
 use std::result::Result;
@@ -41,6 +49,23 @@ pub struct Program {
     main_id: FileId,
     /// The cache holding the sources and parsed terms of the main source as well as imports.
     cache: Cache,
+    /// The arguments to apply the program to, if it is a top-level function. See
+    /// [`Program::set_args`].
+    args: Option<Vec<(Ident, ProgramArg)>>,
+    /// The evaluation context, holding in particular the environment variables the program is
+    /// allowed to read.
+    context: EvalContext,
+    /// If evaluation should fail when warnings are emitted. See [`Program::set_deny_warnings`].
+    deny_warnings: bool,
+}
+
+/// An argument of a top-level function program, as provided on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramArg {
+    /// A Nickel expression, parsed and typechecked as a standalone source (`--arg`).
+    Expr(String),
+    /// A string taken verbatim (`--arg-str`).
+    Str(String),
 }
 
 impl Program {
@@ -53,7 +78,13 @@ impl Program {
         let mut cache = Cache::new();
         let main_id = cache.add_file(path)?;
 
-        Ok(Program { main_id, cache })
+        Ok(Program {
+            main_id,
+            cache,
+            args: None,
+            context: EvalContext::new(),
+            deny_warnings: false,
+        })
     }
 
     /// Create a program by reading it from a generic source.
@@ -65,44 +96,269 @@ impl Program {
         let mut cache = Cache::new();
         let main_id = cache.add_source(source_name, source)?;
 
-        Ok(Program { main_id, cache })
+        Ok(Program {
+            main_id,
+            cache,
+            args: None,
+            context: EvalContext::new(),
+            deny_warnings: false,
+        })
+    }
+
+    /// Set the arguments of the program.
+    ///
+    /// If the program is a top-level function, it is applied to the record whose fields are the
+    /// given arguments before being evaluated. When the argument is a record pattern, missing and
+    /// unknown arguments are reported upfront, and the record is checked against the contract of
+    /// the pattern. Providing arguments to a program which isn't a function is an error.
+    pub fn set_args(&mut self, args: Vec<(Ident, ProgramArg)>) {
+        self.args = Some(args);
+    }
+
+    /// Allow the program to read the environment variable `name`. By default, the program can't
+    /// read any environment variable.
+    pub fn allow_env(&mut self, name: impl Into<String>) {
+        self.context.allow_env(name);
+    }
+
+    /// Return the environment variables read by the program during evaluation, together with
+    /// their value. Evaluating the program again with the same values for these variables gives
+    /// the same result.
+    pub fn accessed_env(&self) -> &BTreeMap<String, Option<String>> {
+        self.context.accessed_env()
+    }
+
+    /// Turn warnings into errors: if warnings are emitted during evaluation, evaluation fails with
+    /// [`Error::DeniedWarnings`] instead of returning the result.
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// Return the warnings emitted during evaluation, such as the use of deprecated values, sorted
+    /// by position.
+    pub fn warnings(&self) -> Vec<EvalWarning> {
+        let mut warnings = self.context.warnings().to_vec();
+        warnings.sort_by_key(|EvalWarning::Deprecated { usage, .. }| {
+            usage.into_opt().map(|span| (span.src_id, span.start))
+        });
+        warnings
+    }
+
+    /// Silence the traces emitted by the `trace` builtin. By default, traces are printed on the
+    /// standard error.
+    pub fn set_silence_traces(&mut self, silence: bool) {
+        self.context.set_silence_traces(silence);
+    }
+
+    /// Enable the profiling of evaluation. See [`crate::eval::profile`].
+    pub fn enable_profiling(&mut self) {
+        self.context.enable_profiling();
+    }
+
+    /// Return the profile of the evaluations performed so far, or `None` if profiling is not
+    /// enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.context.profile()
+    }
+
+    /// Install a debugger, which observes the subsequent evaluations. See
+    /// [`crate::eval::debugger`].
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.context.set_debugger(debugger);
+    }
+
+    /// Fail with the warnings emitted so far if warnings are denied.
+    fn check_warnings(&self) -> Result<(), Error> {
+        let warnings = self.warnings();
+        if self.deny_warnings && !warnings.is_empty() {
+            Err(warnings.into())
+        } else {
+            Ok(())
+        }
     }
 
     /// Retrieve the parsed term and typecheck it, and generate a fresh global environment. Return
     /// both.
     fn prepare_eval(&mut self) -> Result<(RichTerm, eval::Environment), Error> {
         let GlobalEnv { eval_env, type_env } = self.cache.prepare_stdlib()?;
+
+        // Program transformations desugar the argument pattern of a function away, so we need to
+        // look at the parsed term before preparing it.
+        let pattern = if self.args.is_some() {
+            self.cache.parse_strict(self.main_id)?;
+            self.cache.get_ref(self.main_id).and_then(fun_pattern)
+        } else {
+            None
+        };
+
         self.cache.prepare(self.main_id, &type_env)?;
-        Ok((self.cache.get(self.main_id).unwrap(), eval_env))
+        let t = self.cache.get(self.main_id).unwrap();
+
+        let t = match self.args.clone() {
+            Some(args) => self.apply_args(t, pattern, &args, &type_env)?,
+            None => t,
+        };
+
+        if let (Some(profile), Some(contracts_id)) = (
+            self.context.profile_mut(),
+            self.cache.id_of("<stdlib/contract.ncl>"),
+        ) {
+            profile.hide_file(contracts_id);
+        }
+
+        if let Some(debugger) = self.context.debugger_mut() {
+            debugger.load_sources(self.cache.files(), self.cache.stdlib_ids());
+        }
+
+        // Reporting the position of traces requires the sources, which are all loaded by now.
+        let files = self.cache.files_weak();
+        let stdlib_ids = self.cache.stdlib_ids().to_vec();
+        self.context.set_trace_handler(move |trace| {
+            report_trace(files.upgrade().as_deref(), &stdlib_ids, trace)
+        });
+
+        Ok((t, eval_env))
+    }
+
+    /// Apply the program `t` to the record built from `args`. `pattern` is the argument pattern of
+    /// `t`, or `None` if `t` is not a function.
+    fn apply_args(
+        &mut self,
+        t: RichTerm,
+        pattern: Option<Destruct>,
+        args: &[(Ident, ProgramArg)],
+        type_env: &typecheck::Environment,
+    ) -> Result<RichTerm, Error> {
+        let mut duplicates: Vec<Ident> = Vec::new();
+        for (index, (id, _)) in args.iter().enumerate() {
+            if args[..index].iter().any(|(other, _)| other == id) && !duplicates.contains(id) {
+                duplicates.push(id.clone());
+            }
+        }
+        if !duplicates.is_empty() {
+            return Err(ArgError::DuplicateArgs(duplicates).into());
+        }
+
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None if args.is_empty() => return Ok(t),
+            None => {
+                let names = args.iter().map(|(id, _)| id.clone()).collect();
+                return Err(ArgError::NotAFunction(names, t.pos).into());
+            }
+        };
+
+        if let Destruct::Record {
+            matches,
+            open,
+            span,
+            ..
+        } = &pattern
+        {
+            let fields: Vec<(&Ident, &MetaValue)> = matches
+                .iter()
+                .map(|m| match m {
+                    Match::Simple(id, meta) | Match::Assign(id, meta, _) => (id, meta),
+                })
+                .collect();
+
+            let missing: Vec<(Ident, Option<String>)> = fields
+                .iter()
+                .filter(|(id, meta)| {
+                    meta.value.is_none() && !args.iter().any(|(name, _)| name == *id)
+                })
+                .map(|(id, meta)| ((*id).clone(), meta.doc.clone()))
+                .collect();
+
+            if !missing.is_empty() {
+                return Err(ArgError::MissingArgs(missing, *span).into());
+            }
+
+            if !open {
+                let unknown: Vec<Ident> = args
+                    .iter()
+                    .filter(|(name, _)| !fields.iter().any(|(id, _)| *id == name))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+
+                if !unknown.is_empty() {
+                    return Err(ArgError::UnknownArgs(unknown, *span).into());
+                }
+            }
+        }
+
+        let mut record = HashMap::new();
+        for (id, arg) in args {
+            let value = match arg {
+                ProgramArg::Str(s) => mk_term::string(s.clone()),
+                ProgramArg::Expr(source) => {
+                    let file_id = self.cache.add_tmp(format!("<arg {}>", id), source.clone());
+                    self.cache.prepare(file_id, type_env)?;
+                    self.cache.get(file_id).unwrap()
+                }
+            };
+            record.insert(id.clone(), value);
+        }
+
+        Ok(mk_app!(t, Term::Record(record, RecordAttrs::default())))
     }
 
     /// Parse if necessary, typecheck and then evaluate the program.
     pub fn eval(&mut self) -> Result<RichTerm, Error> {
         let (t, global_env) = self.prepare_eval()?;
-        eval::eval(t, &global_env, &mut self.cache).map_err(|e| e.into())
+        let result = eval::eval(t, &global_env, &mut self.cache, &mut self.context)?;
+        self.check_warnings()?;
+        Ok(result)
     }
 
     /// Same as `eval`, but proceeds to a full evaluation.
     pub fn eval_full(&mut self) -> Result<RichTerm, Error> {
         let (t, global_env) = self.prepare_eval()?;
-        eval::eval_full(t, &global_env, &mut self.cache).map_err(|e| e.into())
+        let result = eval::eval_full(t, &global_env, &mut self.cache, &mut self.context)?;
+        self.check_warnings()?;
+        Ok(result)
+    }
+
+    /// Same as `eval_full`, but collects up to `max_errors` contract violations, missing field
+    /// definitions and merge conflicts raised by independent fields instead of stopping at the
+    /// first one (see [`eval::eval_full_collect`]).
+    /// Evaluation still fails if any error is raised.
+    pub fn eval_full_collect(&mut self, max_errors: usize) -> Result<RichTerm, Error> {
+        let (t, global_env) = self.prepare_eval()?;
+        let result = eval::eval_full_collect(
+            t,
+            &global_env,
+            &mut self.cache,
+            &mut self.context,
+            max_errors,
+        )?;
+        self.check_warnings()?;
+        Ok(result)
     }
 
     /// Same as `eval_full`, but does not substitute all variables.
     pub fn eval_deep(&mut self) -> Result<RichTerm, Error> {
         let (t, global_env) = self.prepare_eval()?;
-        eval::eval_deep(t, &global_env, &mut self.cache).map_err(|e| e.into())
+        let result = eval::eval_deep(t, &global_env, &mut self.cache, &mut self.context)?;
+        self.check_warnings()?;
+        Ok(result)
     }
 
     /// Wrapper for [`query`].
     pub fn query(&mut self, path: Option<String>) -> Result<Term, Error> {
         let global_env = self.cache.prepare_stdlib()?;
-        query(&mut self.cache, self.main_id, &global_env, path)
+        query(
+            &mut self.cache,
+            self.main_id,
+            &global_env,
+            &mut self.context,
+            path,
+        )
     }
 
     /// Load, parse, and typecheck the program and the standard library, if not already done.
     pub fn typecheck(&mut self) -> Result<(), Error> {
-        self.cache.parse(self.main_id)?;
+        self.cache.parse_strict(self.main_id)?;
         self.cache.load_stdlib()?;
         let global_env = self.cache.mk_types_env().expect("program::typecheck(): stdlib has been loaded but was not found in cache on mk_types_env()");
         self.cache
@@ -118,6 +374,21 @@ impl Program {
         Ok(())
     }
 
+    /// Parse the program and run the lints enabled in `config` on it. Imported files are not
+    /// linted.
+    pub fn lint(&mut self, config: &LintConfig) -> Result<Vec<LintWarning>, Error> {
+        self.cache.parse_strict(self.main_id)?;
+
+        let t = self.cache.get_ref(self.main_id).unwrap();
+        Ok(lint::lint(t, config))
+    }
+
+    /// Return the file database of the program, holding the sources of the program and of its
+    /// imports.
+    pub fn files(&self) -> &Files<String> {
+        self.cache.files()
+    }
+
     /// Wrapper for [`report`].
     pub fn report<E>(&mut self, error: E)
     where
@@ -126,6 +397,22 @@ impl Program {
         report(&mut self.cache, error)
     }
 
+    /// Wrapper for [`report_as`].
+    pub fn report_as<E>(&mut self, error: E, format: ErrorFormat)
+    where
+        E: ToDiagnostic<FileId>,
+    {
+        report_as(&mut self.cache, error, format)
+    }
+
+    /// Wrapper for [`render`].
+    pub fn render<E>(&mut self, error: E) -> String
+    where
+        E: ToDiagnostic<FileId>,
+    {
+        render(&mut self.cache, error)
+    }
+
     /// Create a markdown file with documentation for the specified program in `.nickel/doc/program_main_file_name.md`
     #[cfg(feature = "doc")]
     pub fn output_doc(&mut self) -> Result<(), Error> {
@@ -145,10 +432,15 @@ impl Program {
         use crate::pretty::*;
         use pretty::BoxAllocator;
 
-        let Program { ref main_id, cache } = self;
+        let Program {
+            ref main_id, cache, ..
+        } = self;
         let allocator = BoxAllocator;
 
-        let rt = cache.parse_nocache(*main_id)?.0;
+        let (rt, errs) = cache.parse_nocache(*main_id)?;
+        if !errs.no_errors() {
+            return Err(errs.into());
+        }
         let rt = if apply_transforms {
             crate::transform::transform(rt).unwrap()
         } else {
@@ -160,6 +452,15 @@ impl Program {
     }
 }
 
+/// Return the argument pattern of a parsed term if it is a function, or `None` otherwise.
+fn fun_pattern(rt: &RichTerm) -> Option<Destruct> {
+    match rt.as_ref() {
+        Term::FunPattern(_, pat, _) => Some(pat.clone()),
+        Term::Fun(..) => Some(Destruct::Empty),
+        _ => None,
+    }
+}
+
 /// Query the metadata of a path of a term in the cache.
 ///
 /// The path is a list of dot separated identifiers. For example, querying `{a = {b  = ..}}` with
@@ -179,6 +480,7 @@ pub fn query(
     cache: &mut Cache,
     file_id: FileId,
     global_env: &GlobalEnv,
+    ctx: &mut EvalContext,
     path: Option<String>,
 ) -> Result<Term, Error> {
     cache.prepare(file_id, &global_env.type_env)?;
@@ -205,7 +507,35 @@ pub fn query(
         cache.get_owned(file_id).unwrap()
     };
 
-    Ok(eval::eval_meta(t, &global_env.eval_env, cache)?.into())
+    Ok(eval::eval_meta(t, &global_env.eval_env, cache, ctx)?.into())
+}
+
+/// Print a trace on the standard error, as a note pointing to the call to `trace`.
+fn report_trace(files: Option<&Files<String>>, stdlib_ids: &[FileId], trace: &Trace) {
+    let empty = Files::new();
+    // If the sources have been modified since the evaluation started, the positions may not be
+    // valid anymore, and the trace is reported without them.
+    let (files, labels) = match files {
+        Some(files) => (
+            files,
+            trace
+                .pos_outside(stdlib_ids)
+                .into_opt()
+                .map(|span| vec![primary(&span)])
+                .unwrap_or_default(),
+        ),
+        None => (&empty, Vec::new()),
+    };
+    let notes = trace.value_to_string().into_iter().collect();
+    let diagnostic = Diagnostic::note()
+        .with_message(&trace.message)
+        .with_labels(labels)
+        .with_notes(notes);
+
+    let writer = StandardStream::stderr(ColorChoice::Always);
+    let config = codespan_reporting::term::Config::default();
+    // Failing to print a trace is not worth interrupting evaluation.
+    let _ = codespan_reporting::term::emit(&mut writer.lock(), &config, files, &diagnostic);
 }
 
 /// Pretty-print an error.
@@ -218,14 +548,50 @@ pub fn report<E>(cache: &mut Cache, error: E)
 where
     E: ToDiagnostic<FileId>,
 {
-    let writer = StandardStream::stderr(ColorChoice::Always);
-    let config = codespan_reporting::term::Config::default();
+    report_as(cache, error, ErrorFormat::Text)
+}
+
+/// Report an error on the standard error in the given format. See [`crate::diagnostic`] for the
+/// machine-readable formats.
+pub fn report_as<E>(cache: &mut Cache, error: E, format: ErrorFormat)
+where
+    E: ToDiagnostic<FileId>,
+{
     let contracts_id = cache.id_of("<stdlib/contract.ncl>");
     let diagnostics = error.to_diagnostic(cache.files_mut(), contracts_id);
 
-    let result = diagnostics.iter().try_for_each(|d| {
-        codespan_reporting::term::emit(&mut writer.lock(), &config, cache.files_mut(), d)
-    });
+    let result = match format {
+        ErrorFormat::Text => {
+            let writer = StandardStream::stderr(ColorChoice::Always);
+            let config = codespan_reporting::term::Config::default();
+
+            diagnostics
+                .iter()
+                .try_for_each(|d| {
+                    codespan_reporting::term::emit(
+                        &mut writer.lock(),
+                        &config,
+                        cache.files_mut(),
+                        d,
+                    )
+                })
+                .map_err(|err| err.to_string())
+        }
+        ErrorFormat::Json | ErrorFormat::Sarif => {
+            let diagnostics: Vec<StructuredDiagnostic> = diagnostics
+                .iter()
+                .map(|d| StructuredDiagnostic::from_codespan(cache.files(), d))
+                .collect();
+
+            if format == ErrorFormat::Json {
+                diagnostic::write_json_lines(io::stderr().lock(), &diagnostics)
+            } else {
+                diagnostic::write_sarif(io::stderr().lock(), &diagnostics)
+            }
+            .map_err(|err| err.to_string())
+        }
+    };
+
     match result {
         Ok(()) => (),
         Err(err) => panic!(
@@ -235,6 +601,30 @@ where
     };
 }
 
+/// Render an error as text without colors, as it would be reported on a terminal.
+pub fn render<E>(cache: &mut Cache, error: E) -> String
+where
+    E: ToDiagnostic<FileId>,
+{
+    let contracts_id = cache.id_of("<stdlib/contract.ncl>");
+    let diagnostics = error.to_diagnostic(cache.files_mut(), contracts_id);
+    render_diagnostics(cache.files(), &diagnostics)
+}
+
+/// Render diagnostics as text without colors. Used by [render], and by the debugger which keeps
+/// its own copy of the sources.
+pub fn render_diagnostics(files: &Files<String>, diagnostics: &[Diagnostic<FileId>]) -> String {
+    let config = codespan_reporting::term::Config::default();
+    let mut writer = NoColor::new(Vec::new());
+
+    for d in diagnostics.iter() {
+        // Writing to a vector can't fail.
+        codespan_reporting::term::emit(&mut writer, &config, files, d).unwrap();
+    }
+
+    String::from_utf8_lossy(&writer.into_inner()).into_owned()
+}
+
 #[cfg(feature = "doc")]
 mod doc {
     use crate::cache::Cache;
@@ -249,7 +639,7 @@ mod doc {
 
     /// Create a markdown file with documentation for the specified FileId.
     pub fn output_doc(cache: &mut Cache, file_id: FileId) -> Result<(), Error> {
-        cache.parse(file_id)?;
+        cache.parse_strict(file_id)?;
 
         for (file_id, term) in cache.terms() {
             let document = AstNode::from(NodeValue::Document);
@@ -369,6 +759,7 @@ mod tests {
     use crate::parser::{grammar, lexer};
     use crate::position::TermPos;
     use crate::term::SharedTerm;
+    use assert_matches::assert_matches;
     use codespan::Files;
     use std::io::Cursor;
 
@@ -425,4 +816,224 @@ mod tests {
         // that this test fails.
         eval_full("{y = fun x => x, x = fun y => y}").unwrap();
     }
+
+    fn eval_full_with_args(s: &str, args: Vec<(&str, ProgramArg)>) -> Result<RichTerm, Error> {
+        let mut p = Program::new_from_source(Cursor::new(s), "<test>").unwrap();
+        p.set_args(
+            args.into_iter()
+                .map(|(name, arg)| (Ident::from(name), arg))
+                .collect(),
+        );
+        p.eval_full()
+    }
+
+    #[test]
+    fn function_args() {
+        use crate::error::ArgError;
+
+        let program =
+            "fun {a | doc \"first\", b | Num ? 1, c | Str} => if c == \"foo\" then a + b else 0";
+
+        let t = eval_full_with_args(
+            program,
+            vec![
+                ("a", ProgramArg::Expr(String::from("1 + 1"))),
+                ("c", ProgramArg::Str(String::from("foo"))),
+            ],
+        )
+        .unwrap();
+        assert_eq!(t.without_pos(), Term::Num(3.0).into());
+
+        match eval_full_with_args(program, vec![]) {
+            Err(Error::ArgError(ArgError::MissingArgs(missing, _))) => assert_eq!(
+                missing,
+                vec![
+                    (Ident::from("a"), Some(String::from("first"))),
+                    (Ident::from("c"), None)
+                ]
+            ),
+            res => panic!("expected missing arguments, got {:?}", res),
+        }
+
+        assert!(matches!(
+            eval_full_with_args(
+                program,
+                vec![
+                    ("a", ProgramArg::Expr(String::from("1"))),
+                    ("c", ProgramArg::Str(String::from("foo"))),
+                    ("d", ProgramArg::Expr(String::from("1"))),
+                ]
+            ),
+            Err(Error::ArgError(ArgError::UnknownArgs(..)))
+        ));
+        assert!(matches!(
+            eval_full_with_args(
+                program,
+                vec![
+                    ("a", ProgramArg::Expr(String::from("1"))),
+                    ("c", ProgramArg::Expr(String::from("1"))),
+                ]
+            ),
+            Err(Error::EvalError(EvalError::BlameError(..)))
+        ));
+        assert!(matches!(
+            eval_full_with_args("{a = 1}", vec![("a", ProgramArg::Expr(String::from("1")))]),
+            Err(Error::ArgError(ArgError::NotAFunction(..)))
+        ));
+        assert_eq!(
+            eval_full_with_args(
+                program,
+                vec![
+                    ("a", ProgramArg::Expr(String::from("1"))),
+                    ("c", ProgramArg::Str(String::from("foo"))),
+                    ("a", ProgramArg::Str(String::from("2"))),
+                ]
+            ),
+            Err(Error::ArgError(ArgError::DuplicateArgs(vec![Ident::from(
+                "a"
+            )])))
+        );
+    }
+
+    #[test]
+    fn deprecated_warnings() {
+        let program =
+            "let lib = {old | deprecated \"use new\" | default = 1, new | default = old} in
+            let cfg = lib & {old = 2} in
+            {a = cfg.old, b = cfg.old, c = cfg.new, d = cfg.old}";
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        p.eval_full().unwrap();
+        let warnings = p.warnings();
+
+        // One warning for setting `old`, one for each access to `old`
+        assert_eq!(warnings.len(), 4);
+        assert!(warnings.iter().all(|w| match w {
+            EvalWarning::Deprecated { message, usage, .. } =>
+                message == "use new" && usage.is_def(),
+        }));
+
+        // Evaluating again doesn't report the same sites twice
+        p.eval_full().unwrap();
+        assert_eq!(p.warnings().len(), 4);
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        p.set_deny_warnings(true);
+        assert!(matches!(p.eval_full(), Err(Error::DeniedWarnings(w)) if w.len() == 4));
+
+        let mut p = Program::new_from_source(Cursor::new("{old | deprecated \"x\" = 1}"), "<test>")
+            .unwrap();
+        p.eval_full().unwrap();
+        assert!(p.warnings().is_empty());
+    }
+
+    #[test]
+    fn collect_errors() {
+        use crate::error::EvalErrors;
+
+        let program = "{
+              a | Num = \"1\",
+              b = {c | Str = 1, d = 2},
+              e = [1, 2 | Bool, 3],
+              f = a,
+            }";
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        match p.eval_full_collect(10) {
+            Err(Error::EvalErrors(EvalErrors { errors, truncated })) => {
+                // `f` raises the same error as `a`, which is only reported once
+                let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+                assert_eq!(paths, vec!["a", "b.c", "e[1]"]);
+                assert!(errors
+                    .iter()
+                    .all(|e| matches!(e.error, EvalError::BlameError(..))));
+                assert!(!truncated);
+            }
+            result => panic!("expected collected errors, got {:?}", result),
+        }
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        assert_matches!(
+            p.eval_full_collect(2),
+            Err(Error::EvalErrors(EvalErrors { errors, truncated: true })) if errors.len() == 2
+        );
+
+        // At least one error is reported
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        assert_matches!(
+            p.eval_full_collect(0),
+            Err(Error::EvalErrors(EvalErrors { errors, truncated: true })) if errors.len() == 1
+        );
+
+        let program = "{
+              a = {b | Num} & {c = 1},
+              d = {e = 1} & {e = 2},
+              f | Num = \"1\",
+            }";
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        match p.eval_full_collect(10) {
+            Err(Error::EvalErrors(EvalErrors { errors, .. })) => {
+                let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+                assert_eq!(paths, vec!["a.b", "d.e", "f"]);
+                assert_matches!(errors[0].error, EvalError::MissingFieldDef(..));
+                assert_matches!(errors[1].error, EvalError::MergeIncompatibleArgs(..));
+            }
+            result => panic!("expected collected errors, got {:?}", result),
+        }
+
+        let mut p =
+            Program::new_from_source(Cursor::new("{a = 1, b = [{c = 2}]}"), "<test>").unwrap();
+        assert_eq!(
+            p.eval_full_collect(10).map(Term::from),
+            Program::new_from_source(Cursor::new("{a = 1, b = [{c = 2}]}"), "<test>")
+                .unwrap()
+                .eval_full()
+                .map(Term::from)
+        );
+    }
+
+    #[test]
+    fn recovered_syntax_errors() {
+        let program = "{ a = (1 + 2 }, b = 1 + }";
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        assert_matches!(p.typecheck(), Err(Error::ParseErrors(errs)) if errs.errors.len() == 2);
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        assert_matches!(p.lint(&LintConfig::default()), Err(Error::ParseErrors(_)));
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        assert_matches!(p.eval(), Err(Error::ParseErrors(_)));
+    }
+
+    #[test]
+    fn profile() {
+        use crate::eval::profile::FrameKind;
+
+        let program = "let Pos = contract.from_predicate (fun x => x > 0) in
+            let r = {
+              fact = fun n => if n == 0 then 1 else n * fact (n - 1),
+              a | Pos = fact 5,
+            } in r.a";
+
+        let mut p = Program::new_from_source(Cursor::new(program), "<test>").unwrap();
+        p.enable_profiling();
+        p.eval_full().unwrap();
+
+        let frames = p.profile().unwrap().frames();
+        let count = |kind, name: &str| {
+            frames
+                .iter()
+                .find(|(frame, _)| frame.kind == kind && frame.name == name)
+                .map(|(_, stats)| stats.count)
+        };
+
+        assert_eq!(count(FrameKind::Function, "fact"), Some(6));
+        assert_eq!(count(FrameKind::Thunk, "a"), Some(1));
+        assert_eq!(count(FrameKind::Contract, "Pos"), Some(1));
+        // The frames of the builtin contracts are hidden
+        assert!(frames.iter().all(|(frame, _)| p
+            .files()
+            .name(frame.span.src_id)
+            .to_string_lossy()
+            != "<stdlib/contract.ncl>"));
+    }
 }
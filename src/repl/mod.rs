@@ -8,6 +8,7 @@
 //! formatting), etc.
 use crate::cache::{Cache, GlobalEnv};
 use crate::error::{Error, EvalError, IOError, ParseError, ParseErrors, ReplError};
+use crate::eval::context::EvalContext;
 use crate::identifier::Ident;
 use crate::parser::{grammar, lexer, ExtendedTerm};
 use crate::term::{RichTerm, Term};
@@ -77,6 +78,8 @@ pub struct ReplImpl {
     /// The initial type environment, without the toplevel declarations made inside the REPL. Used
     /// to typecheck imports in a fresh environment.
     init_type_env: typecheck::Environment,
+    /// The evaluation context, shared by all the evaluations of the session.
+    context: EvalContext,
 }
 
 impl ReplImpl {
@@ -87,6 +90,7 @@ impl ReplImpl {
             parser: grammar::ExtendedTermParser::new(),
             env: GlobalEnv::new(),
             init_type_env: typecheck::Environment::new(),
+            context: EvalContext::new(),
         }
     }
 
@@ -112,7 +116,7 @@ impl ReplImpl {
 
         let (term, parse_errs) = self
             .parser
-            .parse_term_tolerant(file_id, lexer::Lexer::new(exp))?;
+            .parse_term_tolerant(file_id, lexer::Lexer::new(exp));
 
         if !parse_errs.no_errors() {
             return Err(parse_errs.into());
@@ -134,6 +138,17 @@ impl ReplImpl {
                 repl_impl.cache.resolve_imports(*id).unwrap();
             }
 
+            // Imports are typechecked first, such that their type is known when typechecking the
+            // input.
+            for id in &pending {
+                repl_impl
+                    .cache
+                    .typecheck(*id, &repl_impl.init_type_env)
+                    .map_err(|cache_err| {
+                        cache_err.unwrap_error("repl::eval_(): expected imports to be parsed")
+                    })?;
+            }
+
             typecheck::type_check_in_env(&t, &repl_impl.env.type_env, &repl_impl.cache)?;
 
             if let Some(id) = id {
@@ -145,15 +160,6 @@ impl ReplImpl {
                 );
             }
 
-            for id in &pending {
-                repl_impl
-                    .cache
-                    .typecheck(*id, &repl_impl.init_type_env)
-                    .map_err(|cache_err| {
-                        cache_err.unwrap_error("repl::eval_(): expected imports to be parsed")
-                    })?;
-            }
-
             let t = transform::transform(t).map_err(|err| Error::ParseErrors(err.into()))?;
             for id in &pending {
                 repl_impl
@@ -168,7 +174,10 @@ impl ReplImpl {
         match term {
             ExtendedTerm::RichTerm(t) => {
                 let t = prepare(self, None, t)?;
-                Ok(eval_function(t, &self.env.eval_env, &mut self.cache)?.into())
+                Ok(
+                    eval_function(t, &self.env.eval_env, &mut self.cache, &mut self.context)?
+                        .into(),
+                )
             }
             ExtendedTerm::ToplevelLet(id, t) => {
                 let t = prepare(self, Some(id.clone()), t)?;
@@ -194,7 +203,7 @@ impl Repl for ReplImpl {
             .cache
             .add_file(OsString::from(path.as_ref()))
             .map_err(IOError::from)?;
-        self.cache.parse(file_id)?;
+        self.cache.parse_strict(file_id)?;
         let RichTerm { term, pos } = self.cache.get_ref(file_id).unwrap();
 
         // Check that the entry is a record, which is a precondition of transform_inner
@@ -229,6 +238,11 @@ impl Repl for ReplImpl {
         let (term, pending) = import_resolution::resolve_imports(term, &mut self.cache)?;
         for id in &pending {
             self.cache.resolve_imports(*id).unwrap();
+            self.cache
+                .typecheck(*id, &self.init_type_env)
+                .map_err(|cache_err| {
+                    cache_err.unwrap_error("repl::typecheck(): expected imports to be parsed")
+                })?;
         }
         typecheck::type_check_in_env(&term, &self.env.type_env, &self.cache)?;
 
@@ -244,7 +258,7 @@ impl Repl for ReplImpl {
         use crate::program;
 
         let file_id = self.cache.add_tmp("<repl-query>", String::from(exp));
-        program::query(&mut self.cache, file_id, &self.env, None)
+        program::query(&mut self.cache, file_id, &self.env, &mut self.context, None)
     }
 
     fn cache_mut(&mut self) -> &mut Cache {
@@ -315,11 +329,9 @@ impl InputParser {
         };
 
         match result {
-            Ok((t, e)) if e.no_errors() => InputStatus::Complete(t),
-            Ok((_, e)) if e.errors.iter().all(|e| partial(e)) => InputStatus::Partial,
-            Ok((_, e)) => InputStatus::Failed(e),
-            Err(e) if partial(&e) => InputStatus::Partial,
-            Err(err) => InputStatus::Failed(err.into()),
+            (t, e) if e.no_errors() => InputStatus::Complete(t),
+            (_, e) if e.errors.iter().all(|e| partial(e)) => InputStatus::Partial,
+            (_, e) => InputStatus::Failed(e),
         }
     }
 }
@@ -136,6 +136,7 @@ pub struct Attributes {
     pub types: bool,
     pub default: bool,
     pub value: bool,
+    pub deprecated: bool,
 }
 
 // By default, show all available metadata.
@@ -147,6 +148,7 @@ impl Default for Attributes {
             types: true,
             default: true,
             value: true,
+            deprecated: true,
         }
     }
 }
@@ -247,6 +249,14 @@ fn write_query_result_<R: QueryPrinter>(
                 _ => (),
             }
 
+            match meta.deprecated {
+                Some(ref s) if selected_attrs.deprecated => {
+                    renderer.write_metadata(out, "deprecated", s)?;
+                    found = true;
+                }
+                _ => (),
+            }
+
             match meta.doc {
                 Some(ref s) if selected_attrs.doc => {
                     renderer.write_doc(out, s)?;
@@ -2,13 +2,11 @@
 use super::simple_frontend::{input, serialize, InputError, InputResult};
 use super::{Repl, ReplImpl};
 use crate::cache::Cache;
+use crate::diagnostic::{LabelStyle, Severity, StructuredDiagnostic, StructuredLabel};
 use crate::error::ToDiagnostic;
 use crate::serialize::ExportFormat;
-use codespan::{FileId, Files};
-use codespan_reporting::{
-    diagnostic::{Diagnostic, Label, LabelStyle, Severity},
-    term::termcolor::Ansi,
-};
+use codespan::FileId;
+use codespan_reporting::{diagnostic::Diagnostic, term::termcolor::Ansi};
 use serde::Serialize;
 use serde_repr::Serialize_repr;
 use std::convert::TryInto;
@@ -29,7 +27,7 @@ pub enum WasmResultTag {
     Error = 3,
 }
 
-/// Severity of an error diagnostic. WASM wrapper for the corresponding codespan type.
+/// Severity of an error diagnostic. WASM wrapper for the corresponding structured type.
 #[derive(Serialize_repr, Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
 pub enum WasmErrorSeverity {
@@ -56,7 +54,7 @@ impl From<Severity> for WasmErrorSeverity {
     }
 }
 
-/// Style of an error label. WASM wrapper for the corresponding codespan type.
+/// Style of an error label. WASM wrapper for the corresponding structured type.
 #[derive(Serialize_repr, Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
 pub enum WasmErrorLabelStyle {
@@ -73,7 +71,7 @@ impl From<LabelStyle> for WasmErrorLabelStyle {
     }
 }
 
-/// A serializable error diagnostic. WASM wrapper for the corresponding codespan type.
+/// A serializable error diagnostic. WASM wrapper for [`StructuredDiagnostic`].
 #[derive(Serialize)]
 pub struct WasmErrorDiagnostic {
     pub severity: WasmErrorSeverity,
@@ -82,22 +80,19 @@ pub struct WasmErrorDiagnostic {
     labels: Vec<WasmErrorLabel>,
 }
 
-impl WasmErrorDiagnostic {
-    fn from_codespan(files: &Files<String>, diag: Diagnostic<FileId>) -> Self {
+impl From<StructuredDiagnostic> for WasmErrorDiagnostic {
+    fn from(diag: StructuredDiagnostic) -> Self {
         WasmErrorDiagnostic {
             severity: diag.severity.into(),
             msg: diag.message,
             notes: diag.notes,
-            labels: diag
-                .labels
-                .into_iter()
-                .map(|label| WasmErrorLabel::from_codespan(files, label))
-                .collect(),
+            labels: diag.labels.into_iter().map(WasmErrorLabel::from).collect(),
         }
     }
 }
 
-/// A serializable error label. WASM wrapper for the corresponding codespan type.
+/// A serializable error label. WASM wrapper for [`StructuredLabel`]. As opposed to the latter,
+/// lines and columns start at 0.
 #[derive(Serialize)]
 pub struct WasmErrorLabel {
     msg: String,
@@ -108,34 +103,15 @@ pub struct WasmErrorLabel {
     pub col_end: usize,
 }
 
-impl WasmErrorLabel {
-    fn from_codespan(files: &Files<String>, label: Label<FileId>) -> Self {
-        let start_loc = files.location(label.file_id, label.range.start as u32);
-        let end_loc = files.location(label.file_id, label.range.end as u32);
-
-        let (line_start, col_start, line_end, col_end) = match (start_loc, end_loc) {
-            (Ok(start_loc), Ok(end_loc)) => (
-                start_loc.line.to_usize(),
-                start_loc.column.to_usize(),
-                end_loc.line.to_usize(),
-                end_loc.column.to_usize(),
-            ),
-            (Ok(loc), _) | (_, Ok(loc)) => (
-                loc.line.to_usize(),
-                loc.column.to_usize(),
-                loc.line.to_usize(),
-                loc.column.to_usize(),
-            ),
-            _ => (0, 0, 0, 0),
-        };
-
+impl From<StructuredLabel> for WasmErrorLabel {
+    fn from(label: StructuredLabel) -> Self {
         WasmErrorLabel {
             msg: label.message,
             style: label.style.into(),
-            line_start,
-            col_start,
-            line_end,
-            col_end,
+            line_start: label.line_start.saturating_sub(1),
+            col_start: label.col_start.saturating_sub(1),
+            line_end: label.line_end.saturating_sub(1),
+            col_end: label.col_end.saturating_sub(1),
         }
     }
 }
@@ -199,7 +175,7 @@ impl WasmInputResult {
                 let msg = diags_to_string(cache, &diagnostics);
                 let errors: Vec<WasmErrorDiagnostic> = diagnostics
                     .into_iter()
-                    .map(|diag| WasmErrorDiagnostic::from_codespan(cache.files(), diag))
+                    .map(|diag| StructuredDiagnostic::from_codespan(cache.files(), &diag).into())
                     .collect();
                 (msg, errors)
             }
@@ -7,6 +7,7 @@ use serde::ser::{Error, Serialize, SerializeMap, Serializer};
 use std::collections::HashMap;
 use std::fmt;
 use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 /// Available export formats.
@@ -59,6 +60,19 @@ impl FromStr for ExportFormat {
     }
 }
 
+impl ExportFormat {
+    /// Infer the format of a file from its extension. Return `None` if the extension isn't
+    /// recognized.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_ref() {
+            "json" => Some(ExportFormat::Json),
+            "yaml" | "yml" => Some(ExportFormat::Yaml),
+            "toml" => Some(ExportFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
 /// Implicitly convert float to integers when possible to avoid trailing zeros. Note this this
 /// only work if the float is in range of either `i64` or `f64`. It seems there's no easy general
 /// solution (working for both YAML, TOML, and JSON) to choose the way floating point values are
@@ -208,6 +222,78 @@ where
     }
 }
 
+/// Return the content of a term, skipping the potential metavalues wrapping it.
+fn unwrap_meta(rt: &RichTerm) -> &RichTerm {
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => unwrap_meta(t),
+        _ => rt,
+    }
+}
+
+/// Serialize an array as a stream of YAML documents, one for each element, separated by `---`.
+///
+/// The documents are serialized in a buffer first, such that nothing is written if one of them
+/// can't be serialized.
+pub fn to_writer_multi_doc<W>(mut writer: W, rt: &RichTerm) -> Result<(), SerializationError>
+where
+    W: io::Write,
+{
+    let docs = match unwrap_meta(rt).as_ref() {
+        Term::Array(docs) => docs,
+        _ => return Err(SerializationError::NotAnArray(rt.clone())),
+    };
+
+    let mut buffer = Vec::new();
+    for doc in docs.iter() {
+        validate(ExportFormat::Yaml, doc)?;
+        // `serde_yaml` already starts each document with a `---` separator
+        serde_yaml::to_writer(&mut buffer, doc)
+            .map_err(|err| SerializationError::Other(err.to_string()))?;
+    }
+
+    writer
+        .write_all(&buffer)
+        .map_err(|err| SerializationError::Other(err.to_string()))
+}
+
+/// Split the result of a multi-file export into the files to write.
+///
+/// The term must be a record whose fields are relative paths, mapped to the content of the
+/// corresponding file. The format of each file is inferred from its extension, and files with an
+/// unknown extension are exported in the raw format. Paths that are absolute or that contain `..`
+/// components are rejected, so that all the files end up inside the output directory.
+///
+/// The content of each file is validated for its format. Return the list of files sorted by path.
+pub fn split_files(
+    rt: &RichTerm,
+) -> Result<Vec<(PathBuf, ExportFormat, RichTerm)>, SerializationError> {
+    let fields = match unwrap_meta(rt).as_ref() {
+        Term::Record(fields, _) => fields,
+        _ => return Err(SerializationError::NotARecord(rt.clone())),
+    };
+
+    let mut files = fields
+        .iter()
+        .map(|(id, t)| {
+            let path = PathBuf::from(&id.label);
+            let is_relative = path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+
+            if !is_relative || path.file_name().is_none() {
+                return Err(SerializationError::InvalidPath(id.clone()));
+            }
+
+            let format = ExportFormat::from_extension(&path).unwrap_or(ExportFormat::Raw);
+            validate(format, t)?;
+            Ok((path, format, t.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    files.sort_by(|(p1, _, _), (p2, _, _)| p1.cmp(p2));
+    Ok(files)
+}
+
 pub fn to_string(format: ExportFormat, rt: &RichTerm) -> Result<String, SerializationError> {
     match format {
         ExportFormat::Json => serde_json::to_string_pretty(&rt)
@@ -298,7 +384,8 @@ mod tests {
                 $crate::eval::eval(
                     mk_term::op2(BinaryOp::Eq(), from_json, evaluated.clone()),
                     &Environment::new(),
-                    &mut $crate::cache::resolvers::DummyResolver {}
+                    &mut $crate::cache::resolvers::DummyResolver {},
+                    &mut $crate::eval::context::EvalContext::new()
                 )
                 .map(Term::from),
                 Ok(Term::Bool(true))
@@ -307,7 +394,8 @@ mod tests {
                 $crate::eval::eval(
                     mk_term::op2(BinaryOp::Eq(), from_yaml, evaluated.clone()),
                     &Environment::new(),
-                    &mut $crate::cache::resolvers::DummyResolver {}
+                    &mut $crate::cache::resolvers::DummyResolver {},
+                    &mut $crate::eval::context::EvalContext::new()
                 )
                 .map(Term::from),
                 Ok(Term::Bool(true))
@@ -316,7 +404,8 @@ mod tests {
                 $crate::eval::eval(
                     mk_term::op2(BinaryOp::Eq(), from_toml, evaluated),
                     &Environment::new(),
-                    &mut $crate::cache::resolvers::DummyResolver {}
+                    &mut $crate::cache::resolvers::DummyResolver {},
+                    &mut $crate::eval::context::EvalContext::new()
                 )
                 .map(Term::from),
                 Ok(Term::Bool(true))
@@ -406,4 +495,59 @@ mod tests {
         assert_involutory!("{val = [\"a\", 3, []]}");
         assert_involutory!("{a.foo.bar = \"2\", b = false, c = [{d = \"e\"}, {d = \"f\"}]}");
     }
+
+    fn eval(s: &str) -> RichTerm {
+        mk_program(s).and_then(|mut p| p.eval_full()).unwrap()
+    }
+
+    #[test]
+    fn multi_doc() {
+        let mut out = Vec::new();
+        to_writer_multi_doc(&mut out, &eval("[{a = 1}, \"b\"]")).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "---\na: 1\n---\nb\n");
+
+        to_writer_multi_doc(Vec::new(), &eval("{a = 1}")).unwrap_err();
+        to_writer_multi_doc(Vec::new(), &eval("[fun x => x]")).unwrap_err();
+
+        // Nothing is written if a document after the first one is invalid
+        let mut out = Vec::new();
+        to_writer_multi_doc(&mut out, &eval("[{a = 1}, fun x => x]")).unwrap_err();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn multi_file() {
+        let files = split_files(&eval(
+            "{\"b/c.YML\" = [1], \"a.json\" = {}, \"./d\" = \"raw\", \"e.toml\" = {f = 1}}",
+        ))
+        .unwrap();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, format, _)| (path.to_str().unwrap(), *format))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("./d", ExportFormat::Raw),
+                ("a.json", ExportFormat::Json),
+                ("b/c.YML", ExportFormat::Yaml),
+                ("e.toml", ExportFormat::Toml),
+            ]
+        );
+
+        assert!(matches!(
+            split_files(&eval("[1]")),
+            Err(SerializationError::NotARecord(_))
+        ));
+        assert!(matches!(
+            split_files(&eval("{\"a.txt\" = 1}")),
+            Err(SerializationError::NotAString(_))
+        ));
+        for path in &["../a.json", "a/../../b.json", "/a.json", "", "."] {
+            assert!(matches!(
+                split_files(&eval(&format!("{{\"{}\" = 1}}", path))),
+                Err(SerializationError::InvalidPath(_))
+            ));
+        }
+    }
 }
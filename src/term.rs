@@ -237,6 +237,8 @@ pub struct Contract {
 #[derive(Debug, PartialEq, Clone)]
 pub struct MetaValue {
     pub doc: Option<String>,
+    /// The deprecation message, if the value is deprecated.
+    pub deprecated: Option<String>,
     pub types: Option<Contract>,
     pub contracts: Vec<Contract>,
     pub priority: MergePriority,
@@ -247,6 +249,7 @@ impl From<RichTerm> for MetaValue {
     fn from(rt: RichTerm) -> Self {
         MetaValue {
             doc: None,
+            deprecated: None,
             types: None,
             contracts: Vec::new(),
             priority: Default::default(),
@@ -265,6 +268,7 @@ impl MetaValue {
     pub fn new() -> Self {
         MetaValue {
             doc: None,
+            deprecated: None,
             types: None,
             contracts: Vec::new(),
             priority: Default::default(),
@@ -291,6 +295,7 @@ impl MetaValue {
         // Keep the inner value
         let MetaValue {
             doc,
+            deprecated,
             types,
             mut contracts,
             priority,
@@ -311,6 +316,7 @@ impl MetaValue {
 
         MetaValue {
             doc: doc.or(inner.doc),
+            deprecated: deprecated.or(inner.deprecated),
             types: types.or(inner.types),
             contracts,
             priority: std::cmp::min(priority, inner.priority),
@@ -833,6 +839,9 @@ pub enum UnaryOp {
     NumFromStr(),
     /// Transform a string to an enum.
     EnumFromStr(),
+    /// Read an environment variable. Only the variables allowed by the evaluation context (see
+    /// [`crate::eval::context::EvalContext`]) can be read.
+    EnvVar(),
 }
 
 /// position of a unary operator
@@ -936,6 +945,12 @@ pub enum BinaryOp {
     /// Match a regex on a string, and returns the captured groups together, the index of the
     /// match, etc.
     StrMatch(),
+
+    /// Emit a trace with a message and return the second argument unchanged. If the flag is set,
+    /// the second argument, which is expected to have been deeply evaluated beforehand, is part of
+    /// the trace. Traces are handled by the evaluation context (see
+    /// [`crate::eval::context::EvalContext::trace`]).
+    Trace(bool),
 }
 
 impl BinaryOp {
@@ -1241,6 +1256,7 @@ impl RichTerm {
                     .map_or(Ok(None), |res| res.map(Some))?;
                     let meta = MetaValue {
                         doc: meta.doc,
+                        deprecated: meta.deprecated,
                         types,
                         contracts,
                         priority: meta.priority,
@@ -694,15 +694,12 @@ fn type_check_<L: Linearizer>(
             .map_err(|err| err.into_typecheck_err(state, rt.pos)),
         Term::Import(_) => unify(state, strict, ty, mk_typewrapper::dynamic())
             .map_err(|err| err.into_typecheck_err(state, rt.pos)),
-        // We use the apparent type of the import for checking. This function doesn't recursively
-        // typecheck imports: this is the responsibility of the caller.
-        Term::ResolvedImport(file_id) => {
-            let t = state
-                .resolver
-                .get(*file_id)
-                .expect("Internal error: resolved import not found during typechecking.");
+        // We use the apparent type of the import for checking, which is the type inferred when
+        // the import was typechecked, if any. This function doesn't recursively typecheck imports:
+        // this is the responsibility of the caller.
+        Term::ResolvedImport(_) => {
             let ty_import: TypeWrapper = apparent_type(
-                t.as_ref(),
+                rt.as_ref(),
                 Some(&Envs::from_envs(&envs)),
                 Some(state.resolver),
             )
@@ -792,8 +789,9 @@ impl From<ApparentType> for TypeWrapper {
 /// - if `bound_exp` is a constant (string, number, boolean or symbol) which type can be deduced
 ///   directly without unfolding the expression further, return the corresponding exact type.
 /// - if `bound_exp` is an array, return `Array Dyn`.
-/// - if `bound_exp` is a resolved import, return the apparent type of the imported term. Returns
-///   `Dyn` if the resolver is not passed as a parameter to the function.
+/// - if `bound_exp` is a resolved import, return the type inferred when typechecking the import
+///   if it is known, or the apparent type of the imported term otherwise. Returns `Dyn` if the
+///   resolver is not passed as a parameter to the function.
 /// - Otherwise, return an approximation of the type (currently `Dyn`, but could be more precise in
 ///   the future, such as `Dyn -> Dyn` for functions, `{ | Dyn}` for records, and so on).
 pub fn apparent_type(
@@ -826,6 +824,10 @@ pub fn apparent_type(
             .unwrap_or(ApparentType::Approximated(Types(AbsType::Dyn()))),
         Term::ResolvedImport(f) => {
             if let Some(r) = resolver {
+                if let Some(ty) = r.get_type(*f) {
+                    return ApparentType::Inferred(ty);
+                }
+
                 let t = r
                     .get(*f)
                     .expect("Internal error: resolved import not found during typechecking.");
@@ -172,6 +172,8 @@ pub fn get_uop_type(
             mk_typewrapper::str(),
             mk_tyw_enum!(mk_typewrapper::dynamic()),
         ),
+        // Str -> Dyn
+        UnaryOp::EnvVar() => (mk_typewrapper::str(), mk_typewrapper::dynamic()),
     })
 }
 
@@ -350,6 +352,11 @@ pub fn get_bop_type(
                 ("groups", mk_typewrapper::array(AbsType::Str()))
             ),
         ),
+        // forall a. Str -> a -> a
+        BinaryOp::Trace(_) => {
+            let ty = TypeWrapper::Ptr(state.table.fresh_var());
+            (mk_typewrapper::str(), ty.clone(), ty)
+        }
         // Str -> Str -> Array Str
         BinaryOp::StrSplit() => (
             mk_typewrapper::str(),
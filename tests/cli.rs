@@ -0,0 +1,76 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run `nickel` with the given arguments, feeding the program on the standard input.
+fn nickel(args: &[&str], program: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nickel"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // `nickel` may exit before reading its input, such as when its arguments are invalid
+    let _ = child.stdin.take().unwrap().write_all(program.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+const PROGRAM: &str = "fun {env | Str, replicas | Num ? 1} => {name = env, count = replicas}";
+
+#[test]
+fn export_args() {
+    let output = nickel(
+        &["export", "--arg-str", "env=prod", "--arg", "replicas=2"],
+        PROGRAM,
+    );
+    assert!(output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value, serde_json::json!({"name": "prod", "count": 2}));
+}
+
+#[test]
+fn export_missing_args() {
+    let output = nickel(&["export"], PROGRAM);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0802"), "{}", stderr);
+    assert!(
+        stderr.contains("missing argument to the program"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn export_duplicate_args() {
+    let output = nickel(
+        &["export", "--arg-str", "env=prod", "--arg-str", "env=dev"],
+        PROGRAM,
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0804"), "{}", stderr);
+    assert!(
+        stderr.contains("`env` provided more than once"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn export_max_errors() {
+    let program = "{a | Num = \"a\", b | Str = 1}";
+    let output = nickel(&["export", "--all-errors", "--max-errors", "0"], program);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("at least one error must be reported"),
+        "{}",
+        stderr
+    );
+
+    let output = nickel(&["export", "--all-errors", "--max-errors", "1"], program);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("E0301").count(), 1, "{}", stderr);
+}
@@ -1,6 +1,6 @@
 use assert_matches::assert_matches;
 use codespan::Files;
-use nickel_lang::error::{Error, EvalError, ToDiagnostic};
+use nickel_lang::error::{Error, EvalError, FixData, ToDiagnostic};
 
 use nickel_lang_utilities::eval;
 
@@ -92,6 +92,34 @@ fn records_contracts_simple() {
     assert_raise_blame!("let x | {a: Num, s: {foo: Bool}} = {a = 1, s = {}} in %deep_seq% x x");
 }
 
+#[test]
+fn records_contracts_missing_fields() {
+    let missing = |s: &str| match eval(s) {
+        Err(Error::EvalError(err)) => match err.fix_data() {
+            Some(FixData::MissingFields(fields, pos)) if pos.as_opt_ref().is_some() => {
+                let mut names: Vec<String> = fields.into_iter().map(|(id, _)| id.label).collect();
+                names.sort();
+                names
+            }
+            data => panic!("unexpected fix data {:?}", data),
+        },
+        result => panic!("unexpected result {:?}", result),
+    };
+
+    assert_eq!(
+        missing("let x | {a: Num, s: Str, b: Bool} = {a = 1} in %deep_seq% x x"),
+        vec!["b", "s"]
+    );
+    assert_eq!(
+        missing("let x | {a: Num, s: {foo: Bool}} = {a = 1, s = {}} in %deep_seq% x x"),
+        vec!["foo"]
+    );
+    assert_eq!(
+        missing("let x = {a | Num, b | Str} & {a = 1} in x.b"),
+        vec!["b"]
+    );
+}
+
 #[test]
 fn records_contracts_poly() {
     // TODO: this test should ultimately pass (i.e., the program should be rejected)
@@ -173,6 +201,37 @@ fn lists_contracts() {
     res.unwrap_err().to_diagnostic(&mut files, None);
 }
 
+#[test]
+fn blame_reports_value() {
+    let mut files = Files::new();
+    let notes = |res: Result<_, Error>, files: &mut Files<String>| -> Vec<String> {
+        res.unwrap_err()
+            .to_diagnostic(files, None)
+            .into_iter()
+            .flat_map(|diag| diag.notes)
+            .collect()
+    };
+
+    let notes_missing = notes(eval("{a = 1, d = 2} | {a : Num, b : Str}"), &mut files);
+    assert!(notes_missing.contains(&String::from("missing fields: `b`")));
+    assert!(notes_missing.contains(&String::from("extra fields: `d`")));
+    assert!(notes_missing.contains(&String::from("value:\n{ a = 1, d = 2, }")));
+
+    let notes_nested = notes(
+        eval("%deep_seq% ({items = [1, \"a\"]} | {items : Array Num}) false"),
+        &mut files,
+    );
+    assert!(notes_nested.contains(&String::from(
+        "expected a value of type `Num` at `items[_]`"
+    )));
+
+    // Cyclic values are rendered up to a fixed depth.
+    let notes_cyclic = notes(eval("let r = {a = b, b = {c = a}} in r | Num"), &mut files);
+    assert!(notes_cyclic
+        .iter()
+        .any(|note| note.starts_with("value:") && note.contains('…')));
+}
+
 #[test]
 fn records_contracts_closed() {
     assert_raise_blame!("{a=1} | {}");
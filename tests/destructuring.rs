@@ -39,6 +39,14 @@ fn default() {
     assert_eq!(eval_file("destructuring/default.ncl"), Ok(Term::Bool(true)));
 }
 
+#[test]
+fn default_contract() {
+    assert_eq!(
+        eval_file("destructuring/default_contract.ncl"),
+        Ok(Term::Bool(true))
+    );
+}
+
 #[test]
 fn typecontract() {
     assert_eq!(
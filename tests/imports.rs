@@ -1,7 +1,9 @@
 use assert_matches::assert_matches;
-use nickel_lang::error::{Error, EvalError, TypecheckError};
+use nickel_lang::cache::{normalize_path, Cache, CacheError};
+use nickel_lang::error::{Error, EvalError, ImportError, TypecheckError};
 use nickel_lang::program::Program;
 use nickel_lang::term::Term;
+use nickel_lang::typecheck::Environment;
 use std::io::BufReader;
 use std::path::PathBuf;
 
@@ -70,6 +72,30 @@ fn typecheck_fail() {
     );
 }
 
+#[test]
+fn syntax_error_fail() {
+    let mut prog = Program::new_from_source(
+        BufReader::new(format!("let x = {} in 1", mk_import("syntax_error.ncl")).as_bytes()),
+        "should_fail",
+    )
+    .unwrap();
+    assert_matches!(
+        prog.eval(),
+        Err(Error::ImportError(ImportError::ParseErrors(..)))
+    );
+
+    // An import of a file with errors already in the cache fails as well
+    let mut cache = Cache::new();
+    for name in ["a", "b"] {
+        let file_id = cache.add_string(name, mk_import("syntax_error.ncl"));
+        cache.parse(file_id).unwrap();
+        assert_matches!(
+            cache.resolve_imports(file_id),
+            Err(CacheError::Error(ImportError::ParseErrors(..)))
+        );
+    }
+}
+
 #[test]
 fn static_typing_fail() {
     let mut prog = Program::new_from_source(
@@ -83,6 +109,144 @@ fn static_typing_fail() {
     );
 }
 
+#[test]
+fn static_typing_imported_types() {
+    let mut prog = Program::new_from_source(
+        BufReader::new(
+            format!(
+                "let lib = {} in (lib.f 1 : Num)",
+                mk_import("typed_lib.ncl")
+            )
+            .as_bytes(),
+        ),
+        "should_be = 2",
+    )
+    .unwrap();
+    assert_eq!(prog.eval().map(Term::from), Ok(Term::Num(2.)));
+
+    let mut prog = Program::new_from_source(
+        BufReader::new(
+            format!(
+                "let lib = {} in (lib.f lib.name : Num)",
+                mk_import("typed_lib.ncl")
+            )
+            .as_bytes(),
+        ),
+        "should_fail",
+    )
+    .unwrap();
+    assert_matches!(
+        prog.typecheck(),
+        Err(Error::TypecheckError(TypecheckError::TypeMismatch(..)))
+    );
+}
+
+#[test]
+fn static_typing_shared_import() {
+    let mut cache = Cache::new();
+    let global_env = Environment::new();
+
+    // The typed library is loaded first by another source, which is not typechecked
+    let lib_id = cache.add_string("lib", mk_import("typed_lib.ncl"));
+    cache.parse(lib_id).unwrap();
+    cache.resolve_imports(lib_id).unwrap();
+
+    // The second importer of the library must be typechecked after the library
+    let main_id = cache.add_string(
+        "main",
+        format!(
+            "{{x = {}, y = ({}).f 2}}",
+            mk_import("typed_importer.ncl"),
+            mk_import("typed_lib.ncl")
+        ),
+    );
+    cache.parse(main_id).unwrap();
+    cache.resolve_imports(main_id).unwrap();
+    assert!(cache.typecheck(main_id, &global_env).is_ok());
+}
+
+#[test]
+fn invalidate_imported_types() {
+    let dir = std::env::temp_dir();
+    let lib_path = dir.join(format!("nickel-invalidate-lib-{}.ncl", std::process::id()));
+    let main_path = dir.join(format!("nickel-invalidate-main-{}.ncl", std::process::id()));
+    std::fs::write(&lib_path, "{f : Num -> Num = fun x => x}").unwrap();
+    std::fs::write(
+        &main_path,
+        format!("let lib = import {:?} in (lib.f 1 : Num)", lib_path),
+    )
+    .unwrap();
+
+    let mut cache = Cache::new();
+    let global_env = Environment::new();
+    let main_id = cache.add_file(main_path.as_os_str()).unwrap();
+    cache.parse(main_id).unwrap();
+    cache.resolve_imports(main_id).unwrap();
+    assert!(cache.typecheck(main_id, &global_env).is_ok());
+
+    let lib_id = cache.id_of(lib_path.as_os_str()).unwrap();
+    cache
+        .files_mut()
+        .update(lib_id, String::from("{f : Str -> Str = fun x => x}"));
+    cache.invalidate(lib_id);
+    cache.parse(lib_id).unwrap();
+    cache.resolve_imports(lib_id).unwrap();
+    assert_matches!(
+        cache.typecheck(main_id, &global_env),
+        Err(CacheError::Error(TypecheckError::ArrowTypeMismatch(..)))
+    );
+
+    std::fs::remove_file(lib_path).unwrap();
+    std::fs::remove_file(main_path).unwrap();
+}
+
+#[test]
+fn replaced_source_invalidates_all_importers() {
+    let dir = std::env::temp_dir();
+    let lib_path = dir.join(format!("nickel-replaced-lib-{}.ncl", std::process::id()));
+    std::fs::write(&lib_path, "{a : Num = 1}").unwrap();
+    let main_src = format!("let lib = import {:?} in (lib.a + 1 : Num)", lib_path);
+
+    let mut cache = Cache::new();
+    let global_env = Environment::new();
+    let main_ids: Vec<_> = (0..2)
+        .map(|i| cache.add_string(format!("main-{}", i), main_src.clone()))
+        .collect();
+    for main_id in main_ids.iter() {
+        cache.parse(*main_id).unwrap();
+        cache.resolve_imports(*main_id).unwrap();
+        assert!(cache.typecheck(*main_id, &global_env).is_ok());
+    }
+
+    let lib_name = normalize_path(&lib_path).unwrap();
+    let lib_id = cache.replace_string(lib_name, String::from("{a : Str = \"a\"}"));
+    assert_eq!(cache.id_of(lib_path.as_os_str()), Some(lib_id));
+
+    let mut importers = cache.invalidate(lib_id);
+    importers.sort();
+    assert_eq!(importers, main_ids);
+
+    cache.parse(lib_id).unwrap();
+    cache.resolve_imports(lib_id).unwrap();
+    for main_id in main_ids {
+        assert_matches!(
+            cache.typecheck(main_id, &global_env),
+            Err(CacheError::Error(_))
+        );
+    }
+
+    // The content of the file on disk is shadowed by the replaced source
+    let other_id = cache.add_string("other", main_src);
+    cache.parse(other_id).unwrap();
+    cache.resolve_imports(other_id).unwrap();
+    assert_matches!(
+        cache.typecheck(other_id, &global_env),
+        Err(CacheError::Error(_))
+    );
+
+    std::fs::remove_file(lib_path).unwrap();
+}
+
 #[test]
 fn serialize() {
     use nickel_lang::term::Term;
@@ -138,3 +138,19 @@ fn importing() {
 fn overriding() {
     check_file("overriding.ncl");
 }
+
+#[test]
+fn evaluated_strings() {
+    use nickel_lang::term::{StrChunk, Term};
+
+    let s = "a \"b\" %{c} \\ %d";
+    let pretty_s = pretty(&Term::Str(String::from(s)).into());
+    assert_eq!(pretty_s, r#""a \"b\" \%{c} \\ %d""#);
+
+    match parse(&pretty_s).unwrap().as_ref() {
+        Term::StrChunks(chunks) => {
+            assert_eq!(chunks.as_slice(), &[StrChunk::Literal(String::from(s))])
+        }
+        t => panic!("expected a string literal, got {:?}", t),
+    }
+}
@@ -1,11 +1,12 @@
 use assert_matches::assert_matches;
 use codespan::Files;
 use nickel_lang::cache::resolvers::DummyResolver;
-use nickel_lang::error::TypecheckError;
+use nickel_lang::error::{FixData, TypecheckError};
+use nickel_lang::identifier::Ident;
 use nickel_lang::parser::{grammar, lexer};
 use nickel_lang::term::RichTerm;
 use nickel_lang::typecheck::{type_check_in_env, Environment};
-use nickel_lang::types::Types;
+use nickel_lang::types::{AbsType, Types};
 
 fn type_check(rt: &RichTerm) -> Result<Types, TypecheckError> {
     type_check_in_env(rt, &Environment::new(), &mut DummyResolver {})
@@ -237,3 +238,31 @@ fn recursive_let() {
         Err(TypecheckError::TypeMismatch(..))
     );
 }
+
+#[test]
+fn fix_data() {
+    assert_matches!(
+        type_check_expr("let r : {foo : Num, bar : Str} = {foo = 1, bar = \"a\"} in (r.fo : Num)")
+            .unwrap_err()
+            .fix_data(),
+        Some(FixData::MisspelledField(id, candidates))
+            if id.label == "fo" && id.pos.as_opt_ref().is_some() && candidates == vec![Ident::from("foo")]
+    );
+    assert_matches!(
+        type_check_expr("let r : {foo : Num} = {foo = 1} in (r.bar : Num)")
+            .unwrap_err()
+            .fix_data(),
+        None
+    );
+    assert_matches!(
+        type_check_expr("let f = fun x => x + 1 in (f 1 : Num)")
+            .unwrap_err()
+            .fix_data(),
+        Some(FixData::DynamicValue(Types(AbsType::Arrow(dom, codom)), _))
+            if dom.0 == AbsType::Dyn() && codom.0 == AbsType::Num()
+    );
+    assert_matches!(
+        type_check_expr("map").unwrap_err().fix_data(),
+        Some(FixData::UnboundIdentifier(id, _)) if id.label == "map"
+    );
+}
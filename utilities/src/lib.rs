@@ -134,7 +134,13 @@ pub fn bench_terms<'r>(rts: Vec<Bench<'r>>) -> Box<dyn Fn(&mut Criterion) + 'r>
                     },
                     |(mut c_local, id, t)| {
                         c_local.prepare(id, &type_env).unwrap();
-                        eval::eval(t, &eval_env, &mut c_local).unwrap()
+                        eval::eval(
+                            t,
+                            &eval_env,
+                            &mut c_local,
+                            &mut eval::context::EvalContext::new(),
+                        )
+                        .unwrap()
                     },
                     criterion::BatchSize::LargeInput,
                 )
@@ -176,7 +182,7 @@ macro_rules! ncl_bench_group {
         pub fn $group_name() {
             use nickel_lang::{
                 cache::{GlobalEnv, Cache, ImportResolver},
-                eval::eval,
+                eval::{context::EvalContext, eval},
                 transform::import_resolution::resolve_imports,
             };
 
@@ -198,7 +204,7 @@ macro_rules! ncl_bench_group {
                         },
                         |(mut c_local, id, t)| {
                             c_local.prepare(id, &type_env).unwrap();
-                            eval(t, &eval_env, &mut c_local).unwrap()
+                            eval(t, &eval_env, &mut c_local, &mut EvalContext::new()).unwrap()
                         },
                         criterion::BatchSize::LargeInput,
                         )